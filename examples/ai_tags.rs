@@ -2,9 +2,8 @@ use std::{collections::HashMap, time::Instant};
 
 use booru_db::{
     db,
-    index::{Index, IndexLoader, RangeIndex, RangeIndexLoader},
-    query::Item,
-    Query, Queryable, RangeQuery, ID,
+    index::{IdMap, Index, IndexLoader, RangeIndex, RangeIndexLoader, Term},
+    IndexContext, Query, Queryable, RangeQuery, ID,
 };
 use sqlx::postgres::PgPoolOptions;
 
@@ -80,7 +79,7 @@ async fn main() {
 
     let start_time = Instant::now();
     let db = DbLoader::new()
-        .with_loader("id", IdIndex::default())
+        .with_loader("id", IdMap::loader(|post: &BooruPost| post.id))
         .with_loader(
             "ai",
             AiTagIndexLoader {
@@ -88,6 +87,7 @@ async fn main() {
                 name_to_id,
             },
         )
+        .with_progress(1_000_000, |indexed| println!("Indexed {indexed} posts"))
         .load(posts.into_values());
     let elapsed_ns = start_time.elapsed().as_nanos();
     println!(
@@ -108,15 +108,14 @@ async fn main() {
 
     let reverse = false;
     let page_1 = result.get(0, 20, reverse);
-    let id_index: &IdIndex = db.index().unwrap();
-    for id in page_1 {
-        print!("ID: {id}, ");
-        if let Some(post_id) = id_index.id_to_post_id.get(&id) {
-            println!("PostID: {post_id}");
-        }
+    let id_map: &IdMap<BooruPost, u32> = db.index().unwrap();
+    for (id, post_id) in page_1.iter().zip(id_map.to_external(&page_1)) {
+        println!("ID: {id}, PostID: {post_id}");
     }
 }
 
+// A RangeIndex per tag is more than field_index! covers, it only generates indexes
+// over one field, so AiTagIndex implements IndexLoader and Index itself.
 pub struct AiTagIndexLoader {
     tags: HashMap<u32, RangeIndexLoader<u16>>,
     name_to_id: HashMap<String, u32>,
@@ -129,7 +128,7 @@ impl IndexLoader<BooruPost> for AiTagIndexLoader {
         }
     }
 
-    fn load(self: Box<Self>) -> Box<(dyn Index<BooruPost>)> {
+    fn load(self: Box<Self>) -> Box<dyn Index<BooruPost>> {
         let tags = self
             .tags
             .into_iter()
@@ -143,25 +142,20 @@ impl IndexLoader<BooruPost> for AiTagIndexLoader {
     }
 }
 
+#[derive(Clone)]
 pub struct AiTagIndex {
     tags: HashMap<u32, RangeIndex<u16>>,
     name_to_id: HashMap<String, u32>,
 }
 
-impl Index<BooruPost> for AiTagIndex {
-    fn query<'s>(
-        &'s self,
-        _ident: Option<&str>,
-        mut text: &str,
-        inverse: bool,
-    ) -> Option<Query<Queryable<'s>>> {
-        let mut range_query = RangeQuery::All;
-        if let Some((tag, q)) = text.split_once(':') {
-            if let Ok(q) = q.parse::<RangeQuery<u16>>() {
-                text = tag;
-                range_query = q;
-            }
-        }
+impl AiTagIndex {
+    // `ai:solo` or `ai:solo:>=90`.
+    fn query_ai(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let (text, range_query) = match term.sub_path() {
+            [] => (term.value, RangeQuery::All),
+            [tag] => (*tag, term.value.parse::<RangeQuery<u16>>().ok()?),
+            _ => return None,
+        };
         let tag_id = text
             .parse::<u32>()
             .ok()
@@ -171,6 +165,26 @@ impl Index<BooruPost> for AiTagIndex {
         query.inverse = inverse;
         Some(query)
     }
+}
+
+impl Index<BooruPost> for AiTagIndex {
+    fn query<'s>(
+        &'s self,
+        ident: Option<&str>,
+        text: &str,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_ai(&Term::parse(ident, text), inverse)
+    }
+
+    fn query_term<'s>(
+        &'s self,
+        _context: &IndexContext<'_, BooruPost>,
+        term: &Term<'_>,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_ai(term, inverse)
+    }
 
     fn insert(&mut self, id: ID, post: &BooruPost) {
         for &(tag, score) in &post.ai_tags {
@@ -193,56 +207,8 @@ impl Index<BooruPost> for AiTagIndex {
         self.remove(id, old);
         self.insert(id, new);
     }
-}
-
-#[derive(Default)]
-struct IdIndex {
-    id_to_post_id: HashMap<ID, u32>,
-    post_id_to_id: HashMap<u32, ID>,
-}
-
-impl IndexLoader<BooruPost> for IdIndex {
-    fn add(&mut self, id: ID, post: &BooruPost) {
-        self.id_to_post_id.insert(id, post.id);
-        self.post_id_to_id.insert(post.id, id);
-    }
-
-    fn load(self: Box<Self>) -> Box<dyn Index<BooruPost>> {
-        self
-    }
-}
-
-impl Index<BooruPost> for IdIndex {
-    fn query<'s>(
-        &'s self,
-        _ident: Option<&str>,
-        text: &str,
-        inverse: bool,
-    ) -> Option<Query<Queryable<'s>>> {
-        let post_id: u32 = text.parse().ok()?;
-        let ids = self
-            .post_id_to_id
-            .get(&post_id)
-            .map(|&id| vec![id])
-            .unwrap_or_default();
-        Some(Query::new(Item::Single(Queryable::IDsOwned(ids)), inverse))
-    }
 
-    fn insert(&mut self, id: ID, post: &BooruPost) {
-        self.id_to_post_id.insert(id, post.id);
-        self.post_id_to_id.insert(post.id, id);
-    }
-
-    fn remove(&mut self, id: ID, post: &BooruPost) {
-        self.id_to_post_id.remove(&id);
-        self.post_id_to_id.remove(&post.id);
-    }
-
-    fn update(&mut self, id: ID, old: &BooruPost, new: &BooruPost) {
-        if old.id == new.id {
-            return;
-        }
-        self.remove(id, old);
-        self.insert(id, new);
+    fn clone_index(&self) -> Option<Box<dyn Index<BooruPost>>> {
+        Some(Box::new(self.clone()))
     }
 }
@@ -0,0 +1,92 @@
+// A standalone search server over posts read from a JSON lines file.
+//
+// cargo run --example server --features server -- posts.jsonl 127.0.0.1:8080
+//
+// Each line is a post like {"id": 1, "tags": ["1girl", "solo"]}, then
+// curl '127.0.0.1:8080/search?q=solo+-1girl&limit=10' returns the matching post ids.
+use std::{env, fs::File, io::BufReader, sync::Arc, sync::RwLock};
+
+use booru_db::{
+    db, field_index,
+    import::{ImportError, JsonLines},
+    index::{IdMap, KeysIndex},
+    server::{Searcher, Server},
+    Query, QueryResult, ID,
+};
+use serde_json::Value;
+
+pub struct BooruPost {
+    id: u32,
+    tags: Vec<String>,
+}
+
+db!(BooruPost);
+
+field_index! {
+    struct TagIndex(TagIndexLoader) for BooruPost: KeysIndex<String> = |post| &post.tags;
+}
+
+// Searcher is implemented for a wrapper so writers can update the Db behind the lock.
+struct DbSearcher(RwLock<Db>);
+
+impl Searcher for DbSearcher {
+    fn query(&self, query: &Query<String>) -> Result<QueryResult, Vec<String>> {
+        self.0.read().unwrap().query(query)
+    }
+
+    // Post ids rather than the Db's internal ids.
+    fn page(&self, result: &QueryResult, offset: usize, limit: usize) -> Vec<ID> {
+        let db = self.0.read().unwrap();
+        let id_map: &IdMap<BooruPost, u32> = db.index().unwrap();
+        result
+            .get(offset, limit, true)
+            .into_iter()
+            .filter_map(|id| id_map.external(id))
+            .collect()
+    }
+
+    fn autocomplete(&self, prefix: &str, limit: usize) -> Vec<(String, usize)> {
+        let db = self.0.read().unwrap();
+        let tags: &TagIndex = db.index().unwrap();
+        let mut tags: Vec<(String, usize)> = tags
+            .0
+            .keys_with_prefix(prefix)
+            .map(|(tag, count)| (tag.clone(), count))
+            .collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        tags.truncate(limit);
+        tags
+    }
+}
+
+fn post(value: Value) -> Result<BooruPost, ImportError> {
+    let id = value["id"].as_u64().and_then(|id| u32::try_from(id).ok());
+    let tags = value["tags"].as_array().map(|tags| {
+        tags.iter()
+            .filter_map(|tag| tag.as_str().map(str::to_string))
+            .collect()
+    });
+    match (id, tags) {
+        (Some(id), Some(tags)) => Ok(BooruPost { id, tags }),
+        _ => Err(ImportError::parse(0, "expected an id and a list of tags")),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args().skip(1);
+    let path = args.next().ok_or("usage: server <posts.jsonl> [addr]")?;
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8080".to_string());
+
+    let mut loader = DbLoader::new()
+        .with_loader("id", IdMap::loader(|post: &BooruPost| post.id))
+        .with_default(TagIndexLoader::default());
+    let reader = BufReader::new(File::open(path)?);
+    loader.try_extend(JsonLines::<_, Value>::new(reader).posts(post))?;
+    let db = loader.finish();
+    println!("loaded {} posts, listening on {addr}", db.len());
+
+    Server::new(Arc::new(DbSearcher(RwLock::new(db))))
+        .with_error_log(|e| eprintln!("server: {e}"))
+        .serve(addr)?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+/* C API of booru-db built with the ffi feature, see src/ffi.rs. */
+
+#ifndef BOORU_DB_H
+#define BOORU_DB_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FfiDbLoader FfiDbLoader;
+typedef struct FfiDb FfiDb;
+typedef struct QueryResult QueryResult;
+
+/* Tags are the default index, `id:1,2` matches posts by id. */
+FfiDbLoader *booru_db_loader_new(void);
+
+/* Adds a post with tags_len tags, false if id was already added or a tag isn't valid UTF-8. */
+bool booru_db_loader_add(FfiDbLoader *loader, uint32_t id, const char *const *tags,
+                         size_t tags_len);
+
+/* Builds the db, loader is freed. */
+FfiDb *booru_db_loader_finish(FfiDbLoader *loader);
+
+void booru_db_free(FfiDb *db);
+
+/* Adds a post, false if the id is in use or a tag isn't valid UTF-8. */
+bool booru_db_insert(FfiDb *db, uint32_t id, const char *const *tags, size_t tags_len);
+
+/* Removes the post with id, false if there is none. */
+bool booru_db_remove(FfiDb *db, uint32_t id);
+
+/* Runs a query such as `tag -other`, null if it can't be parsed or a term is unknown. */
+QueryResult *booru_db_query(const FfiDb *db, const char *query);
+
+size_t booru_db_result_matched(const QueryResult *result);
+
+/* Writes the ids of up to limit matches starting at offset to out, in the order posts
+ * were added or the latest first if reverse. Returns how many were written. */
+size_t booru_db_result_page(const FfiDb *db, const QueryResult *result, size_t offset,
+                            size_t limit, bool reverse, uint32_t *out);
+
+void booru_db_result_free(QueryResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BOORU_DB_H */
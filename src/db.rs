@@ -0,0 +1,2297 @@
+use std::{
+    any::{type_name, TypeId},
+    borrow::{Borrow, Cow},
+    cell::OnceCell,
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "rand")]
+use rand::{thread_rng, Rng};
+
+#[cfg(feature = "rand")]
+use crate::ApproxCount;
+
+use crate::{
+    index::{ChangeSet, Index, IndexLoader, Term},
+    packed, persist,
+    query::{
+        stats,
+        util::{to_checks, to_ids},
+        ArenaItem, EvalStats, Item, NodeId, PairCache, QueryArena, QueryHints, Rewrites,
+        SavedQueries, WarmTerms,
+    },
+    DbSink, DependencyError, DuplicateIdentifier, Identifier, IdentifierOptions, NegativePolicy,
+    Packed, PostStore, Query, QueryError, QueryResult, Queryable, QueryableOwned, Tenants,
+    TermCount, ValidationError, VerifyReport, ID,
+};
+
+// Loaders by type in the order registered, a loader can be registered under multiple
+// identifiers.
+struct LoaderMap<P> {
+    entries: Vec<(TypeId, Box<dyn IndexLoader<P>>)>,
+}
+
+impl<P: 'static> LoaderMap<P> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    // Registering a loader type again replaces it in place.
+    fn insert<T: IndexLoader<P>>(&mut self, t: T) {
+        let type_id = TypeId::of::<T>();
+        match self.get_mut(&type_id) {
+            Some(loader) => *loader = Box::new(t),
+            None => self.entries.push((type_id, Box::new(t))),
+        }
+    }
+
+    fn get_mut(&mut self, type_id: &TypeId) -> Option<&mut Box<dyn IndexLoader<P>>> {
+        self.entries
+            .iter_mut()
+            .find(|(t, _)| t == type_id)
+            .map(|(_, loader)| loader)
+    }
+
+    fn remove(&mut self, type_id: &TypeId) -> Option<Box<dyn IndexLoader<P>>> {
+        let position = self.entries.iter().position(|(t, _)| t == type_id)?;
+        Some(self.entries.remove(position).1)
+    }
+
+    fn type_ids(&self) -> Vec<TypeId> {
+        self.entries.iter().map(|(type_id, _)| *type_id).collect()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&TypeId, &Box<dyn IndexLoader<P>>)> {
+        self.entries
+            .iter()
+            .map(|(type_id, loader)| (type_id, loader))
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = (&TypeId, &mut Box<dyn IndexLoader<P>>)> {
+        self.entries
+            .iter_mut()
+            .map(|(type_id, loader)| (&*type_id, loader))
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn IndexLoader<P>>> {
+        self.entries.iter_mut().map(|(_, loader)| loader)
+    }
+
+    // Empty forks of the loaders that support it, kept to rebuild indexes that can't be cloned.
+    fn forks(&self) -> HashMap<TypeId, Box<dyn IndexLoader<P>>> {
+        self.iter()
+            .filter_map(|(type_id, loader)| Some((*type_id, loader.fork()?)))
+            .collect()
+    }
+}
+
+// Indexes by type in the order their loaders were registered, which is the order
+// insert, remove and update call them in. Db::index looks them up by the type alone.
+// Indexes are shared with snapshots until written to.
+struct IndexMap<P> {
+    positions: HashMap<TypeId, usize>,
+    entries: Vec<(TypeId, Arc<dyn Index<P>>)>,
+    // Empty loaders by index type for make_mut.
+    loaders: Arc<HashMap<TypeId, Box<dyn IndexLoader<P>>>>,
+}
+
+impl<P> Clone for IndexMap<P> {
+    fn clone(&self) -> Self {
+        Self {
+            positions: self.positions.clone(),
+            entries: self.entries.clone(),
+            loaders: self.loaders.clone(),
+        }
+    }
+}
+
+// Clones index first if a snapshot shares it. Indexes without clone_index are rebuilt
+// from the posts in store with a fork of their loader, before the write being made.
+fn make_mut<'a, P: 'static>(
+    index: &'a mut Arc<dyn Index<P>>,
+    loader: Option<&dyn IndexLoader<P>>,
+    store: Option<&PostStore<P>>,
+) -> &'a mut dyn Index<P> {
+    if Arc::get_mut(index).is_none() {
+        let copy = index.clone_index().or_else(|| {
+            let mut loader = loader?.fork()?;
+            for (id, post) in store?.iter() {
+                loader.add(id, post);
+            }
+            Some(loader.load())
+        });
+        let copy = copy.expect(
+            "an index shared with a snapshot needs Index::clone_index, \
+             or a store and a loader supporting IndexLoader::fork to be written to",
+        );
+        *index = Arc::from(copy);
+    }
+    Arc::get_mut(index).unwrap()
+}
+
+impl<P: 'static> IndexMap<P> {
+    fn new() -> Self {
+        Self {
+            positions: HashMap::new(),
+            entries: Vec::new(),
+            loaders: Arc::default(),
+        }
+    }
+
+    fn get<T: Index<P>>(&self) -> Option<&T> {
+        let index = self.by_type(&TypeId::of::<T>())?;
+        index.as_any().downcast_ref()
+    }
+
+    fn get_mut<T: Index<P>>(&mut self, store: Option<&PostStore<P>>) -> Option<&mut T> {
+        let type_id = TypeId::of::<T>();
+        let position = *self.positions.get(&type_id)?;
+        let loader = self.loaders.get(&type_id).map(|loader| loader.as_ref());
+        let index = make_mut(&mut self.entries[position].1, loader, store);
+        index.as_any_mut().downcast_mut()
+    }
+
+    fn by_type(&self, type_id: &TypeId) -> Option<&Arc<dyn Index<P>>> {
+        let position = *self.positions.get(type_id)?;
+        Some(&self.entries[position].1)
+    }
+
+    fn insert_boxed(&mut self, t: Box<dyn Index<P>>) {
+        let key = t.as_any().type_id();
+        match self.positions.get(&key) {
+            Some(&position) => self.entries[position].1 = Arc::from(t),
+            None => {
+                self.positions.insert(key, self.entries.len());
+                self.entries.push((key, Arc::from(t)));
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&TypeId, &Arc<dyn Index<P>>)> {
+        self.entries.iter().map(|(type_id, index)| (type_id, index))
+    }
+
+    // Moves indexes after their dependencies, otherwise keeping registration order.
+    fn sort_dependencies(&mut self) -> Result<(), DependencyError> {
+        let mut pending = std::mem::take(&mut self.entries);
+        let dependencies: HashMap<TypeId, Vec<TypeId>> = pending
+            .iter()
+            .map(|(type_id, index)| (*type_id, index.dependencies()))
+            .collect();
+        for dependency in dependencies.values().flatten() {
+            if !self.positions.contains_key(dependency) {
+                return Err(DependencyError::Missing);
+            }
+        }
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|(type_id, _)| {
+                    dependencies[type_id]
+                        .iter()
+                        .all(|dependency| self.entries.iter().any(|(t, _)| t == dependency))
+                })
+                .ok_or(DependencyError::Cyclic)?;
+            self.entries.push(pending.remove(ready));
+        }
+        for (position, (type_id, _)) in self.entries.iter().enumerate() {
+            self.positions.insert(*type_id, position);
+        }
+        Ok(())
+    }
+
+    // Calls f with each index in order and the indexes written before it.
+    fn write(
+        &mut self,
+        identifiers: &HashMap<Option<String>, TypeId>,
+        identifier_options: IdentifierOptions,
+        store: Option<&PostStore<P>>,
+        mut f: impl FnMut(&TypeId, &mut dyn Index<P>, &WriteContext<'_, P>),
+    ) {
+        for position in 0..self.entries.len() {
+            let (written, rest) = self.entries.split_at_mut(position);
+            let (type_id, index) = &mut rest[0];
+            let context = WriteContext {
+                identifiers,
+                identifier_options,
+                written,
+            };
+            let loader = self.loaders.get(type_id).map(|loader| loader.as_ref());
+            f(type_id, make_mut(index, loader, store), &context);
+        }
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Arc<dyn Index<P>>> {
+        self.entries.iter().map(|(_, index)| index)
+    }
+}
+
+// How often to report progress and the callback.
+type Progress = (usize, Box<dyn FnMut(usize) + Send>);
+
+// Queries taking at least the duration are passed to the callback, see Db::set_slow_query_log.
+type SlowQueryLog = (Duration, Arc<dyn Fn(&str, &EvalStats) + Send + Sync>);
+
+/// Builds a Db from posts of type P using the registered IndexLoaders.
+pub struct DbLoader<P> {
+    identifiers: HashMap<Option<String>, TypeId>,
+    // type names of loaders for DuplicateIdentifier.
+    loader_names: HashMap<TypeId, &'static str>,
+    identifier_options: IdentifierOptions,
+    loaders: LoaderMap<P>,
+    len: usize,
+    progress: Option<Progress>,
+    store: Option<PostStore<P>>,
+    tenants: Option<Tenants<P>>,
+}
+
+impl<P: 'static> Default for DbLoader<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: 'static> DbLoader<P> {
+    pub fn new() -> Self {
+        Self {
+            identifiers: HashMap::new(),
+            loader_names: HashMap::new(),
+            identifier_options: IdentifierOptions::default(),
+            loaders: LoaderMap::new(),
+            len: 0,
+            progress: None,
+            store: None,
+            tenants: None,
+        }
+    }
+
+    /// Keeps a copy of every post in the Db, see PostStore.
+    /// Posts aren't written to snapshots so a Db from open_mmap starts with an empty store.
+    pub fn with_store(mut self) -> Self
+    where
+        P: Clone,
+    {
+        self.store = Some(PostStore::new());
+        self
+    }
+
+    /// Normalizes identifiers and the identifiers of terms, such as to route `Score:>10`
+    /// to score. Identifiers already registered are normalized too, Err if two collide.
+    pub fn with_identifier_options(
+        mut self,
+        options: IdentifierOptions,
+    ) -> Result<Self, DuplicateIdentifier> {
+        self.identifier_options = options;
+        let identifiers = std::mem::take(&mut self.identifiers);
+        for (identifier, type_id) in identifiers {
+            let identifier = identifier.map(|i| options.normalize(&i).into_owned());
+            if let Some(existing) = self.identifiers.get(&identifier) {
+                return Err(DuplicateIdentifier {
+                    identifier,
+                    existing: self.loader_names[existing],
+                    loader: self.loader_names[&type_id],
+                });
+            }
+            self.identifiers.insert(identifier, type_id);
+        }
+        Ok(self)
+    }
+
+    /// Partitions posts by the tenant f returns, see Db::query_tenant.
+    /// Tenants are written to snapshots, so open_mmap needs one saved with tenants.
+    pub fn with_tenants(
+        mut self,
+        f: impl Fn(&P) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.tenants = Some(Tenants::new(f));
+        self
+    }
+
+    /// Adds posts then calls finish.
+    pub fn load(mut self, posts: impl IntoIterator<Item = P>) -> Db<P> {
+        self.extend(posts);
+        self.finish()
+    }
+
+    /// Calls f with the number of posts added every `every` posts.
+    pub fn with_progress(mut self, every: usize, f: impl FnMut(usize) + Send + 'static) -> Self {
+        self.progress = Some((every.max(1), Box::new(f)));
+        self
+    }
+
+    /// Like load but posts are split into shards that are added on separate threads.
+    /// Loaders that don't support IndexLoader::fork and merge are given every post on this
+    /// thread. Progress counts posts added by the shards.
+    pub fn load_parallel<T: Borrow<P> + Sync>(
+        mut self,
+        posts: &[T],
+        threads: usize,
+    ) -> io::Result<Db<P>> {
+        // Merging an empty fork checks the loader supports merge before adding posts.
+        let forked: HashSet<TypeId> = self
+            .loaders
+            .iter_mut()
+            .filter_map(|(type_id, loader)| {
+                let fork = loader.fork()?;
+                loader.merge(fork).ok().map(|_| *type_id)
+            })
+            .collect();
+        if forked.is_empty() || posts.is_empty() {
+            for post in posts {
+                self.add(post.borrow());
+            }
+            return Ok(self.try_finish()?);
+        }
+        let shard_size = posts.len().div_ceil(threads.max(1));
+        let shards: Vec<&[T]> = posts.chunks(shard_size).collect();
+        let forks: Vec<Vec<_>> = shards
+            .iter()
+            .map(|_| {
+                self.loaders
+                    .iter()
+                    .filter(|(type_id, _)| forked.contains(type_id))
+                    .filter_map(|(type_id, loader)| loader.fork().map(|fork| (*type_id, fork)))
+                    .collect()
+            })
+            .collect();
+
+        let start = self.len;
+        let added = AtomicUsize::new(0);
+        let every = self.progress.as_ref().map(|(every, _)| *every);
+        let progress = Mutex::new(self.progress.as_mut().map(|(_, f)| f));
+        let loaders = &mut self.loaders;
+        thread::scope(|scope| {
+            let handles: Vec<_> = shards
+                .iter()
+                .zip(forks)
+                .enumerate()
+                .map(|(shard_index, (shard, mut forks))| {
+                    let offset = start + shard_index * shard_size;
+                    let (added, progress) = (&added, &progress);
+                    scope.spawn(move || {
+                        for (i, post) in shard.iter().enumerate() {
+                            let id = (offset + i) as ID;
+                            for (_, fork) in forks.iter_mut() {
+                                fork.add(id, post.borrow());
+                            }
+                            let added = start + added.fetch_add(1, Ordering::Relaxed) + 1;
+                            if every.is_some_and(|every| added.is_multiple_of(every)) {
+                                if let Some(f) = progress.lock().unwrap().as_mut() {
+                                    f(added);
+                                }
+                            }
+                        }
+                        forks
+                    })
+                })
+                .collect();
+
+            for (type_id, loader) in loaders.iter_mut() {
+                if forked.contains(type_id) {
+                    continue;
+                }
+                for (i, post) in posts.iter().enumerate() {
+                    loader.add((start + i) as ID, post.borrow());
+                }
+            }
+
+            if let Some(store) = &mut self.store {
+                for (i, post) in posts.iter().enumerate() {
+                    store.insert_ref((start + i) as ID, post.borrow());
+                }
+            }
+            if let Some(tenants) = &mut self.tenants {
+                for (i, post) in posts.iter().enumerate() {
+                    tenants.add((start + i) as ID, post.borrow());
+                }
+            }
+
+            for handle in handles {
+                for (type_id, fork) in handle.join().unwrap() {
+                    loaders.get_mut(&type_id).unwrap().merge(fork)?;
+                }
+            }
+            Ok::<_, io::Error>(())
+        })?;
+        self.len += posts.len();
+        Ok(self.try_finish()?)
+    }
+
+    /// Creates a Db from a snapshot written by Db::save_mmap.
+    /// Every loader must support IndexLoader::open with the same identifiers used to save.
+    /// Checksums aren't checked, see persist::Snapshot::verify.
+    ///
+    /// # Safety
+    /// The file is mapped, it must not be written to or truncated while the Db is alive,
+    /// see persist::Snapshot::open.
+    pub unsafe fn open_mmap(mut self, path: impl AsRef<Path>) -> io::Result<Db<P>> {
+        let snapshot = persist::Snapshot::open(path)?;
+        let missing = |name: &str| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("snapshot is missing section {name}"),
+            )
+        };
+        let base = snapshot.section("base").ok_or_else(|| missing("base"))?;
+        let mut reader = base.reader();
+        let len = reader.read_len(size_of::<Packed>())?;
+        let checks = reader.read_checks(len)?.to_vec();
+        let base_checks = base_from_checks(checks);
+        let len = reader.read_u64()? as usize;
+        let hidden = reader.read_ids(len)?.to_vec();
+
+        let names = persist::index_section_names(&self.identifiers);
+        let order = self.loaders.type_ids();
+        let forks = self.loaders.forks();
+        let mut db = Db::from_parts(self.identifiers, order, forks, base_checks, |type_id| {
+            let name = &names[&type_id];
+            let section = snapshot.section(name).ok_or_else(|| missing(name))?;
+            let loader = self.loaders.remove(&type_id).unwrap();
+            loader.open(section)
+        })?;
+        let mut hidden = QueryableOwned::from(hidden);
+        hidden.check_and_convert();
+        db.hidden = Arc::new(hidden);
+        db.identifier_options = self.identifier_options;
+        db.posts = self.store.map(Arc::new);
+        if let Some(mut tenants) = self.tenants {
+            let section = snapshot
+                .section("tenants")
+                .ok_or_else(|| missing("tenants"))?;
+            tenants.open(&section)?;
+            db.tenants = Some(Arc::new(tenants));
+        }
+        Ok(db)
+    }
+
+    /// Posts are given ids in the order they are added,
+    /// allowing posts to be streamed in from an async source.
+    pub fn add(&mut self, post: &P) {
+        let id = self.len as ID;
+        for loader in self.loaders.values_mut() {
+            loader.add(id, post);
+        }
+        if let Some(store) = &mut self.store {
+            store.insert_ref(id, post);
+        }
+        if let Some(tenants) = &mut self.tenants {
+            tenants.add(id, post);
+        }
+        self.len += 1;
+        if let Some((every, f)) = &mut self.progress {
+            if self.len.is_multiple_of(*every) {
+                f(self.len);
+            }
+        }
+    }
+
+    pub fn extend(&mut self, posts: impl IntoIterator<Item = P>) {
+        for post in posts {
+            self.add(&post);
+        }
+    }
+
+    /// Stops at the first error, keeping posts added before it.
+    pub fn try_extend<E>(
+        &mut self,
+        posts: impl IntoIterator<Item = Result<P, E>>,
+    ) -> Result<(), E> {
+        for post in posts {
+            self.add(&post?);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Panics if index dependencies are missing or cyclic, see try_finish.
+    pub fn finish(self) -> Db<P> {
+        match self.try_finish() {
+            Ok(db) => db,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Like finish, Err if an index depends on one that isn't loaded or indexes
+    /// depend on each other.
+    pub fn try_finish(mut self) -> Result<Db<P>, DependencyError> {
+        if let Some((every, f)) = &mut self.progress {
+            if !self.len.is_multiple_of(*every) {
+                f(self.len);
+            }
+        }
+        let mut db = Db::new(self.identifiers, self.loaders, self.len)?;
+        db.identifier_options = self.identifier_options;
+        db.posts = self.store.map(Arc::new);
+        db.tenants = self.tenants.map(|mut tenants| {
+            tenants.finish();
+            Arc::new(tenants)
+        });
+        Ok(db)
+    }
+
+    /// Panics if there already is a default index, see add_default.
+    pub fn with_default<L: IndexLoader<P>>(mut self, loader: L) -> Self {
+        if let Err(e) = self.add_default(loader) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Panics if an identifier is already registered, see add_loader.
+    pub fn with_loader<I: Identifier, L: IndexLoader<P>>(
+        mut self,
+        identifier: I,
+        loader: L,
+    ) -> Self {
+        if let Err(e) = self.add_loader(identifier, loader) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Like with_default, Err if there already is a default index.
+    pub fn add_default<L: IndexLoader<P>>(&mut self, loader: L) -> Result<(), DuplicateIdentifier> {
+        self.insert_loader(None, loader)
+    }
+
+    /// Like with_loader, Err if an identifier is already registered.
+    /// Nothing is registered on Err, so loaders from plugins can be skipped.
+    pub fn add_loader<I: Identifier, L: IndexLoader<P>>(
+        &mut self,
+        identifier: I,
+        loader: L,
+    ) -> Result<(), DuplicateIdentifier> {
+        self.insert_loader(Some(identifier.to_idents()), loader)
+    }
+
+    fn insert_loader<L: IndexLoader<P>>(
+        &mut self,
+        identifiers: Option<Vec<String>>,
+        loader: L,
+    ) -> Result<(), DuplicateIdentifier> {
+        let type_id = TypeId::of::<L>();
+        let loader_name = type_name::<L>();
+        let options = self.identifier_options;
+        let identifiers: Vec<Option<String>> = identifiers
+            .map(|i| {
+                i.iter()
+                    .map(|i| Some(options.normalize(i).into_owned()))
+                    .collect()
+            })
+            .unwrap_or(Vec::from([None]));
+        for (i, identifier) in identifiers.iter().enumerate() {
+            let existing = match self.identifiers.get(identifier) {
+                Some(existing) => self.loader_names[existing],
+                // Repeated in identifiers.
+                None if identifiers[..i].contains(identifier) => loader_name,
+                None => continue,
+            };
+            return Err(DuplicateIdentifier {
+                identifier: identifier.clone(),
+                existing,
+                loader: loader_name,
+            });
+        }
+        for identifier in identifiers {
+            self.identifiers.insert(identifier, type_id);
+        }
+        self.loader_names.insert(type_id, loader_name);
+        self.loaders.insert(loader);
+        Ok(())
+    }
+}
+
+/// Indexes of posts of type P, terms are routed to an index by their identifier.
+pub struct Db<P> {
+    identifiers: HashMap<Option<String>, TypeId>,
+    indexes: IndexMap<P>,
+    // ids in the db, stored as ids instead of checks when they're sparse.
+    base_checks: Arc<QueryableOwned>,
+    saved: SavedQueries,
+    rewrites: Rewrites,
+    // `order:name` sorts by the Index::order of the index with the identifier.
+    orders: HashMap<String, String>,
+    negative_policy: NegativePolicy,
+    identifier_options: IdentifierOptions,
+    slow_query_log: Option<SlowQueryLog>,
+    warm: Arc<WarmTerms>,
+    pairs: Arc<PairCache>,
+    // incremented by every write, changed has the seq each id was last written at.
+    seq: u64,
+    changed: Arc<HashMap<ID, u64>>,
+    // changed has every write after this seq, earlier ones were forgotten.
+    changes_from: u64,
+    // ids inserted after loading with the seq they were inserted at in seq order,
+    // for query_pinned. Removed ids are left in, they're no longer in the base.
+    inserted: Arc<Vec<(u64, ID)>>,
+    // ids removed from base_checks by hide, their index data is kept.
+    hidden: Arc<QueryableOwned>,
+    posts: Option<Arc<PostStore<P>>>,
+    tenants: Option<Arc<Tenants<P>>>,
+}
+
+impl<P: 'static> Db<P> {
+    fn new(
+        identifiers: HashMap<Option<String>, TypeId>,
+        mut loaders: LoaderMap<P>,
+        len: usize,
+    ) -> Result<Self, DependencyError> {
+        let last_id = len.checked_sub(1);
+        let base_checks = if let Some(last_id) = last_id {
+            let last_id = last_id as ID;
+            let mut checks = vec![Packed::MAX; packed::words_for(last_id)];
+            if let Some(check) = checks.last_mut() {
+                // Every bit up to and including last_id's.
+                *check = packed::bit(last_id) | (packed::bit(last_id) - 1);
+            }
+            base_from_checks(checks)
+        } else {
+            QueryableOwned::default()
+        };
+
+        let order = loaders.type_ids();
+        let forks = loaders.forks();
+        Self::from_parts(identifiers, order, forks, base_checks, |type_id| {
+            let loader = loaders.remove(&type_id).unwrap();
+            Ok(loader.load())
+        })
+    }
+
+    // identifiers map to loader types, load is called once per loader type in order.
+    // forks are empty loaders by loader type, see LoaderMap::forks.
+    fn from_parts<E: From<DependencyError>>(
+        identifiers: HashMap<Option<String>, TypeId>,
+        order: Vec<TypeId>,
+        mut forks: HashMap<TypeId, Box<dyn IndexLoader<P>>>,
+        base_checks: QueryableOwned,
+        mut load: impl FnMut(TypeId) -> Result<Box<dyn Index<P>>, E>,
+    ) -> Result<Self, E> {
+        let mut indexes = IndexMap::new();
+        // loader type -> index type, a loader can have multiple identifiers.
+        let mut loaded = HashMap::new();
+        let mut loaders = HashMap::new();
+        for type_id in order {
+            let index = load(type_id)?;
+            let index_type = index.as_any().type_id();
+            loaded.insert(type_id, index_type);
+            if let Some(fork) = forks.remove(&type_id) {
+                loaders.insert(index_type, fork);
+            }
+            indexes.insert_boxed(index);
+        }
+        indexes.loaders = Arc::new(loaders);
+        indexes.sort_dependencies()?;
+        let index_identifiers = identifiers
+            .into_iter()
+            .map(|(identifier, type_id)| (identifier, loaded[&type_id]))
+            .collect();
+
+        Ok(Self {
+            identifiers: index_identifiers,
+            indexes,
+            base_checks: Arc::new(base_checks),
+            saved: SavedQueries::new(),
+            rewrites: Rewrites::new(),
+            orders: HashMap::new(),
+            negative_policy: NegativePolicy::default(),
+            identifier_options: IdentifierOptions::default(),
+            slow_query_log: None,
+            warm: Arc::default(),
+            pairs: Arc::default(),
+            seq: 0,
+            changed: Arc::default(),
+            changes_from: 0,
+            inserted: Arc::default(),
+            hidden: Arc::default(),
+            posts: None,
+            tenants: None,
+        })
+    }
+
+    /// Opens a snapshot written by Db::save_mmap with the indexes of loader,
+    /// see DbLoader::open_mmap.
+    ///
+    /// # Safety
+    /// See DbLoader::open_mmap.
+    pub unsafe fn open_mmap(path: impl AsRef<Path>, loader: DbLoader<P>) -> io::Result<Self> {
+        loader.open_mmap(path)
+    }
+
+    /// Writes base_checks, tenants and every index to a snapshot that can be opened with
+    /// Db::open_mmap. Every index must support Index::save.
+    /// Written to a temporary file first, see persist::write_atomic.
+    pub fn save_mmap(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        persist::write_atomic(path, |writer| self.write_mmap(writer))
+    }
+
+    fn write_mmap(&self, writer: &mut persist::SnapshotWriter) -> io::Result<()> {
+        writer.begin_section("base")?;
+        let checks = self.checks();
+        writer.write_u64(checks.len() as u64)?;
+        writer.write_checks(&checks)?;
+        let hidden = match &*self.hidden {
+            QueryableOwned::IDs { ids } => ids.clone(),
+            QueryableOwned::Checks { checks, .. } => to_ids(checks),
+        };
+        writer.write_u64(hidden.len() as u64)?;
+        writer.write_ids(&hidden)?;
+        writer.end_section()?;
+        if let Some(tenants) = &self.tenants {
+            writer.begin_section("tenants")?;
+            tenants.save(writer)?;
+            writer.end_section()?;
+        }
+        let names = persist::index_section_names(&self.identifiers);
+        for (type_id, index) in self.indexes.iter() {
+            let name = &names[type_id];
+            writer.begin_section(name)?;
+            index.save(writer)?;
+            writer.end_section()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the ids changed since seq and their data in every index to a snapshot
+    /// that Db::apply_delta applies to a copy of this db taken at seq.
+    /// Every index must support Index::save_delta. Returns the seq to pass next time.
+    /// Written to a temporary file first, see persist::write_atomic. Once written the
+    /// changes up to since are forgotten, as the delta before this one persisted them.
+    pub fn snapshot_delta(&mut self, path: impl AsRef<Path>, since: u64) -> io::Result<u64> {
+        let ids = self.changed_since(since).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("changes up to {} were forgotten", self.changes_from),
+            )
+        })?;
+        persist::write_atomic(path, |writer| self.write_delta(writer, since, &ids))?;
+        self.forget_changes(since);
+        Ok(self.seq)
+    }
+
+    fn write_delta(
+        &self,
+        writer: &mut persist::SnapshotWriter,
+        since: u64,
+        ids: &[ID],
+    ) -> io::Result<()> {
+        writer.begin_section("delta")?;
+        writer.write_u64(since)?;
+        writer.write_u64(self.seq)?;
+        writer.write_u64(ids.len() as u64)?;
+        writer.write_ids(ids)?;
+        // Which of ids are in the db and which are hidden.
+        for set in [&*self.base_checks, &*self.hidden] {
+            let ids: Vec<ID> = ids.iter().copied().filter(|&id| set.contains(id)).collect();
+            writer.write_u64(ids.len() as u64)?;
+            writer.write_ids(&ids)?;
+        }
+        writer.end_section()?;
+        if let Some(tenants) = &self.tenants {
+            writer.begin_section("tenants")?;
+            tenants.save_delta(ids, writer)?;
+            writer.end_section()?;
+        }
+        let names = persist::index_section_names(&self.identifiers);
+        for (type_id, index) in self.indexes.iter() {
+            let name = &names[type_id];
+            writer.begin_section(name)?;
+            index.save_delta(ids, writer)?;
+            writer.end_section()?;
+        }
+        Ok(())
+    }
+
+    /// Applies a snapshot written by Db::snapshot_delta, returning the seq it was written at.
+    /// Every index must support Index::apply_delta.
+    pub fn apply_delta(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        let snapshot = persist::Snapshot::read(path)?;
+        let missing = |name: &str| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("delta is missing section {name}"),
+            )
+        };
+        let delta = snapshot.section("delta").ok_or_else(|| missing("delta"))?;
+        let mut reader = delta.reader();
+        let _since = reader.read_u64()?;
+        let seq = reader.read_u64()?;
+        let len = reader.read_u64()? as usize;
+        let ids = reader.read_ids(len)?.to_vec();
+        let len = reader.read_u64()? as usize;
+        let present = reader.read_ids(len)?.to_vec();
+        let len = reader.read_u64()? as usize;
+        let hidden = reader.read_ids(len)?.to_vec();
+
+        let mut sections = HashMap::new();
+        for (type_id, name) in persist::index_section_names(&self.identifiers) {
+            let section = snapshot.section(&name).ok_or_else(|| missing(&name))?;
+            sections.insert(type_id, section);
+        }
+        let tenants = match self.tenants {
+            Some(_) => Some(
+                snapshot
+                    .section("tenants")
+                    .ok_or_else(|| missing("tenants"))?,
+            ),
+            None => None,
+        };
+        // Dependencies first, so derived indexes can read their updated data.
+        let mut result = Ok(());
+        self.indexes.write(
+            &self.identifiers,
+            self.identifier_options,
+            self.posts.as_deref(),
+            |type_id, index, context| {
+                if result.is_ok() {
+                    let section = sections.remove(type_id).unwrap();
+                    result = index.apply_delta_in(context, &ids, section);
+                }
+            },
+        );
+        result?;
+        if let (Some(tenants), Some(section)) = (&mut self.tenants, tenants) {
+            Arc::make_mut(tenants).apply_delta(&ids, &mut section.reader())?;
+        }
+        let base = Arc::make_mut(&mut self.base_checks);
+        let hidden_ids = Arc::make_mut(&mut self.hidden);
+        for &id in &ids {
+            // Both lists are sorted like ids.
+            if present.binary_search(&id).is_ok() {
+                base.insert(id);
+            } else {
+                base.remove(id);
+            }
+            if hidden.binary_search(&id).is_ok() {
+                hidden_ids.insert(id);
+            } else {
+                hidden_ids.remove(id);
+            }
+        }
+        for id in ids {
+            self.update_caches(id);
+            self.touch(id);
+        }
+        Ok(seq)
+    }
+
+    /// Incremented by every insert, remove, update, hide and unhide.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Ids written after seq, sorted. None if the changes after seq were forgotten.
+    pub fn changed_since(&self, seq: u64) -> Option<Vec<ID>> {
+        if seq < self.changes_from {
+            return None;
+        }
+        let mut ids: Vec<ID> = self
+            .changed
+            .iter()
+            .filter(|(_, &changed)| changed > seq)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        Some(ids)
+    }
+
+    /// Drops the changes up to seq, changed_since before seq returns None after.
+    pub fn forget_changes(&mut self, seq: u64) {
+        let seq = seq.min(self.seq);
+        if seq <= self.changes_from {
+            return;
+        }
+        Arc::make_mut(&mut self.changed).retain(|_, &mut changed| changed > seq);
+        self.changes_from = seq;
+    }
+
+    fn touch(&mut self, id: ID) {
+        self.seq += 1;
+        Arc::make_mut(&mut self.changed).insert(id, self.seq);
+    }
+
+    /// Every id in the db that isn't hidden, built from ids each call when they're
+    /// sparse. Queries run over the ids without building it, see Query::run_base.
+    pub fn checks(&self) -> Cow<'_, [Packed]> {
+        match &*self.base_checks {
+            QueryableOwned::Checks { checks, .. } => Cow::Borrowed(checks),
+            QueryableOwned::IDs { ids } => Cow::Owned(to_checks(ids)),
+        }
+    }
+
+    /// The number of ids in the db that aren't hidden.
+    pub fn len(&self) -> usize {
+        self.base_checks.matched()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn index<T: 'static + Index<P>>(&self) -> Option<&T> {
+        self.indexes.get()
+    }
+
+    pub fn index_mut<T: 'static + Index<P>>(&mut self) -> Option<&mut T> {
+        self.indexes.get_mut(self.posts.as_deref())
+    }
+
+    /// Every index in the order its loader was registered, which is the order insert,
+    /// remove and update call them in.
+    pub fn indexes(&self) -> impl Iterator<Item = &dyn Index<P>> {
+        self.indexes.values().map(|index| index.as_ref())
+    }
+
+    /// The index `ident:value` terms are routed to.
+    pub fn index_by_ident(&self, ident: &str) -> Option<&dyn Index<P>> {
+        self.routed_index(&Some(self.normalize(ident)))
+    }
+
+    /// See DbLoader::with_identifier_options.
+    pub fn identifier_options(&self) -> IdentifierOptions {
+        self.identifier_options
+    }
+
+    fn normalize(&self, ident: &str) -> String {
+        self.identifier_options.normalize(ident).into_owned()
+    }
+
+    /// The index terms without a known identifier are routed to.
+    pub fn default_index(&self) -> Option<&dyn Index<P>> {
+        self.routed_index(&None)
+    }
+
+    fn routed_index(&self, ident: &Option<String>) -> Option<&dyn Index<P>> {
+        let type_id = self.identifiers.get(ident)?;
+        self.indexes.by_type(type_id).map(|index| index.as_ref())
+    }
+
+    /// Every registered identifier sorted, without the default index.
+    pub fn identifiers(&self) -> Vec<&str> {
+        let mut identifiers: Vec<&str> = self
+            .identifiers
+            .keys()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        identifiers.sort_unstable();
+        identifiers
+    }
+
+    /// The identifiers index T was registered with, None for the default index.
+    pub fn identifiers_of<T: 'static + Index<P>>(&self) -> Vec<Option<&str>> {
+        let type_id = TypeId::of::<T>();
+        let mut identifiers: Vec<Option<&str>> = self
+            .identifiers
+            .iter()
+            .filter(|(_, index_type_id)| **index_type_id == type_id)
+            .map(|(ident, _)| ident.as_deref())
+            .collect();
+        identifiers.sort_unstable();
+        identifiers
+    }
+
+    /// The lowest id not in use, hidden ids are still in use.
+    pub fn next_id(&self) -> ID {
+        let checks = self.checks();
+        let mut id = packed::first_id(checks.len());
+        'outer: for (index, &c) in checks.iter().enumerate() {
+            if let Some(free) = packed::ids(index, !c).find(|&free| !self.hidden.contains(free)) {
+                id = free;
+                break 'outer;
+            }
+        }
+        id
+    }
+
+    /// Removes id from every result without touching indexes, undone by unhide.
+    /// Returns false if id isn't in the db or is already hidden.
+    pub fn hide(&mut self, id: ID) -> bool {
+        if !self.base_checks.contains(id) {
+            return false;
+        }
+        Arc::make_mut(&mut self.base_checks).remove(id);
+        Arc::make_mut(&mut self.hidden).insert(id);
+        self.touch(id);
+        true
+    }
+
+    /// Returns false if id isn't hidden.
+    pub fn unhide(&mut self, id: ID) -> bool {
+        if !self.hidden.contains(id) {
+            return false;
+        }
+        Arc::make_mut(&mut self.hidden).remove(id);
+        Arc::make_mut(&mut self.base_checks).insert(id);
+        self.touch(id);
+        true
+    }
+
+    pub fn is_hidden(&self, id: ID) -> bool {
+        self.hidden.contains(id)
+    }
+
+    pub fn hidden(&self) -> &QueryableOwned {
+        &self.hidden
+    }
+
+    /// A frozen copy for readers that must not see later writes, such as paginating one query.
+    /// Indexes are shared until either db writes to one, which clones that index.
+    /// Indexes without Index::clone_index are rebuilt from the store instead, writing to
+    /// one panics if there's no store or its loader doesn't support IndexLoader::fork.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            identifiers: self.identifiers.clone(),
+            indexes: self.indexes.clone(),
+            base_checks: self.base_checks.clone(),
+            saved: self.saved.clone(),
+            rewrites: self.rewrites.clone(),
+            orders: self.orders.clone(),
+            negative_policy: self.negative_policy,
+            identifier_options: self.identifier_options,
+            slow_query_log: self.slow_query_log.clone(),
+            warm: self.warm.clone(),
+            pairs: self.pairs.clone(),
+            seq: self.seq,
+            changed: self.changed.clone(),
+            changes_from: self.changes_from,
+            inserted: self.inserted.clone(),
+            hidden: self.hidden.clone(),
+            posts: self.posts.clone(),
+            tenants: self.tenants.clone(),
+        }
+    }
+
+    /// Cross checks the Db's data for debugging corrupt state, such as after a crash
+    /// while applying a delta. With a store every stored post is checked against
+    /// every index, which costs a pass over all posts.
+    pub fn verify(&self) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        for (source, ids) in [("base", &self.base_checks), ("hidden", &self.hidden)] {
+            if let Some(problem) = ids.verify() {
+                report.push(source, None, problem);
+            }
+        }
+        let hidden = match &*self.hidden {
+            QueryableOwned::Checks { checks, .. } => to_ids(checks),
+            QueryableOwned::IDs { ids } => ids.clone(),
+        };
+        for &id in hidden.iter().filter(|&&id| self.base_checks.contains(id)) {
+            report.push("hidden", Some(id), "hidden id is also in base");
+        }
+        for (type_id, index) in self.indexes.iter() {
+            let source = self.index_name(*type_id);
+            for problem in index.verify() {
+                report.push(&source, None, problem);
+            }
+        }
+        let Some(store) = self.store() else {
+            return report;
+        };
+        for id in to_ids(&self.checks()).into_iter().chain(hidden) {
+            if !store.contains(id) {
+                report.push("store", Some(id), "id in use has no stored post");
+            }
+        }
+        for (id, post) in store.iter() {
+            report.posts_checked += 1;
+            if !self.contains(id) {
+                report.push("store", Some(id), "stored post's id isn't in use");
+            }
+            for (type_id, index) in self.indexes.iter() {
+                if !index.verify_post(id, post) {
+                    let source = self.index_name(*type_id);
+                    report.push(&source, Some(id), "post isn't indexed");
+                }
+            }
+            if let Some(tenants) = self.tenants() {
+                let tenant = tenants.tenant_of(post);
+                let found = tenant
+                    .as_deref()
+                    .and_then(|tenant| tenants.get(tenant))
+                    .is_some_and(|ids| ids.contains(id));
+                if tenant.is_some() && !found {
+                    report.push("tenants", Some(id), "post missing from its tenant");
+                }
+            }
+        }
+        report
+    }
+
+    // The identifiers of an index joined by commas, for messages.
+    fn index_name(&self, type_id: TypeId) -> String {
+        let mut names: Vec<&str> = self
+            .identifiers
+            .iter()
+            .filter(|(_, index_type_id)| **index_type_id == type_id)
+            .map(|(ident, _)| ident.as_deref().unwrap_or("default"))
+            .collect();
+        names.sort_unstable();
+        names.join(",")
+    }
+
+    /// Runs f against a snapshot and keeps its writes only if it returns Ok,
+    /// so a batch is applied whole or not at all and readers never see part of it.
+    /// For a Db behind a RwLock, take the snapshot under a read lock, run the
+    /// transaction on it and swap it in.
+    ///
+    /// The first write to each index, the base ids, the store or tenants clones all
+    /// of it, so even a single insert costs a copy of every index it touches.
+    /// Batch writes into one transaction rather than wrapping each write, and write
+    /// to the Db directly when partial batches don't matter.
+    pub fn transaction<T, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let mut tx = self.snapshot();
+        let result = f(&mut tx);
+        if result.is_ok() {
+            *self = tx;
+        }
+        result
+    }
+
+    pub fn query(&self, query: &Query<String>) -> Result<QueryResult, Vec<String>> {
+        let checks = self.logged(query, || {
+            self.map_query(query)
+                .map(|mapped| mapped.run_base(&self.base_checks))
+        })?;
+        Ok(QueryResult::new(checks).with_seq(self.seq))
+    }
+
+    /// Like query for a query parsed into arena, with its terms looked up into one
+    /// arena instead of a Vec per chain. Rewrites, the pair cache, the slow query log
+    /// and order terms work on a Query, with any of them the query is run through query.
+    pub fn query_arena(
+        &self,
+        arena: &QueryArena<String>,
+        root: NodeId,
+    ) -> Result<QueryResult, Vec<String>> {
+        let order_terms = || {
+            (0..arena.len() as NodeId).any(|id| {
+                matches!(&arena.node(id).item, ArenaItem::Single(term) if self.is_order_term(term))
+            })
+        };
+        if !self.rewrites.is_empty()
+            || self.pairs.capacity() != 0
+            || self.slow_query_log.is_some()
+            || (!self.orders.is_empty() && order_terms())
+        {
+            return self.query(&arena.to_query(root));
+        }
+        let mut mapped = QueryArena::new();
+        let root = arena
+            .try_map_into(root, &mut mapped, |text, inverse| {
+                self.query_term(text, inverse, 0, true)
+            })
+            .map_err(|e| e.into_iter().map(|s| s.to_string()).collect::<Vec<_>>())?;
+        stats::record(|stats| {
+            stats.terms += (0..mapped.len() as NodeId)
+                .filter(|&id| matches!(mapped.node(id).item, ArenaItem::Single(_)))
+                .count()
+        });
+        let checks = mapped.run_base(root, &self.base_checks);
+        Ok(QueryResult::new(checks).with_seq(self.seq))
+    }
+
+    /// Like query with what running it cost, such as to log slow queries.
+    pub fn query_with_stats(
+        &self,
+        query: &Query<String>,
+    ) -> Result<(QueryResult, EvalStats), Vec<String>> {
+        let (checks, stats) = self.stats_of(query, || {
+            self.map_query(query)
+                .map(|mapped| mapped.run_base(&self.base_checks))
+        });
+        Ok((QueryResult::new(checks?).with_seq(self.seq), stats))
+    }
+
+    /// Calls log with the canonical form of every query taking at least threshold
+    /// and what it cost, see Query::to_canonical. Only queries run by query and the
+    /// query_* methods returning a QueryResult are timed.
+    pub fn set_slow_query_log(
+        &mut self,
+        threshold: Duration,
+        log: impl Fn(&str, &EvalStats) + Send + Sync + 'static,
+    ) {
+        self.slow_query_log = Some((threshold, Arc::new(log)));
+    }
+
+    pub fn clear_slow_query_log(&mut self) {
+        self.slow_query_log = None;
+    }
+
+    // Runs run, which looks query up and runs it, collecting what it cost.
+    fn stats_of<T>(&self, query: &Query<String>, run: impl FnOnce() -> T) -> (T, EvalStats) {
+        let start = Instant::now();
+        let (value, mut stats) = stats::collect(run);
+        stats.duration = start.elapsed();
+        if let Some((threshold, log)) = &self.slow_query_log {
+            if stats.duration >= *threshold {
+                log(&query.to_canonical(), &stats);
+            }
+        }
+        (value, stats)
+    }
+
+    // Like stats_of without collecting anything when there's no slow query log.
+    fn logged<T>(&self, query: &Query<String>, run: impl FnOnce() -> T) -> T {
+        if self.slow_query_log.is_none() {
+            return run();
+        }
+        self.stats_of(query, run).0
+    }
+
+    /// Like query but also counts the posts each term matches on its own, in the order
+    /// terms appear after rewrites, such as the post count shown next to each tag of
+    /// a search. A term repeated in query is counted once. Terms an index looks up to
+    /// a single set of ids are counted from it without running them.
+    pub fn query_with_counts(
+        &self,
+        query: &Query<String>,
+    ) -> Result<(QueryResult, Vec<TermCount>), Vec<String>> {
+        let result = self.query(query)?;
+        let query = self.rewrite(query);
+        let base = OnceCell::new();
+        let base = || base.get_or_init(|| self.checks());
+        let mut counts: Vec<TermCount> = Vec::new();
+        for (term, inverse) in query.tags() {
+            if counts
+                .iter()
+                .any(|c| &c.term == term && c.inverse == inverse)
+            {
+                continue;
+            }
+            let looked_up = self
+                .query_term(term, false, 0, true)
+                .ok_or_else(|| vec![term.clone()])?;
+            let matched = match &looked_up.item {
+                Item::Single(queryable)
+                    if !looked_up.inverse
+                        && !matches!(queryable, Queryable::Lazy(_) | Queryable::Not(_)) =>
+                {
+                    // Indexes keep the ids of hidden posts.
+                    if self.hidden.matched() == 0 {
+                        queryable.estimate(self.len())
+                    } else {
+                        queryable.matched_in(base())
+                    }
+                }
+                _ => packed::count(&looked_up.run(base())),
+            };
+            counts.push(TermCount {
+                term: term.clone(),
+                inverse,
+                matched,
+            });
+        }
+        Ok((result, counts))
+    }
+
+    /// Like query but only matches ids in scope, a result of a query such as
+    /// `-deleted approved` computed once and reused for every user query.
+    pub fn query_scoped(
+        &self,
+        query: &Query<String>,
+        scope: &QueryResult,
+    ) -> Result<QueryResult, Vec<String>> {
+        // scope may be stale, ids removed since it was computed are dropped here.
+        let base: Vec<Packed> = self
+            .checks()
+            .iter()
+            .zip(scope.checks())
+            .map(|(base, scope)| base & scope)
+            .collect();
+        self.query_with_base(query, &base)
+    }
+
+    /// Like query but only matches posts of tenant, see DbLoader::with_tenants.
+    /// Unknown tenants and dbs without tenants match nothing.
+    pub fn query_tenant(
+        &self,
+        tenant: &str,
+        query: &Query<String>,
+    ) -> Result<QueryResult, Vec<String>> {
+        let base = match &self.tenants {
+            Some(tenants) => tenants.scope(tenant, &self.checks()),
+            None => Vec::new(),
+        };
+        self.query_with_base(query, &base)
+    }
+
+    pub fn tenants(&self) -> Option<&Tenants<P>> {
+        self.tenants.as_deref()
+    }
+
+    /// Like query but applies the NegativePolicy, see Query::is_negative_only.
+    pub fn query_checked(&self, query: &Query<String>) -> Result<QueryResult, QueryError> {
+        let query = self.rewrite(query);
+        if query.is_negative_only() {
+            let allowed = match self.negative_policy {
+                NegativePolicy::Allow => true,
+                NegativePolicy::Reject => false,
+                NegativePolicy::MaxTerms(max) => query.tags().len() <= max,
+            };
+            if !allowed {
+                return Err(QueryError::NegativeOnly);
+            }
+        }
+        let checks = self.logged(&query, || {
+            let mapped = self.lookup(&query).map_err(QueryError::MissingTags)?;
+            Ok(self
+                .run_negative(&mapped)
+                .unwrap_or_else(|| mapped.run_base(&self.base_checks)))
+        })?;
+        Ok(QueryResult::new(checks).with_seq(self.seq))
+    }
+
+    // Removes each term's ids from a copy of base_checks for `-a -b` queries,
+    // skipping the runner's full passes. None for any other query.
+    fn run_negative(&self, query: &Query<Queryable>) -> Option<Vec<Packed>> {
+        // The ids a term removes, `-tag` is an inverted term or a Not.
+        fn removed<'q, 'i>(query: &'q Query<Queryable<'i>>) -> Option<&'q Queryable<'i>> {
+            match &query.item {
+                Item::Single(Queryable::Not(inner)) if !query.inverse => Some(inner),
+                Item::Single(tag) if query.inverse => Some(tag),
+                _ => None,
+            }
+        }
+        let terms: Vec<&Queryable> = match &query.item {
+            Item::AndChain(items) if !query.inverse => {
+                items.iter().map(removed).collect::<Option<_>>()?
+            }
+            _ => vec![removed(query)?],
+        };
+        let mut checks = self.checks().into_owned();
+        for tag in terms {
+            stats::touch(checks.len());
+            tag.and(&mut checks, true);
+        }
+        Some(checks)
+    }
+
+    pub fn negative_policy(&self) -> NegativePolicy {
+        self.negative_policy
+    }
+
+    pub fn set_negative_policy(&mut self, policy: NegativePolicy) {
+        self.negative_policy = policy;
+    }
+
+    fn query_with_base(
+        &self,
+        query: &Query<String>,
+        base: &[Packed],
+    ) -> Result<QueryResult, Vec<String>> {
+        let checks = self.logged(query, || {
+            self.map_query(query).map(|mapped| mapped.run(base))
+        })?;
+        Ok(QueryResult::new(checks).with_seq(self.seq))
+    }
+
+    /// Like query but follows hints, see QueryHints::split for parsing them.
+    pub fn query_hinted(
+        &self,
+        query: &Query<String>,
+        hints: &QueryHints,
+    ) -> Result<QueryResult, Vec<String>> {
+        if hints.is_empty() {
+            return self.query(query);
+        }
+        let mut query = self.rewrite(query).into_owned();
+        hints.apply_order(&mut query);
+        let checks = self.logged(&query, || {
+            self.lookup_with(&query, !hints.nocache).map(|mapped| {
+                if hints.order.is_some() {
+                    mapped.run_in_order(&self.checks())
+                } else {
+                    mapped.run_base(&self.base_checks)
+                }
+            })
+        })?;
+        Ok(QueryResult::new(checks).with_seq(self.seq))
+    }
+
+    /// Like query but without posts inserted after seq, the QueryResult::seq of the
+    /// first page, so uploads between page loads don't shift later pages.
+    /// Posts removed since still drop out. Pins before the seq passed to
+    /// forget_inserted include the posts inserted up to it.
+    pub fn query_pinned(
+        &self,
+        query: &Query<String>,
+        seq: u64,
+    ) -> Result<QueryResult, Vec<String>> {
+        let mut base = self.checks().into_owned();
+        let after = self.inserted.partition_point(|&(at, _)| at <= seq);
+        for &(_, id) in &self.inserted[after..] {
+            if let Some(check) = base.get_mut(packed::word(id)) {
+                *check &= !packed::bit(id);
+            }
+        }
+        Ok(self.query_with_base(query, &base)?.with_seq(seq))
+    }
+
+    /// Drops the inserts up to seq kept for query_pinned, such as the seq of the
+    /// oldest page still being browsed.
+    pub fn forget_inserted(&mut self, seq: u64) {
+        let after = self.inserted.partition_point(|&(at, _)| at <= seq);
+        if after > 0 {
+            Arc::make_mut(&mut self.inserted).drain(..after);
+        }
+    }
+
+    /// Estimates how many posts match query by checking the ids in random checks
+    /// of the db, enough for the share of matches to be within error_bound such as
+    /// 0.01 for ±1%. Exact when that would check most of the db.
+    /// Matches clustered by id make the real error larger than the margin.
+    #[cfg(feature = "rand")]
+    pub fn approx_count(
+        &self,
+        query: &Query<String>,
+        error_bound: f64,
+    ) -> Result<ApproxCount, Vec<String>> {
+        let total = self.len();
+        let error_bound = error_bound.clamp(1e-6, 1.0);
+        let samples = (1.96 * 1.96 * 0.25 / (error_bound * error_bound)).ceil() as usize;
+        if samples.saturating_mul(2) >= total {
+            let matched = self.query(query)?.matched();
+            return Ok(ApproxCount {
+                estimate: matched,
+                margin: 0,
+                exact: true,
+            });
+        }
+        let query = self.map_query(query)?;
+        let mut rng = thread_rng();
+        // Ids of random checks, or random ids when they're sparse.
+        let mut sample = match &*self.base_checks {
+            QueryableOwned::Checks { checks, .. } => {
+                let mut sample = Vec::with_capacity(samples);
+                let mut visited = HashSet::new();
+                while sample.len() < samples && visited.len() < checks.len() {
+                    let index = rng.gen_range(0..checks.len());
+                    if visited.insert(index) {
+                        sample.extend(packed::ids(index, checks[index]));
+                    }
+                }
+                sample
+            }
+            QueryableOwned::IDs { ids } => rand::seq::index::sample(&mut rng, ids.len(), samples)
+                .into_iter()
+                .map(|index| ids[index])
+                .collect(),
+        };
+        sample.sort_unstable();
+        let sampled = sample.len();
+        // Run rather than matched id by id, as IDSlices are scanned by contains.
+        let hits = packed::count(&query.run_base(&QueryableOwned::from(sample)));
+        let share = hits as f64 / sampled.max(1) as f64;
+        let margin = 1.96 * (share * (1.0 - share) / sampled.max(1) as f64).sqrt();
+        Ok(ApproxCount {
+            estimate: (share * total as f64).round() as usize,
+            margin: (margin * total as f64).ceil() as usize,
+            exact: false,
+        })
+    }
+
+    /// Whether id matches query, without running it against every id.
+    /// Hidden and removed ids match nothing.
+    pub fn matches(&self, query: &Query<String>, id: ID) -> Result<bool, Vec<String>> {
+        let query = self.map_query(query)?;
+        Ok(self.base_checks.contains(id) && query.matches(id))
+    }
+
+    // Rewrites query and looks up its terms.
+    fn map_query(&self, query: &Query<String>) -> Result<Query<Queryable<'_>>, Vec<String>> {
+        self.lookup(&self.rewrite(query))
+    }
+
+    fn rewrite<'q>(&self, query: &'q Query<String>) -> Cow<'q, Query<String>> {
+        if self.rewrites.is_empty() {
+            Cow::Borrowed(query)
+        } else {
+            Cow::Owned(self.rewrites.apply(query.clone()))
+        }
+    }
+
+    fn lookup(&self, query: &Query<String>) -> Result<Query<Queryable<'_>>, Vec<String>> {
+        self.lookup_with(query, true)
+    }
+
+    // Without cached, warmed terms and pairs are skipped and the query isn't recorded.
+    fn lookup_with(
+        &self,
+        query: &Query<String>,
+        cached: bool,
+    ) -> Result<Query<Queryable<'_>>, Vec<String>> {
+        let query = self.without_order_terms(query);
+        if cached {
+            self.pairs.record(&query);
+        }
+        let combined;
+        let query = if !cached || self.pairs.is_empty() {
+            &*query
+        } else {
+            combined = self.pairs.combine(&query);
+            &combined
+        };
+        let mapped = query
+            .try_map(|text, inverse| self.query_term(text, inverse, 0, cached))
+            .map_err(|e| e.into_iter().map(|s| s.to_string()).collect::<Vec<_>>())?;
+        stats::record(|stats| stats.terms += mapped.tags().len());
+        Ok(mapped)
+    }
+
+    /// Every term that can't be routed to an index or is rejected by it,
+    /// checked without running the query.
+    pub fn validate<T: AsRef<str> + Clone>(&self, query: &Query<T>) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        if self.rewrites.is_empty() {
+            for (term, _) in query.tags() {
+                self.validate_term(term.as_ref(), 0, &mut errors);
+            }
+        } else {
+            let query = query
+                .try_map(|term, inverse| {
+                    let term = term.as_ref().to_string();
+                    Some(Query::new(Item::Single(term), inverse))
+                })
+                .unwrap_or_else(|_| unreachable!());
+            let query = self.rewrites.apply(query);
+            for (term, _) in query.tags() {
+                self.validate_term(term, 0, &mut errors);
+            }
+        }
+        errors.sort_unstable();
+        errors.dedup();
+        errors
+    }
+
+    fn validate_term(&self, text: &str, depth: usize, errors: &mut Vec<ValidationError>) {
+        let (ident, value) = self.route(text);
+        if ident.is_none() && self.is_order_term(text) {
+            return;
+        }
+        if ident.is_none() {
+            if let Some(name) = self.saved.name(text) {
+                if depth >= SavedQueries::MAX_DEPTH {
+                    errors.push(ValidationError::TooDeep(text.to_string()));
+                    return;
+                }
+                match self.saved.get(name) {
+                    Some(saved) => {
+                        for (term, _) in saved.tags() {
+                            self.validate_term(term, depth + 1, errors);
+                        }
+                    }
+                    None => errors.push(ValidationError::UnknownSavedQuery(text.to_string())),
+                }
+                return;
+            }
+        }
+        match self.identifiers.get(&ident) {
+            Some(type_id) => {
+                let index = self.indexes.by_type(type_id).unwrap();
+                if !index.validate(ident.as_deref(), value) {
+                    errors.push(ValidationError::Invalid(text.to_string()));
+                }
+            }
+            None => errors.push(ValidationError::NoIndex(text.to_string())),
+        }
+    }
+
+    fn is_order_term(&self, text: &str) -> bool {
+        text.strip_prefix("order:")
+            .is_some_and(|order| self.order_ids(order).is_some())
+    }
+
+    // Drops order terms from chains, as matching every post they would widen an
+    // OrChain to the whole db. A query of only order terms matches every post.
+    fn without_order_terms<'q>(&self, query: &'q Query<String>) -> Cow<'q, Query<String>> {
+        let is_order = |query: &Query<String>| matches!(&query.item, Item::Single(term) if self.is_order_term(term));
+        if self.orders.is_empty()
+            || !query
+                .tags()
+                .iter()
+                .any(|(term, _)| self.is_order_term(term))
+        {
+            return Cow::Borrowed(query);
+        }
+        let query = query.clone().transform(&mut |mut query| {
+            if let Item::AndChain(items) | Item::OrChain(items) = &mut query.item {
+                let len = items.len();
+                items.retain(|item| !is_order(item));
+                if len > 0 && items.is_empty() {
+                    return Query::new(Item::All, false);
+                }
+            }
+            query
+        });
+        if is_order(&query) {
+            return Cow::Owned(Query::new(Item::All, false));
+        }
+        Cow::Owned(query)
+    }
+
+    // The identifier and value of text, or no identifier and the whole text
+    // when the prefix isn't a known identifier.
+    fn route<'t>(&self, text: &'t str) -> (Option<String>, &'t str) {
+        text.split_once(':')
+            .map(|(ident, value)| {
+                let ident = Some(self.normalize(ident));
+                if self.identifiers.contains_key(&ident) {
+                    (ident, value)
+                } else {
+                    (None, text)
+                }
+            })
+            .unwrap_or((None, text))
+    }
+
+    fn query_term(
+        &self,
+        text: &str,
+        inverse: bool,
+        depth: usize,
+        cached: bool,
+    ) -> Option<Query<Queryable<'_>>> {
+        if cached {
+            if let Some(pair) = self.pairs.get(text) {
+                return Some(Query::new(Item::Single(pair), inverse));
+            }
+            if let Some(warm) = self.warm.get(text, inverse) {
+                return Some(Query::new(Item::Single(warm), false));
+            }
+        }
+        let (ident, _) = self.route(text);
+        if ident.is_none() && self.is_order_term(text) {
+            return Some(Query::new(Item::All, false));
+        }
+        // Index identifiers take precedence over saved queries.
+        if ident.is_none() {
+            if let Some(name) = self.saved.name(text) {
+                if depth >= SavedQueries::MAX_DEPTH {
+                    return None;
+                }
+                let saved = self.without_order_terms(self.saved.get(name)?);
+                let query = saved
+                    .try_map(|text, inverse| self.query_term(text, inverse, depth + 1, cached))
+                    .ok()?;
+                return Some(if inverse { query.negated() } else { query });
+            }
+        }
+        self.index_term(text, inverse)
+    }
+
+    // Looks text up in the index it's routed to, skipping saved and warmed terms.
+    fn index_term(&self, text: &str, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let (ident, value) = self.route(text);
+        let type_id = self.identifiers.get(&ident);
+        let index = self.indexes.by_type(type_id?).unwrap();
+        let context = IndexContext {
+            db: self,
+            type_id: *type_id?,
+        };
+        let term = Term::parse(ident.as_deref(), value);
+        index.query_term(&context, &term, inverse)
+    }
+
+    /// Precomputes the ids of each term and their complement so `term` and `-term`
+    /// copy a mask instead of being rebuilt every query. Kept up to date by insert,
+    /// remove and update. Returns the terms no index could look up.
+    pub fn warm<T: Into<String>>(&mut self, terms: impl IntoIterator<Item = T>) -> Vec<String> {
+        let all = vec![Packed::MAX; self.base_checks.checks_len()];
+        let mut missing = Vec::new();
+        for term in terms {
+            let term = term.into();
+            let Some(checks) = self.index_term(&term, false).map(|query| query.run(&all)) else {
+                missing.push(term);
+                continue;
+            };
+            Arc::make_mut(&mut self.warm).insert(term, checks);
+        }
+        missing
+    }
+
+    /// Warms the n terms matching the most posts, from indexes supporting Index::top_terms.
+    /// Returns the terms warmed.
+    pub fn warm_top(&mut self, n: usize) -> Vec<String> {
+        let mut top: Vec<(String, usize)> = Vec::new();
+        for (type_id, index) in self.indexes.iter() {
+            // Terms of the default index need no identifier.
+            let ident = self
+                .identifiers
+                .iter()
+                .filter(|(_, index_type_id)| *index_type_id == type_id)
+                .map(|(ident, _)| ident.as_deref())
+                .min();
+            let Some(ident) = ident else {
+                continue;
+            };
+            top.extend(
+                index
+                    .top_terms(n)
+                    .into_iter()
+                    .map(|(term, count)| match ident {
+                        Some(ident) => (format!("{ident}:{term}"), count),
+                        None => (term, count),
+                    }),
+            );
+        }
+        top.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        top.truncate(n);
+        let terms: Vec<String> = top.into_iter().map(|(term, _)| term).collect();
+        self.warm(terms.iter().cloned());
+        terms
+    }
+
+    pub fn warmed(&self) -> &WarmTerms {
+        &self.warm
+    }
+
+    pub fn warmed_mut(&mut self) -> &mut WarmTerms {
+        Arc::make_mut(&mut self.warm)
+    }
+
+    /// Caches the intersection of a and b, used for queries requiring both.
+    /// Returns false if either can't be looked up.
+    pub fn cache_pair(&mut self, a: &str, b: &str) -> bool {
+        let all = vec![Packed::MAX; self.base_checks.checks_len()];
+        let checks = match (self.index_term(a, false), self.index_term(b, false)) {
+            (Some(a), Some(b)) => Query::new(Item::AndChain(vec![a, b]), false).run(&all),
+            _ => return false,
+        };
+        let mut intersection = QueryableOwned::from(checks);
+        intersection.check_and_convert();
+        Arc::make_mut(&mut self.pairs).insert(PairCache::key(a, b), intersection);
+        true
+    }
+
+    /// Caches the pairs of tags queried together most since the last call, up to
+    /// PairCache::capacity, and drops cached pairs that weren't queried since.
+    /// Returns the keys of the pairs now cached.
+    pub fn adapt_pairs(&mut self) -> Vec<String> {
+        let pairs = Arc::make_mut(&mut self.pairs);
+        let capacity = pairs.capacity();
+        let hot: Vec<String> = pairs
+            .take_counts()
+            .into_iter()
+            .take(capacity)
+            .map(|(key, _)| key)
+            .collect();
+        let cold: Vec<String> = pairs
+            .keys()
+            .filter(|key| !hot.iter().any(|hot| hot == key))
+            .map(str::to_string)
+            .collect();
+        for key in cold {
+            pairs.remove(&key);
+        }
+        let mut cached = Vec::new();
+        for key in hot {
+            let cache = match PairCache::split(&key) {
+                Some(_) if self.pairs.contains(&key) => true,
+                Some((a, b)) => self.cache_pair(a, b),
+                None => false,
+            };
+            if cache {
+                cached.push(key);
+            }
+        }
+        cached
+    }
+
+    pub fn pair_cache(&self) -> &PairCache {
+        &self.pairs
+    }
+
+    pub fn pair_cache_mut(&mut self) -> &mut PairCache {
+        Arc::make_mut(&mut self.pairs)
+    }
+
+    // Updates warmed terms and cached pairs after id's post changed.
+    fn update_caches(&mut self, id: ID) {
+        if self.warm.is_empty() && self.pairs.is_empty() {
+            return;
+        }
+        let term_matches = |term: &str| {
+            self.index_term(term, false)
+                .is_some_and(|query| query.matches(id))
+        };
+        let warm: Vec<(String, bool)> = self
+            .warm
+            .terms()
+            .map(|term| (term.to_string(), term_matches(term)))
+            .collect();
+        let pairs: Vec<(String, bool)> = self
+            .pairs
+            .keys()
+            .map(|key| {
+                let matches =
+                    PairCache::split(key).is_some_and(|(a, b)| term_matches(a) && term_matches(b));
+                (key.to_string(), matches)
+            })
+            .collect();
+        if !warm.is_empty() {
+            let cache = Arc::make_mut(&mut self.warm);
+            for (term, matches) in warm {
+                cache.set(&term, id, matches);
+            }
+        }
+        if !pairs.is_empty() {
+            let cache = Arc::make_mut(&mut self.pairs);
+            for (key, matches) in pairs {
+                cache.set(&key, id, matches);
+            }
+        }
+    }
+
+    /// Posts similar to id from the first index that supports Index::similar.
+    /// Indexes made with field_index! read the keys of posts from the store, see with_store.
+    pub fn similar(&self, id: ID, limit: usize) -> Vec<(ID, f32)> {
+        self.indexes
+            .iter()
+            .find_map(|(type_id, index)| {
+                let context = IndexContext {
+                    db: self,
+                    type_id: *type_id,
+                };
+                index.similar(&context, id, limit)
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn saved_queries(&self) -> &SavedQueries {
+        &self.saved
+    }
+
+    pub fn saved_queries_mut(&mut self) -> &mut SavedQueries {
+        &mut self.saved
+    }
+
+    /// Passes run on every query given to Db::query before terms are looked up.
+    pub fn rewrites(&self) -> &Rewrites {
+        &self.rewrites
+    }
+
+    pub fn rewrites_mut(&mut self) -> &mut Rewrites {
+        &mut self.rewrites
+    }
+
+    /// Registers `order:name` and `order:name_desc`, which page results in the
+    /// Index::order of the index with identifier, such as `add_order("id", "id")` for
+    /// post id order from an IdMap when internal ids were assigned in another order.
+    /// Order terms are dropped from queries, on their own they match every post,
+    /// see order_of and get_ordered.
+    /// False if no index with identifier has an order.
+    pub fn add_order(&mut self, name: impl Into<String>, identifier: &str) -> bool {
+        let ident = Some(self.normalize(identifier));
+        let has_order = self
+            .identifiers
+            .get(&ident)
+            .is_some_and(|type_id| self.indexes.by_type(type_id).unwrap().order().is_some());
+        if has_order {
+            self.orders.insert(name.into(), self.normalize(identifier));
+        }
+        has_order
+    }
+
+    /// The value of the last known `order:` term in query, such as `id_desc`.
+    pub fn order_of<'q>(&self, query: &'q Query<String>) -> Option<&'q str> {
+        query
+            .tags()
+            .into_iter()
+            .rev()
+            .filter_map(|(term, _)| term.strip_prefix("order:"))
+            .find(|order| self.order_ids(order).is_some())
+    }
+
+    /// Like QueryResult::get_sorted in the order registered by add_order,
+    /// order is `name` or `name_desc`. None if the order isn't known.
+    pub fn get_ordered(
+        &self,
+        result: &QueryResult,
+        order: &str,
+        index: usize,
+        limit: usize,
+    ) -> Option<Vec<ID>> {
+        let (ids, reverse) = self.order_ids(order)?;
+        Some(result.get_sorted(ids.iter().copied(), index, limit, reverse))
+    }
+
+    fn order_ids(&self, order: &str) -> Option<(&[ID], bool)> {
+        let (name, reverse) = match order.strip_suffix("_desc") {
+            Some(name) => (name, true),
+            None => (order.strip_suffix("_asc").unwrap_or(order), false),
+        };
+        let ident = Some(self.orders.get(name)?.clone());
+        let index = self.indexes.by_type(self.identifiers.get(&ident)?)?;
+        Some((index.order()?, reverse))
+    }
+
+    /// Whether id is in use, hidden ids included.
+    pub fn contains(&self, id: ID) -> bool {
+        self.base_checks.contains(id) || self.hidden.contains(id)
+    }
+
+    /// Returns false without touching indexes if id is already in use,
+    /// indexes would hold both posts. See upsert.
+    pub fn insert(&mut self, id: ID, post: &P) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        Arc::make_mut(&mut self.base_checks).insert(id);
+        self.indexes.write(
+            &self.identifiers,
+            self.identifier_options,
+            self.posts.as_deref(),
+            |_, index, context| index.insert_in(context, id, post),
+        );
+        if let Some(store) = self.store_mut() {
+            store.insert_ref(id, post);
+        }
+        if let Some(tenants) = &mut self.tenants {
+            Arc::make_mut(tenants).insert(id, post);
+        }
+        self.update_caches(id);
+        self.touch(id);
+        Arc::make_mut(&mut self.inserted).push((self.seq, id));
+        true
+    }
+
+    /// Inserts post, or updates id from old if it's in use.
+    /// Returns false if id is in use and old is None with no post stored for id.
+    pub fn upsert(&mut self, id: ID, post: &P, old: Option<&P>) -> bool {
+        if !self.contains(id) {
+            return self.insert(id, post);
+        }
+        if let Some(old) = old {
+            self.update(id, old, post);
+            return true;
+        }
+        match self.store().and_then(|store| store.get_shared(id)) {
+            Some(old) => {
+                self.update(id, &old, post);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: ID, post: &P) {
+        Arc::make_mut(&mut self.hidden).remove(id);
+        Arc::make_mut(&mut self.base_checks).remove(id);
+        self.indexes.write(
+            &self.identifiers,
+            self.identifier_options,
+            self.posts.as_deref(),
+            |_, index, context| index.remove_in(context, id, post),
+        );
+        if let Some(store) = self.store_mut() {
+            store.remove(id);
+        }
+        if let Some(tenants) = &mut self.tenants {
+            Arc::make_mut(tenants).remove(id, post);
+        }
+        self.update_caches(id);
+        self.touch(id);
+    }
+
+    /// Hidden posts stay hidden.
+    pub fn update(&mut self, id: ID, old: &P, new: &P) {
+        self.update_indexes(id, old, new);
+        if let Some(store) = self.store_mut() {
+            store.insert_ref(id, new);
+        }
+    }
+
+    fn update_indexes(&mut self, id: ID, old: &P, new: &P) {
+        if !self.hidden.contains(id) {
+            Arc::make_mut(&mut self.base_checks).insert(id);
+        }
+        self.indexes.write(
+            &self.identifiers,
+            self.identifier_options,
+            self.posts.as_deref(),
+            |_, index, context| index.update_in(context, id, old, new),
+        );
+        if let Some(tenants) = &mut self.tenants {
+            Arc::make_mut(tenants).update(id, old, new);
+        }
+        self.update_caches(id);
+        self.touch(id);
+    }
+
+    /// Like update but skips indexes whose fields aren't in changes.
+    pub fn update_changed(&mut self, id: ID, old: &P, new: &P, changes: ChangeSet) {
+        self.update_changed_except(id, old, new, changes, None);
+    }
+
+    // Like update_changed, skip was already updated but its dependents still are.
+    fn update_changed_except(
+        &mut self,
+        id: ID,
+        old: &P,
+        new: &P,
+        changes: ChangeSet,
+        skip: Option<TypeId>,
+    ) {
+        if !self.hidden.contains(id) {
+            Arc::make_mut(&mut self.base_checks).insert(id);
+        }
+        let mut updated = Vec::new();
+        self.indexes.write(
+            &self.identifiers,
+            self.identifier_options,
+            self.posts.as_deref(),
+            |type_id, index, context| {
+                let dependency_updated = || {
+                    index
+                        .dependencies()
+                        .iter()
+                        .any(|dependency| updated.contains(dependency))
+                };
+                if Some(*type_id) == skip {
+                    updated.push(*type_id);
+                } else if index.fields().intersects(changes) || dependency_updated() {
+                    index.update_in(context, id, old, new);
+                    updated.push(*type_id);
+                }
+            },
+        );
+        if let Some(store) = self.store_mut() {
+            store.insert_ref(id, new);
+        }
+        if let Some(tenants) = &mut self.tenants {
+            Arc::make_mut(tenants).update(id, old, new);
+        }
+        self.update_caches(id);
+        self.touch(id);
+    }
+
+    /// Posts kept by DbLoader::with_store.
+    pub fn store(&self) -> Option<&PostStore<P>> {
+        self.posts.as_deref()
+    }
+
+    fn store_mut(&mut self) -> Option<&mut PostStore<P>> {
+        self.posts.as_mut().map(Arc::make_mut)
+    }
+
+    /// The stored post of id, None without a store.
+    pub fn post(&self, id: ID) -> Option<&P> {
+        self.store()?.get(id)
+    }
+
+    /// The stored posts of ids such as a page from QueryResult::get, ids without one are skipped.
+    pub fn hydrate(&self, ids: &[ID]) -> Vec<&P> {
+        let Some(store) = self.store() else {
+            return Vec::new();
+        };
+        ids.iter().filter_map(|&id| store.get(id)).collect()
+    }
+
+    /// Like remove with the stored post, returns it.
+    /// None without doing anything if no post is stored for id.
+    pub fn remove_id(&mut self, id: ID) -> Option<Arc<P>> {
+        let old = self.store()?.get_shared(id)?;
+        self.remove(id, &old);
+        Some(old)
+    }
+
+    /// Removes every post matching query, for bulk deletes.
+    /// fetch returns the post of an id, ids it returns None for are skipped.
+    /// Returns how many were removed.
+    pub fn remove_matching(
+        &mut self,
+        query: &Query<String>,
+        mut fetch: impl FnMut(ID) -> Option<P>,
+    ) -> Result<usize, Vec<String>> {
+        let result = self.query(query)?;
+        let mut removed = 0;
+        for id in to_ids(result.checks()) {
+            if let Some(post) = fetch(id) {
+                self.remove(id, &post);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Like remove_matching with the stored posts, see DbLoader::with_store.
+    pub fn remove_matching_stored(&mut self, query: &Query<String>) -> Result<usize, Vec<String>> {
+        let result = self.query(query)?;
+        let removed = to_ids(result.checks())
+            .into_iter()
+            .filter(|&id| self.remove_id(id).is_some())
+            .count();
+        Ok(removed)
+    }
+
+    /// Changes the index I for every post matching query in one go, such as
+    /// `|tags: &mut TagIndex, matches| tags.0.add_to("x".into(), matches)`,
+    /// then calls edit on a copy of each stored post and updates the other indexes
+    /// reading I's fields, its dependents and tenants like update_changed.
+    /// Without a stored post only I changes. Returns how many posts matched,
+    /// 0 if there is no index I.
+    pub fn update_matching<I: Index<P>>(
+        &mut self,
+        query: &Query<String>,
+        change: impl FnOnce(&mut I, &[Packed]),
+        mut edit: impl FnMut(&mut P),
+    ) -> Result<usize, Vec<String>>
+    where
+        P: Clone,
+    {
+        let result = self.query(query)?;
+        let Some(index) = self.indexes.get_mut::<I>(self.posts.as_deref()) else {
+            return Ok(0);
+        };
+        let changes = index.fields();
+        change(index, result.checks());
+        let ids = to_ids(result.checks());
+        for &id in &ids {
+            let Some(old) = self.store().and_then(|store| store.get_shared(id)) else {
+                self.update_caches(id);
+                self.touch(id);
+                continue;
+            };
+            let mut new = P::clone(&old);
+            edit(&mut new);
+            let skip = Some(TypeId::of::<I>());
+            self.update_changed_except(id, &old, &new, changes, skip);
+        }
+        Ok(ids.len())
+    }
+
+    /// Like update with the stored post as old.
+    /// Returns false without doing anything if no post is stored for id.
+    pub fn update_id(&mut self, id: ID, new: P) -> bool {
+        let Some(old) = self.store().and_then(|store| store.get_shared(id)) else {
+            return false;
+        };
+        self.update_indexes(id, &old, &new);
+        self.store_mut().unwrap().insert(id, new);
+        true
+    }
+}
+
+/// The indexes written before the one being written, see Index::insert_in.
+/// Includes every index in its Index::dependencies, already holding the write.
+pub struct WriteContext<'d, P> {
+    identifiers: &'d HashMap<Option<String>, TypeId>,
+    identifier_options: IdentifierOptions,
+    written: &'d [(TypeId, Arc<dyn Index<P>>)],
+}
+
+impl<P: 'static> WriteContext<'_, P> {
+    /// None if T isn't registered or is written after this index.
+    pub fn index<T: 'static + Index<P>>(&self) -> Option<&T> {
+        let type_id = TypeId::of::<T>();
+        self.by_type(&type_id)?.as_any().downcast_ref()
+    }
+
+    /// The index `ident:value` terms are routed to, None if it's written after this one.
+    pub fn index_by_ident(&self, ident: &str) -> Option<&dyn Index<P>> {
+        let ident = self.identifier_options.normalize(ident).into_owned();
+        let type_id = self.identifiers.get(&Some(ident))?;
+        self.by_type(type_id)
+    }
+
+    fn by_type(&self, type_id: &TypeId) -> Option<&dyn Index<P>> {
+        self.written
+            .iter()
+            .find(|(t, _)| t == type_id)
+            .map(|(_, index)| index.as_ref())
+    }
+}
+
+/// The Db an index is queried from, see Index::query_in and Index::query_term.
+/// For indexes in other crates that need more than the term, such as a vector
+/// index building a mask as long as the Db's.
+pub struct IndexContext<'d, P> {
+    db: &'d Db<P>,
+    type_id: TypeId,
+}
+
+impl<P: 'static> IndexContext<'_, P> {
+    /// The identifiers the index was registered with, None for the default index.
+    pub fn identifiers(&self) -> Vec<Option<&str>> {
+        let mut identifiers: Vec<Option<&str>> = self
+            .db
+            .identifiers
+            .iter()
+            .filter(|(_, type_id)| **type_id == self.type_id)
+            .map(|(ident, _)| ident.as_deref())
+            .collect();
+        identifiers.sort_unstable();
+        identifiers
+    }
+
+    /// Ids in the db that aren't hidden, see Db::checks.
+    pub fn checks(&self) -> Cow<'_, [Packed]> {
+        self.db.checks()
+    }
+
+    /// The number of checks queries run over, masks should be this long.
+    pub fn checks_len(&self) -> usize {
+        self.db.base_checks.checks_len()
+    }
+
+    /// See Db::len.
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// The id the next post would be inserted at, see Db::next_id.
+    pub fn next_id(&self) -> ID {
+        self.db.next_id()
+    }
+
+    /// The stored post of id, None if the db has no store, see Db::post.
+    pub fn post(&self, id: ID) -> Option<&P> {
+        self.db.post(id)
+    }
+}
+
+impl<P: 'static> DbSink<P> for Db<P> {
+    fn next_id(&self) -> ID {
+        Db::next_id(self)
+    }
+
+    fn insert(&mut self, id: ID, post: &P) {
+        Db::insert(self, id, post);
+    }
+
+    fn update(&mut self, id: ID, old: &P, new: &P) {
+        Db::update(self, id, old, new)
+    }
+
+    fn remove(&mut self, id: ID, post: &P) {
+        Db::remove(self, id, post)
+    }
+}
+
+// Checks are kept unless ids would be much smaller, see QueryableOwned::check_and_convert.
+fn base_from_checks(checks: Vec<Packed>) -> QueryableOwned {
+    let mut base = QueryableOwned::from(checks);
+    base.check_and_convert();
+    base
+}
@@ -0,0 +1,522 @@
+//! Arrow IPC stream output, readable by pyarrow, polars and arrow-rs. The schema and
+//! record batch messages are flatbuffers, written by the small encoder below.
+
+use std::io::{self, Write};
+
+use super::{ColumnData, ExportBatch, ExportWriter};
+
+// Metadata version V5.
+const VERSION: i16 = 4;
+// MessageHeader union types.
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+// Type union types.
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_UTF8: u8 = 5;
+const TYPE_LIST: u8 = 12;
+// FloatingPoint precision.
+const DOUBLE: i16 = 2;
+const CONTINUATION: u32 = 0xFFFF_FFFF;
+
+// A flatbuffer value, tables hold their fields by field id.
+enum Fb<'a> {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Str(&'a str),
+    Table(Vec<Option<Fb<'a>>>),
+    Tables(Vec<Fb<'a>>),
+    // FieldNode and Buffer are both structs of two longs.
+    Structs(&'a [[i64; 2]]),
+}
+
+impl Fb<'_> {
+    fn inline_size(&self) -> usize {
+        match self {
+            Fb::Bool(_) | Fb::U8(_) => 1,
+            Fb::I16(_) => 2,
+            Fb::I64(_) => 8,
+            // I32 and the offset to anything else.
+            _ => 4,
+        }
+    }
+}
+
+fn align(buf: &mut Vec<u8>, to: usize) {
+    buf.resize(buf.len().next_multiple_of(to), 0);
+}
+
+// Offsets are unsigned so children are always written after what points to them.
+fn patch(buf: &mut [u8], at: usize, target: usize) {
+    let offset = (target - at) as u32;
+    buf[at..at + 4].copy_from_slice(&offset.to_le_bytes());
+}
+
+fn finish_fb(root: &Fb) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    let root_pos = write_fb(&mut buf, root);
+    patch(&mut buf, 0, root_pos);
+    buf
+}
+
+// Returns the position offsets to value point at.
+fn write_fb(buf: &mut Vec<u8>, value: &Fb) -> usize {
+    match value {
+        Fb::Str(s) => {
+            align(buf, 4);
+            let pos = buf.len();
+            buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            buf.extend_from_slice(s.as_bytes());
+            buf.push(0);
+            pos
+        }
+        Fb::Structs(items) => {
+            // The items after the length must be 8 aligned.
+            while !(buf.len() + 4).is_multiple_of(8) {
+                buf.push(0);
+            }
+            let pos = buf.len();
+            buf.extend_from_slice(&(items.len() as u32).to_le_bytes());
+            for item in items.iter() {
+                buf.extend_from_slice(&item[0].to_le_bytes());
+                buf.extend_from_slice(&item[1].to_le_bytes());
+            }
+            pos
+        }
+        Fb::Tables(tables) => {
+            align(buf, 4);
+            let pos = buf.len();
+            buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+            buf.resize(pos + 4 + tables.len() * 4, 0);
+            for (i, table) in tables.iter().enumerate() {
+                let table_pos = write_fb(buf, table);
+                patch(buf, pos + 4 + i * 4, table_pos);
+            }
+            pos
+        }
+        Fb::Table(fields) => write_table(buf, fields),
+        _ => unreachable!("scalars are written inline"),
+    }
+}
+
+fn write_table(buf: &mut Vec<u8>, fields: &[Option<Fb>]) -> usize {
+    // Largest fields first keeps each aligned, the table starts 8 aligned.
+    let mut order: Vec<usize> = (0..fields.len()).filter(|&i| fields[i].is_some()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(fields[i].as_ref().unwrap().inline_size()));
+    let mut offsets = vec![0u16; fields.len()];
+    let mut size = 4usize;
+    for &i in &order {
+        let field_size = fields[i].as_ref().unwrap().inline_size();
+        size = size.next_multiple_of(field_size);
+        offsets[i] = size as u16;
+        size += field_size;
+    }
+
+    align(buf, 2);
+    let vtable = buf.len();
+    buf.extend_from_slice(&(4 + 2 * fields.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(size as u16).to_le_bytes());
+    for offset in &offsets {
+        buf.extend_from_slice(&offset.to_le_bytes());
+    }
+    align(buf, 8);
+    let table = buf.len();
+    buf.extend_from_slice(&((table - vtable) as i32).to_le_bytes());
+    buf.resize(table + size, 0);
+
+    let mut children = Vec::new();
+    for &i in &order {
+        let at = table + offsets[i] as usize;
+        let field = fields[i].as_ref().unwrap();
+        match field {
+            Fb::Bool(v) => buf[at] = *v as u8,
+            Fb::U8(v) => buf[at] = *v,
+            Fb::I16(v) => buf[at..at + 2].copy_from_slice(&v.to_le_bytes()),
+            Fb::I32(v) => buf[at..at + 4].copy_from_slice(&v.to_le_bytes()),
+            Fb::I64(v) => buf[at..at + 8].copy_from_slice(&v.to_le_bytes()),
+            child => children.push((at, child)),
+        }
+    }
+    for (at, child) in children {
+        let pos = write_fb(buf, child);
+        patch(buf, at, pos);
+    }
+    table
+}
+
+// Field { name, nullable, type_type, type, dictionary, children }.
+fn field<'a>(
+    name: &'a str,
+    nullable: bool,
+    type_type: u8,
+    ty: Fb<'a>,
+    children: Vec<Fb<'a>>,
+) -> Fb<'a> {
+    Fb::Table(vec![
+        Some(Fb::Str(name)),
+        Some(Fb::Bool(nullable)),
+        Some(Fb::U8(type_type)),
+        Some(ty),
+        None,
+        Some(Fb::Tables(children)),
+    ])
+}
+
+// Int { bitWidth, is_signed }.
+fn int_type<'a>(bit_width: i32, signed: bool) -> Fb<'a> {
+    Fb::Table(vec![Some(Fb::I32(bit_width)), Some(Fb::Bool(signed))])
+}
+
+fn column_field<'a>(name: &'a str, data: &ColumnData) -> Fb<'a> {
+    match data {
+        ColumnData::UInt(_) => field(name, true, TYPE_INT, int_type(64, false), Vec::new()),
+        ColumnData::Int(_) => field(name, true, TYPE_INT, int_type(64, true), Vec::new()),
+        ColumnData::Float(_) => {
+            let ty = Fb::Table(vec![Some(Fb::I16(DOUBLE))]);
+            field(name, true, TYPE_FLOATING_POINT, ty, Vec::new())
+        }
+        ColumnData::Text(_) => field(name, true, TYPE_UTF8, Fb::Table(Vec::new()), Vec::new()),
+        ColumnData::TextList(_) => {
+            let item = field("item", false, TYPE_UTF8, Fb::Table(Vec::new()), Vec::new());
+            field(name, false, TYPE_LIST, Fb::Table(Vec::new()), vec![item])
+        }
+    }
+}
+
+// Message { version, header_type, header, bodyLength }.
+fn message(header_type: u8, header: Fb, body_length: usize) -> Vec<u8> {
+    finish_fb(&Fb::Table(vec![
+        Some(Fb::I16(VERSION)),
+        Some(Fb::U8(header_type)),
+        Some(header),
+        Some(Fb::I64(body_length as i64)),
+    ]))
+}
+
+// The buffers and field nodes of a record batch, in schema order.
+#[derive(Default)]
+struct Body {
+    bytes: Vec<u8>,
+    nodes: Vec<[i64; 2]>,
+    buffers: Vec<[i64; 2]>,
+}
+
+impl Body {
+    fn push_buffer(&mut self, bytes: &[u8]) {
+        let offset = self.bytes.len();
+        self.bytes.extend_from_slice(bytes);
+        align(&mut self.bytes, 8);
+        self.buffers.push([offset as i64, bytes.len() as i64]);
+    }
+
+    // An empty validity buffer when every value is present.
+    fn push_validity(&mut self, valid: impl Iterator<Item = bool>) {
+        let mut bitmap = Vec::new();
+        let (mut len, mut nulls) = (0usize, 0);
+        for valid in valid {
+            if len.is_multiple_of(8) {
+                bitmap.push(0u8);
+            }
+            if valid {
+                bitmap[len / 8] |= 1 << (len % 8);
+            } else {
+                nulls += 1;
+            }
+            len += 1;
+        }
+        self.nodes.push([len as i64, nulls]);
+        self.push_buffer(if nulls == 0 { &[] } else { &bitmap });
+    }
+
+    fn push_values<T, const N: usize>(
+        &mut self,
+        values: &[Option<T>],
+        to_bytes: impl Fn(&T) -> [u8; N],
+    ) {
+        self.push_validity(values.iter().map(Option::is_some));
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.as_ref().map_or([0; N], &to_bytes))
+            .collect();
+        self.push_buffer(&bytes);
+    }
+
+    // Offsets then data of utf8 values, Err if they don't fit Arrow's i32 offsets.
+    fn push_strings<'s>(&mut self, strings: impl Iterator<Item = &'s str>) -> io::Result<()> {
+        let mut offsets = vec![0i32];
+        let mut data = Vec::new();
+        for s in strings {
+            data.extend_from_slice(s.as_bytes());
+            offsets.push(to_offset(data.len())?);
+        }
+        self.push_buffer(&to_bytes(&offsets));
+        self.push_buffer(&data);
+        Ok(())
+    }
+
+    fn push_column(&mut self, data: &ColumnData) -> io::Result<()> {
+        match data {
+            ColumnData::UInt(values) => self.push_values(values, |v| v.to_le_bytes()),
+            ColumnData::Int(values) => self.push_values(values, |v| v.to_le_bytes()),
+            ColumnData::Float(values) => self.push_values(values, |v| v.to_le_bytes()),
+            ColumnData::Text(values) => {
+                self.push_validity(values.iter().map(Option::is_some));
+                self.push_strings(values.iter().map(|v| v.as_deref().unwrap_or_default()))?;
+            }
+            ColumnData::TextList(values) => {
+                self.push_validity(values.iter().map(|_| true));
+                let mut offsets = vec![0i32];
+                let mut items = 0;
+                for list in values {
+                    items += list.len();
+                    offsets.push(to_offset(items)?);
+                }
+                self.push_buffer(&to_bytes(&offsets));
+                self.push_validity(values.iter().flatten().map(|_| true));
+                self.push_strings(values.iter().flatten().map(String::as_str))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_offset(len: usize) -> io::Result<i32> {
+    i32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "column too large for an arrow batch",
+        )
+    })
+}
+
+fn to_bytes(offsets: &[i32]) -> Vec<u8> {
+    offsets.iter().flat_map(|o| o.to_le_bytes()).collect()
+}
+
+/// Writes an Arrow IPC stream, the schema is taken from the first batch and every
+/// batch must have the same columns. ids are a non-null uint32 column, TextList a list
+/// of utf8. An export without batches writes no schema, only the end of stream.
+pub struct ArrowWriter<W: Write> {
+    w: W,
+    // Names and variants of the first batch's columns.
+    schema: Option<Vec<(String, std::mem::Discriminant<ColumnData>)>>,
+}
+
+impl<W: Write> ArrowWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w, schema: None }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    // Continuation marker, metadata length, metadata padded to 8 bytes then the body.
+    fn write_message(&mut self, metadata: &[u8], body: &[u8]) -> io::Result<()> {
+        let padded = metadata.len().next_multiple_of(8);
+        self.w.write_all(&CONTINUATION.to_le_bytes())?;
+        self.w.write_all(&(padded as i32).to_le_bytes())?;
+        self.w.write_all(metadata)?;
+        self.w.write_all(&vec![0; padded - metadata.len()])?;
+        self.w.write_all(body)
+    }
+
+    fn write_schema(&mut self, batch: &ExportBatch) -> io::Result<()> {
+        let mut fields = vec![field(
+            "id",
+            false,
+            TYPE_INT,
+            int_type(32, false),
+            Vec::new(),
+        )];
+        fields.extend(batch.columns.iter().map(|c| column_field(&c.name, &c.data)));
+        // Schema { endianness, fields }, little endian is the default.
+        let schema = Fb::Table(vec![None, Some(Fb::Tables(fields))]);
+        self.write_message(&message(HEADER_SCHEMA, schema, 0), &[])
+    }
+}
+
+impl<W: Write> ExportWriter for ArrowWriter<W> {
+    fn write_batch(&mut self, batch: &ExportBatch) -> io::Result<()> {
+        let columns: Vec<_> = batch
+            .columns
+            .iter()
+            .map(|c| (c.name.clone(), std::mem::discriminant(&c.data)))
+            .collect();
+        match &self.schema {
+            Some(schema) if *schema != columns => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "batch columns differ from the first batch",
+                ))
+            }
+            Some(_) => {}
+            None => {
+                self.write_schema(batch)?;
+                self.schema = Some(columns);
+            }
+        }
+
+        let mut body = Body::default();
+        body.nodes.push([batch.ids.len() as i64, 0]);
+        body.push_buffer(&[]);
+        let ids: Vec<u8> = batch.ids.iter().flat_map(|id| id.to_le_bytes()).collect();
+        body.push_buffer(&ids);
+        for column in &batch.columns {
+            body.push_column(&column.data)?;
+        }
+        // RecordBatch { length, nodes, buffers }.
+        let record_batch = Fb::Table(vec![
+            Some(Fb::I64(batch.ids.len() as i64)),
+            Some(Fb::Structs(&body.nodes)),
+            Some(Fb::Structs(&body.buffers)),
+        ]);
+        let metadata = message(HEADER_RECORD_BATCH, record_batch, body.bytes.len());
+        self.write_message(&metadata, &body.bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.write_all(&CONTINUATION.to_le_bytes())?;
+        self.w.write_all(&0u32.to_le_bytes())?;
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads flatbuffers back by following offsets and vtables.
+    fn u32_at(buf: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    fn i64_at(buf: &[u8], at: usize) -> i64 {
+        i64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+    }
+
+    fn deref(buf: &[u8], at: usize) -> usize {
+        at + u32_at(buf, at)
+    }
+
+    fn field_pos(buf: &[u8], table: usize, id: usize) -> Option<usize> {
+        let soffset = i32::from_le_bytes(buf[table..table + 4].try_into().unwrap());
+        let vtable = (table as i64 - soffset as i64) as usize;
+        let vtable_size = u16::from_le_bytes([buf[vtable], buf[vtable + 1]]) as usize;
+        if 4 + id * 2 >= vtable_size {
+            return None;
+        }
+        let at = vtable + 4 + id * 2;
+        let offset = u16::from_le_bytes([buf[at], buf[at + 1]]) as usize;
+        (offset != 0).then_some(table + offset)
+    }
+
+    fn string(buf: &[u8], at: usize) -> &str {
+        let len = u32_at(buf, at);
+        std::str::from_utf8(&buf[at + 4..at + 4 + len]).unwrap()
+    }
+
+    fn vector(buf: &[u8], at: usize) -> (usize, usize) {
+        (u32_at(buf, at), at + 4)
+    }
+
+    // Splits a stream into (metadata, body) messages up to the end marker.
+    fn messages(stream: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut messages = Vec::new();
+        let mut pos = 0;
+        loop {
+            assert_eq!(u32_at(stream, pos), CONTINUATION as usize);
+            let len = u32_at(stream, pos + 4);
+            pos += 8;
+            if len == 0 {
+                assert_eq!(pos, stream.len());
+                return messages;
+            }
+            assert_eq!(len % 8, 0);
+            let metadata = &stream[pos..pos + len];
+            let root = deref(metadata, 0);
+            let body_len = i64_at(metadata, field_pos(metadata, root, 3).unwrap()) as usize;
+            pos += len;
+            messages.push((metadata, &stream[pos..pos + body_len]));
+            pos += body_len;
+        }
+    }
+
+    fn batch() -> ExportBatch {
+        ExportBatch::new(vec![1, 5, 9])
+            .with_int("score", |id| (id != 5).then_some(id as i64 * 10))
+            .with_text("source", |id| Some(format!("s{id}")))
+            .with_text_list("tags", |id| (0..id % 3).map(|t| format!("t{t}")).collect())
+    }
+
+    #[test]
+    fn writes_schema_then_batches() {
+        let mut writer = ArrowWriter::new(Vec::new());
+        writer.write_batch(&batch()).unwrap();
+        writer.write_batch(&batch()).unwrap();
+        writer.finish().unwrap();
+        let stream = writer.into_inner();
+        let messages = messages(&stream);
+        assert_eq!(messages.len(), 3);
+
+        let (schema, body) = messages[0];
+        assert!(body.is_empty());
+        let root = deref(schema, 0);
+        assert_eq!(schema[field_pos(schema, root, 1).unwrap()], HEADER_SCHEMA);
+        let header = deref(schema, field_pos(schema, root, 2).unwrap());
+        let (len, fields) = vector(schema, deref(schema, field_pos(schema, header, 1).unwrap()));
+        let names: Vec<&str> = (0..len)
+            .map(|i| {
+                let field = deref(schema, fields + i * 4);
+                string(schema, deref(schema, field_pos(schema, field, 0).unwrap()))
+            })
+            .collect();
+        assert_eq!(names, ["id", "score", "source", "tags"]);
+
+        let (metadata, body) = messages[1];
+        let root = deref(metadata, 0);
+        assert_eq!(
+            metadata[field_pos(metadata, root, 1).unwrap()],
+            HEADER_RECORD_BATCH
+        );
+        let header = deref(metadata, field_pos(metadata, root, 2).unwrap());
+        assert_eq!(i64_at(metadata, field_pos(metadata, header, 0).unwrap()), 3);
+        let (nodes, _) = vector(
+            metadata,
+            deref(metadata, field_pos(metadata, header, 1).unwrap()),
+        );
+        // id, score, source, tags and the tags' items.
+        assert_eq!(nodes, 5);
+        let (buffers, buffers_at) = vector(
+            metadata,
+            deref(metadata, field_pos(metadata, header, 2).unwrap()),
+        );
+        assert_eq!(buffers_at % 8, 0);
+        assert_eq!(buffers, 2 + 2 + 3 + 2 + 3);
+        let buffer = |i: usize| {
+            let offset = i64_at(metadata, buffers_at + i * 16) as usize;
+            let len = i64_at(metadata, buffers_at + i * 16 + 8) as usize;
+            assert_eq!(offset % 8, 0);
+            &body[offset..offset + len]
+        };
+        assert_eq!(buffer(1), [1u32, 5, 9].map(u32::to_le_bytes).concat());
+        // The score of 5 is null.
+        assert_eq!(buffer(2), [0b101]);
+        assert_eq!(buffer(3), [10i64, 0, 90].map(i64::to_le_bytes).concat());
+        assert_eq!(buffer(6), b"s1s5s9");
+        // 1, 2 and 0 tags.
+        assert_eq!(buffer(8), [0i32, 1, 3, 3].map(i32::to_le_bytes).concat());
+        assert_eq!(buffer(11), b"t0t0t1");
+    }
+
+    #[test]
+    fn rejects_changed_columns() {
+        let mut writer = ArrowWriter::new(Vec::new());
+        writer.write_batch(&batch()).unwrap();
+        let other = ExportBatch::new(vec![2]).with_int("score", |_| None);
+        assert!(writer.write_batch(&other).is_err());
+    }
+}
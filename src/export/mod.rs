@@ -0,0 +1,233 @@
+//! Exports query results joined with index values as CSV, JSON lines with the `json`
+//! feature or Arrow IPC streams with the `arrow` feature. Parquet isn't written,
+//! convert the Arrow stream with the arrow or parquet crates.
+
+use std::io::{self, Write};
+
+use crate::{packed, QueryResult, ID};
+
+#[cfg(feature = "arrow")]
+mod arrow;
+
+#[cfg(feature = "arrow")]
+pub use arrow::ArrowWriter;
+
+#[derive(Clone, Debug)]
+pub enum ColumnData {
+    UInt(Vec<Option<u64>>),
+    Int(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    TextList(Vec<Vec<String>>),
+}
+
+impl ColumnData {
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnData::UInt(values) => values.len(),
+            ColumnData::Int(values) => values.len(),
+            ColumnData::Float(values) => values.len(),
+            ColumnData::Text(values) => values.len(),
+            ColumnData::TextList(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    pub data: ColumnData,
+}
+
+/// Columns of values for a list of ids, one row per id.
+/// Values are usually read from indexes, e.g. `RangeIndex::id_values`.
+#[derive(Clone, Debug, Default)]
+pub struct ExportBatch {
+    pub ids: Vec<ID>,
+    pub columns: Vec<Column>,
+}
+
+impl ExportBatch {
+    pub fn new(ids: Vec<ID>) -> Self {
+        Self {
+            ids,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Every match in result sorted by id ascending.
+    pub fn from_result(result: &QueryResult) -> Self {
+        Self::new(result.get(0, result.matched(), false))
+    }
+
+    /// Batches of at most batch_size matches so large results don't have to be exported at once.
+    /// The checks are walked once, each batch continues where the last ended.
+    pub fn batches(result: &QueryResult, batch_size: usize) -> impl Iterator<Item = Self> + '_ {
+        let batch_size = batch_size.max(1);
+        let mut ids = result
+            .checks()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, &check)| packed::ids(index, check));
+        std::iter::from_fn(move || {
+            let batch: Vec<ID> = ids.by_ref().take(batch_size).collect();
+            (!batch.is_empty()).then(|| Self::new(batch))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn with_column(mut self, name: &str, data: ColumnData) -> Self {
+        self.columns.push(Column {
+            name: name.to_string(),
+            data,
+        });
+        self
+    }
+
+    pub fn with_uint(self, name: &str, f: impl Fn(ID) -> Option<u64>) -> Self {
+        let values = self.ids.iter().map(|&id| f(id)).collect();
+        self.with_column(name, ColumnData::UInt(values))
+    }
+
+    pub fn with_int(self, name: &str, f: impl Fn(ID) -> Option<i64>) -> Self {
+        let values = self.ids.iter().map(|&id| f(id)).collect();
+        self.with_column(name, ColumnData::Int(values))
+    }
+
+    pub fn with_float(self, name: &str, f: impl Fn(ID) -> Option<f64>) -> Self {
+        let values = self.ids.iter().map(|&id| f(id)).collect();
+        self.with_column(name, ColumnData::Float(values))
+    }
+
+    pub fn with_text(self, name: &str, f: impl Fn(ID) -> Option<String>) -> Self {
+        let values = self.ids.iter().map(|&id| f(id)).collect();
+        self.with_column(name, ColumnData::Text(values))
+    }
+
+    pub fn with_text_list(self, name: &str, f: impl Fn(ID) -> Vec<String>) -> Self {
+        let values = self.ids.iter().map(|&id| f(id)).collect();
+        self.with_column(name, ColumnData::TextList(values))
+    }
+}
+
+/// Output format for ExportBatches.
+pub trait ExportWriter {
+    fn write_batch(&mut self, batch: &ExportBatch) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Lists are written space separated.
+pub struct CsvWriter<W: Write> {
+    w: W,
+    wrote_header: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            w,
+            wrote_header: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+impl<W: Write> ExportWriter for CsvWriter<W> {
+    fn write_batch(&mut self, batch: &ExportBatch) -> io::Result<()> {
+        if !self.wrote_header {
+            let mut header = vec!["id".to_string()];
+            header.extend(batch.columns.iter().map(|c| csv_field(&c.name)));
+            writeln!(self.w, "{}", header.join(","))?;
+            self.wrote_header = true;
+        }
+        for (row, id) in batch.ids.iter().enumerate() {
+            let mut fields = vec![id.to_string()];
+            for column in &batch.columns {
+                let field = match &column.data {
+                    ColumnData::UInt(values) => values[row].map(|v| v.to_string()),
+                    ColumnData::Int(values) => values[row].map(|v| v.to_string()),
+                    ColumnData::Float(values) => values[row].map(|v| v.to_string()),
+                    ColumnData::Text(values) => values[row].as_deref().map(csv_field),
+                    ColumnData::TextList(values) => Some(csv_field(&values[row].join(" "))),
+                };
+                fields.push(field.unwrap_or_default());
+            }
+            writeln!(self.w, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// One JSON object per row, non-finite floats are written as null.
+#[cfg(feature = "json")]
+pub struct JsonLinesWriter<W: Write> {
+    w: W,
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> ExportWriter for JsonLinesWriter<W> {
+    // Written field by field to keep the columns in order.
+    fn write_batch(&mut self, batch: &ExportBatch) -> io::Result<()> {
+        for (row, id) in batch.ids.iter().enumerate() {
+            write!(self.w, "{{\"id\":{id}")?;
+            for column in &batch.columns {
+                write!(self.w, ",")?;
+                serde_json::to_writer(&mut self.w, &column.name)?;
+                write!(self.w, ":")?;
+                match &column.data {
+                    ColumnData::UInt(values) => serde_json::to_writer(&mut self.w, &values[row]),
+                    ColumnData::Int(values) => serde_json::to_writer(&mut self.w, &values[row]),
+                    ColumnData::Float(values) => serde_json::to_writer(&mut self.w, &values[row]),
+                    ColumnData::Text(values) => serde_json::to_writer(&mut self.w, &values[row]),
+                    ColumnData::TextList(values) => {
+                        serde_json::to_writer(&mut self.w, &values[row])
+                    }
+                }?;
+            }
+            writeln!(self.w, "}}")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
@@ -0,0 +1,279 @@
+//! C API over a Db of posts with an id and tags, for embedding the index in servers
+//! written in other languages. Build it as a shared library from a crate depending on
+//! booru-db with the ffi feature and `crate-type = ["cdylib"]`.
+//!
+//! Handles are opaque pointers owned by the caller, free each with its free function.
+//! Ids given to and returned by the API are the post ids passed to add and insert.
+//! include/booru_db.h declares the API for C, keep it in sync with this module.
+//!
+//! Handles aren't synchronized. Calls taking a const db only read it and may run on
+//! several threads at once, calls taking a mutable loader or db must not overlap any
+//! other call on it. A result can be read or freed on any thread, paging it reads the
+//! db it came from.
+//!
+//! Panics are caught at the boundary rather than unwinding into C, the call then
+//! returns false, null or 0. A db that panicked while being written may be left
+//! partly updated.
+
+use std::{
+    collections::HashSet,
+    ffi::{c_char, CStr},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{
+    index::{FieldIndex, IdMap, Index, IndexLoader, KeysIndex, KeysIndexLoader, Term},
+    Db, DbLoader, IndexContext, Query, QueryResult, Queryable, ID,
+};
+
+#[derive(Clone)]
+pub struct FfiPost {
+    pub id: u32,
+    pub tags: Vec<String>,
+}
+
+struct TagIndexLoader(KeysIndexLoader<String>);
+
+impl IndexLoader<FfiPost> for TagIndexLoader {
+    fn add(&mut self, id: ID, post: &FfiPost) {
+        self.0.add(id, &post.tags);
+    }
+
+    fn load(self: Box<Self>) -> Box<dyn Index<FfiPost>> {
+        Box::new(TagIndex(self.0.load()))
+    }
+}
+
+#[derive(Clone)]
+struct TagIndex(KeysIndex<String>);
+
+impl Index<FfiPost> for TagIndex {
+    fn query<'s>(
+        &'s self,
+        _ident: Option<&str>,
+        text: &str,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.0.query_term(&Term::parse(None, text), inverse)
+    }
+
+    fn query_term<'s>(
+        &'s self,
+        _context: &IndexContext<'_, FfiPost>,
+        term: &Term<'_>,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.0.query_term(&term.without_prefixes(), inverse)
+    }
+
+    fn insert(&mut self, id: ID, post: &FfiPost) {
+        self.0.insert(id, &post.tags);
+    }
+
+    fn remove(&mut self, id: ID, post: &FfiPost) {
+        self.0.remove(id, &post.tags);
+    }
+
+    fn update(&mut self, id: ID, old: &FfiPost, new: &FfiPost) {
+        self.0.update(id, &old.tags, &new.tags);
+    }
+
+    fn clone_index(&self) -> Option<Box<dyn Index<FfiPost>>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// A DbLoader that rejects post ids added twice.
+pub struct FfiDbLoader {
+    loader: DbLoader<FfiPost>,
+    ids: HashSet<u32>,
+}
+
+pub type FfiDb = Db<FfiPost>;
+
+// Runs f, returning default if it panics as unwinding into C is undefined behavior.
+fn guard<T>(default: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+// None if any tag isn't valid UTF-8.
+unsafe fn read_post(id: u32, tags: *const *const c_char, tags_len: usize) -> Option<FfiPost> {
+    let tags = if tags_len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(tags, tags_len)
+            .iter()
+            .map(|&tag| CStr::from_ptr(tag).to_str().ok().map(str::to_string))
+            .collect::<Option<Vec<String>>>()?
+    };
+    Some(FfiPost { id, tags })
+}
+
+fn internal(db: &FfiDb, id: u32) -> Option<ID> {
+    db.index::<IdMap<FfiPost, u32>>()?.internal(&id)
+}
+
+/// Tags are the default index, `id:1,2` matches posts by id.
+#[no_mangle]
+pub extern "C" fn booru_db_loader_new() -> *mut FfiDbLoader {
+    guard(ptr::null_mut(), || {
+        let loader = DbLoader::new()
+            .with_store()
+            .with_loader("id", IdMap::loader(|post: &FfiPost| post.id))
+            .with_default(TagIndexLoader(KeysIndexLoader::new()));
+        let loader = FfiDbLoader {
+            loader,
+            ids: HashSet::new(),
+        };
+        Box::into_raw(Box::new(loader))
+    })
+}
+
+/// Adds a post with tags_len tags, false if id was already added or a tag isn't valid UTF-8.
+///
+/// # Safety
+/// loader must come from booru_db_loader_new, tags must point to tags_len
+/// NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_loader_add(
+    loader: *mut FfiDbLoader,
+    id: u32,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> bool {
+    guard(false, || {
+        let loader = &mut *loader;
+        if loader.ids.contains(&id) {
+            return false;
+        }
+        let Some(post) = read_post(id, tags, tags_len) else {
+            return false;
+        };
+        loader.ids.insert(id);
+        loader.loader.add(&post);
+        true
+    })
+}
+
+/// Builds the db, loader is freed.
+///
+/// # Safety
+/// loader must come from booru_db_loader_new and not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_loader_finish(loader: *mut FfiDbLoader) -> *mut FfiDb {
+    let loader = Box::from_raw(loader);
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(loader.loader.finish()))
+    })
+}
+
+/// # Safety
+/// db must come from booru_db_loader_finish and not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_free(db: *mut FfiDb) {
+    guard((), || drop(Box::from_raw(db)));
+}
+
+/// Adds a post, false if the id is in use or a tag isn't valid UTF-8.
+///
+/// # Safety
+/// db must come from booru_db_loader_finish, tags as in booru_db_loader_add.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_insert(
+    db: *mut FfiDb,
+    id: u32,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> bool {
+    guard(false, || {
+        let db = &mut *db;
+        if internal(db, id).is_some() {
+            return false;
+        }
+        let Some(post) = read_post(id, tags, tags_len) else {
+            return false;
+        };
+        let internal = db.next_id();
+        db.insert(internal, &post)
+    })
+}
+
+/// Removes the post with id, false if there is none.
+///
+/// # Safety
+/// db must come from booru_db_loader_finish.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_remove(db: *mut FfiDb, id: u32) -> bool {
+    guard(false, || {
+        let db = &mut *db;
+        let Some(internal) = internal(db, id) else {
+            return false;
+        };
+        db.remove_id(internal).is_some()
+    })
+}
+
+/// Runs a query such as `tag -other`, null if it can't be parsed or a term is unknown.
+///
+/// # Safety
+/// db must come from booru_db_loader_finish, query must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_query(
+    db: *const FfiDb,
+    query: *const c_char,
+) -> *mut QueryResult {
+    guard(ptr::null_mut(), || {
+        let Ok(text) = CStr::from_ptr(query).to_str() else {
+            return ptr::null_mut();
+        };
+        let Ok(query) = Query::parse(text) else {
+            return ptr::null_mut();
+        };
+        match (*db).query(&query) {
+            Ok(result) => Box::into_raw(Box::new(result)),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+/// result must come from booru_db_query.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_result_matched(result: *const QueryResult) -> usize {
+    guard(0, || (*result).matched())
+}
+
+/// Writes the ids of up to limit matches starting at offset to out, in the order posts
+/// were added or the latest first if reverse. Returns how many were written.
+///
+/// # Safety
+/// db and result must come from booru_db_loader_finish and booru_db_query,
+/// out must have room for limit ids.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_result_page(
+    db: *const FfiDb,
+    result: *const QueryResult,
+    offset: usize,
+    limit: usize,
+    reverse: bool,
+    out: *mut u32,
+) -> usize {
+    guard(0, || {
+        let Some(id_map) = (*db).index::<IdMap<FfiPost, u32>>() else {
+            return 0;
+        };
+        let page = id_map.to_external(&(*result).get(offset, limit, reverse));
+        if !page.is_empty() {
+            slice::from_raw_parts_mut(out, page.len()).copy_from_slice(&page);
+        }
+        page.len()
+    })
+}
+
+/// # Safety
+/// result must come from booru_db_query and not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn booru_db_result_free(result: *mut QueryResult) {
+    guard((), || drop(Box::from_raw(result)));
+}
@@ -0,0 +1,172 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use crate::{Db, Query, QueryResult};
+
+/// Past versions of a Db for queries such as what a search returned yesterday.
+/// Versions are Db::snapshot checkpoints taken by record, so only the seqs and times
+/// a checkpoint covers can be queried, writes between checkpoints aren't kept.
+/// Checkpoints share indexes with the Db until it writes to them, each version of a
+/// written index is kept until its checkpoint is dropped.
+pub struct History<P> {
+    capacity: usize,
+    // Oldest first.
+    checkpoints: VecDeque<Checkpoint<P>>,
+}
+
+pub struct Checkpoint<P> {
+    pub seq: u64,
+    pub time: SystemTime,
+    /// The last time record found the Db still at seq, the checkpoint is the Db as of
+    /// any time from time to until.
+    pub until: SystemTime,
+    pub db: Db<P>,
+}
+
+impl<P: 'static> History<P> {
+    /// Keeps up to capacity checkpoints, dropping the oldest.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// Takes a checkpoint of db. If nothing was written since the last checkpoint it's
+    /// kept and covers up to now instead.
+    pub fn record(&mut self, db: &Db<P>) {
+        let now = SystemTime::now();
+        if let Some(latest) = self.checkpoints.back_mut() {
+            if latest.seq == db.seq() {
+                latest.until = now;
+                return;
+            }
+        }
+        let snapshot = db.snapshot();
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(Checkpoint {
+            seq: db.seq(),
+            time: now,
+            until: now,
+            db: snapshot,
+        });
+    }
+
+    pub fn latest(&self) -> Option<&Checkpoint<P>> {
+        self.checkpoints.back()
+    }
+
+    pub fn checkpoints(&self) -> impl Iterator<Item = &Checkpoint<P>> {
+        self.checkpoints.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// The latest checkpoint at or before seq, see Db::seq.
+    pub fn at_seq(&self, seq: u64) -> Option<&Checkpoint<P>> {
+        self.checkpoints.iter().rev().find(|c| c.seq <= seq)
+    }
+
+    /// The latest checkpoint taken at or before time.
+    pub fn at_time(&self, time: SystemTime) -> Option<&Checkpoint<P>> {
+        self.checkpoints.iter().rev().find(|c| c.time <= time)
+    }
+
+    /// Runs query against the db as of seq.
+    /// None unless a checkpoint was taken at seq, see record.
+    pub fn query_at(
+        &self,
+        seq: u64,
+        query: &Query<String>,
+    ) -> Option<Result<QueryResult, Vec<String>>> {
+        let checkpoint = self.at_seq(seq).filter(|c| c.seq == seq)?;
+        Some(checkpoint.db.query(query))
+    }
+
+    /// Runs query against the db as of time with the seq it was at.
+    /// None unless a checkpoint covers time, see Checkpoint::until.
+    pub fn query_at_time(
+        &self,
+        time: SystemTime,
+        query: &Query<String>,
+    ) -> Option<(u64, Result<QueryResult, Vec<String>>)> {
+        let checkpoint = self.at_time(time).filter(|c| time <= c.until)?;
+        Some((checkpoint.seq, checkpoint.db.query(query)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{query::Item, DbLoader};
+
+    fn all() -> Query<String> {
+        Query::new(Item::All, false)
+    }
+
+    fn matched(result: Option<Result<QueryResult, Vec<String>>>) -> Option<usize> {
+        result.map(|result| result.unwrap().matched())
+    }
+
+    #[test]
+    fn at_or_before() {
+        let mut db = DbLoader::<()>::new().load([(), ()]);
+        let mut history = History::new(4);
+        assert!(history.at_seq(0).is_none());
+        history.record(&db);
+        let first = db.seq();
+        db.insert(2, &());
+        db.insert(3, &());
+        let between = db.seq() - 1;
+        // Unchanged, the first checkpoint is kept.
+        history.record(&db);
+        history.record(&db);
+        assert_eq!(history.len(), 2);
+        let second = db.seq();
+
+        assert_eq!(history.at_seq(first).unwrap().seq, first);
+        assert_eq!(history.at_seq(between).unwrap().seq, first);
+        assert_eq!(history.at_seq(second + 5).unwrap().seq, second);
+        assert_eq!(matched(history.query_at(first, &all())), Some(2));
+        assert_eq!(matched(history.query_at(second, &all())), Some(4));
+        // Not a checkpoint, the Db had a write after first.
+        assert!(history.query_at(between, &all()).is_none());
+
+        let checkpoint = history.latest().unwrap();
+        let (seq, result) = history.query_at_time(checkpoint.until, &all()).unwrap();
+        assert_eq!((seq, result.unwrap().matched()), (second, 4));
+        let before = history.checkpoints().next().unwrap().time - Duration::from_secs(1);
+        assert!(history.at_time(before).is_none());
+        let after = checkpoint.until + Duration::from_secs(60);
+        assert_eq!(history.at_time(after).unwrap().seq, second);
+        // Later than record last saw the Db.
+        assert!(history.query_at_time(after, &all()).is_none());
+    }
+
+    #[test]
+    fn record_drops_oldest() {
+        let mut db = DbLoader::<()>::new().load([()]);
+        let mut history = History::new(2);
+        let mut seqs = Vec::new();
+        for id in 1..4 {
+            history.record(&db);
+            seqs.push(db.seq());
+            db.insert(id, &());
+        }
+        assert_eq!(history.len(), 2);
+        let kept: Vec<u64> = history.checkpoints().map(|c| c.seq).collect();
+        assert_eq!(kept, seqs[1..]);
+        assert!(history.at_seq(seqs[0]).is_none());
+        assert!(history.query_at(seqs[0], &all()).is_none());
+        assert_eq!(matched(history.query_at(seqs[2], &all())), Some(3));
+    }
+}
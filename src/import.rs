@@ -0,0 +1,265 @@
+use std::{
+    fmt,
+    io::{self, BufRead},
+    str::FromStr,
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    /// Line is 1 based, pointing at the start of the row.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl ImportError {
+    pub fn parse(line: usize, message: impl Into<String>) -> Self {
+        Self::Parse {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+pub struct CsvRow {
+    headers: Arc<[String]>,
+    fields: Vec<String>,
+    line: usize,
+}
+
+impl CsvRow {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub fn field(&self, index: usize) -> Option<&str> {
+        self.fields.get(index).map(String::as_str)
+    }
+
+    /// Field under the header column named name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let index = self.headers.iter().position(|h| h == name)?;
+        self.field(index)
+    }
+
+    /// Empty fields are None.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, ImportError>
+    where
+        T::Err: fmt::Display,
+    {
+        match self.get(name) {
+            None | Some("") => Ok(None),
+            Some(field) => field
+                .parse()
+                .map(Some)
+                .map_err(|err| ImportError::parse(self.line, format!("{name}: {err}"))),
+        }
+    }
+
+    /// Like parse but missing or empty fields are an error.
+    pub fn require<T: FromStr>(&self, name: &str) -> Result<T, ImportError>
+    where
+        T::Err: fmt::Display,
+    {
+        self.parse(name)?
+            .ok_or_else(|| ImportError::parse(self.line, format!("{name}: missing")))
+    }
+}
+
+/// Streams rows from RFC 4180 style CSV.
+/// Quoted fields may contain delimiters, escaped quotes and newlines.
+pub struct CsvReader<R> {
+    reader: R,
+    delimiter: char,
+    headers: Arc<[String]>,
+    line: usize,
+    buf: String,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    /// The first row is read as the headers.
+    pub fn new(reader: R) -> Result<Self, ImportError> {
+        Self::with_delimiter(reader, ',')
+    }
+
+    pub fn with_delimiter(reader: R, delimiter: char) -> Result<Self, ImportError> {
+        let mut csv = Self {
+            reader,
+            delimiter,
+            headers: Arc::from([]),
+            line: 0,
+            buf: String::new(),
+        };
+        if let Some((_, headers)) = csv.read_record()? {
+            csv.headers = headers.into();
+        }
+        Ok(csv)
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_line(&mut self) -> Result<bool, ImportError> {
+        self.buf.clear();
+        let read = self.reader.read_line(&mut self.buf)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.line += 1;
+        if self.buf.ends_with('\n') {
+            self.buf.pop();
+            if self.buf.ends_with('\r') {
+                self.buf.pop();
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_record(&mut self) -> Result<Option<(usize, Vec<String>)>, ImportError> {
+        if !self.read_line()? {
+            return Ok(None);
+        }
+        let start = self.line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            let mut chars = self.buf.chars().peekable();
+            while let Some(c) = chars.next() {
+                if quoted {
+                    if c == '"' {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                            field.push('"');
+                        } else {
+                            quoted = false;
+                        }
+                    } else {
+                        field.push(c);
+                    }
+                } else if c == '"' && field.is_empty() {
+                    quoted = true;
+                } else if c == self.delimiter {
+                    fields.push(std::mem::take(&mut field));
+                } else {
+                    field.push(c);
+                }
+            }
+            if !quoted {
+                break;
+            }
+            if !self.read_line()? {
+                return Err(ImportError::parse(start, "unterminated quoted field"));
+            }
+            field.push('\n');
+        }
+        fields.push(field);
+        Ok(Some((start, fields)))
+    }
+
+    /// Maps every row to a post, to be fed into DbLoader::try_extend.
+    pub fn posts<P>(
+        self,
+        mut f: impl FnMut(&CsvRow) -> Result<P, ImportError>,
+    ) -> impl Iterator<Item = Result<P, ImportError>> {
+        self.map(move |row| row.and_then(|row| f(&row)))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = Result<CsvRow, ImportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            return match self.read_record() {
+                Ok(None) => None,
+                // Skip blank lines.
+                Ok(Some((_, fields))) if fields.len() == 1 && fields[0].is_empty() => continue,
+                Ok(Some((line, fields))) => Some(Ok(CsvRow {
+                    headers: self.headers.clone(),
+                    fields,
+                    line,
+                })),
+                Err(err) => Some(Err(err)),
+            };
+        }
+    }
+}
+
+/// Streams one JSON value per line, skipping blank lines.
+#[cfg(feature = "json")]
+pub struct JsonLines<R, T> {
+    reader: R,
+    line: usize,
+    buf: String,
+    _values: std::marker::PhantomData<T>,
+}
+
+#[cfg(feature = "json")]
+impl<R: BufRead, T: serde::de::DeserializeOwned> JsonLines<R, T> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            buf: String::new(),
+            _values: std::marker::PhantomData,
+        }
+    }
+
+    /// Maps every value to a post, to be fed into DbLoader::try_extend.
+    pub fn posts<P>(
+        self,
+        mut f: impl FnMut(T) -> Result<P, ImportError>,
+    ) -> impl Iterator<Item = Result<P, ImportError>> {
+        self.map(move |value| value.and_then(&mut f))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<R: BufRead, T: serde::de::DeserializeOwned> Iterator for JsonLines<R, T> {
+    type Item = Result<T, ImportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(err) => return Some(Err(err.into())),
+            }
+            let line = self.buf.trim();
+            if line.is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(line)
+                    .map_err(|err| ImportError::parse(self.line, err.to_string())),
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{io, str::FromStr, sync::Arc};
+
+use crate::{query::Queryable, IndexContext, Query, ID};
+
+use super::{Index, IndexLoader, RangeIndex, RangeIndexLoader, RangeQuery, Term};
+
+/// Register DimensionsIndex under all of these, `with_loader(DIMENSIONS_IDENTIFIERS, ...)`.
+pub const DIMENSIONS_IDENTIFIERS: [&str; 4] = ["width", "height", "ratio", "mpixels"];
+
+type Extractor<P> = Arc<dyn Fn(&P) -> Option<(u32, u32)> + Send + Sync>;
+
+/// A decimal stored in hundredths, `ratio` also accepts `16:9`.
+struct Hundredths(u32);
+
+impl FromStr for Hundredths {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = match s.split_once(':') {
+            Some((w, h)) => {
+                let w: f64 = w.parse().map_err(|_| ())?;
+                let h: f64 = h.parse().map_err(|_| ())?;
+                w / h
+            }
+            None => s.parse().map_err(|_| ())?,
+        };
+        if !value.is_finite() || value < 0.0 {
+            return Err(());
+        }
+        Ok(Self((value * 100.0).round().min(u32::MAX as f64) as u32))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Dimensions {
+    width: u32,
+    height: u32,
+    /// width / height in hundredths, None when height is 0.
+    ratio: Option<u32>,
+    /// Megapixels in hundredths.
+    mpixels: u32,
+}
+
+impl Dimensions {
+    fn new((width, height): (u32, u32)) -> Self {
+        let (w, h) = (width as u64, height as u64);
+        let ratio = (h != 0).then(|| ((w * 100 + h / 2) / h).min(u32::MAX as u64) as u32);
+        let mpixels = ((w * h + 5_000) / 10_000).min(u32::MAX as u64) as u32;
+        Self {
+            width,
+            height,
+            ratio,
+            mpixels,
+        }
+    }
+}
+
+pub struct DimensionsIndexLoader<P> {
+    extract: Extractor<P>,
+    width: RangeIndexLoader<u32>,
+    height: RangeIndexLoader<u32>,
+    ratio: RangeIndexLoader<u32>,
+    mpixels: RangeIndexLoader<u32>,
+}
+
+impl<P> DimensionsIndexLoader<P> {
+    pub fn new(extract: impl Fn(&P) -> Option<(u32, u32)> + Send + Sync + 'static) -> Self {
+        Self::with_extractor(Arc::new(extract))
+    }
+
+    fn with_extractor(extract: Extractor<P>) -> Self {
+        Self {
+            extract,
+            width: RangeIndexLoader::new(),
+            height: RangeIndexLoader::new(),
+            ratio: RangeIndexLoader::new(),
+            mpixels: RangeIndexLoader::new(),
+        }
+    }
+}
+
+impl<P: 'static> IndexLoader<P> for DimensionsIndexLoader<P> {
+    fn add(&mut self, id: ID, post: &P) {
+        let Some(dimensions) = (self.extract)(post) else {
+            return;
+        };
+        let dimensions = Dimensions::new(dimensions);
+        self.width.add(id, dimensions.width);
+        self.height.add(id, dimensions.height);
+        if let Some(ratio) = dimensions.ratio {
+            self.ratio.add(id, ratio);
+        }
+        self.mpixels.add(id, dimensions.mpixels);
+    }
+
+    fn load(self: Box<Self>) -> Box<dyn Index<P>> {
+        Box::new(DimensionsIndex {
+            extract: self.extract,
+            width: self.width.load(),
+            height: self.height.load(),
+            ratio: self.ratio.load(),
+            mpixels: self.mpixels.load(),
+        })
+    }
+
+    fn fork(&self) -> Option<Box<dyn IndexLoader<P>>> {
+        Some(Box::new(Self::with_extractor(self.extract.clone())))
+    }
+
+    fn merge(&mut self, other: Box<dyn IndexLoader<P>>) -> io::Result<()> {
+        let other = other.into_any().downcast::<Self>().unwrap();
+        self.width.merge(other.width);
+        self.height.merge(other.height);
+        self.ratio.merge(other.ratio);
+        self.mpixels.merge(other.mpixels);
+        Ok(())
+    }
+}
+
+/// Image size terms from a (width, height) extractor:
+/// `width:>1920`, `height:1080`, `ratio:16:9`, `ratio:>1.5`, `mpixels:2..8`
+/// and the shortcuts `ratio:portrait`, `ratio:landscape` and `ratio:square`.
+/// Ratio and megapixels are rounded to two decimals so `ratio:1.78` matches 16:9.
+pub struct DimensionsIndex<P> {
+    extract: Extractor<P>,
+    width: RangeIndex<u32>,
+    height: RangeIndex<u32>,
+    ratio: RangeIndex<u32>,
+    mpixels: RangeIndex<u32>,
+}
+
+// Derive would require P: Clone.
+impl<P> Clone for DimensionsIndex<P> {
+    fn clone(&self) -> Self {
+        Self {
+            extract: self.extract.clone(),
+            width: self.width.clone(),
+            height: self.height.clone(),
+            ratio: self.ratio.clone(),
+            mpixels: self.mpixels.clone(),
+        }
+    }
+}
+
+impl<P> DimensionsIndex<P> {
+    pub fn loader(
+        extract: impl Fn(&P) -> Option<(u32, u32)> + Send + Sync + 'static,
+    ) -> DimensionsIndexLoader<P> {
+        DimensionsIndexLoader::new(extract)
+    }
+
+    pub fn width(&self) -> &RangeIndex<u32> {
+        &self.width
+    }
+
+    pub fn height(&self) -> &RangeIndex<u32> {
+        &self.height
+    }
+
+    /// width / height in hundredths.
+    pub fn ratio(&self) -> &RangeIndex<u32> {
+        &self.ratio
+    }
+
+    /// Megapixels in hundredths.
+    pub fn mpixels(&self) -> &RangeIndex<u32> {
+        &self.mpixels
+    }
+
+    fn insert_dimensions(&mut self, id: ID, dimensions: Dimensions) {
+        self.width.insert(id, dimensions.width);
+        self.height.insert(id, dimensions.height);
+        if let Some(ratio) = dimensions.ratio {
+            self.ratio.insert(id, ratio);
+        }
+        self.mpixels.insert(id, dimensions.mpixels);
+    }
+
+    fn remove_dimensions(&mut self, id: ID, dimensions: Dimensions) {
+        self.width.remove(id, dimensions.width);
+        self.height.remove(id, dimensions.height);
+        if let Some(ratio) = dimensions.ratio {
+            self.ratio.remove(id, ratio);
+        }
+        self.mpixels.remove(id, dimensions.mpixels);
+    }
+
+    // ident is one of DIMENSIONS_IDENTIFIERS, text the value after it.
+    fn query_value(&self, ident: &str, text: &str, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let hundredths = |text: &str| {
+            text.parse::<RangeQuery<Hundredths>>()
+                .ok()
+                .map(|query| query.map(|value| value.0))
+        };
+        match ident {
+            "width" => Some(self.width.query(text.parse().ok()?, inverse)),
+            "height" => Some(self.height.query(text.parse().ok()?, inverse)),
+            "ratio" => {
+                let query = match text {
+                    "portrait" => RangeQuery::LT(100),
+                    "landscape" => RangeQuery::GT(100),
+                    "square" => RangeQuery::EQ(100),
+                    text => hundredths(text)?,
+                };
+                Some(self.ratio.query(query, inverse))
+            }
+            "mpixels" => Some(self.mpixels.query(hundredths(text)?, inverse)),
+            _ => None,
+        }
+    }
+}
+
+impl<P: 'static> Index<P> for DimensionsIndex<P> {
+    fn query<'s>(
+        &'s self,
+        ident: Option<&str>,
+        text: &str,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_value(ident?, text, inverse)
+    }
+
+    fn query_term<'s>(
+        &'s self,
+        _context: &IndexContext<'_, P>,
+        term: &Term<'_>,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        let term = term.without_prefixes();
+        self.query_value(term.ident()?, term.value, inverse)
+    }
+
+    fn insert(&mut self, id: ID, post: &P) {
+        if let Some(dimensions) = (self.extract)(post) {
+            self.insert_dimensions(id, Dimensions::new(dimensions));
+        }
+    }
+
+    fn remove(&mut self, id: ID, post: &P) {
+        if let Some(dimensions) = (self.extract)(post) {
+            self.remove_dimensions(id, Dimensions::new(dimensions));
+        }
+    }
+
+    fn update(&mut self, id: ID, old: &P, new: &P) {
+        let old = (self.extract)(old).map(Dimensions::new);
+        let new = (self.extract)(new).map(Dimensions::new);
+        if old == new {
+            return;
+        }
+        if let Some(old) = old {
+            self.remove_dimensions(id, old);
+        }
+        if let Some(new) = new {
+            self.insert_dimensions(id, new);
+        }
+    }
+
+    fn clone_index(&self) -> Option<Box<dyn Index<P>>> {
+        Some(Box::new(self.clone()))
+    }
+}
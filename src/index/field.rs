@@ -0,0 +1,502 @@
+use std::{hash::Hash, str::FromStr};
+
+use crate::{query::Item, Query, Queryable, ID};
+
+use super::{
+    FlagIndex, FlagIndexLoader, FlagQuery, KeyIndex, KeyIndexLoader, KeysIndex, KeysIndexLoader,
+    RangeIndex, RangeIndexLoader, Term, ValueIndex, ValueIndexLoader,
+};
+
+/// An index over a single post field, the building block field_index! wraps.
+pub trait FieldIndex: Clone + Send + Sync + Sized + 'static {
+    type Loader: Send + Sync + 'static;
+    /// What the field getter returns for a post.
+    type Value<'p>;
+
+    fn new_loader() -> Self::Loader;
+
+    fn add(loader: &mut Self::Loader, id: ID, value: Self::Value<'_>);
+
+    /// Safe if other's ids are higher than any id loader has.
+    fn merge(loader: &mut Self::Loader, other: Self::Loader);
+
+    fn load(loader: Self::Loader) -> Self;
+
+    /// None if the term can't be parsed, see Term::without_prefixes.
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>>;
+
+    fn insert(&mut self, id: ID, value: Self::Value<'_>);
+
+    fn remove(&mut self, id: ID, value: Self::Value<'_>);
+
+    fn update(&mut self, id: ID, old: Self::Value<'_>, new: Self::Value<'_>);
+
+    /// See Index::top_terms.
+    fn top_terms(&self, _limit: usize) -> Vec<(String, usize)> {
+        Vec::new()
+    }
+
+    /// See Index::verify.
+    fn verify(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// See Index::verify_post.
+    fn verify_value(&self, _id: ID, _value: Self::Value<'_>) -> bool {
+        true
+    }
+
+    /// See Index::similar, value_of gives the values of other posts.
+    fn similar<'p>(
+        &self,
+        _id: ID,
+        _value: Self::Value<'p>,
+        _value_of: &dyn Fn(ID) -> Option<Self::Value<'p>>,
+        _total: usize,
+        _limit: usize,
+    ) -> Option<Vec<(ID, f32)>> {
+        None
+    }
+}
+
+// A key's ids, `-key` as their complement rather than an inverted term.
+fn single(queryable: Queryable<'_>, inverse: bool) -> Query<Queryable<'_>> {
+    if inverse {
+        Query::new(Item::Single(queryable.complement()), false)
+    } else {
+        Query::new(Item::Single(queryable), false)
+    }
+}
+
+impl<K: Clone + Eq + Hash + FromStr + ToString + Send + Sync + 'static> FieldIndex
+    for KeysIndex<K>
+{
+    type Loader = KeysIndexLoader<K>;
+    type Value<'p> = &'p [K];
+
+    fn new_loader() -> Self::Loader {
+        KeysIndexLoader::new()
+    }
+
+    fn add(loader: &mut Self::Loader, id: ID, value: &[K]) {
+        loader.add(id, value);
+    }
+
+    fn merge(loader: &mut Self::Loader, other: Self::Loader) {
+        loader.merge(other);
+    }
+
+    fn load(loader: Self::Loader) -> Self {
+        loader.load()
+    }
+
+    // `a,b` matches either key unless a key with the comma exists, `"a,b"` only that key.
+    // `a*` matches every key the Glob matches unless the key `a*` exists.
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let key: Option<K> = term.value.parse().ok();
+        if let Some(queryable) = key.and_then(|key| self.get(&key)) {
+            return Some(single(queryable, inverse));
+        }
+        let mut query = if let Some(glob) = term.glob() {
+            let keys = self.keys().filter(|key| glob.is_match(&key.to_string()));
+            self.get_many(keys, false)
+        } else if term.is_list() {
+            let keys = term
+                .items
+                .iter()
+                .map(|key| key.parse().ok())
+                .collect::<Option<Vec<K>>>()?;
+            self.get_many(&keys, false)
+        } else {
+            return None;
+        };
+        if matches!(query.item, Item::Empty) {
+            return None;
+        }
+        query.inverse = inverse;
+        Some(query)
+    }
+
+    fn insert(&mut self, id: ID, value: &[K]) {
+        KeysIndex::insert(self, id, value);
+    }
+
+    fn remove(&mut self, id: ID, value: &[K]) {
+        KeysIndex::remove(self, id, value);
+    }
+
+    fn update(&mut self, id: ID, old: &[K], new: &[K]) {
+        KeysIndex::update(self, id, old, new);
+    }
+
+    fn top_terms(&self, limit: usize) -> Vec<(String, usize)> {
+        self.top(limit)
+            .into_iter()
+            .map(|(key, count)| (key.to_string(), count))
+            .collect()
+    }
+
+    fn verify(&self) -> Vec<String> {
+        KeysIndex::verify(self)
+    }
+
+    fn verify_value(&self, id: ID, value: &[K]) -> bool {
+        value
+            .iter()
+            .all(|key| self.items.get(key).is_some_and(|ids| ids.contains(id)))
+    }
+
+    fn similar<'p>(
+        &self,
+        id: ID,
+        value: &'p [K],
+        value_of: &dyn Fn(ID) -> Option<&'p [K]>,
+        total: usize,
+        limit: usize,
+    ) -> Option<Vec<(ID, f32)>> {
+        Some(KeysIndex::similar(self, id, value, value_of, total, limit))
+    }
+}
+
+impl<K: Clone + Eq + Hash + FromStr + Send + Sync + 'static> FieldIndex for KeyIndex<K> {
+    type Loader = KeyIndexLoader<K>;
+    type Value<'p> = &'p K;
+
+    fn new_loader() -> Self::Loader {
+        KeyIndexLoader::new()
+    }
+
+    fn add(loader: &mut Self::Loader, id: ID, value: &K) {
+        loader.add(id, value);
+    }
+
+    fn merge(loader: &mut Self::Loader, other: Self::Loader) {
+        loader.merge(other);
+    }
+
+    fn load(loader: Self::Loader) -> Self {
+        loader.load()
+    }
+
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let key: K = term.value.parse().ok()?;
+        let queryable = self.get(&key)?;
+        Some(single(queryable, inverse))
+    }
+
+    fn insert(&mut self, id: ID, value: &K) {
+        KeyIndex::insert(self, id, value);
+    }
+
+    fn remove(&mut self, id: ID, value: &K) {
+        KeyIndex::remove(self, id, value);
+    }
+
+    fn update(&mut self, id: ID, old: &K, new: &K) {
+        KeyIndex::update(self, id, old, new);
+    }
+
+    fn verify(&self) -> Vec<String> {
+        KeyIndex::verify(self)
+    }
+
+    fn verify_value(&self, id: ID, value: &K) -> bool {
+        self.items.get(value).is_some_and(|ids| ids.contains(id))
+    }
+}
+
+impl<V: Clone + Eq + Ord + FromStr + Send + Sync + 'static> FieldIndex for RangeIndex<V> {
+    type Loader = RangeIndexLoader<V>;
+    type Value<'p> = V;
+
+    fn new_loader() -> Self::Loader {
+        RangeIndexLoader::new()
+    }
+
+    fn add(loader: &mut Self::Loader, id: ID, value: V) {
+        loader.add(id, value);
+    }
+
+    fn merge(loader: &mut Self::Loader, other: Self::Loader) {
+        loader.merge(other);
+    }
+
+    fn load(loader: Self::Loader) -> Self {
+        loader.load()
+    }
+
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let query = self.parse_query(term.value)?;
+        Some(self.query(query, inverse))
+    }
+
+    fn insert(&mut self, id: ID, value: V) {
+        RangeIndex::insert(self, id, value);
+    }
+
+    fn remove(&mut self, id: ID, value: V) {
+        RangeIndex::remove(self, id, value);
+    }
+
+    fn update(&mut self, id: ID, old: V, new: V) {
+        RangeIndex::update(self, id, old, new);
+    }
+
+    fn verify(&self) -> Vec<String> {
+        RangeIndex::verify(self)
+    }
+
+    fn verify_value(&self, id: ID, value: V) -> bool {
+        self.contains(&value, id)
+    }
+}
+
+impl<V: Clone + Eq + Hash + Ord + FromStr + Send + Sync + 'static> FieldIndex for ValueIndex<V> {
+    type Loader = ValueIndexLoader<V>;
+    type Value<'p> = V;
+
+    fn new_loader() -> Self::Loader {
+        ValueIndexLoader::new()
+    }
+
+    fn add(loader: &mut Self::Loader, id: ID, value: V) {
+        loader.add(id, value);
+    }
+
+    fn merge(loader: &mut Self::Loader, other: Self::Loader) {
+        loader.merge(other);
+    }
+
+    fn load(loader: Self::Loader) -> Self {
+        loader.load()
+    }
+
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let query = self.parse_query(term.value)?;
+        Some(self.query(query, inverse))
+    }
+
+    fn insert(&mut self, id: ID, value: V) {
+        ValueIndex::insert(self, id, value);
+    }
+
+    fn remove(&mut self, id: ID, value: V) {
+        ValueIndex::remove(self, id, value);
+    }
+
+    fn update(&mut self, id: ID, old: V, new: V) {
+        ValueIndex::update(self, id, old, new);
+    }
+
+    fn verify(&self) -> Vec<String> {
+        ValueIndex::verify(self)
+    }
+
+    fn verify_value(&self, id: ID, value: V) -> bool {
+        self.contains(&value, id)
+    }
+}
+
+impl FieldIndex for FlagIndex {
+    type Loader = FlagIndexLoader;
+    type Value<'p> = bool;
+
+    fn new_loader() -> Self::Loader {
+        FlagIndexLoader::new()
+    }
+
+    fn add(loader: &mut Self::Loader, id: ID, value: bool) {
+        loader.add(id, value);
+    }
+
+    fn merge(loader: &mut Self::Loader, other: Self::Loader) {
+        loader.merge(other);
+    }
+
+    fn load(loader: Self::Loader) -> Self {
+        loader.load()
+    }
+
+    fn query_term(&self, term: &Term<'_>, inverse: bool) -> Option<Query<Queryable<'_>>> {
+        let query: FlagQuery = term.value.parse().ok()?;
+        let mut query = self.get(query);
+        query.inverse ^= inverse;
+        Some(query)
+    }
+
+    fn insert(&mut self, id: ID, value: bool) {
+        FlagIndex::insert(self, id, value);
+    }
+
+    fn remove(&mut self, id: ID, value: bool) {
+        FlagIndex::remove(self, id, value);
+    }
+
+    fn update(&mut self, id: ID, old: bool, new: bool) {
+        FlagIndex::update(self, id, old, new);
+    }
+
+    fn verify_value(&self, id: ID, value: bool) -> bool {
+        self.contains(id) == value
+    }
+}
+
+/// Generates an IndexLoader and Index for one field of a post,
+/// the field getter returns the FieldIndex's Value. Indexes over several fields or
+/// with an index per key, like AiTagIndex in examples/ai_tags.rs, implement
+/// IndexLoader and Index themselves.
+///
+/// ```ignore
+/// field_index! {
+///     pub struct TagIndex(TagIndexLoader) for BooruPost: KeysIndex<String> = |post| &post.tags;
+///     pub struct ScoreIndex(ScoreIndexLoader) for BooruPost: RangeIndex<i32> = |post| post.score;
+/// }
+/// ```
+#[macro_export]
+macro_rules! field_index {
+    ($(
+        $(#[$meta:meta])*
+        $vis:vis struct $index:ident($loader:ident) for $post:ty: $inner:ty = |$p:ident| $get:expr;
+    )*) => {$(
+        $vis struct $loader(<$inner as ::booru_db::index::FieldIndex>::Loader);
+
+        #[allow(unused)]
+        impl $loader {
+            pub fn new() -> Self {
+                Self(<$inner as ::booru_db::index::FieldIndex>::new_loader())
+            }
+        }
+
+        impl ::std::default::Default for $loader {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl ::booru_db::index::IndexLoader<$post> for $loader {
+            fn add(&mut self, id: ::booru_db::ID, post: &$post) {
+                let $p = post;
+                <$inner as ::booru_db::index::FieldIndex>::add(&mut self.0, id, $get);
+            }
+
+            fn fork(
+                &self,
+            ) -> ::std::option::Option<::std::boxed::Box<dyn ::booru_db::index::IndexLoader<$post>>>
+            {
+                ::std::option::Option::Some(::std::boxed::Box::new(Self::new()))
+            }
+
+            fn merge(
+                &mut self,
+                other: ::std::boxed::Box<dyn ::booru_db::index::IndexLoader<$post>>,
+            ) -> ::std::io::Result<()> {
+                let other = other.into_any().downcast::<Self>().unwrap();
+                <$inner as ::booru_db::index::FieldIndex>::merge(&mut self.0, other.0);
+                ::std::result::Result::Ok(())
+            }
+
+            fn load(
+                self: ::std::boxed::Box<Self>,
+            ) -> ::std::boxed::Box<dyn ::booru_db::index::Index<$post>> {
+                let inner = <$inner as ::booru_db::index::FieldIndex>::load(self.0);
+                ::std::boxed::Box::new($index(inner))
+            }
+        }
+
+        $(#[$meta])*
+        $vis struct $index(pub $inner);
+
+        #[allow(unused)]
+        impl $index {
+            pub fn loader() -> $loader {
+                $loader::new()
+            }
+        }
+
+        impl ::booru_db::index::Index<$post> for $index {
+            fn query<'s>(
+                &'s self,
+                _ident: ::std::option::Option<&str>,
+                text: &str,
+                inverse: bool,
+            ) -> ::std::option::Option<::booru_db::Query<::booru_db::Queryable<'s>>> {
+                let term = ::booru_db::index::Term::parse(::std::option::Option::None, text);
+                ::booru_db::index::FieldIndex::query_term(&self.0, &term, inverse)
+            }
+
+            fn query_term<'s>(
+                &'s self,
+                _context: &::booru_db::IndexContext<'_, $post>,
+                term: &::booru_db::index::Term<'_>,
+                inverse: bool,
+            ) -> ::std::option::Option<::booru_db::Query<::booru_db::Queryable<'s>>> {
+                let term = term.without_prefixes();
+                ::booru_db::index::FieldIndex::query_term(&self.0, &term, inverse)
+            }
+
+            fn insert(&mut self, id: ::booru_db::ID, post: &$post) {
+                let $p = post;
+                ::booru_db::index::FieldIndex::insert(&mut self.0, id, $get);
+            }
+
+            fn remove(&mut self, id: ::booru_db::ID, post: &$post) {
+                let $p = post;
+                ::booru_db::index::FieldIndex::remove(&mut self.0, id, $get);
+            }
+
+            fn update(&mut self, id: ::booru_db::ID, old: &$post, new: &$post) {
+                let old = {
+                    let $p = old;
+                    $get
+                };
+                let new = {
+                    let $p = new;
+                    $get
+                };
+                ::booru_db::index::FieldIndex::update(&mut self.0, id, old, new);
+            }
+
+            fn top_terms(&self, limit: usize) -> ::std::vec::Vec<(::std::string::String, usize)> {
+                ::booru_db::index::FieldIndex::top_terms(&self.0, limit)
+            }
+
+            fn verify(&self) -> ::std::vec::Vec<::std::string::String> {
+                ::booru_db::index::FieldIndex::verify(&self.0)
+            }
+
+            fn verify_post(&self, id: ::booru_db::ID, post: &$post) -> bool {
+                let $p = post;
+                ::booru_db::index::FieldIndex::verify_value(&self.0, id, $get)
+            }
+
+            fn clone_index(
+                &self,
+            ) -> ::std::option::Option<::std::boxed::Box<dyn ::booru_db::index::Index<$post>>> {
+                ::std::option::Option::Some(::std::boxed::Box::new($index(self.0.clone())))
+            }
+
+            // Reads the values of posts from the Db's store.
+            fn similar(
+                &self,
+                context: &::booru_db::IndexContext<'_, $post>,
+                id: ::booru_db::ID,
+                limit: usize,
+            ) -> ::std::option::Option<::std::vec::Vec<(::booru_db::ID, f32)>> {
+                fn get(post: &$post) -> <$inner as ::booru_db::index::FieldIndex>::Value<'_> {
+                    let $p = post;
+                    $get
+                }
+                let value_of = |id: ::booru_db::ID| context.post(id).map(get);
+                let value = value_of(id)?;
+                ::booru_db::index::FieldIndex::similar(
+                    &self.0,
+                    id,
+                    value,
+                    &value_of,
+                    context.len(),
+                    limit,
+                )
+            }
+        }
+    )*};
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::{
+    query::{Item, Queryable},
+    Query, ID,
+};
+
+use super::{KeyIndex, KeyIndexLoader};
+
+/// Extension aliases and the groups `filetype:video` expands to.
+#[derive(Clone, Debug)]
+pub struct FileTypes {
+    aliases: HashMap<String, String>,
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl Default for FileTypes {
+    fn default() -> Self {
+        Self::new()
+            .with_alias("jpeg", "jpg")
+            .with_alias("jpe", "jpg")
+            .with_alias("jfif", "jpg")
+            .with_alias("tif", "tiff")
+            .with_alias("m4v", "mp4")
+            .with_group(
+                "image",
+                ["jpg", "png", "gif", "webp", "avif", "bmp", "tiff"],
+            )
+            .with_group("video", ["mp4", "webm", "mkv", "mov", "avi"])
+            .with_group("audio", ["mp3", "ogg", "wav", "flac", "m4a"])
+            .with_group("archive", ["zip", "rar", "7z"])
+    }
+}
+
+impl FileTypes {
+    /// No aliases or groups, see Default for the common ones.
+    pub fn new() -> Self {
+        Self {
+            aliases: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn with_alias(mut self, alias: &str, ext: &str) -> Self {
+        let ext = self.canonical(ext);
+        self.aliases.insert(normalize(alias), ext);
+        self
+    }
+
+    /// Replaces any group with the same name.
+    pub fn with_group<'a>(mut self, name: &str, exts: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut exts: Vec<String> = exts.into_iter().map(|ext| self.canonical(ext)).collect();
+        exts.sort_unstable();
+        exts.dedup();
+        self.groups.insert(normalize(name), exts);
+        self
+    }
+
+    /// Lowercase without a leading dot, with aliases resolved.
+    pub fn canonical(&self, ext: &str) -> String {
+        let ext = normalize(ext);
+        match self.aliases.get(&ext) {
+            Some(canonical) => canonical.clone(),
+            None => ext,
+        }
+    }
+
+    pub fn group(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(&normalize(name)).map(Vec::as_slice)
+    }
+}
+
+fn normalize(ext: &str) -> String {
+    ext.trim().trim_start_matches('.').to_lowercase()
+}
+
+#[derive(Default)]
+pub struct FileTypeIndexLoader {
+    types: FileTypes,
+    keys: KeyIndexLoader<String>,
+}
+
+impl FileTypeIndexLoader {
+    /// Uses FileTypes::default.
+    pub fn new() -> Self {
+        Self::with_types(FileTypes::default())
+    }
+
+    pub fn with_types(types: FileTypes) -> Self {
+        Self {
+            types,
+            keys: KeyIndexLoader::new(),
+        }
+    }
+
+    /// ext is canonicalized, `.JPEG` is stored as `jpg`.
+    pub fn add(&mut self, id: ID, ext: &str) {
+        self.keys.add(id, &self.types.canonical(ext));
+    }
+
+    /// Safe if other's ids are higher than any id self has.
+    pub fn merge(&mut self, other: Self) {
+        self.keys.merge(other.keys);
+    }
+
+    pub fn load(self) -> FileTypeIndex {
+        FileTypeIndex {
+            types: self.types,
+            keys: self.keys.load(),
+        }
+    }
+}
+
+/// File extensions for `filetype:jpg` terms, aliases like `jpeg` resolve to `jpg`
+/// and group names like `video` match any extension in the group.
+pub struct FileTypeIndex {
+    types: FileTypes,
+    keys: KeyIndex<String>,
+}
+
+impl FileTypeIndex {
+    pub fn loader() -> FileTypeIndexLoader {
+        FileTypeIndexLoader::new()
+    }
+
+    pub fn types(&self) -> &FileTypes {
+        &self.types
+    }
+
+    pub fn keys(&self) -> &KeyIndex<String> {
+        &self.keys
+    }
+
+    /// An extension, alias or group name, unknown extensions match nothing.
+    pub fn get(&self, value: &str) -> Query<Queryable<'_>> {
+        if let Some(exts) = self.types.group(value) {
+            return self.keys.get_many(exts.iter().map(String::as_str), false);
+        }
+        let item = match self.keys.get(&self.types.canonical(value)) {
+            Some(queryable) => Item::Single(queryable),
+            None => Item::Empty,
+        };
+        Query::new(item, false)
+    }
+
+    pub fn insert(&mut self, id: ID, ext: &str) {
+        self.keys.insert(id, &self.types.canonical(ext));
+    }
+
+    pub fn remove(&mut self, id: ID, ext: &str) {
+        self.keys.remove(id, &self.types.canonical(ext));
+    }
+
+    pub fn update(&mut self, id: ID, old: &str, new: &str) {
+        self.keys
+            .update(id, &self.types.canonical(old), &self.types.canonical(new));
+    }
+}
@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+use crate::{
+    query::{Item, Queryable, QueryableOwned},
+    Query, ID,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlagQuery {
+    True,
+    False,
+    Any,
+}
+
+impl FromStr for FlagQuery {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "true" | "yes" => Ok(Self::True),
+            "false" | "no" => Ok(Self::False),
+            "any" => Ok(Self::Any),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FlagIndexLoader {
+    set: QueryableOwned,
+}
+
+impl FlagIndexLoader {
+    pub fn new() -> Self {
+        Self {
+            set: QueryableOwned::default(),
+        }
+    }
+
+    pub fn add(&mut self, id: ID, value: bool) {
+        if value {
+            self.set.insert_unchecked(id);
+        }
+    }
+
+    /// Safe if other's ids are higher than any id self has.
+    pub fn merge(&mut self, other: Self) {
+        self.set.extend_unchecked(other.set);
+    }
+
+    pub fn load(mut self) -> FlagIndex {
+        self.set.check_and_convert();
+        FlagIndex { set: self.set }
+    }
+}
+
+/// Only ids with a true value are stored.
+#[derive(Clone, Default)]
+pub struct FlagIndex {
+    set: QueryableOwned,
+}
+
+impl FlagIndex {
+    pub fn loader() -> FlagIndexLoader {
+        FlagIndexLoader::new()
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, id: ID) -> bool {
+        self.set.contains(id)
+    }
+
+    /// Number of ids with a true value.
+    #[inline(always)]
+    pub fn matched(&self) -> usize {
+        self.set.matched()
+    }
+
+    /// False is returned as the inverse of the true set so the complement
+    /// is only computed against base_checks when the query runs.
+    pub fn get(&self, query: FlagQuery) -> Query<Queryable<'_>> {
+        match query {
+            FlagQuery::True => Query::new(Item::Single((&self.set).into()), false),
+            FlagQuery::False => Query::new(Item::Single((&self.set).into()), true),
+            FlagQuery::Any => Query::all(),
+        }
+    }
+
+    pub fn insert(&mut self, id: ID, value: bool) {
+        if value {
+            self.set.insert(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: ID, value: bool) {
+        if value {
+            self.set.remove(id);
+        }
+    }
+
+    pub fn update(&mut self, id: ID, old: bool, new: bool) {
+        if old == new {
+            return;
+        }
+        self.remove(id, old);
+        self.insert(id, new);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    query::{util::to_ids, Item, Queryable},
+    IndexContext, Query, QueryResult, ID,
+};
+
+use super::{Index, IndexLoader, Term};
+
+type Extractor<P, E> = Arc<dyn Fn(&P) -> E + Send + Sync>;
+
+pub struct IdMapLoader<P, E> {
+    extract: Extractor<P, E>,
+    to_external: Vec<Option<E>>,
+    to_internal: HashMap<E, ID>,
+}
+
+impl<P, E: Copy + Eq + Hash> IdMapLoader<P, E> {
+    pub fn new(extract: impl Fn(&P) -> E + Send + Sync + 'static) -> Self {
+        Self::with_extractor(Arc::new(extract))
+    }
+
+    fn with_extractor(extract: Extractor<P, E>) -> Self {
+        Self {
+            extract,
+            to_external: Vec::new(),
+            to_internal: HashMap::new(),
+        }
+    }
+}
+
+impl<P: 'static, E: Copy + Eq + Ord + Hash + FromStr + Send + Sync + 'static> IndexLoader<P>
+    for IdMapLoader<P, E>
+{
+    fn add(&mut self, id: ID, post: &P) {
+        let external = (self.extract)(post);
+        set(&mut self.to_external, id, Some(external));
+        self.to_internal.insert(external, id);
+    }
+
+    fn load(self: Box<Self>) -> Box<dyn Index<P>> {
+        Box::new(IdMap {
+            extract: self.extract,
+            to_external: self.to_external,
+            to_internal: self.to_internal,
+            order: OnceLock::new(),
+        })
+    }
+
+    fn fork(&self) -> Option<Box<dyn IndexLoader<P>>> {
+        Some(Box::new(Self::with_extractor(self.extract.clone())))
+    }
+
+    fn merge(&mut self, other: Box<dyn IndexLoader<P>>) -> io::Result<()> {
+        let other = other.into_any().downcast::<Self>().unwrap();
+        for (id, external) in other.to_external.into_iter().enumerate() {
+            if external.is_some() {
+                set(&mut self.to_external, id as ID, external);
+            }
+        }
+        self.to_internal.extend(other.to_internal);
+        Ok(())
+    }
+}
+
+fn set<E: Copy>(to_external: &mut Vec<Option<E>>, id: ID, external: Option<E>) {
+    let index = id as usize;
+    if index >= to_external.len() {
+        to_external.resize(index + 1, None);
+    }
+    to_external[index] = external;
+}
+
+/// Converts between internal ids and the ids posts have outside the db,
+/// `id:123` or `id:123,456` match posts by their external id.
+pub struct IdMap<P, E> {
+    extract: Extractor<P, E>,
+    to_external: Vec<Option<E>>,
+    to_internal: HashMap<E, ID>,
+    // Internal ids by external id, built on first use and dropped by writes.
+    order: OnceLock<Vec<ID>>,
+}
+
+// Derive would require P: Clone.
+impl<P, E: Clone> Clone for IdMap<P, E> {
+    fn clone(&self) -> Self {
+        Self {
+            extract: self.extract.clone(),
+            to_external: self.to_external.clone(),
+            to_internal: self.to_internal.clone(),
+            order: self.order.clone(),
+        }
+    }
+}
+
+impl<P, E: Copy + Eq + Hash> IdMap<P, E> {
+    pub fn loader(extract: impl Fn(&P) -> E + Send + Sync + 'static) -> IdMapLoader<P, E> {
+        IdMapLoader::new(extract)
+    }
+
+    pub fn external(&self, id: ID) -> Option<E> {
+        self.to_external.get(id as usize).copied().flatten()
+    }
+
+    pub fn internal(&self, external: &E) -> Option<ID> {
+        self.to_internal.get(external).copied()
+    }
+
+    /// External ids in the same order, ids without one are skipped.
+    pub fn to_external(&self, ids: &[ID]) -> Vec<E> {
+        ids.iter().filter_map(|&id| self.external(id)).collect()
+    }
+
+    /// Internal ids in the same order, unknown external ids are skipped.
+    pub fn to_internal(&self, external: &[E]) -> Vec<ID> {
+        external.iter().filter_map(|e| self.internal(e)).collect()
+    }
+
+    /// External ids of every match in result, ordered by internal id.
+    pub fn result_to_external(&self, result: &QueryResult) -> Vec<E> {
+        self.to_external(&to_ids(result.checks()))
+    }
+
+    /// A Queryable matching the posts with these external ids.
+    pub fn get(&self, external: &[E]) -> Queryable<'_> {
+        let mut ids = self.to_internal(external);
+        ids.sort_unstable();
+        ids.dedup();
+        Queryable::IDsOwned(ids)
+    }
+
+    /// Internal ids ordered by external id, for sorting by post id when internal ids
+    /// were assigned in another order. Rebuilt on the first call after a write.
+    pub fn order(&self) -> &[ID]
+    where
+        E: Ord,
+    {
+        self.order.get_or_init(|| {
+            let mut order: Vec<(E, ID)> = self
+                .to_internal
+                .iter()
+                .map(|(&external, &id)| (external, id))
+                .collect();
+            order.sort_unstable();
+            order.into_iter().map(|(_, id)| id).collect()
+        })
+    }
+
+    fn insert_id(&mut self, id: ID, external: E) {
+        self.order.take();
+        set(&mut self.to_external, id, Some(external));
+        self.to_internal.insert(external, id);
+    }
+
+    // A comma list of external ids.
+    fn query_items(&self, items: &[&str], inverse: bool) -> Option<Query<Queryable<'_>>>
+    where
+        E: FromStr,
+    {
+        let external = items
+            .iter()
+            .map(|e| e.parse().ok())
+            .collect::<Option<Vec<E>>>()?;
+        Some(Query::new(Item::Single(self.get(&external)), inverse))
+    }
+
+    fn remove_id(&mut self, id: ID, external: E) {
+        self.order.take();
+        set(&mut self.to_external, id, None);
+        if self.to_internal.get(&external) == Some(&id) {
+            self.to_internal.remove(&external);
+        }
+    }
+}
+
+impl<P: 'static, E: Copy + Eq + Ord + Hash + FromStr + Send + Sync + 'static> Index<P>
+    for IdMap<P, E>
+{
+    fn query<'s>(
+        &'s self,
+        _ident: Option<&str>,
+        text: &str,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_items(&Term::parse(None, text).items, inverse)
+    }
+
+    fn query_term<'s>(
+        &'s self,
+        _context: &IndexContext<'_, P>,
+        term: &Term<'_>,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_items(&term.without_prefixes().items, inverse)
+    }
+
+    fn insert(&mut self, id: ID, post: &P) {
+        self.insert_id(id, (self.extract)(post));
+    }
+
+    fn remove(&mut self, id: ID, post: &P) {
+        self.remove_id(id, (self.extract)(post));
+    }
+
+    fn update(&mut self, id: ID, old: &P, new: &P) {
+        let (old, new) = ((self.extract)(old), (self.extract)(new));
+        if old == new {
+            return;
+        }
+        self.remove_id(id, old);
+        self.insert_id(id, new);
+    }
+
+    fn order(&self) -> Option<&[ID]> {
+        Some(IdMap::order(self))
+    }
+
+    fn clone_index(&self) -> Option<Box<dyn Index<P>>> {
+        Some(Box::new(self.clone()))
+    }
+}
@@ -1,8 +1,10 @@
-use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, io, ops::RangeBounds};
 
 use crate::{
+    index::keys::{chain, verify_items},
+    persist::{read_key_delta, write_key_delta, PersistKey, SectionReader, SnapshotWriter},
     query::{Queryable, QueryableOwned},
-    ID,
+    Query, QueryResult, ID,
 };
 
 #[derive(Default)]
@@ -25,6 +27,17 @@ impl<K: Clone + Eq + Hash> KeyIndexLoader<K> {
         queryable.insert_unchecked(id);
     }
 
+    /// Safe if other's ids are higher than any id self has.
+    pub fn merge(&mut self, other: Self) {
+        for (key, queryable) in other.items {
+            if let Some(self_queryable) = self.items.get_mut(&key) {
+                self_queryable.extend_unchecked(queryable);
+            } else {
+                self.items.insert(key, queryable);
+            }
+        }
+    }
+
     pub fn load(mut self) -> KeyIndex<K> {
         for queryable in self.items.values_mut() {
             queryable.check_and_convert();
@@ -33,7 +46,7 @@ impl<K: Clone + Eq + Hash> KeyIndexLoader<K> {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct KeyIndex<K: Eq + Hash> {
     pub items: HashMap<K, QueryableOwned>,
 }
@@ -44,14 +57,97 @@ impl<K: Clone + Eq + Hash> KeyIndex<K> {
     }
 
     #[inline(always)]
-    pub fn get<'i, Q: ?Sized>(&'i self, k: &Q) -> Option<Queryable<'i>>
+    pub fn get<'i, Q>(&'i self, k: &Q) -> Option<Queryable<'i>>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.items.get(k).map(|queryable| queryable.into())
     }
 
+    /// Ids with any of keys, see KeysIndex::get_many.
+    pub fn get_many<'i, 'q, Q>(
+        &'i self,
+        keys: impl IntoIterator<Item = &'q Q>,
+        and: bool,
+    ) -> Query<Queryable<'i>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq + 'q,
+    {
+        chain(keys.into_iter().map(|key| self.get(key)), and)
+    }
+
+    /// Ids with a key in range, such as ratings or days, as an OrChain of each key.
+    /// Scans every key, fine for the few keys a KeyIndex should have.
+    pub fn get_range(&self, range: impl RangeBounds<K>) -> Query<Queryable<'_>>
+    where
+        K: Ord,
+    {
+        let mut keys: Vec<&K> = self
+            .items
+            .keys()
+            .filter(|key| range.contains(key))
+            .collect();
+        keys.sort_unstable();
+        chain(keys.into_iter().map(|key| self.get(key)), false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.items.keys()
+    }
+
+    /// Every key with its ids, in no particular order.
+    pub fn iter(&self) -> <&Self as IntoIterator>::IntoIter {
+        self.into_iter()
+    }
+
+    /// Every key with the number of ids it matches, in no particular order.
+    pub fn counts(&self) -> impl Iterator<Item = (&K, usize)> {
+        self.items
+            .iter()
+            .map(|(key, queryable)| (key, queryable.matched()))
+    }
+
+    /// Keys starting with prefix with their counts, in no particular order.
+    /// Scans every key.
+    pub fn keys_with_prefix<'i>(
+        &'i self,
+        prefix: &'i str,
+    ) -> impl Iterator<Item = (&'i K, usize)> + 'i
+    where
+        K: AsRef<str>,
+    {
+        self.counts()
+            .filter(move |(key, _)| key.as_ref().starts_with(prefix))
+    }
+
+    /// Match counts per key among ids in result, keys without matches are skipped.
+    /// Sorted by count descending or ascending if reverse.
+    pub fn group_by(&self, result: &QueryResult, limit: usize, reverse: bool) -> Vec<(&K, usize)> {
+        let mut groups: Vec<(&K, usize)> = self
+            .items
+            .iter()
+            .map(|(key, queryable)| (key, queryable.matched_in(result.checks())))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        if reverse {
+            groups.sort_by_key(|(_, count)| *count);
+        } else {
+            groups.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        }
+        groups.truncate(limit);
+        groups
+    }
+
     pub fn insert(&mut self, id: ID, key: &K) {
         if !self.items.contains_key(key) {
             self.items.insert(key.clone(), QueryableOwned::default());
@@ -76,4 +172,69 @@ impl<K: Clone + Eq + Hash> KeyIndex<K> {
         self.remove(id, old);
         self.insert(id, new);
     }
+
+    /// See Index::verify.
+    pub fn verify(&self) -> Vec<String> {
+        verify_items(self.items.values())
+    }
+}
+
+impl<K: Eq + Hash + PersistKey> KeyIndex<K> {
+    /// Writes the index to the current section, open it with MappedKeysIndex.
+    pub fn save(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u64(self.items.len() as u64)?;
+        for (key, queryable) in &self.items {
+            key.write_key(w)?;
+            w.write_queryable(queryable)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the keys of ids to the current section, read back by apply_delta.
+    pub fn save_delta(&self, ids: &[ID], w: &mut SnapshotWriter) -> io::Result<()> {
+        write_key_delta(w, self.items.iter(), ids)
+    }
+
+    /// Replaces the keys of ids with those written by save_delta.
+    pub fn apply_delta(&mut self, ids: &[ID], r: &mut SectionReader) -> io::Result<()>
+    where
+        K: Clone,
+    {
+        for queryable in self.items.values_mut() {
+            for id in queryable.filter_ids(ids) {
+                queryable.remove(id);
+            }
+        }
+        self.items.retain(|_, queryable| queryable.matched() > 0);
+        for (key, ids) in read_key_delta::<K>(r)? {
+            for id in ids {
+                self.insert(id, &key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash> IntoIterator for KeyIndex<K> {
+    type Item = (K, QueryableOwned);
+    type IntoIter = <HashMap<K, QueryableOwned> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'i, K: Eq + Hash> IntoIterator for &'i KeyIndex<K> {
+    type Item = (&'i K, Queryable<'i>);
+    #[allow(clippy::type_complexity)]
+    type IntoIter = std::iter::Map<
+        <&'i HashMap<K, QueryableOwned> as IntoIterator>::IntoIter,
+        fn((&'i K, &'i QueryableOwned)) -> (&'i K, Queryable<'i>),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items
+            .iter()
+            .map(|(key, queryable)| (key, Queryable::from(queryable)))
+    }
 }
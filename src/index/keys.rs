@@ -1,8 +1,10 @@
-use std::{borrow::Borrow, hash::Hash};
+use std::{borrow::Borrow, hash::Hash, io};
 
 use crate::{
-    query::{Queryable, QueryableOwned},
-    ID,
+    packed,
+    persist::{read_key_delta, write_key_delta, PersistKey, SectionReader, SnapshotWriter},
+    query::{Item, Queryable, QueryableOwned},
+    Packed, Query, QueryResult, ID,
 };
 
 #[derive(Default)]
@@ -27,6 +29,17 @@ impl<'k, K: Clone + Eq + Hash + 'k> KeysIndexLoader<K> {
         }
     }
 
+    /// Safe if other's ids are higher than any id self has.
+    pub fn merge(&mut self, other: Self) {
+        for (key, queryable) in other.items {
+            if let Some(self_queryable) = self.items.get_mut(&key) {
+                self_queryable.extend_unchecked(queryable);
+            } else {
+                self.items.insert(key, queryable);
+            }
+        }
+    }
+
     pub fn load(mut self) -> KeysIndex<K> {
         for queryable in self.items.values_mut() {
             queryable.check_and_convert();
@@ -35,6 +48,7 @@ impl<'k, K: Clone + Eq + Hash + 'k> KeysIndexLoader<K> {
     }
 }
 
+#[derive(Clone)]
 pub struct KeysIndex<K: Eq + Hash> {
     pub items: fxhash::FxHashMap<K, QueryableOwned>,
 }
@@ -45,23 +59,150 @@ impl<'k, K: Clone + Eq + Hash + 'k> KeysIndex<K> {
     }
 
     #[inline(always)]
-    pub fn get<'i, Q: ?Sized>(&'i self, k: &Q) -> Option<Queryable<'i>>
+    pub fn get<'i, Q>(&'i self, k: &Q) -> Option<Queryable<'i>>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.items.get(k).map(|queryable| queryable.into())
     }
 
+    /// Ids with any of keys, or every key if and. Missing keys match nothing.
+    /// Used for comma lists, aliases and wildcards that expand to many keys.
+    pub fn get_many<'i, 'q, Q>(
+        &'i self,
+        keys: impl IntoIterator<Item = &'q Q>,
+        and: bool,
+    ) -> Query<Queryable<'i>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq + 'q,
+    {
+        chain(keys.into_iter().map(|key| self.get(key)), and)
+    }
+
     #[inline(always)]
-    pub fn matched<Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    pub fn matched<Q>(&self, k: &Q) -> Option<usize>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         self.items.get(k).map(|queryable| queryable.matched())
     }
 
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.items.keys()
+    }
+
+    /// Every key with its ids, in no particular order.
+    pub fn iter(&self) -> <&Self as IntoIterator>::IntoIter {
+        self.into_iter()
+    }
+
+    /// Every key with the number of ids it matches, in no particular order.
+    pub fn counts(&self) -> impl Iterator<Item = (&K, usize)> {
+        self.items
+            .iter()
+            .map(|(key, queryable)| (key, queryable.matched()))
+    }
+
+    /// Keys starting with prefix with their counts, in no particular order.
+    /// Scans every key.
+    pub fn keys_with_prefix<'i>(
+        &'i self,
+        prefix: &'i str,
+    ) -> impl Iterator<Item = (&'i K, usize)> + 'i
+    where
+        K: AsRef<str>,
+    {
+        self.counts()
+            .filter(move |(key, _)| key.as_ref().starts_with(prefix))
+    }
+
+    /// The keys matching the most ids with their counts, most first.
+    pub fn top(&self, limit: usize) -> Vec<(&K, usize)> {
+        let mut top: Vec<(&K, usize)> = self.counts().collect();
+        top.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        top.truncate(limit);
+        top
+    }
+
+    /// Match counts per key among ids in result, keys without matches are skipped.
+    /// Sorted by count descending or ascending if reverse.
+    pub fn group_by(&self, result: &QueryResult, limit: usize, reverse: bool) -> Vec<(&K, usize)> {
+        let mut groups: Vec<(&K, usize)> = self
+            .items
+            .iter()
+            .map(|(key, queryable)| (key, queryable.matched_in(result.checks())))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        if reverse {
+            groups.sort_by_key(|(_, count)| *count);
+        } else {
+            groups.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        }
+        groups.truncate(limit);
+        groups
+    }
+
+    /// Posts sharing keys with id scored by IDF weighted Jaccard similarity, best first.
+    /// keys are the keys of id and keys_of gives those of other posts, such as from a
+    /// PostStore, so only the ids of id's keys are read. total is the number of posts,
+    /// used for IDF. Candidates are the posts with the highest weighted overlap, only those
+    /// get a full score. Candidates keys_of returns None for are skipped.
+    pub fn similar<'p, I: IntoIterator<Item = &'p K>>(
+        &self,
+        id: ID,
+        keys: I,
+        keys_of: impl Fn(ID) -> Option<I>,
+        total: usize,
+        limit: usize,
+    ) -> Vec<(ID, f32)>
+    where
+        K: 'p,
+    {
+        let idf = |key: &K| {
+            let matched = self.items.get(key).map_or(0, |ids| ids.matched());
+            (1.0 + total.max(1) as f32 / matched.max(1) as f32).ln()
+        };
+        let mut weight = 0.0;
+        let mut overlap: fxhash::FxHashMap<ID, f32> = fxhash::FxHashMap::default();
+        for key in keys {
+            let Some(queryable) = self.items.get(key) else {
+                continue;
+            };
+            let key_weight = idf(key);
+            weight += key_weight;
+            for_each_id(queryable, |other| {
+                if other != id {
+                    *overlap.entry(other).or_default() += key_weight;
+                }
+            });
+        }
+        let mut candidates: Vec<(ID, f32)> = overlap.into_iter().collect();
+        candidates.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates.truncate(limit.saturating_mul(4));
+
+        let mut scored: Vec<(ID, f32)> = candidates
+            .into_iter()
+            .filter_map(|(other, overlap)| {
+                let other_weight: f32 = keys_of(other)?.into_iter().map(idf).sum();
+                Some((other, overlap / (weight + other_weight - overlap)))
+            })
+            .collect();
+        scored.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        scored
+    }
+
     pub fn insert(&mut self, id: ID, keys: impl IntoIterator<Item = &'k K>) {
         for key in keys.into_iter() {
             if !self.items.contains_key(key) {
@@ -83,6 +224,34 @@ impl<'k, K: Clone + Eq + Hash + 'k> KeysIndex<K> {
         }
     }
 
+    /// Adds key to every id in checks, such as the matches of a query,
+    /// in one pass instead of one insert per id.
+    pub fn add_to(&mut self, key: K, checks: &[Packed]) {
+        if checks.iter().all(|check| *check == 0) {
+            return;
+        }
+        self.items.entry(key).or_default().union(checks);
+    }
+
+    /// Removes key from every id in checks, see add_to.
+    pub fn remove_from<Q>(&mut self, key: &Q, checks: &[Packed])
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some(queryable) = self.items.get_mut(key) {
+            queryable.difference(checks);
+            if queryable.matched() == 0 {
+                self.items.remove(key);
+            }
+        }
+    }
+
+    /// See Index::verify.
+    pub fn verify(&self) -> Vec<String> {
+        verify_items(self.items.values())
+    }
+
     pub fn update(&mut self, id: ID, old: &[K], new: &[K]) {
         if old == new {
             return;
@@ -93,3 +262,110 @@ impl<'k, K: Clone + Eq + Hash + 'k> KeysIndex<K> {
         self.insert(id, new.difference(&old).copied());
     }
 }
+
+impl<K: Eq + Hash + PersistKey> KeysIndex<K> {
+    /// Writes the index to the current section, open it with MappedKeysIndex.
+    pub fn save(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u64(self.items.len() as u64)?;
+        for (key, queryable) in &self.items {
+            key.write_key(w)?;
+            w.write_queryable(queryable)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the keys of ids to the current section, read back by apply_delta.
+    pub fn save_delta(&self, ids: &[ID], w: &mut SnapshotWriter) -> io::Result<()> {
+        write_key_delta(w, self.items.iter(), ids)
+    }
+
+    /// Replaces the keys of ids with those written by save_delta.
+    pub fn apply_delta(&mut self, ids: &[ID], r: &mut SectionReader) -> io::Result<()>
+    where
+        K: Clone,
+    {
+        for queryable in self.items.values_mut() {
+            for id in queryable.filter_ids(ids) {
+                queryable.remove(id);
+            }
+        }
+        self.items.retain(|_, queryable| queryable.matched() > 0);
+        for (key, ids) in read_key_delta::<K>(r)? {
+            for id in ids {
+                KeysIndex::insert(self, id, [&key]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash> IntoIterator for KeysIndex<K> {
+    type Item = (K, QueryableOwned);
+    type IntoIter = <fxhash::FxHashMap<K, QueryableOwned> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'i, K: Eq + Hash> IntoIterator for &'i KeysIndex<K> {
+    type Item = (&'i K, Queryable<'i>);
+    #[allow(clippy::type_complexity)]
+    type IntoIter = std::iter::Map<
+        <&'i fxhash::FxHashMap<K, QueryableOwned> as IntoIterator>::IntoIter,
+        fn((&'i K, &'i QueryableOwned)) -> (&'i K, Queryable<'i>),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items
+            .iter()
+            .map(|(key, queryable)| (key, Queryable::from(queryable)))
+    }
+}
+
+/// An OrChain or AndChain over queryables with None for missing keys, sized up front.
+pub(crate) fn chain<'i>(
+    queryables: impl Iterator<Item = Option<Queryable<'i>>>,
+    and: bool,
+) -> Query<Queryable<'i>> {
+    let mut items = Vec::with_capacity(queryables.size_hint().0);
+    for queryable in queryables {
+        match queryable {
+            Some(queryable) => items.push(Query::new(Item::Single(queryable), false)),
+            None if and => return Query::new(Item::Empty, false),
+            None => {}
+        }
+    }
+    let item = match items.len() {
+        0 if and => Item::All,
+        0 => Item::Empty,
+        1 => items.pop().unwrap().item,
+        _ if and => Item::AndChain(items),
+        _ => Item::OrChain(items),
+    };
+    Query::new(item, false)
+}
+
+// Removing a key's last id removes the key.
+pub(crate) fn verify_items<'i>(items: impl Iterator<Item = &'i QueryableOwned>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for queryable in items {
+        if let Some(problem) = queryable.verify() {
+            problems.push(format!("key ids: {problem}"));
+        } else if queryable.matched() == 0 {
+            problems.push("key with no ids".to_string());
+        }
+    }
+    problems
+}
+
+fn for_each_id(queryable: &QueryableOwned, mut f: impl FnMut(ID)) {
+    match queryable {
+        QueryableOwned::IDs { ids } => ids.iter().copied().for_each(f),
+        QueryableOwned::Checks { checks, .. } => {
+            for (index, &check) in checks.iter().enumerate() {
+                packed::ids(index, check).for_each(&mut f);
+            }
+        }
+    }
+}
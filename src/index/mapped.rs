@@ -0,0 +1,271 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, io, marker::PhantomData};
+
+use crate::{
+    persist::{PersistKey, PersistValue, Section, StoredQueryable},
+    query::{Item, Queryable},
+    Query, RangeQuery, ID,
+};
+
+/// Read only KeysIndex or KeyIndex borrowing its ids from a Snapshot section.
+pub struct MappedKeysIndex<K> {
+    section: Section,
+    items: HashMap<K, StoredQueryable>,
+}
+
+impl<K: PersistKey + Eq + Hash> MappedKeysIndex<K> {
+    /// Opens a section written by KeysIndex::save or KeyIndex::save.
+    pub fn open(section: Section) -> io::Result<Self> {
+        let mut reader = section.reader();
+        // a key, the queryable kind and its len and matched.
+        let count = reader.read_len(18)?;
+        let mut items = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let key = K::read_key(&mut reader)?;
+            let stored = reader.read_queryable()?;
+            items.insert(key, stored);
+        }
+        Ok(Self { section, items })
+    }
+
+    #[inline(always)]
+    pub fn get<Q>(&self, k: &Q) -> Option<Queryable<'_>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items
+            .get(k)
+            .map(|stored| self.section.queryable(stored))
+    }
+
+    #[inline(always)]
+    pub fn matched<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items.get(k).map(|stored| stored.matched())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.items.keys()
+    }
+}
+
+/// Read only RangeIndex borrowing its ids and values from a Snapshot section.
+pub struct MappedRangeIndex<V> {
+    section: Section,
+    len: usize,
+    ids_offset: usize,
+    values_offset: usize,
+    present: StoredQueryable,
+    _values: PhantomData<V>,
+}
+
+impl<V: PersistValue> MappedRangeIndex<V> {
+    /// Opens a section written by RangeIndex::save.
+    pub fn open(section: Section) -> io::Result<Self> {
+        let mut reader = section.reader();
+        let len = reader.read_u64()? as usize;
+        reader.align(8)?;
+        let ids_offset = reader.position();
+        reader.read_ids(len)?;
+        let values_offset = reader.position();
+        let values_len = len
+            .checked_mul(V::SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "values out of bounds"))?;
+        reader.skip(values_len)?;
+        reader.align(8)?;
+        let present = reader.read_queryable()?;
+        Ok(Self {
+            section,
+            len,
+            ids_offset,
+            values_offset,
+            present,
+            _values: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Ids sorted by value.
+    pub fn ids(&self) -> &[ID] {
+        self.section.ids(self.ids_offset, self.len)
+    }
+
+    pub fn value(&self, index: usize) -> Option<V> {
+        if index >= self.len {
+            return None;
+        }
+        let offset = self.values_offset + index * V::SIZE;
+        Some(V::decode(self.section.bytes_at(offset, V::SIZE)))
+    }
+
+    // First index where pred is false.
+    fn partition_point(&self, mut pred: impl FnMut(&V) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(&self.value(mid).unwrap()) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    pub fn get(&self, query: RangeQuery<V>) -> Query<Queryable<'_>> {
+        let eq = matches!(query, RangeQuery::EQ(_));
+        let (start, end) = match query {
+            RangeQuery::EQ(value) => (
+                self.partition_point(|v| *v < value),
+                self.partition_point(|v| *v <= value),
+            ),
+            RangeQuery::GT(value) => (self.partition_point(|v| *v <= value), self.len),
+            RangeQuery::GTE(value) => (self.partition_point(|v| *v < value), self.len),
+            RangeQuery::LT(value) => (0, self.partition_point(|v| *v < value)),
+            RangeQuery::LTE(value) => (0, self.partition_point(|v| *v <= value)),
+            RangeQuery::Range(min, max) => (
+                self.partition_point(|v| *v < min),
+                self.partition_point(|v| *v <= max),
+            ),
+            RangeQuery::All => (0, self.len),
+            RangeQuery::None => {
+                let item = Item::Single(self.section.queryable(&self.present));
+                return Query::new(item, true);
+            }
+        };
+        // Ids are stored sorted by value then id, so only the ids of one value are
+        // sorted like IDs needs, ranges are IDSlices borrowed as they are.
+        let ids = &self.ids()[start..end.max(start)];
+        let queryable = if eq {
+            Queryable::IDs(ids)
+        } else {
+            Queryable::IDSlices(vec![ids])
+        };
+        Query::new(Item::Single(queryable), false)
+    }
+
+    /// See RangeIndex::query.
+    pub fn query(&self, query: RangeQuery<V>, inverse: bool) -> Query<Queryable<'_>> {
+        if matches!(query, RangeQuery::None) {
+            let item = Item::Single(self.section.queryable(&self.present));
+            return Query::new(item, !inverse);
+        }
+        let mut query = self.get(query);
+        if !inverse {
+            return query;
+        }
+        query.inverse = true;
+        let present = Query::new(Item::Single(self.section.queryable(&self.present)), false);
+        Query::new(Item::AndChain(vec![present, query]), false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::RangeIndex,
+        persist::{Snapshot, SnapshotWriter},
+        QueryableOwned,
+    };
+
+    fn round_trip(index: &RangeIndex<u32>, name: &str) -> MappedRangeIndex<u32> {
+        let path = std::env::temp_dir().join(format!("mapped-{name}-{}", std::process::id()));
+        let mut writer = SnapshotWriter::create(&path).unwrap();
+        writer.begin_section("range").unwrap();
+        index.save(&mut writer).unwrap();
+        writer.end_section().unwrap();
+        writer.finish().unwrap();
+        let snapshot = Snapshot::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        MappedRangeIndex::open(snapshot.section("range").unwrap()).unwrap()
+    }
+
+    fn loaded(len: ID) -> RangeIndex<u32> {
+        let mut loader = RangeIndex::loader();
+        for id in 0..len {
+            loader.add(id, (id * 7) % 10);
+        }
+        loader.load()
+    }
+
+    const QUERIES: [fn() -> RangeQuery<u32>; 12] = [
+        || RangeQuery::EQ(1),
+        || RangeQuery::EQ(10),
+        || RangeQuery::GT(4),
+        || RangeQuery::GT(9),
+        || RangeQuery::GTE(0),
+        || RangeQuery::LT(3),
+        || RangeQuery::LT(0),
+        || RangeQuery::LTE(9),
+        || RangeQuery::Range(2, 6),
+        || RangeQuery::Range(6, 2),
+        || RangeQuery::All,
+        || RangeQuery::None,
+    ];
+
+    #[test]
+    fn range_matches_loaded() {
+        let index = loaded(5000);
+        let mapped = round_trip(&index, "range");
+        assert_eq!(mapped.len(), 5000);
+        assert_eq!(
+            mapped.value(mapped.ids().iter().position(|&id| id == 13).unwrap()),
+            Some(1)
+        );
+
+        let base: Vec<ID> = (0..5000).collect();
+        let base = QueryableOwned::from(base);
+        for query in QUERIES {
+            let expected = index.get(query()).run_base(&base);
+            assert_eq!(mapped.get(query()).run_base(&base), expected);
+            for inverse in [false, true] {
+                let expected = index.query(query(), inverse).run_base(&base);
+                assert_eq!(mapped.query(query(), inverse).run_base(&base), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn range_matches_loaded_with_small_base() {
+        let index = loaded(5000);
+        let mapped = round_trip(&index, "small-base");
+
+        // Few enough ids that the runner checks each one with contains.
+        let base: Vec<ID> = vec![3, 17, 500, 4999];
+        let base = QueryableOwned::from(base);
+        for query in QUERIES {
+            let expected = index.get(query()).run_base(&base);
+            assert_eq!(mapped.get(query()).run_base(&base), expected);
+            let expected = index.query(query(), true).run_base(&base);
+            assert_eq!(mapped.query(query(), true).run_base(&base), expected);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let index = loaded(0);
+        let mapped = round_trip(&index, "empty");
+        assert!(mapped.is_empty());
+        assert_eq!(mapped.value(0), None);
+
+        let base: Vec<ID> = vec![0, 1, 2];
+        let base = QueryableOwned::from(base);
+        for query in QUERIES {
+            let expected = index.get(query()).run_base(&base);
+            assert_eq!(mapped.get(query()).run_base(&base), expected);
+            let expected = index.query(query(), true).run_base(&base);
+            assert_eq!(mapped.query(query(), true).run_base(&base), expected);
+        }
+    }
+}
@@ -1,20 +1,82 @@
+mod dimensions;
+mod field;
+mod file_type;
+mod flag;
+mod id_map;
 mod key;
 mod keys;
+mod mapped;
+mod ordered;
+mod phash;
+mod pool;
 mod range;
+mod relation;
+mod term;
 mod text;
+mod user_set;
+mod value;
+#[cfg(feature = "vector")]
+mod vector;
 
+use std::{any::TypeId, io, ops::BitOr};
+
+pub use dimensions::{DimensionsIndex, DimensionsIndexLoader, DIMENSIONS_IDENTIFIERS};
 use downcast_rs::{impl_downcast, Downcast};
+pub use field::FieldIndex;
+pub use file_type::{FileTypeIndex, FileTypeIndexLoader, FileTypes};
+pub use flag::{FlagIndex, FlagIndexLoader, FlagQuery};
+pub use id_map::{IdMap, IdMapLoader};
 pub use key::{KeyIndex, KeyIndexLoader};
 pub use keys::{KeysIndex, KeysIndexLoader};
-pub use range::{ChunkedVec, RangeIndex, RangeIndexLoader, RangeQuery};
-pub use text::{NgramIndex, TextIndex, TextIndexLoader, TextQuery};
+pub use mapped::{MappedKeysIndex, MappedRangeIndex};
+pub use ordered::OrderedIds;
+pub use phash::{PHashIndex, PHashIndexLoader, PHashQuery};
+pub use pool::{PoolIndex, PoolIndexLoader};
+pub use range::{ChunkedVec, RangeAggregate, RangeIndex, RangeIndexLoader, RangeQuery};
+pub use relation::{RelationIndex, RelationIndexLoader, RelationQuery};
+pub use term::Term;
+pub use text::{
+    CjkBigrams, Glob, GlobPart, NgramIndex, SplitText, StringHandle, StringPool, TextIndex,
+    TextIndexLoader, TextQuery, Tokenizer, WholeText,
+};
+pub use user_set::{UserSetIndex, UserSetIndexLoader};
+pub use value::{ValueIndex, ValueIndexLoader};
+#[cfg(feature = "vector")]
+pub use vector::{VectorIndex, VectorIndexLoader};
 
-use crate::{Query, Queryable, ID};
+use crate::{
+    persist::{Section, SnapshotWriter},
+    IndexContext, Query, Queryable, WriteContext, ID,
+};
 
 pub trait IndexLoader<P>: Downcast + Send + Sync {
     fn add(&mut self, id: ID, post: &P);
 
     fn load(self: Box<Self>) -> Box<dyn Index<P>>;
+
+    /// An empty loader that is given a shard of the posts on another thread.
+    /// None if the loader can't be split, it will be given every post on one thread.
+    /// Loaders returning Some must also support merge.
+    fn fork(&self) -> Option<Box<dyn IndexLoader<P>>> {
+        None
+    }
+
+    /// Merges a loader returned by fork.
+    /// Shards are merged in order so other only has ids higher than self.
+    fn merge(&mut self, _other: Box<dyn IndexLoader<P>>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "IndexLoader doesn't support merge",
+        ))
+    }
+
+    /// Opens the section written by Index::save instead of loading posts.
+    fn open(self: Box<Self>, _section: Section) -> io::Result<Box<dyn Index<P>>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "IndexLoader doesn't support open",
+        ))
+    }
 }
 
 impl_downcast!(IndexLoader<P>);
@@ -27,11 +89,183 @@ pub trait Index<P>: Downcast + Send + Sync {
         inverse: bool,
     ) -> Option<Query<Queryable<'s>>>;
 
+    /// Like query with the Db it runs in, Db queries call this.
+    /// Override instead of query for indexes that need the context.
+    fn query_in<'s>(
+        &'s self,
+        _context: &IndexContext<'_, P>,
+        ident: Option<&str>,
+        text: &str,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query(ident, text, inverse)
+    }
+
+    /// Like query_in with the term split into its prefixes, Db queries call this.
+    /// Override for identifiers with their own prefixes, such as `ai:solo:>=90`.
+    fn query_term<'s>(
+        &'s self,
+        context: &IndexContext<'_, P>,
+        term: &Term<'_>,
+        inverse: bool,
+    ) -> Option<Query<Queryable<'s>>> {
+        self.query_in(context, term.ident(), term.text, inverse)
+    }
+
     fn insert(&mut self, id: ID, post: &P);
 
     fn remove(&mut self, id: ID, post: &P);
 
     fn update(&mut self, id: ID, old: &P, new: &P);
+
+    /// Index types this index reads while being written, such as a tag count index
+    /// reading the tag index. Db writes them first whatever order they were registered in.
+    fn dependencies(&self) -> Vec<TypeId> {
+        Vec::new()
+    }
+
+    /// Like insert with the indexes written before this one, Db writes call this.
+    /// Override instead of insert for indexes derived from their dependencies.
+    fn insert_in(&mut self, _context: &WriteContext<'_, P>, id: ID, post: &P) {
+        self.insert(id, post)
+    }
+
+    /// Like remove with the indexes written before this one, see insert_in.
+    fn remove_in(&mut self, _context: &WriteContext<'_, P>, id: ID, post: &P) {
+        self.remove(id, post)
+    }
+
+    /// Like update with the indexes written before this one, see insert_in.
+    /// Db::update_changed also updates indexes with a dependency that was updated.
+    fn update_in(&mut self, _context: &WriteContext<'_, P>, id: ID, old: &P, new: &P) {
+        self.update(id, old, new)
+    }
+
+    /// Posts similar to id, best first with their scores.
+    /// Indexes that can't rank similarity return None, see KeysIndex::similar.
+    fn similar(
+        &self,
+        _context: &IndexContext<'_, P>,
+        _id: ID,
+        _limit: usize,
+    ) -> Option<Vec<(ID, f32)>> {
+        None
+    }
+
+    /// The terms matching the most posts with their counts, used by Db::warm_top.
+    fn top_terms(&self, _limit: usize) -> Vec<(String, usize)> {
+        Vec::new()
+    }
+
+    /// Whether query would accept the term, used by Db::validate.
+    /// Override to check the syntax without building the query.
+    fn validate(&self, ident: Option<&str>, text: &str) -> bool {
+        self.query(ident, text, false).is_some()
+    }
+
+    /// Ids in the index's own order such as by external post id, see Db::add_order.
+    fn order(&self) -> Option<&[ID]> {
+        None
+    }
+
+    /// Problems with the index's own data such as unsorted columns, used by Db::verify.
+    fn verify(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether post is indexed under id, true if the index can't tell. Used by Db::verify.
+    fn verify_post(&self, _id: ID, _post: &P) -> bool {
+        true
+    }
+
+    /// Fields of P this index reads, used to skip update when none of them changed.
+    fn fields(&self) -> ChangeSet {
+        ChangeSet::ALL
+    }
+
+    /// A copy for Db::snapshot, made when an index shared with a snapshot is written to.
+    /// None if the index can't be cloned, it's rebuilt from the Db's store instead,
+    /// see Db::snapshot.
+    fn clone_index(&self) -> Option<Box<dyn Index<P>>> {
+        None
+    }
+
+    /// Writes the index to a section of a snapshot, see IndexLoader::open.
+    fn save(&self, _w: &mut SnapshotWriter) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Index doesn't support save",
+        ))
+    }
+
+    /// Writes the data of ids to a section of a delta, see Db::snapshot_delta.
+    fn save_delta(&self, _ids: &[ID], _w: &mut SnapshotWriter) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Index doesn't support save_delta",
+        ))
+    }
+
+    /// Replaces the data of ids with a section written by save_delta, see Db::apply_delta.
+    fn apply_delta(&mut self, _ids: &[ID], _section: Section) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Index doesn't support apply_delta",
+        ))
+    }
+
+    /// Like apply_delta with the indexes written before this one, Db::apply_delta calls
+    /// this after applying the deltas of its dependencies, see insert_in.
+    fn apply_delta_in(
+        &mut self,
+        _context: &WriteContext<'_, P>,
+        ids: &[ID],
+        section: Section,
+    ) -> io::Result<()> {
+        self.apply_delta(ids, section)
+    }
 }
 
 impl_downcast!(Index<P>);
+
+/// Bit mask of post fields, field numbers 0..64 are chosen by the user.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ChangeSet(u64);
+
+impl ChangeSet {
+    pub const ALL: Self = Self(u64::MAX);
+    pub const NONE: Self = Self(0);
+
+    pub fn new() -> Self {
+        Self::NONE
+    }
+
+    pub fn field(field: u32) -> Self {
+        assert!(field < 64);
+        Self(1 << field)
+    }
+
+    pub fn with(self, field: u32) -> Self {
+        self | Self::field(field)
+    }
+
+    pub fn contains(&self, field: u32) -> bool {
+        self.intersects(Self::field(field))
+    }
+
+    pub fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for ChangeSet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
@@ -0,0 +1,101 @@
+use std::{iter::Copied, slice::Iter};
+
+use crate::{
+    query::{Queryable, QueryableOwned},
+    ID,
+};
+
+/// Ids in a user defined order, such as favorite or pool order,
+/// with a sorted copy used for queries.
+#[derive(Clone, Debug, Default)]
+pub struct OrderedIds {
+    order: Vec<ID>,
+    set: QueryableOwned,
+}
+
+impl OrderedIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Duplicate ids keep their first position.
+    pub fn from_order(order: Vec<ID>) -> Self {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let order = if sorted.len() == order.len() {
+            order
+        } else {
+            let mut seen = fxhash::FxHashSet::default();
+            order.into_iter().filter(|id| seen.insert(*id)).collect()
+        };
+        let mut set = QueryableOwned::from(sorted);
+        set.check_and_convert();
+        Self { order, set }
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, id: ID) -> bool {
+        self.set.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Ids in order.
+    pub fn ids(&self) -> &[ID] {
+        &self.order
+    }
+
+    /// Ids in order, for QueryResult::get_sorted.
+    pub fn iter(&self) -> Copied<Iter<'_, ID>> {
+        self.order.iter().copied()
+    }
+
+    #[inline(always)]
+    pub fn queryable(&self) -> Queryable<'_> {
+        (&self.set).into()
+    }
+
+    pub fn position(&self, id: ID) -> Option<usize> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.order.iter().position(|&i| i == id)
+    }
+
+    /// Appends id, returns false if it was already present.
+    pub fn push(&mut self, id: ID) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.order.push(id);
+        self.set.insert(id);
+        true
+    }
+
+    /// Inserts id at index or moves it there if already present.
+    /// Index is clamped to the length.
+    pub fn insert(&mut self, index: usize, id: ID) {
+        if let Some(position) = self.position(id) {
+            self.order.remove(position);
+        } else {
+            self.set.insert(id);
+        }
+        let index = index.min(self.order.len());
+        self.order.insert(index, id);
+    }
+
+    /// Returns the position id was removed from.
+    pub fn remove(&mut self, id: ID) -> Option<usize> {
+        let position = self.position(id)?;
+        self.order.remove(position);
+        self.set.remove(id);
+        Some(position)
+    }
+}
@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use crate::{
+    query::{Item, Queryable},
+    Query, ID,
+};
+
+use super::Term;
+
+/// `<hex>~<max distance>`, a missing distance means an exact match.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PHashQuery {
+    pub hash: u64,
+    pub max_distance: u32,
+}
+
+impl FromStr for PHashQuery {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, max_distance) = match s.split_once('~') {
+            Some((hash, distance)) => (hash, distance.parse().map_err(|_| ())?),
+            None => (s, 0),
+        };
+        let hash = hash.strip_prefix("0x").unwrap_or(hash);
+        if hash.is_empty() || hash.len() > 16 {
+            return Err(());
+        }
+        let hash = u64::from_str_radix(hash, 16).map_err(|_| ())?;
+        Ok(Self { hash, max_distance })
+    }
+}
+
+impl PHashQuery {
+    /// Parses the term's value, so quoted hashes work too.
+    pub fn from_term(term: &Term<'_>) -> Option<Self> {
+        term.value.parse().ok()
+    }
+}
+
+#[inline(always)]
+fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+struct Node {
+    hash: u64,
+    // Empty once every id with this hash is removed, the node is kept to preserve the tree.
+    ids: Vec<ID>,
+    children: Vec<(u32, usize)>,
+}
+
+#[derive(Default)]
+pub struct PHashIndexLoader {
+    hashes: Vec<(ID, u64)>,
+}
+
+impl PHashIndexLoader {
+    pub fn new() -> Self {
+        Self { hashes: Vec::new() }
+    }
+
+    pub fn add(&mut self, id: ID, hash: u64) {
+        self.hashes.push((id, hash));
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.hashes.extend(other.hashes);
+    }
+
+    pub fn load(self) -> PHashIndex {
+        let mut index = PHashIndex::default();
+        for (id, hash) in self.hashes {
+            index.insert(id, hash);
+        }
+        index
+    }
+}
+
+/// 64 bit perceptual hashes in a BK-tree for Hamming distance lookups.
+#[derive(Default)]
+pub struct PHashIndex {
+    nodes: Vec<Node>,
+    hashes: fxhash::FxHashMap<ID, u64>,
+}
+
+impl PHashIndex {
+    pub fn loader() -> PHashIndexLoader {
+        PHashIndexLoader::new()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn hash(&self, id: ID) -> Option<u64> {
+        self.hashes.get(&id).copied()
+    }
+
+    /// Ids within max_distance of hash with their distance, nearest first.
+    pub fn find(&self, hash: u64, max_distance: u32) -> Vec<(ID, u32)> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let d = distance(node.hash, hash);
+            if d <= max_distance {
+                found.extend(node.ids.iter().map(|&id| (id, d)));
+            }
+            let min = d.saturating_sub(max_distance);
+            let max = d.saturating_add(max_distance);
+            for &(child_distance, child) in &node.children {
+                if child_distance >= min && child_distance <= max {
+                    stack.push(child);
+                }
+            }
+        }
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+
+    pub fn get(&self, query: PHashQuery) -> Query<Queryable<'_>> {
+        let mut ids: Vec<ID> = self
+            .find(query.hash, query.max_distance)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        Query::new(Item::Single(Queryable::IDsOwned(ids)), false)
+    }
+
+    /// Replaces any hash id already has.
+    pub fn insert(&mut self, id: ID, hash: u64) {
+        if let Some(old) = self.hashes.insert(id, hash) {
+            self.remove_from_tree(id, old);
+        }
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                hash,
+                ids: vec![id],
+                children: Vec::new(),
+            });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let d = distance(self.nodes[current].hash, hash);
+            if d == 0 {
+                self.nodes[current].ids.push(id);
+                return;
+            }
+            let child = self.nodes[current]
+                .children
+                .iter()
+                .find(|(child_distance, _)| *child_distance == d)
+                .map(|(_, child)| *child);
+            match child {
+                Some(child) => current = child,
+                None => {
+                    let node = self.nodes.len();
+                    self.nodes.push(Node {
+                        hash,
+                        ids: vec![id],
+                        children: Vec::new(),
+                    });
+                    self.nodes[current].children.push((d, node));
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: ID) {
+        if let Some(hash) = self.hashes.remove(&id) {
+            self.remove_from_tree(id, hash);
+        }
+    }
+
+    fn remove_from_tree(&mut self, id: ID, hash: u64) {
+        let mut current = 0;
+        while current < self.nodes.len() {
+            let d = distance(self.nodes[current].hash, hash);
+            if d == 0 {
+                self.nodes[current].ids.retain(|&i| i != id);
+                return;
+            }
+            let Some(&(_, child)) = self.nodes[current]
+                .children
+                .iter()
+                .find(|(child_distance, _)| *child_distance == d)
+            else {
+                return;
+            };
+            current = child;
+        }
+    }
+}
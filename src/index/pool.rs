@@ -0,0 +1,195 @@
+use std::{borrow::Borrow, hash::Hash};
+
+use crate::{
+    query::{Queryable, QueryableOwned},
+    ID,
+};
+
+use super::OrderedIds;
+
+#[derive(Default)]
+pub struct PoolIndexLoader<K> {
+    pools: fxhash::FxHashMap<K, Vec<ID>>,
+}
+
+impl<K: Clone + Eq + Hash> PoolIndexLoader<K> {
+    pub fn new() -> Self {
+        Self {
+            pools: fxhash::FxHashMap::default(),
+        }
+    }
+
+    /// Appends ids to the end of pool.
+    pub fn add(&mut self, pool: K, ids: impl IntoIterator<Item = ID>) {
+        self.pools.entry(pool).or_default().extend(ids);
+    }
+
+    pub fn load(self) -> PoolIndex<K> {
+        let mut index = PoolIndex {
+            pools: fxhash::FxHashMap::default(),
+            post_pools: fxhash::FxHashMap::default(),
+            any: QueryableOwned::default(),
+        };
+        for (pool, order) in self.pools {
+            let ids = OrderedIds::from_order(order);
+            for id in ids.iter() {
+                index.post_pools.entry(id).or_default().push(pool.clone());
+            }
+            index.pools.insert(pool, ids);
+        }
+        let mut any: Vec<ID> = index.post_pools.keys().copied().collect();
+        any.sort_unstable();
+        index.any = any.into();
+        index.any.check_and_convert();
+        index
+    }
+}
+
+/// Pools of posts in a user defined order, for `pool:123` queries,
+/// sorting results by pool order and finding a post's position in a pool.
+pub struct PoolIndex<K> {
+    pools: fxhash::FxHashMap<K, OrderedIds>,
+    post_pools: fxhash::FxHashMap<ID, Vec<K>>,
+    any: QueryableOwned,
+}
+
+impl<K: Clone + Eq + Hash> PoolIndex<K> {
+    pub fn loader() -> PoolIndexLoader<K> {
+        PoolIndexLoader::new()
+    }
+
+    #[inline(always)]
+    pub fn get<Q>(&self, pool: &Q) -> Option<Queryable<'_>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.pools.get(pool).map(OrderedIds::queryable)
+    }
+
+    /// Ids in any pool.
+    #[inline(always)]
+    pub fn any(&self) -> Queryable<'_> {
+        (&self.any).into()
+    }
+
+    #[inline(always)]
+    pub fn matched<Q>(&self, pool: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.pools.get(pool).map(OrderedIds::len)
+    }
+
+    /// Ids in pool order, for QueryResult::get_sorted.
+    pub fn ordered<Q>(&self, pool: &Q) -> Option<&OrderedIds>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.pools.get(pool)
+    }
+
+    pub fn position<Q>(&self, pool: &Q, id: ID) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.pools.get(pool)?.position(id)
+    }
+
+    /// Pools containing id.
+    pub fn pools_of(&self, id: ID) -> &[K] {
+        self.post_pools.get(&id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &K> {
+        self.pools.keys()
+    }
+
+    fn link(&mut self, pool: &K, id: ID) {
+        let pools = self.post_pools.entry(id).or_default();
+        if pools.is_empty() {
+            self.any.insert(id);
+        }
+        pools.push(pool.clone());
+    }
+
+    fn unlink<Q>(&mut self, pool: &Q, id: ID)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some(pools) = self.post_pools.get_mut(&id) {
+            pools.retain(|p| p.borrow() != pool);
+            if pools.is_empty() {
+                self.post_pools.remove(&id);
+                self.any.remove(id);
+            }
+        }
+    }
+
+    /// Inserts id at index in pool or moves it there if already present.
+    pub fn insert(&mut self, pool: K, index: usize, id: ID) {
+        let ids = self.pools.entry(pool.clone()).or_default();
+        let is_new = !ids.contains(id);
+        ids.insert(index, id);
+        if is_new {
+            self.link(&pool, id);
+        }
+    }
+
+    /// Appends id to the end of pool.
+    pub fn push(&mut self, pool: K, id: ID) {
+        if self.pools.entry(pool.clone()).or_default().push(id) {
+            self.link(&pool, id);
+        }
+    }
+
+    /// Returns the position id was removed from.
+    pub fn remove<Q>(&mut self, pool: &Q, id: ID) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let position = self.pools.get_mut(pool)?.remove(id)?;
+        self.unlink(pool, id);
+        Some(position)
+    }
+
+    /// Replaces the order of pool.
+    pub fn set(&mut self, pool: K, order: Vec<ID>) {
+        self.remove_pool(&pool);
+        let ids = OrderedIds::from_order(order);
+        for id in ids.iter() {
+            self.link(&pool, id);
+        }
+        self.pools.insert(pool, ids);
+    }
+
+    pub fn remove_pool<Q>(&mut self, pool: &Q) -> Option<OrderedIds>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let ids = self.pools.remove(pool)?;
+        for id in ids.iter() {
+            self.unlink(pool, id);
+        }
+        Some(ids)
+    }
+
+    /// Removes id from every pool, for when the post itself is removed.
+    pub fn remove_id(&mut self, id: ID) {
+        let Some(pools) = self.post_pools.remove(&id) else {
+            return;
+        };
+        self.any.remove(id);
+        for pool in pools {
+            if let Some(ids) = self.pools.get_mut(&pool) {
+                ids.remove(id);
+            }
+        }
+    }
+}
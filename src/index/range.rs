@@ -1,13 +1,17 @@
 use std::{
     cmp::Ordering,
     collections::HashMap,
+    io,
     ops::Bound::{self, *},
     str::FromStr,
+    sync::OnceLock,
 };
 
 use crate::{
-    query::{Item, Queryable},
-    Query, ID,
+    packed,
+    persist::{PersistValue, SectionReader, SnapshotWriter},
+    query::{Item, Queryable, QueryableOwned},
+    Packed, Query, QueryResult, ID,
 };
 
 pub enum RangeQuery<V> {
@@ -17,7 +21,10 @@ pub enum RangeQuery<V> {
     LT(V),
     LTE(V),
     Range(V, V),
+    /// Any value.
     All,
+    /// No value.
+    None,
 }
 
 impl<V: Ord> RangeQuery<V> {
@@ -30,6 +37,23 @@ impl<V: Ord> RangeQuery<V> {
             RangeQuery::LTE(value) => v <= value,
             RangeQuery::Range(start, end) => v >= start && v <= end,
             RangeQuery::All => true,
+            RangeQuery::None => false,
+        }
+    }
+}
+
+impl<V> RangeQuery<V> {
+    /// Converts the bounds, such as parsing f64 then scaling to a fixed point value.
+    pub fn map<U>(self, mut f: impl FnMut(V) -> U) -> RangeQuery<U> {
+        match self {
+            RangeQuery::EQ(value) => RangeQuery::EQ(f(value)),
+            RangeQuery::GT(value) => RangeQuery::GT(f(value)),
+            RangeQuery::GTE(value) => RangeQuery::GTE(f(value)),
+            RangeQuery::LT(value) => RangeQuery::LT(f(value)),
+            RangeQuery::LTE(value) => RangeQuery::LTE(f(value)),
+            RangeQuery::Range(start, end) => RangeQuery::Range(f(start), f(end)),
+            RangeQuery::All => RangeQuery::All,
+            RangeQuery::None => RangeQuery::None,
         }
     }
 }
@@ -38,7 +62,11 @@ impl<V: FromStr> FromStr for RangeQuery<V> {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.contains("..") {
+        if s == "any" {
+            Ok(Self::All)
+        } else if s == "none" {
+            Ok(Self::None)
+        } else if s.contains("..") {
             let mut split = s.split("..");
             let min = split.next().ok_or(())?;
             let max = split.next().ok_or(())?;
@@ -67,22 +95,34 @@ impl<V: FromStr> FromStr for RangeQuery<V> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct RangeAggregate<V> {
+    pub count: usize,
+    pub min: Option<V>,
+    pub max: Option<V>,
+    pub sum: f64,
+    /// Counts per bucket, see [`RangeIndex::aggregate`].
+    pub histogram: Vec<usize>,
+}
+
+impl<V> RangeAggregate<V> {
+    pub fn avg(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum / self.count as f64)
+    }
+}
+
+/// Only keeps (value, id) pairs, RangeIndex builds its id to value map when first needed.
 #[derive(Default)]
 pub struct RangeIndexLoader<V> {
-    id_values: HashMap<ID, V>,
     values: Vec<(V, ID)>,
 }
 
 impl<V: Clone + Eq + Ord> RangeIndexLoader<V> {
     pub fn new() -> Self {
-        Self {
-            id_values: HashMap::new(),
-            values: Vec::new(),
-        }
-    }
-
-    pub fn id_values(&self) -> &HashMap<ID, V> {
-        &self.id_values
+        Self { values: Vec::new() }
     }
 
     pub fn values(&self) -> &Vec<(V, ID)> {
@@ -90,41 +130,57 @@ impl<V: Clone + Eq + Ord> RangeIndexLoader<V> {
     }
 
     pub fn add(&mut self, id: ID, v: V) {
-        self.id_values.insert(id, v.clone());
         self.values.push((v, id));
     }
 
+    pub fn merge(&mut self, other: Self) {
+        self.values.extend(other.values);
+    }
+
     pub fn load(mut self) -> RangeIndex<V> {
         self.values.sort_unstable();
         let mut ids = ChunkedVec::new(100_000);
+        let mut present = Vec::with_capacity(self.values.len());
         for (_, id) in &self.values {
             ids.push(*id);
+            present.push(*id);
         }
         let mut values = ChunkedVec::new(100_000);
-        for value in self.values {
+        for (value, _) in self.values {
             values.push(value);
         }
+        present.sort_unstable();
+        present.dedup();
+        let mut present = QueryableOwned::from(present);
+        present.check_and_convert();
         RangeIndex {
             ids,
-            id_values: self.id_values,
+            id_values: OnceLock::new(),
             values,
+            present,
         }
     }
 }
 
-#[derive(Default)]
+// ids and values are parallel columns sorted by value then id, always modified
+// together so their chunks line up.
+#[derive(Clone, Default)]
 pub struct RangeIndex<V> {
     ids: ChunkedVec<ID>,
-    id_values: HashMap<ID, V>,
-    values: ChunkedVec<(V, ID)>,
+    // Built from the columns on first use, then kept up to date.
+    id_values: OnceLock<HashMap<ID, V>>,
+    values: ChunkedVec<V>,
+    // ids that have a value
+    present: QueryableOwned,
 }
 
 impl<V: Clone + Eq + Ord> RangeIndex<V> {
     pub fn new() -> Self {
         Self {
             ids: ChunkedVec::new(100_000),
-            id_values: HashMap::new(),
+            id_values: OnceLock::new(),
             values: ChunkedVec::new(100_000),
+            present: QueryableOwned::default(),
         }
     }
 
@@ -132,18 +188,47 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
         &self.ids
     }
 
+    /// The value of each id. Built on the first call, which costs a pass over the index.
     pub fn id_values(&self) -> &HashMap<ID, V> {
-        &self.id_values
+        self.id_values.get_or_init(|| {
+            let mut id_values = HashMap::with_capacity(self.ids.len());
+            for (value, id) in self.values.iter().zip(self.ids.iter()) {
+                id_values.insert(*id, value.clone());
+            }
+            id_values
+        })
     }
 
-    pub fn values(&self) -> &ChunkedVec<(V, ID)> {
+    /// Sorted values, values()[i] is the value of ids()[i].
+    pub fn values(&self) -> &ChunkedVec<V> {
         &self.values
     }
 
+    pub fn present(&self) -> &QueryableOwned {
+        &self.present
+    }
+
     pub fn loader() -> RangeIndexLoader<V> {
         RangeIndexLoader::new()
     }
 
+    /// Like get but inverse only matches ids that have a value,
+    /// so -score:>10 doesn't match ids without a score.
+    /// Use RangeQuery::None to match ids without a value.
+    pub fn query(&self, query: RangeQuery<V>, inverse: bool) -> Query<Queryable<'_>> {
+        if matches!(query, RangeQuery::None) {
+            let item = Item::Single((&self.present).into());
+            return Query::new(item, !inverse);
+        }
+        let mut query = self.get(query);
+        if !inverse {
+            return query;
+        }
+        query.inverse = true;
+        let present = Query::new(Item::Single((&self.present).into()), false);
+        Query::new(Item::AndChain(vec![present, query]), false)
+    }
+
     pub fn get(&self, query: RangeQuery<V>) -> Query<Queryable<'_>> {
         let range = match query {
             RangeQuery::EQ(value) => self.eq(&value),
@@ -153,50 +238,124 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
             RangeQuery::LTE(value) => self.lte(&value),
             RangeQuery::Range(min, max) => self.range(&min, &max),
             RangeQuery::All => Some((Bound::Included(0), Bound::Unbounded)),
+            RangeQuery::None => {
+                let item = Item::Single((&self.present).into());
+                return Query::new(item, true);
+            }
         };
         if range.is_none() {
-            let queryable = Queryable::IDs(&[]);
-            let item = Item::Single(queryable);
-            return Query::new(item, false);
+            return Query::empty();
         }
         let (start, end) = range.unwrap();
 
-        let item = Item::OrChain(
-            self.ids
-                .as_slices(start, end)
-                .into_iter()
-                .map(|slice| {
-                    let queryable = Queryable::IDs(slice);
-                    let item = Item::Single(queryable);
-                    Query::new(item, false)
-                })
-                .collect(),
-        );
+        let slices = self.ids.as_slices(start, end);
+        let item = Item::Single(Queryable::IDSlices(slices));
         Query::new(item, false)
     }
 
     pub fn insert(&mut self, id: ID, value: V) {
-        self.id_values.insert(id, value.clone());
+        if let Some(id_values) = self.id_values.get_mut() {
+            id_values.insert(id, value.clone());
+        }
+        self.present.insert(id);
 
-        let value_id = (value, id);
-        let Err(index) = self.values.binary_search(&value_id) else {
+        let Err(index) = self.position(&value, id) else {
             return;
         };
         self.ids.insert(index, id);
-        self.values.insert(index, value_id);
+        self.values.insert(index, value);
     }
 
     pub fn remove(&mut self, id: ID, value: V) {
-        self.id_values.remove(&id);
+        if let Some(id_values) = self.id_values.get_mut() {
+            id_values.remove(&id);
+        }
+        self.present.remove(id);
 
-        let value_id = (value, id);
-        let Ok(index) = self.values.binary_search(&value_id) else {
+        let Ok(index) = self.position(&value, id) else {
             return;
         };
         self.ids.remove(index);
         self.values.remove(index);
     }
 
+    /// Whether id has value.
+    pub fn contains(&self, value: &V, id: ID) -> bool {
+        self.position(value, id).is_ok()
+    }
+
+    /// See Index::verify.
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.ids.len() != self.values.len() {
+            problems.push(format!(
+                "{} ids but {} values",
+                self.ids.len(),
+                self.values.len()
+            ));
+        }
+        let mut last: Option<(&V, ID)> = None;
+        for (value, &id) in self.values.iter().zip(self.ids.iter()) {
+            if last.is_some_and(|last| last >= (value, id)) {
+                problems.push(format!("id {id} out of order"));
+            }
+            if !self.present.contains(id) {
+                problems.push(format!("id {id} has a value but isn't present"));
+            }
+            last = Some((value, id));
+        }
+        if let Some(problem) = self.present.verify() {
+            problems.push(format!("present: {problem}"));
+        } else if self.present.matched() != self.ids.len() {
+            problems.push(format!(
+                "{} ids present but {} have values",
+                self.present.matched(),
+                self.ids.len()
+            ));
+        }
+        if let Some(id_values) = self.id_values.get() {
+            let stale = id_values
+                .iter()
+                .filter(|(&id, value)| !self.contains(value, id))
+                .count();
+            if stale > 0 {
+                problems.push(format!("id_values has {stale} stale entries"));
+            }
+            if id_values.len() != self.ids.len() {
+                problems.push(format!(
+                    "id_values has {} entries for {} ids",
+                    id_values.len(),
+                    self.ids.len()
+                ));
+            }
+        }
+        problems
+    }
+
+    // The values of ids, ids without one are skipped.
+    fn values_of(&self, ids: &[ID]) -> Vec<(ID, V)> {
+        let id_values = self.id_values();
+        ids.iter()
+            .filter_map(|&id| Some((id, id_values.get(&id)?.clone())))
+            .collect()
+    }
+
+    // Like a binary search for (value, id), ids with the same value are sorted.
+    fn position(&self, value: &V, id: ID) -> Result<usize, usize> {
+        let start = self.values.get_first(|probe| probe.cmp(value))?;
+        let end = self.values.get_last(|probe| probe.cmp(value)).unwrap() + 1;
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.ids.get(mid).unwrap().cmp(&id) {
+                Ordering::Equal => return Ok(mid),
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+            }
+        }
+        Err(low)
+    }
+
     pub fn update(&mut self, id: ID, old: V, new: V) {
         if old == new {
             return;
@@ -205,16 +364,158 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
         self.insert(id, new);
     }
 
+    /// Stats of the values of ids in result.
+    /// bounds must be sorted, histogram has bounds.len() + 1 buckets where
+    /// bucket i counts values in bounds[i - 1]..bounds[i].
+    pub fn aggregate(&self, result: &QueryResult, bounds: &[V]) -> RangeAggregate<V>
+    where
+        V: Into<f64>,
+    {
+        let mut aggregate = RangeAggregate {
+            count: 0,
+            min: None,
+            max: None,
+            sum: 0.0,
+            histogram: vec![0; bounds.len() + 1],
+        };
+        let mut add = |value: &V| {
+            aggregate.count += 1;
+            aggregate.sum += value.clone().into();
+            if aggregate
+                .min
+                .as_ref()
+                .map(|min| value < min)
+                .unwrap_or(true)
+            {
+                aggregate.min = Some(value.clone());
+            }
+            if aggregate
+                .max
+                .as_ref()
+                .map(|max| value > max)
+                .unwrap_or(true)
+            {
+                aggregate.max = Some(value.clone());
+            }
+            let bucket = bounds.partition_point(|bound| bound <= value);
+            aggregate.histogram[bucket] += 1;
+        };
+        // Look up each match when the result is smaller than the index,
+        // unless the map hasn't been built.
+        let id_values = self.id_values.get();
+        if let Some(id_values) = id_values.filter(|_| result.matched() < self.values.len()) {
+            for id in result.get(0, result.matched(), false) {
+                if let Some(value) = id_values.get(&id) {
+                    add(value);
+                }
+            }
+        } else {
+            for (value, id) in self.values.iter().zip(self.ids.iter()) {
+                if result.contains(*id) {
+                    add(value);
+                }
+            }
+        }
+        aggregate
+    }
+
+    /// The nth lowest value, duplicates included.
+    pub fn nth_value(&self, n: usize) -> Option<&V> {
+        self.values.get(n)
+    }
+
+    /// Nearest rank percentile, p from 0 to 100. percentile(50.0) is the median.
+    pub fn percentile(&self, p: f64) -> Option<&V> {
+        self.nth_value(percentile_rank(self.values.len(), p)?)
+    }
+
+    /// Values of ids in result with their ids, lowest first.
+    pub fn iter_in<'i: 'r, 'r>(
+        &'i self,
+        result: &'r QueryResult,
+    ) -> impl Iterator<Item = (&'i V, ID)> + 'r {
+        self.values
+            .iter()
+            .zip(self.ids.iter())
+            .filter(|(_, id)| result.contains(**id))
+            .map(|(value, id)| (value, *id))
+    }
+
+    /// A page of the ids in both filter and base sorted by value, like
+    /// QueryResult::get_sorted for a query of just filter, such as a tag when sorting
+    /// a tag's posts by score. base is Db::checks. Ids without a value are skipped.
+    /// Small id lists are sorted directly instead of filtering a copy of base,
+    /// looking up each id in id_values.
+    pub fn get_sorted_in(
+        &self,
+        filter: &Queryable,
+        base: &[Packed],
+        index: usize,
+        limit: usize,
+        reverse: bool,
+    ) -> Vec<ID> {
+        let ids = match filter {
+            Queryable::IDs(ids) => Some(*ids),
+            Queryable::IDsOwned(ids) => Some(ids.as_slice()),
+            _ => None,
+        };
+        let Some(ids) = ids.filter(|ids| ids.len().saturating_mul(16) < self.ids.len()) else {
+            let mut checks = base.to_vec();
+            filter.and(&mut checks, false);
+            // get_sorted pages from the end by matched, which must only count ids in the walk.
+            Queryable::from(&self.present).and(&mut checks, false);
+            let result = QueryResult::new(checks);
+            return result.get_sorted(self.ids.iter().copied(), index, limit, reverse);
+        };
+        let ids: Vec<ID> = ids
+            .iter()
+            .copied()
+            .filter(|&id| packed::contains(base, id))
+            .collect();
+        let mut sorted: Vec<(V, ID)> = self
+            .values_of(&ids)
+            .into_iter()
+            .map(|(id, value)| (value, id))
+            .collect();
+        sorted.sort_unstable();
+        if reverse {
+            sorted.reverse();
+        }
+        sorted
+            .into_iter()
+            .skip(index)
+            .take(limit)
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    /// Like percentile but only over the values of ids in result,
+    /// percentile_in(result, 50.0) is the median of the matches.
+    pub fn percentile_in(&self, result: &QueryResult, p: f64) -> Option<&V> {
+        let len = self.present.matched_in(result.checks());
+        let rank = percentile_rank(len, p)?;
+        self.iter_in(result).nth(rank).map(|(value, _)| value)
+    }
+
+    /// Parses a RangeQuery or `top10%` / `bottom10%`, which become a bound at that
+    /// percentile so ties with the last value in the percent are included.
+    pub fn parse_query(&self, text: &str) -> Option<RangeQuery<V>>
+    where
+        V: FromStr,
+    {
+        parse_percentile_query(text, self.values.len(), |n| self.nth_value(n))
+    }
+
     pub fn eq(&self, value: &V) -> Option<(Bound<usize>, Bound<usize>)> {
-        let start = self.values.get_first(|probe| probe.0.cmp(value)).ok()?;
-        let end = self.values.get_last(|probe| probe.0.cmp(value)).ok()?;
+        let start = self.values.get_first(|probe| probe.cmp(value)).ok()?;
+        let end = self.values.get_last(|probe| probe.cmp(value)).ok()?;
         Some((Included(start), Included(end)))
     }
 
     pub fn gt(&self, value: &V) -> Option<(Bound<usize>, Bound<usize>)> {
         let start = self
             .values
-            .get_last(|probe| probe.0.cmp(value))
+            .get_last(|probe| probe.cmp(value))
             .map(Excluded)
             .unwrap_or_else(Included);
         let end = Unbounded;
@@ -224,7 +525,7 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
     pub fn gte(&self, value: &V) -> Option<(Bound<usize>, Bound<usize>)> {
         let start = self
             .values
-            .get_first(|probe| probe.0.cmp(value))
+            .get_first(|probe| probe.cmp(value))
             .map(Included)
             .unwrap_or_else(Included);
         let end = Unbounded;
@@ -235,7 +536,7 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
         let start = Unbounded;
         let end = self
             .values
-            .get_first(|probe| probe.0.cmp(value))
+            .get_first(|probe| probe.cmp(value))
             .map(Excluded)
             .unwrap_or_else(Excluded);
         Some((start, end))
@@ -245,7 +546,7 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
         let start = Unbounded;
         let end = self
             .values
-            .get_last(|probe| probe.0.cmp(value))
+            .get_last(|probe| probe.cmp(value))
             .map(Included)
             .unwrap_or_else(Excluded);
         Some((start, end))
@@ -254,19 +555,107 @@ impl<V: Clone + Eq + Ord> RangeIndex<V> {
     pub fn range(&self, min: &V, max: &V) -> Option<(Bound<usize>, Bound<usize>)> {
         let start = self
             .values
-            .get_first(|probe| probe.0.cmp(min))
+            .get_first(|probe| probe.cmp(min))
             .map(Included)
             .unwrap_or_else(Included);
         let end = self
             .values
-            .get_last(|probe| probe.0.cmp(max))
+            .get_last(|probe| probe.cmp(max))
             .map(Included)
             .unwrap_or_else(Excluded);
         Some((start, end))
     }
 }
 
-#[derive(Debug)]
+impl<V: PersistValue> RangeIndex<V> {
+    /// Writes the index to the current section, open it with MappedRangeIndex.
+    pub fn save(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u64(self.ids.len() as u64)?;
+        w.align(8)?;
+        for id in self.ids.iter() {
+            w.write_u32(*id)?;
+        }
+        w.align(8)?;
+        let mut bytes = vec![0; V::SIZE];
+        for value in self.values.iter() {
+            value.encode(&mut bytes);
+            w.write_bytes(&bytes)?;
+        }
+        w.align(8)?;
+        w.write_queryable(&self.present)
+    }
+
+    /// Writes the values of ids to the current section, read back by apply_delta.
+    pub fn save_delta(&self, ids: &[ID], w: &mut SnapshotWriter) -> io::Result<()> {
+        let values = self.values_of(ids);
+        w.write_u64(values.len() as u64)?;
+        let ids: Vec<ID> = values.iter().map(|(id, _)| *id).collect();
+        w.write_ids(&ids)?;
+        let mut bytes = vec![0; V::SIZE];
+        for (_, value) in values {
+            value.encode(&mut bytes);
+            w.write_bytes(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the values of ids with those written by save_delta.
+    pub fn apply_delta(&mut self, ids: &[ID], r: &mut SectionReader) -> io::Result<()> {
+        for (id, value) in self.values_of(ids) {
+            self.remove(id, value);
+        }
+        let len = r.read_u64()? as usize;
+        let ids = r.read_ids(len)?;
+        for &id in ids {
+            let value = V::decode(r.read_bytes(V::SIZE)?);
+            self.insert(id, value);
+        }
+        Ok(())
+    }
+}
+
+// RangeIndex::parse_query over len sorted values, nth returns the nth lowest.
+pub(super) fn parse_percentile_query<'v, V: Clone + FromStr + 'v>(
+    text: &str,
+    len: usize,
+    nth: impl Fn(usize) -> Option<&'v V>,
+) -> Option<RangeQuery<V>> {
+    let percent = |text: &str| -> Option<f64> {
+        let p: f64 = text.strip_suffix('%')?.parse().ok()?;
+        (0.0..=100.0).contains(&p).then_some(p)
+    };
+    if let Some(p) = text.strip_prefix("top").and_then(percent) {
+        if p == 0.0 {
+            return Some(RangeQuery::None);
+        }
+        let top = percentile_rank(len, p).map(|rank| len - 1 - rank);
+        return Some(match top.and_then(nth) {
+            Some(value) => RangeQuery::GTE(value.clone()),
+            None => RangeQuery::All,
+        });
+    }
+    if let Some(p) = text.strip_prefix("bottom").and_then(percent) {
+        if p == 0.0 {
+            return Some(RangeQuery::None);
+        }
+        return Some(match percentile_rank(len, p).and_then(nth) {
+            Some(value) => RangeQuery::LTE(value.clone()),
+            None => RangeQuery::All,
+        });
+    }
+    text.parse().ok()
+}
+
+// Index of the nearest rank percentile p among len sorted values.
+fn percentile_rank(len: usize, p: f64) -> Option<usize> {
+    if len == 0 || !(0.0..=100.0).contains(&p) {
+        return None;
+    }
+    let rank = (p / 100.0 * len as f64).ceil() as usize;
+    Some(rank.clamp(1, len) - 1)
+}
+
+#[derive(Clone, Debug)]
 pub struct ChunkedVec<T> {
     vecs: Vec<Vec<T>>,
     chunk_size: usize,
@@ -386,7 +775,7 @@ impl<T> ChunkedVec<T> {
         }
     }
 
-    pub fn iter(&self) -> ChunkedVecIterator<T> {
+    pub fn iter(&self) -> ChunkedVecIterator<'_, T> {
         ChunkedVecIterator::new(self)
     }
 }
@@ -572,3 +961,37 @@ impl<'a, T> DoubleEndedIterator for ChunkedVecIterator<'a, T> {
         item
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::util::to_checks;
+
+    #[test]
+    fn get_sorted_in_small_filter() {
+        let mut loader = RangeIndex::loader();
+        for id in 0..1000 {
+            loader.add(id, (id * 7) % 10);
+        }
+        let index = loader.load();
+        let base = to_checks(&(0..1000).filter(|&id| id != 20).collect::<Vec<ID>>());
+
+        // Few enough ids to skip filtering base, 20 isn't in base and 2000 has no value.
+        let ids: Vec<ID> = vec![3, 10, 11, 20, 500, 999, 2000];
+        let filter = Queryable::IDs(&ids);
+        // Values 1, 0, 7, 0, 3.
+        assert_eq!(
+            index.get_sorted_in(&filter, &base, 0, 10, false),
+            [10, 500, 3, 999, 11]
+        );
+        assert_eq!(index.get_sorted_in(&filter, &base, 1, 2, true), [999, 3]);
+        assert!(index.id_values.get().is_some());
+
+        // The same page from the path filtering base.
+        let filter = Queryable::Checks(&to_checks(&ids));
+        assert_eq!(
+            index.get_sorted_in(&filter, &base, 0, 10, false),
+            [10, 500, 3, 999, 11]
+        );
+    }
+}
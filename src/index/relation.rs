@@ -0,0 +1,227 @@
+use crate::{
+    query::{Item, Queryable, QueryableOwned},
+    Query, ID,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RelationQuery {
+    /// Children of the id.
+    ChildrenOf(ID),
+    HasParent,
+    NoParent,
+    HasChildren,
+    NoChildren,
+    /// Parent, grandparent and so on of the id.
+    AncestorsOf(ID),
+    /// Children, grandchildren and so on of the id.
+    DescendantsOf(ID),
+}
+
+impl RelationQuery {
+    /// Booru style terms: `parent:123`, `parent:any`, `parent:none`,
+    /// `child:any`, `child:none`, `ancestor_of:123` and `descendant_of:123`.
+    pub fn parse(ident: &str, value: &str) -> Option<Self> {
+        match (ident, value) {
+            ("parent", "any") => Some(Self::HasParent),
+            ("parent", "none") => Some(Self::NoParent),
+            ("parent", id) => id.parse().ok().map(Self::ChildrenOf),
+            ("child", "any") => Some(Self::HasChildren),
+            ("child", "none") => Some(Self::NoChildren),
+            ("ancestor_of", id) => id.parse().ok().map(Self::AncestorsOf),
+            ("descendant_of", id) => id.parse().ok().map(Self::DescendantsOf),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RelationIndexLoader {
+    parents: fxhash::FxHashMap<ID, ID>,
+}
+
+impl RelationIndexLoader {
+    pub fn new() -> Self {
+        Self {
+            parents: fxhash::FxHashMap::default(),
+        }
+    }
+
+    pub fn add(&mut self, id: ID, parent: Option<ID>) {
+        if let Some(parent) = parent {
+            self.parents.insert(id, parent);
+        }
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.parents.extend(other.parents);
+    }
+
+    pub fn load(self) -> RelationIndex {
+        let mut edges: Vec<(ID, ID)> = self.parents.iter().map(|(&c, &p)| (p, c)).collect();
+        edges.sort_unstable();
+        let mut children: fxhash::FxHashMap<ID, QueryableOwned> = fxhash::FxHashMap::default();
+        let mut has_children = QueryableOwned::default();
+        for (parent, child) in edges {
+            let entry = children.entry(parent).or_insert_with(|| {
+                has_children.insert_unchecked(parent);
+                QueryableOwned::default()
+            });
+            entry.insert_unchecked(child);
+        }
+        for queryable in children.values_mut() {
+            queryable.check_and_convert();
+        }
+        has_children.check_and_convert();
+        let mut has_parent: Vec<ID> = self.parents.keys().copied().collect();
+        has_parent.sort_unstable();
+        let mut has_parent = QueryableOwned::from(has_parent);
+        has_parent.check_and_convert();
+        RelationIndex {
+            parents: self.parents,
+            children,
+            has_parent,
+            has_children,
+            max_depth: RelationIndex::DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Parent to children edges, one parent per post.
+pub struct RelationIndex {
+    parents: fxhash::FxHashMap<ID, ID>,
+    children: fxhash::FxHashMap<ID, QueryableOwned>,
+    has_parent: QueryableOwned,
+    has_children: QueryableOwned,
+    max_depth: usize,
+}
+
+impl RelationIndex {
+    /// Levels followed by AncestorsOf and DescendantsOf.
+    pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+    pub fn loader() -> RelationIndexLoader {
+        RelationIndexLoader::new()
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn parent(&self, id: ID) -> Option<ID> {
+        self.parents.get(&id).copied()
+    }
+
+    pub fn children(&self, id: ID) -> Option<Queryable<'_>> {
+        self.children.get(&id).map(|queryable| queryable.into())
+    }
+
+    /// Nearest first, stops at max_depth or a cycle.
+    pub fn ancestors(&self, id: ID) -> Vec<ID> {
+        let mut ancestors = Vec::new();
+        let mut current = id;
+        while let Some(parent) = self.parent(current) {
+            if ancestors.len() >= self.max_depth || parent == id || ancestors.contains(&parent) {
+                break;
+            }
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+
+    /// Sorted by id, stops at max_depth.
+    pub fn descendants(&self, id: ID) -> Vec<ID> {
+        let mut seen = fxhash::FxHashSet::default();
+        let mut level = vec![id];
+        for _ in 0..self.max_depth {
+            let mut next = Vec::new();
+            for parent in level {
+                let Some(children) = self.children.get(&parent) else {
+                    continue;
+                };
+                let ids = match children {
+                    QueryableOwned::IDs { ids } => ids.clone(),
+                    QueryableOwned::Checks { checks, .. } => crate::query::util::to_ids(checks),
+                };
+                next.extend(ids.into_iter().filter(|&child| seen.insert(child)));
+            }
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        seen.remove(&id);
+        let mut descendants: Vec<ID> = seen.into_iter().collect();
+        descendants.sort_unstable();
+        descendants
+    }
+
+    pub fn get(&self, query: RelationQuery) -> Query<Queryable<'_>> {
+        let (queryable, inverse) = match query {
+            RelationQuery::ChildrenOf(id) => match self.children(id) {
+                Some(children) => (children, false),
+                None => return Query::empty(),
+            },
+            RelationQuery::HasParent => ((&self.has_parent).into(), false),
+            RelationQuery::NoParent => ((&self.has_parent).into(), true),
+            RelationQuery::HasChildren => ((&self.has_children).into(), false),
+            RelationQuery::NoChildren => ((&self.has_children).into(), true),
+            RelationQuery::AncestorsOf(id) => {
+                let mut ancestors = self.ancestors(id);
+                ancestors.sort_unstable();
+                (Queryable::IDsOwned(ancestors), false)
+            }
+            RelationQuery::DescendantsOf(id) => (Queryable::IDsOwned(self.descendants(id)), false),
+        };
+        Query::new(Item::Single(queryable), inverse)
+    }
+
+    /// Sets or clears the parent of id.
+    pub fn set_parent(&mut self, id: ID, parent: Option<ID>) {
+        if self.parent(id) == parent {
+            return;
+        }
+        if let Some(old) = self.parents.remove(&id) {
+            self.unlink(old, id);
+        }
+        match parent {
+            Some(parent) => {
+                self.parents.insert(id, parent);
+                self.has_parent.insert(id);
+                let children = self.children.entry(parent).or_default();
+                if children.matched() == 0 {
+                    self.has_children.insert(parent);
+                }
+                children.insert(id);
+            }
+            None => self.has_parent.remove(id),
+        }
+    }
+
+    fn unlink(&mut self, parent: ID, child: ID) {
+        if let Some(children) = self.children.get_mut(&parent) {
+            children.remove(child);
+            if children.matched() == 0 {
+                self.children.remove(&parent);
+                self.has_children.remove(parent);
+            }
+        }
+    }
+
+    /// Removes id and its parent edge, its children are left without a parent.
+    pub fn remove(&mut self, id: ID) {
+        self.set_parent(id, None);
+        if let Some(children) = self.children.remove(&id) {
+            self.has_children.remove(id);
+            let ids = match children {
+                QueryableOwned::IDs { ids } => ids,
+                QueryableOwned::Checks { checks, .. } => crate::query::util::to_ids(&checks),
+            };
+            for child in ids {
+                self.parents.remove(&child);
+                self.has_parent.remove(child);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+
+use super::Glob;
+
+/// A term as routed to an index, see Index::query_term. Identifiers can have their
+/// own prefixes, `ai:solo:>=90` has the path `ai`, `solo` and the value `>=90`.
+/// The value is parsed once here so indexes agree on quoting and lists:
+/// `a,b` is the items `a` and `b`, `"a,b"` the single item `a,b` and is never split
+/// into prefixes. Quoted values can't contain `"`, there are no escapes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Term<'t> {
+    /// The identifier the term was routed by then every prefix after it,
+    /// empty for the default index.
+    pub ident_path: Vec<&'t str>,
+    /// The text after the last prefix, without quotes.
+    pub value: &'t str,
+    /// The text after the identifier, what Index::query is given.
+    pub text: &'t str,
+    /// Whether value was quoted, indexes should match it literally.
+    pub quoted: bool,
+    /// Whether an unquoted value has a `*` or `?` not escaped by `\`, see Glob.
+    pub wildcard: bool,
+    /// value split at each comma, value alone if quoted.
+    pub items: Vec<&'t str>,
+}
+
+impl<'t> Term<'t> {
+    /// Splits the text after ident into prefixes at each colon before a quote.
+    /// Terms of the default index aren't split, their text is the value.
+    pub fn parse(ident: Option<&'t str>, text: &'t str) -> Self {
+        let mut ident_path = Vec::new();
+        let mut value = text;
+        if let Some(ident) = ident {
+            ident_path.push(ident);
+            while let Some((prefix, rest)) = value.split_once(':') {
+                if prefix.starts_with('"') {
+                    break;
+                }
+                ident_path.push(prefix);
+                value = rest;
+            }
+        }
+        let unquoted = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'));
+        if let Some(value) = unquoted {
+            return Self {
+                ident_path,
+                value,
+                text,
+                quoted: true,
+                wildcard: false,
+                items: vec![value],
+            };
+        }
+        Self {
+            ident_path,
+            value,
+            text,
+            quoted: false,
+            wildcard: has_wildcard(value),
+            items: value.split(',').collect(),
+        }
+    }
+
+    /// The identifier the term was routed by, None for the default index.
+    pub fn ident(&self) -> Option<&'t str> {
+        self.ident_path.first().copied()
+    }
+
+    /// The prefixes after the identifier, `solo` in `ai:solo:>=90`.
+    pub fn sub_path(&self) -> &[&'t str] {
+        self.ident_path.get(1..).unwrap_or_default()
+    }
+
+    /// Whether value is a list of more than one item.
+    pub fn is_list(&self) -> bool {
+        self.items.len() > 1
+    }
+
+    /// The term with the text after the identifier as its value, for indexes without
+    /// prefixes of their own such as a time field given `time:10:30`.
+    pub fn without_prefixes(&self) -> Cow<'_, Self> {
+        if self.sub_path().is_empty() {
+            return Cow::Borrowed(self);
+        }
+        let mut term = Self::parse(None, self.text);
+        term.ident_path = self.ident_path[..1].to_vec();
+        Cow::Owned(term)
+    }
+
+    /// The value as a Glob if it has wildcards.
+    pub fn glob(&self) -> Option<Glob> {
+        self.wildcard.then(|| Glob::parse(self.value))
+    }
+}
+
+fn has_wildcard(value: &str) -> bool {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return true,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    false
+}
@@ -1,50 +1,416 @@
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, ops::Range, str::FromStr, sync::Arc};
 
 use crate::ID;
 
+use super::Term;
+
 #[derive(Debug)]
 pub enum TextQuery {
     StartsWith(String),
     Contains(String),
     EndsWith(String),
+    /// Patterns the other variants can't express, such as `foo*bar` or `fo?`.
+    Glob(Glob),
 }
 
 impl TextQuery {
+    /// The text matched, the longest literal part of a Glob.
     pub fn text(&self) -> &str {
         match self {
             Self::StartsWith(text) => text,
             Self::Contains(text) => text,
             Self::EndsWith(text) => text,
+            Self::Glob(glob) => glob.literals().max_by_key(|l| l.len()).unwrap_or(""),
+        }
+    }
+
+    /// Like from_str with quoted values matched literally, wildcards included.
+    pub fn from_term(term: &Term<'_>) -> Option<Self> {
+        if term.quoted {
+            return Some(Self::Contains(term.value.to_string()));
         }
+        term.value.parse().ok()
     }
 }
 
 impl FromStr for TextQuery {
     type Err = ();
 
-    fn from_str(mut s: &str) -> Result<Self, Self::Err> {
-        let starts_with = s.len() > 1 && s.ends_with('*');
-        if starts_with {
-            s = &s[..s.len() - 1];
+    /// `*` matches any run of characters and `?` any one, `\*`, `\?` and `\\` are literal.
+    /// Text without wildcards matches anywhere in a token, a lone `*` is literal.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Self::Contains(s.to_string()));
+        }
+        let glob = Glob::parse(s);
+        Ok(match glob.parts() {
+            [] => Self::Contains(String::new()),
+            [GlobPart::Literal(text)]
+            | [GlobPart::Many, GlobPart::Literal(text), GlobPart::Many] => {
+                Self::Contains(text.clone())
+            }
+            [GlobPart::Literal(text), GlobPart::Many] => Self::StartsWith(text.clone()),
+            [GlobPart::Many, GlobPart::Literal(text)] => Self::EndsWith(text.clone()),
+            _ => Self::Glob(glob),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GlobPart {
+    Literal(String),
+    /// `?`, any one character.
+    One,
+    /// `*`, any run of characters.
+    Many,
+}
+
+/// A pattern matched against a whole token, by characters rather than bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Glob {
+    parts: Vec<GlobPart>,
+}
+
+impl Glob {
+    pub fn parse(pattern: &str) -> Self {
+        let mut glob = Self::default();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => glob.push(GlobPart::Many),
+                '?' => glob.push(GlobPart::One),
+                '\\' if matches!(chars.peek(), Some('*' | '?' | '\\')) => {
+                    let c = chars.next().unwrap();
+                    glob.push_char(c);
+                }
+                c => glob.push_char(c),
+            }
+        }
+        glob
+    }
+
+    fn push(&mut self, part: GlobPart) {
+        if part == GlobPart::Many && self.parts.last() == Some(&GlobPart::Many) {
+            return;
+        }
+        self.parts.push(part);
+    }
+
+    fn push_char(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(GlobPart::Literal(text)) => text.push(c),
+            _ => self.parts.push(GlobPart::Literal(c.to_string())),
+        }
+    }
+
+    pub fn parts(&self) -> &[GlobPart] {
+        &self.parts
+    }
+
+    pub fn literals(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            GlobPart::Literal(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The glob with f applied to each literal part, such as Tokenizer::normalize.
+    pub fn map_literals(&self, f: impl Fn(&str) -> String) -> Self {
+        let parts = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                GlobPart::Literal(text) => GlobPart::Literal(f(text)),
+                part => part.clone(),
+            })
+            .collect();
+        Self { parts }
+    }
+
+    /// Whether the whole of text matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut pattern = Vec::new();
+        for part in &self.parts {
+            match part {
+                GlobPart::Literal(literal) => pattern.extend(literal.chars().map(GlobChar::Char)),
+                GlobPart::One => pattern.push(GlobChar::One),
+                GlobPart::Many => pattern.push(GlobChar::Many),
+            }
+        }
+        let text: Vec<char> = text.chars().collect();
+        let (mut p, mut t) = (0, 0);
+        // The last `*` seen and the text position it's retried from.
+        let mut retry = None;
+        while t < text.len() {
+            match pattern.get(p) {
+                Some(GlobChar::Char(c)) if *c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                Some(GlobChar::One) => {
+                    p += 1;
+                    t += 1;
+                }
+                Some(GlobChar::Many) => {
+                    retry = Some((p, t));
+                    p += 1;
+                }
+                _ => match retry {
+                    // The `*` takes one more character.
+                    Some((star, from)) => {
+                        retry = Some((star, from + 1));
+                        p = star + 1;
+                        t = from + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|c| matches!(c, GlobChar::Many))
+    }
+}
+
+enum GlobChar {
+    Char(char),
+    One,
+    Many,
+}
+
+/// Splits the strings a TextIndex holds into the tokens queries are matched against,
+/// a string matches if any of its tokens do.
+pub trait Tokenizer: Send + Sync {
+    fn tokens(&self, text: &str) -> Vec<String>;
+
+    /// Applied to query text, such as lowercasing it to match lowercased tokens.
+    fn normalize(&self, text: &str) -> String {
+        text.to_string()
+    }
+
+    /// Tokens with the byte range of text each came from, used for highlighting.
+    /// By default every place a token occurs in text, tokens that don't occur are skipped.
+    fn token_spans(&self, text: &str) -> Vec<(Range<usize>, String)> {
+        let mut tokens = self.tokens(text);
+        tokens.sort_unstable();
+        tokens.dedup();
+        let mut spans = Vec::new();
+        for token in tokens.into_iter().filter(|token| !token.is_empty()) {
+            for (start, _) in text.match_indices(token.as_str()) {
+                spans.push((start..start + token.len(), token.clone()));
+            }
         }
-        let ends_with = s.len() > 1 && s.starts_with('*');
-        if ends_with {
-            s = &s[1..];
+        spans
+    }
+}
+
+/// The whole string is the only token, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WholeText;
+
+impl Tokenizer for WholeText {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        vec![text.to_string()]
+    }
+}
+
+/// Tokens are the whole string and its parts split on separators,
+/// so `hair*` finds `long_hair` with `_` as a separator.
+#[derive(Clone, Debug)]
+pub struct SplitText {
+    separators: Vec<char>,
+    lowercase: bool,
+}
+
+impl SplitText {
+    pub fn new(separators: impl IntoIterator<Item = char>) -> Self {
+        Self {
+            separators: separators.into_iter().collect(),
+            lowercase: false,
         }
-        let s = s.to_string();
-        if !(starts_with ^ ends_with) {
-            Ok(Self::Contains(s))
-        } else if starts_with {
-            Ok(Self::StartsWith(s))
+    }
+
+    /// Lowercases tokens and queries.
+    pub fn lowercase(mut self) -> Self {
+        self.lowercase = true;
+        self
+    }
+}
+
+impl Tokenizer for SplitText {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        let text = self.normalize(text);
+        let mut tokens: Vec<String> = text
+            .split(self.separators.as_slice())
+            .filter(|part| !part.is_empty() && part.len() < text.len())
+            .map(str::to_string)
+            .collect();
+        tokens.push(text);
+        tokens
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        if self.lowercase {
+            text.to_lowercase()
         } else {
-            Ok(Self::EndsWith(s))
+            text.to_string()
+        }
+    }
+
+    fn token_spans(&self, text: &str) -> Vec<(Range<usize>, String)> {
+        let mut spans = vec![(0..text.len(), self.normalize(text))];
+        let mut start = 0;
+        for (end, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            if end == text.len() || self.separators.contains(&c) {
+                if start < end && end - start < text.len() {
+                    spans.push((start..end, self.normalize(&text[start..end])));
+                }
+                start = end + c.len_utf8();
+            }
+        }
+        spans
+    }
+}
+
+/// Like SplitText on whitespace but runs of CJK characters, which aren't separated
+/// by spaces, are also split into overlapping bigrams so `東方*` finds words inside them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CjkBigrams;
+
+impl Tokenizer for CjkBigrams {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        let mut tokens = vec![text.to_string()];
+        for word in text.split_whitespace() {
+            let mut run: Vec<char> = Vec::new();
+            let mut other = String::new();
+            for c in word.chars().chain([' ']) {
+                if is_cjk(c) {
+                    if !other.is_empty() {
+                        tokens.push(std::mem::take(&mut other));
+                    }
+                    run.push(c);
+                    continue;
+                }
+                match run.len() {
+                    0 => {}
+                    1 => tokens.push(run[0].to_string()),
+                    _ => tokens.extend(run.windows(2).map(|pair| pair.iter().collect())),
+                }
+                run.clear();
+                if c != ' ' {
+                    other.push(c);
+                }
+            }
+            if !other.is_empty() {
+                tokens.push(other);
+            }
+        }
+        tokens
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff // kana
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xac00..=0xd7af // hangul
+        | 0xf900..=0xfaff)
+}
+
+/// Closures returning the tokens of a string, such as a stemmer.
+impl<F: Fn(&str) -> Vec<String> + Send + Sync> Tokenizer for F {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        self(text)
+    }
+}
+
+// Sorts spans and joins the ones that overlap or touch.
+fn merge_spans(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|span| (span.start, span.end));
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(spans.len());
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+/// Handle of a string in a StringPool.
+pub type StringHandle = u32;
+
+/// Strings stored once and referred to by handle, shared by every gram bucket of a
+/// TextIndex. Strings are reference counted and dropped when the last handle is released.
+#[derive(Default)]
+pub struct StringPool {
+    strings: Vec<Option<Arc<str>>>,
+    refs: Vec<u32>,
+    handles: HashMap<Arc<str>, StringHandle>,
+    free: Vec<StringHandle>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The handle of text, adding a reference to it.
+    pub fn intern(&mut self, text: &str) -> StringHandle {
+        if let Some(&handle) = self.handles.get(text) {
+            self.refs[handle as usize] += 1;
+            return handle;
         }
+        let text: Arc<str> = text.into();
+        let handle = match self.free.pop() {
+            Some(handle) => {
+                self.strings[handle as usize] = Some(text.clone());
+                self.refs[handle as usize] = 1;
+                handle
+            }
+            None => {
+                self.strings.push(Some(text.clone()));
+                self.refs.push(1);
+                (self.strings.len() - 1) as StringHandle
+            }
+        };
+        self.handles.insert(text, handle);
+        handle
+    }
+
+    /// Removes a reference, the string is dropped after the last one.
+    pub fn release(&mut self, handle: StringHandle) {
+        let refs = &mut self.refs[handle as usize];
+        *refs -= 1;
+        if *refs == 0 {
+            let text = self.strings[handle as usize].take().unwrap();
+            self.handles.remove(&text);
+            self.free.push(handle);
+        }
+    }
+
+    pub fn handle(&self, text: &str) -> Option<StringHandle> {
+        self.handles.get(text).copied()
+    }
+
+    /// Panics if handle was released.
+    pub fn get(&self, handle: StringHandle) -> &Arc<str> {
+        self.strings[handle as usize].as_ref().unwrap()
+    }
+
+    /// Number of distinct strings.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
     }
 }
 
+/// Tokens by the N byte grams in them as (token handle, string id) sorted by id then handle.
 #[derive(Default)]
 pub struct NgramIndex<const N: usize> {
-    strings: HashMap<[u8; N], Vec<(Arc<str>, ID)>>,
+    strings: HashMap<[u8; N], Vec<(StringHandle, ID)>>,
 }
 
 impl<const N: usize> NgramIndex<N> {
@@ -71,8 +437,8 @@ impl<const N: usize> NgramIndex<N> {
         grams
     }
 
-    pub fn query(&self, text: &str) -> Option<&[(Arc<str>, ID)]> {
-        let mut smallest: Option<&[(Arc<str>, ID)]> = None;
+    pub fn query(&self, text: &str) -> Option<&[(StringHandle, ID)]> {
+        let mut smallest: Option<&[(StringHandle, ID)]> = None;
         for gram in Self::grams(text) {
             if let Some(strings) = self.strings.get(&gram) {
                 if strings.len() < smallest.map(|s| s.len()).unwrap_or(usize::MAX) {
@@ -83,30 +449,32 @@ impl<const N: usize> NgramIndex<N> {
         smallest
     }
 
-    pub fn insert(&mut self, id: ID, text: Arc<str>) {
-        for gram in Self::grams(&text) {
+    /// text is the string of handle.
+    pub fn insert(&mut self, id: ID, handle: StringHandle, text: &str) {
+        for gram in Self::grams(text) {
             let strings = self.strings.entry(gram).or_default();
             let index = strings
-                .binary_search_by_key(&id, |s| s.1)
+                .binary_search_by(|s| (s.1, s.0).cmp(&(id, handle)))
                 .unwrap_or_else(|e| e);
-            strings.insert(index, (text.clone(), id));
+            strings.insert(index, (handle, id));
         }
     }
 
-    /// Only use if id is greater than any existing text
-    pub fn push(&mut self, id: ID, text: Arc<str>) {
-        for gram in Self::grams(&text) {
+    /// Only use if id is greater than any existing id,
+    /// or equal with handle greater than the handles of id.
+    pub fn push(&mut self, id: ID, handle: StringHandle, text: &str) {
+        for gram in Self::grams(text) {
             let strings = self.strings.entry(gram).or_default();
-            strings.push((text.clone(), id));
+            strings.push((handle, id));
         }
     }
 
-    pub fn remove(&mut self, id: ID, text: Arc<str>) {
-        for gram in Self::grams(&text) {
+    pub fn remove(&mut self, id: ID, handle: StringHandle, text: &str) {
+        for gram in Self::grams(text) {
             let Some(strings) = self.strings.get_mut(&gram) else {
                 continue;
             };
-            let Ok(index) = strings.binary_search_by_key(&id, |s| s.1) else {
+            let Ok(index) = strings.binary_search_by(|s| (s.1, s.0).cmp(&(id, handle))) else {
                 continue;
             };
             strings.remove(index);
@@ -117,69 +485,147 @@ impl<const N: usize> NgramIndex<N> {
     }
 }
 
-#[derive(Default)]
+/// Strings such as tag or artist names searched by prefix, suffix or substring.
 pub struct TextIndexLoader {
     next_id: ID,
-    ids_by_string: HashMap<Arc<str>, ID>,
+    pool: StringPool,
+    ids_by_string: HashMap<StringHandle, ID>,
+    strings: HashMap<ID, StringHandle>,
+    tokenizer: Arc<dyn Tokenizer>,
     n1gram_index: NgramIndex<1>,
     n2gram_index: NgramIndex<2>,
 }
 
+impl Default for TextIndexLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TextIndexLoader {
     pub fn new() -> Self {
         Self {
             next_id: 0,
+            pool: StringPool::new(),
             ids_by_string: HashMap::new(),
+            strings: HashMap::new(),
+            tokenizer: Arc::new(WholeText),
             n1gram_index: NgramIndex::new(),
             n2gram_index: NgramIndex::new(),
         }
     }
 
+    /// Set before adding strings, the default WholeText matches the whole string.
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.tokenizer = Arc::new(tokenizer);
+        self
+    }
+
+    /// Strings already added are skipped.
     pub fn add(&mut self, text: String) {
+        if self
+            .pool
+            .handle(&text)
+            .is_some_and(|h| self.ids_by_string.contains_key(&h))
+        {
+            return;
+        }
         let id = self.next_id;
         self.next_id += 1;
-        let text: Arc<str> = text.into();
-        self.ids_by_string.insert(text.clone(), id);
-        self.n1gram_index.push(id, text.clone());
-        self.n2gram_index.push(id, text.clone());
+        let handle = self.pool.intern(&text);
+        self.ids_by_string.insert(handle, id);
+        self.strings.insert(id, handle);
+        for token in intern_tokens(&mut self.pool, &*self.tokenizer, &text) {
+            let token_text = self.pool.get(token);
+            self.n1gram_index.push(id, token, token_text);
+            self.n2gram_index.push(id, token, token_text);
+        }
     }
 
     pub fn load(self) -> TextIndex {
         TextIndex {
             next_id: self.next_id,
+            pool: self.pool,
             ids_by_string: self.ids_by_string,
+            strings: self.strings,
+            tokenizer: self.tokenizer,
             n1gram_index: self.n1gram_index,
             n2gram_index: self.n2gram_index,
         }
     }
 }
 
+// Handles of the distinct tokens of text sorted, the order NgramIndex keeps them in.
+fn intern_tokens(
+    pool: &mut StringPool,
+    tokenizer: &dyn Tokenizer,
+    text: &str,
+) -> Vec<StringHandle> {
+    let mut tokens = tokenizer.tokens(text);
+    tokens.sort_unstable();
+    tokens.dedup();
+    let mut handles: Vec<StringHandle> = tokens.iter().map(|token| pool.intern(token)).collect();
+    handles.sort_unstable();
+    handles
+}
+
 pub struct TextIndex {
     next_id: ID,
-    ids_by_string: HashMap<Arc<str>, ID>,
+    // Holds every string and token once, gram buckets only have handles.
+    pool: StringPool,
+    ids_by_string: HashMap<StringHandle, ID>,
+    strings: HashMap<ID, StringHandle>,
+    tokenizer: Arc<dyn Tokenizer>,
     n1gram_index: NgramIndex<1>,
     n2gram_index: NgramIndex<2>,
 }
 
 impl TextIndex {
+    /// Strings with a token matching query, see Tokenizer.
     pub fn get(&self, query: &TextQuery) -> Vec<Arc<str>> {
-        let text = query.text();
-        let Some(mut smallest) = (match text.len() {
+        let glob = match query {
+            TextQuery::Glob(glob) => Some(glob.map_literals(|text| self.tokenizer.normalize(text))),
+            _ => None,
+        };
+        let text = self.tokenizer.normalize(query.text());
+        let text = text.as_str();
+        if text.is_empty() {
+            return match glob {
+                Some(glob) => self.scan(|token| glob.is_match(token)),
+                None => Vec::new(),
+            };
+        }
+        let Some(candidates) = self.candidates(text) else {
+            return Vec::new();
+        };
+        let mut ids: Vec<ID> = candidates
+            .iter()
+            .filter(|(token, _)| {
+                let token = self.pool.get(*token);
+                match query {
+                    TextQuery::StartsWith(_) => token.starts_with(text),
+                    TextQuery::Contains(_) => token.contains(text),
+                    TextQuery::EndsWith(_) => token.ends_with(text),
+                    TextQuery::Glob(_) => glob.as_ref().is_some_and(|glob| glob.is_match(token)),
+                }
+            })
+            .map(|(_, id)| *id)
+            .collect();
+        ids.dedup();
+        ids.iter()
+            .map(|id| self.pool.get(self.strings[id]).clone())
+            .collect()
+    }
+
+    // Tokens that may contain text by their n-grams, None if none can.
+    fn candidates(&self, text: &str) -> Option<Cow<'_, [(StringHandle, ID)]>> {
+        let smallest = match text.len() {
             0 => None,
             1 => self.n1gram_index.query(text),
             _ => self.n2gram_index.query(text),
-        }) else {
-            return Vec::new();
-        };
-        let mut matches = Vec::with_capacity(smallest.len());
-        if query.text().len() <= 2 && matches!(query, TextQuery::Contains(_)) {
-            for (s, _) in smallest {
-                matches.push(s.clone());
-            }
-        }
-        let mut strings;
+        }?;
         if text.len() >= 4 {
-            let mut bytes = query.text().bytes();
+            let mut bytes = text.bytes();
             let mut grams = Vec::with_capacity((text.len() as f32 / 2.0).ceil() as usize);
             while let (Some(b0), Some(b1)) = (bytes.next(), bytes.next()) {
                 grams.push([b0, b1]);
@@ -192,11 +638,12 @@ impl TextIndex {
                     .filter_map(|g| self.n2gram_index.strings.get(g))
                     .collect();
                 if grams.len() != indexes.len() {
-                    return Vec::new();
+                    return None;
                 }
                 indexes.sort_by_key(|g| g.len());
-                strings = indexes[0].clone();
+                let mut strings = indexes[0].clone();
                 for strings_b in &indexes[1..] {
+                    // A string can have several tokens so ids repeat.
                     let mut cursor = 0;
                     strings.retain(|(_, id)| {
                         while let Some((_, id_b)) = strings_b.get(cursor) {
@@ -204,66 +651,151 @@ impl TextIndex {
                                 cursor += 1;
                                 continue;
                             }
-                            if id_b == id {
-                                cursor += 1;
-                                return true;
-                            }
-                            return false;
+                            return id_b == id;
                         }
                         false
                     });
                 }
                 if strings.len() < smallest.len() {
-                    smallest = strings.as_slice();
+                    return Some(Cow::Owned(strings));
                 }
             }
         }
+        Some(Cow::Borrowed(smallest))
+    }
 
-        match query {
-            TextQuery::StartsWith(text) => {
-                for (s, _) in smallest {
-                    if s.starts_with(text) {
-                        matches.push(s.clone());
-                    }
-                }
-            }
-            TextQuery::Contains(text) => {
-                for (s, _) in smallest {
-                    if s.contains(text) {
-                        matches.push(s.clone());
-                    }
-                }
-            }
-            TextQuery::EndsWith(text) => {
-                for (s, _) in smallest {
-                    if s.ends_with(text) {
-                        matches.push(s.clone());
-                    }
+    // Strings with a token matching f, checking every string for globs without literals.
+    fn scan(&self, f: impl Fn(&str) -> bool) -> Vec<Arc<str>> {
+        let mut ids: Vec<ID> = self
+            .strings
+            .iter()
+            .filter(|(_, &handle)| {
+                let tokens = self.tokenizer.tokens(self.pool.get(handle));
+                tokens.iter().any(|token| f(token))
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids.iter()
+            .map(|id| self.pool.get(self.strings[id]).clone())
+            .collect()
+    }
+
+    /// Like get with the byte ranges of each string matched by query, see highlight.
+    pub fn get_highlighted(&self, query: &TextQuery) -> Vec<(Arc<str>, Vec<Range<usize>>)> {
+        self.get(query)
+            .into_iter()
+            .map(|string| {
+                let spans = self.highlight(query, &string);
+                (string, spans)
+            })
+            .collect()
+    }
+
+    /// Byte ranges of text matched by query, sorted and without overlaps, such as
+    /// where `*maid*` is in a source url. A token normalized to a different length
+    /// than the text it came from is highlighted whole.
+    pub fn highlight(&self, query: &TextQuery, text: &str) -> Vec<Range<usize>> {
+        if let TextQuery::Glob(glob) = query {
+            // Globs match whole tokens.
+            let glob = glob.map_literals(|text| self.tokenizer.normalize(text));
+            let spans = self
+                .tokenizer
+                .token_spans(text)
+                .into_iter()
+                .filter(|(_, token)| glob.is_match(token))
+                .map(|(range, _)| range)
+                .collect();
+            return merge_spans(spans);
+        }
+        let needle = self.tokenizer.normalize(query.text());
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        let mut spans = Vec::new();
+        for (range, token) in self.tokenizer.token_spans(text) {
+            let matches: Vec<usize> = match query {
+                TextQuery::StartsWith(_) => token
+                    .starts_with(&needle)
+                    .then_some(0)
+                    .into_iter()
+                    .collect(),
+                TextQuery::Contains(_) => token.match_indices(&needle).map(|(i, _)| i).collect(),
+                TextQuery::EndsWith(_) => token
+                    .ends_with(&needle)
+                    .then(|| token.len() - needle.len())
+                    .into_iter()
+                    .collect(),
+                TextQuery::Glob(_) => unreachable!(),
+            };
+            for offset in matches {
+                let span = range.start + offset..range.start + offset + needle.len();
+                if token.len() == range.len()
+                    && text.is_char_boundary(span.start)
+                    && text.is_char_boundary(span.end)
+                {
+                    spans.push(span);
+                } else {
+                    spans.push(range.clone());
                 }
             }
         }
-        matches
+        merge_spans(spans)
     }
 
     pub fn insert(&mut self, text: String) {
-        let text: Arc<str> = text.into();
-        if self.ids_by_string.contains_key(&text) {
+        if self.contains(&text) {
             return;
         }
         let id = self.next_id;
         self.next_id += 1;
-        self.ids_by_string.insert(text.clone(), id);
-        self.n1gram_index.insert(id, text.clone());
-        self.n2gram_index.insert(id, text.clone());
+        let handle = self.pool.intern(&text);
+        self.ids_by_string.insert(handle, id);
+        self.strings.insert(id, handle);
+        for token in intern_tokens(&mut self.pool, &*self.tokenizer, &text) {
+            let token_text = self.pool.get(token);
+            self.n1gram_index.insert(id, token, token_text);
+            self.n2gram_index.insert(id, token, token_text);
+        }
     }
 
     pub fn remove(&mut self, text: String) {
-        let text: Arc<str> = text.into();
-        if !self.ids_by_string.contains_key(&text) {
+        let Some(handle) = self.pool.handle(&text) else {
             return;
+        };
+        let Some(id) = self.ids_by_string.remove(&handle) else {
+            return;
+        };
+        self.strings.remove(&id);
+        let mut tokens = self.tokenizer.tokens(&text);
+        tokens.sort_unstable();
+        tokens.dedup();
+        for token_text in tokens {
+            let token = self.pool.handle(&token_text).unwrap();
+            self.n1gram_index.remove(id, token, &token_text);
+            self.n2gram_index.remove(id, token, &token_text);
+            self.pool.release(token);
         }
-        let id = self.ids_by_string.remove(&text).unwrap();
-        self.n1gram_index.remove(id, text.clone());
-        self.n2gram_index.remove(id, text.clone());
+        self.pool.release(handle);
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        self.pool
+            .handle(text)
+            .is_some_and(|handle| self.ids_by_string.contains_key(&handle))
+    }
+
+    /// Number of strings.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// The strings and tokens held, each stored once.
+    pub fn pool(&self) -> &StringPool {
+        &self.pool
     }
 }
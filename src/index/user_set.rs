@@ -0,0 +1,111 @@
+use std::{borrow::Borrow, hash::Hash};
+
+use crate::{query::Queryable, ID};
+
+use super::OrderedIds;
+
+#[derive(Default)]
+pub struct UserSetIndexLoader<U> {
+    items: fxhash::FxHashMap<U, Vec<ID>>,
+}
+
+impl<U: Eq + Hash> UserSetIndexLoader<U> {
+    pub fn new() -> Self {
+        Self {
+            items: fxhash::FxHashMap::default(),
+        }
+    }
+
+    /// Ids are kept in the order they are added for each user.
+    pub fn add(&mut self, user: U, id: ID) {
+        self.items.entry(user).or_default().push(id);
+    }
+
+    pub fn load(self) -> UserSetIndex<U> {
+        let items = self
+            .items
+            .into_iter()
+            .map(|(user, order)| (user, OrderedIds::from_order(order)))
+            .collect();
+        UserSetIndex { items }
+    }
+}
+
+/// Per user sets of ids, such as favorites or upvotes, kept in the order they were added.
+/// Backs queries like `fav:username` and sorting like `ordfav:username`.
+pub struct UserSetIndex<U: Eq + Hash> {
+    items: fxhash::FxHashMap<U, OrderedIds>,
+}
+
+impl<U: Eq + Hash> UserSetIndex<U> {
+    pub fn loader() -> UserSetIndexLoader<U> {
+        UserSetIndexLoader::new()
+    }
+
+    #[inline(always)]
+    pub fn get<Q>(&self, user: &Q) -> Option<Queryable<'_>>
+    where
+        U: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items.get(user).map(OrderedIds::queryable)
+    }
+
+    #[inline(always)]
+    pub fn matched<Q>(&self, user: &Q) -> Option<usize>
+    where
+        U: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items.get(user).map(OrderedIds::len)
+    }
+
+    pub fn contains<Q>(&self, user: &Q, id: ID) -> bool
+    where
+        U: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items.get(user).is_some_and(|ids| ids.contains(id))
+    }
+
+    /// A user's ids oldest first, for QueryResult::get_sorted.
+    pub fn ordered<Q>(&self, user: &Q) -> Option<&OrderedIds>
+    where
+        U: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.items.get(user)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &U> {
+        self.items.keys()
+    }
+
+    /// Appends id to user's set, returns false if it was already present.
+    pub fn insert(&mut self, user: U, id: ID) -> bool {
+        self.items.entry(user).or_default().push(id)
+    }
+
+    pub fn remove<Q>(&mut self, user: &Q, id: ID) -> bool
+    where
+        U: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let Some(ids) = self.items.get_mut(user) else {
+            return false;
+        };
+        let removed = ids.remove(id).is_some();
+        if ids.is_empty() {
+            self.items.remove(user);
+        }
+        removed
+    }
+
+    /// Removes id from every user, for when the post itself is removed.
+    pub fn remove_id(&mut self, id: ID) {
+        self.items.retain(|_, ids| {
+            ids.remove(id);
+            !ids.is_empty()
+        });
+    }
+}
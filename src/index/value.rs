@@ -0,0 +1,251 @@
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    mem,
+    ops::Bound::{Excluded, Unbounded},
+    str::FromStr,
+};
+
+use crate::{
+    index::{keys::chain, range::parse_percentile_query},
+    query::{Item, Queryable},
+    Query, ID,
+};
+
+use super::{KeyIndex, KeyIndexLoader, RangeIndex, RangeIndexLoader, RangeQuery};
+
+/// Loads a ValueIndex, as a KeyIndex if there are at most max_keys distinct values.
+pub struct ValueIndexLoader<V> {
+    values: RangeIndexLoader<V>,
+    max_keys: usize,
+}
+
+impl<V: Clone + Eq + Hash + Ord> Default for ValueIndexLoader<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Clone + Eq + Hash + Ord> ValueIndexLoader<V> {
+    pub const DEFAULT_MAX_KEYS: usize = 256;
+
+    pub fn new() -> Self {
+        Self {
+            values: RangeIndexLoader::new(),
+            max_keys: Self::DEFAULT_MAX_KEYS,
+        }
+    }
+
+    /// Distinct values kept as keys before the index becomes a RangeIndex.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    pub fn add(&mut self, id: ID, value: V) {
+        self.values.add(id, value);
+    }
+
+    /// Safe if other's ids are higher than any id self has.
+    pub fn merge(&mut self, other: Self) {
+        self.values.merge(other.values);
+    }
+
+    pub fn load(self) -> ValueIndex<V> {
+        let mut distinct = HashSet::new();
+        for (value, _) in self.values.values() {
+            distinct.insert(value);
+            if distinct.len() > self.max_keys {
+                break;
+            }
+        }
+        let inner = if distinct.len() <= self.max_keys {
+            let mut keys = KeyIndexLoader::new();
+            for (value, id) in self.values.values() {
+                keys.add(*id, value);
+            }
+            Inner::Key(keys.load())
+        } else {
+            Inner::Range(self.values.load())
+        };
+        ValueIndex {
+            inner,
+            max_keys: self.max_keys,
+        }
+    }
+}
+
+/// A field index for when the number of distinct values isn't known up front.
+/// Kept as a KeyIndex of each value while there are at most max_keys of them,
+/// such as ratings, and becomes a RangeIndex for good once there are more.
+/// Queries are RangeIndex queries either way.
+#[derive(Clone)]
+pub struct ValueIndex<V: Eq + Hash> {
+    inner: Inner<V>,
+    max_keys: usize,
+}
+
+#[derive(Clone)]
+enum Inner<V: Eq + Hash> {
+    Key(KeyIndex<V>),
+    Range(RangeIndex<V>),
+}
+
+impl<V: Clone + Eq + Hash + Ord> ValueIndex<V> {
+    pub fn loader() -> ValueIndexLoader<V> {
+        ValueIndexLoader::new()
+    }
+
+    pub fn max_keys(&self) -> usize {
+        self.max_keys
+    }
+
+    pub fn as_key(&self) -> Option<&KeyIndex<V>> {
+        match &self.inner {
+            Inner::Key(keys) => Some(keys),
+            Inner::Range(_) => None,
+        }
+    }
+
+    pub fn as_range(&self) -> Option<&RangeIndex<V>> {
+        match &self.inner {
+            Inner::Key(_) => None,
+            Inner::Range(range) => Some(range),
+        }
+    }
+
+    /// Like RangeIndex::query, inverse only matches ids that have a value.
+    pub fn query(&self, query: RangeQuery<V>, inverse: bool) -> Query<Queryable<'_>> {
+        let keys = match &self.inner {
+            Inner::Key(keys) => keys,
+            Inner::Range(range) => return range.query(query, inverse),
+        };
+        if matches!(query, RangeQuery::None) {
+            let mut present = keys.get_range(..);
+            present.inverse = !inverse;
+            return present;
+        }
+        let mut query = self.get(query);
+        if !inverse {
+            return query;
+        }
+        query.inverse = true;
+        Query::new(Item::AndChain(vec![keys.get_range(..), query]), false)
+    }
+
+    pub fn get(&self, query: RangeQuery<V>) -> Query<Queryable<'_>> {
+        let keys = match &self.inner {
+            Inner::Key(keys) => keys,
+            Inner::Range(range) => return range.get(query),
+        };
+        match query {
+            RangeQuery::EQ(value) => chain([keys.get(&value)].into_iter(), false),
+            RangeQuery::GT(value) => keys.get_range((Excluded(value), Unbounded)),
+            RangeQuery::GTE(value) => keys.get_range(value..),
+            RangeQuery::LT(value) => keys.get_range(..value),
+            RangeQuery::LTE(value) => keys.get_range(..=value),
+            RangeQuery::Range(min, max) => keys.get_range(min..=max),
+            RangeQuery::All => keys.get_range(..),
+            RangeQuery::None => {
+                let mut present = keys.get_range(..);
+                present.inverse = true;
+                present
+            }
+        }
+    }
+
+    /// See RangeIndex::parse_query.
+    pub fn parse_query(&self, text: &str) -> Option<RangeQuery<V>>
+    where
+        V: FromStr,
+    {
+        parse_percentile_query(text, self.len(), |n| self.nth_value(n))
+    }
+
+    /// Number of values, one per id with a value.
+    pub fn len(&self) -> usize {
+        match &self.inner {
+            Inner::Key(keys) => keys.counts().map(|(_, count)| count).sum(),
+            Inner::Range(range) => range.values().len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The nth lowest value, duplicates included.
+    pub fn nth_value(&self, n: usize) -> Option<&V> {
+        let keys = match &self.inner {
+            Inner::Key(keys) => keys,
+            Inner::Range(range) => return range.nth_value(n),
+        };
+        let mut counts: Vec<(&V, usize)> = keys.counts().collect();
+        counts.sort_unstable();
+        let mut seen = 0;
+        for (value, count) in counts {
+            seen += count;
+            if n < seen {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub fn contains(&self, value: &V, id: ID) -> bool {
+        match &self.inner {
+            Inner::Key(keys) => keys.items.get(value).is_some_and(|ids| ids.contains(id)),
+            Inner::Range(range) => range.contains(value, id),
+        }
+    }
+
+    pub fn insert(&mut self, id: ID, value: V) {
+        match &mut self.inner {
+            Inner::Key(keys) => {
+                keys.insert(id, &value);
+                if keys.len() > self.max_keys {
+                    self.convert();
+                }
+            }
+            Inner::Range(range) => range.insert(id, value),
+        }
+    }
+
+    pub fn remove(&mut self, id: ID, value: V) {
+        match &mut self.inner {
+            Inner::Key(keys) => keys.remove(id, &value),
+            Inner::Range(range) => range.remove(id, value),
+        }
+    }
+
+    pub fn update(&mut self, id: ID, old: V, new: V) {
+        if old == new {
+            return;
+        }
+        self.remove(id, old);
+        self.insert(id, new);
+    }
+
+    /// See Index::verify.
+    pub fn verify(&self) -> Vec<String> {
+        match &self.inner {
+            Inner::Key(keys) => keys.verify(),
+            Inner::Range(range) => range.verify(),
+        }
+    }
+
+    // Rebuilds the keys as a RangeIndex.
+    fn convert(&mut self) {
+        let Inner::Key(keys) = mem::replace(&mut self.inner, Inner::Range(RangeIndex::new()))
+        else {
+            return;
+        };
+        let mut loader = RangeIndexLoader::new();
+        for (value, ids) in keys {
+            for id in ids.to_ids() {
+                loader.add(id, value.clone());
+            }
+        }
+        self.inner = Inner::Range(loader.load());
+    }
+}
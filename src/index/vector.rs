@@ -0,0 +1,240 @@
+use crate::{
+    query::{Item, Queryable},
+    Query, QueryResult, ID,
+};
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+#[inline(always)]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn nearest(centroids: &[Vec<f32>], vector: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(index, centroid)| (index, dot(centroid, vector)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+pub struct VectorIndexLoader {
+    dims: usize,
+    lists: usize,
+    vectors: Vec<(ID, Vec<f32>)>,
+}
+
+impl VectorIndexLoader {
+    /// lists is the number of k-means clusters, around sqrt(posts) is a good start.
+    pub fn new(dims: usize, lists: usize) -> Self {
+        Self {
+            dims,
+            lists: lists.max(1),
+            vectors: Vec::new(),
+        }
+    }
+
+    /// Vectors with the wrong number of dimensions are ignored.
+    pub fn add(&mut self, id: ID, mut vector: Vec<f32>) {
+        if vector.len() != self.dims {
+            return;
+        }
+        normalize(&mut vector);
+        self.vectors.push((id, vector));
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.vectors.extend(other.vectors);
+    }
+
+    pub fn load(self) -> VectorIndex {
+        const ITERATIONS: usize = 10;
+        let lists = self.lists.min(self.vectors.len()).max(1);
+        // Evenly spaced vectors as the initial centroids keeps loading deterministic.
+        let mut centroids: Vec<Vec<f32>> = (0..lists)
+            .filter_map(|i| self.vectors.get(i * self.vectors.len() / lists))
+            .map(|(_, vector)| vector.clone())
+            .collect();
+        if centroids.is_empty() {
+            centroids.push(vec![0.0; self.dims]);
+        }
+        let mut assignments = vec![0; self.vectors.len()];
+        for _ in 0..ITERATIONS {
+            let mut changed = false;
+            for ((_, vector), assignment) in self.vectors.iter().zip(assignments.iter_mut()) {
+                let list = nearest(&centroids, vector);
+                changed |= list != *assignment;
+                *assignment = list;
+            }
+            let mut sums = vec![vec![0.0; self.dims]; centroids.len()];
+            for ((_, vector), &assignment) in self.vectors.iter().zip(&assignments) {
+                sums[assignment]
+                    .iter_mut()
+                    .zip(vector)
+                    .for_each(|(s, v)| *s += v);
+            }
+            for (centroid, mut sum) in centroids.iter_mut().zip(sums) {
+                if sum.iter().any(|v| *v != 0.0) {
+                    normalize(&mut sum);
+                    *centroid = sum;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let mut index = VectorIndex {
+            dims: self.dims,
+            lists: vec![Vec::new(); centroids.len()],
+            centroids,
+            vectors: fxhash::FxHashMap::default(),
+            probes: 8,
+        };
+        for ((id, vector), assignment) in self.vectors.into_iter().zip(assignments) {
+            index.lists[assignment].push(id);
+            index.vectors.insert(id, (assignment, vector));
+        }
+        index
+    }
+}
+
+/// Per post embeddings with approximate nearest neighbor search by cosine similarity.
+/// Vectors are clustered with k-means and only the closest clusters are searched.
+pub struct VectorIndex {
+    dims: usize,
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<ID>>,
+    vectors: fxhash::FxHashMap<ID, (usize, Vec<f32>)>,
+    probes: usize,
+}
+
+impl VectorIndex {
+    pub fn loader(dims: usize, lists: usize) -> VectorIndexLoader {
+        VectorIndexLoader::new(dims, lists)
+    }
+
+    /// Clusters searched per query, more is slower but more accurate.
+    pub fn with_probes(mut self, probes: usize) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+
+    pub fn dims(&self) -> usize {
+        self.dims
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Normalized vector of id.
+    pub fn vector(&self, id: ID) -> Option<&[f32]> {
+        self.vectors.get(&id).map(|(_, vector)| vector.as_slice())
+    }
+
+    fn search_inner(
+        &self,
+        vector: &[f32],
+        limit: usize,
+        mut filter: impl FnMut(ID) -> bool,
+    ) -> Vec<(ID, f32)> {
+        if vector.len() != self.dims || limit == 0 {
+            return Vec::new();
+        }
+        let mut vector = vector.to_vec();
+        normalize(&mut vector);
+        let mut lists: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(index, centroid)| (index, dot(centroid, &vector)))
+            .collect();
+        lists.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        let mut found: Vec<(ID, f32)> = lists
+            .iter()
+            .take(self.probes)
+            .flat_map(|&(list, _)| &self.lists[list])
+            .filter(|&&id| filter(id))
+            .map(|&id| (id, dot(&self.vectors[&id].1, &vector)))
+            .collect();
+        found.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        found.truncate(limit);
+        found
+    }
+
+    /// Up to limit ids most similar to vector, best first.
+    pub fn search(&self, vector: &[f32], limit: usize) -> Vec<(ID, f32)> {
+        self.search_inner(vector, limit, |_| true)
+    }
+
+    /// Like search but only ranks ids in result, to combine with tag filters.
+    /// Results smaller than the probed clusters are ranked exactly.
+    pub fn search_in(&self, result: &QueryResult, vector: &[f32], limit: usize) -> Vec<(ID, f32)> {
+        let probed = self.len() * self.probes / self.centroids.len().max(1);
+        if result.matched() > probed {
+            return self.search_inner(vector, limit, |id| result.contains(id));
+        }
+        if vector.len() != self.dims {
+            return Vec::new();
+        }
+        let mut vector = vector.to_vec();
+        normalize(&mut vector);
+        let mut found: Vec<(ID, f32)> = result
+            .get(0, result.matched(), false)
+            .into_iter()
+            .filter_map(|id| Some((id, dot(self.vector(id)?, &vector))))
+            .collect();
+        found.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        found.truncate(limit);
+        found
+    }
+
+    /// Posts most similar to id, excluding id.
+    pub fn similar(&self, id: ID, limit: usize) -> Vec<(ID, f32)> {
+        let Some(vector) = self.vector(id) else {
+            return Vec::new();
+        };
+        self.search_inner(vector, limit, |other| other != id)
+    }
+
+    /// The nearest limit ids as a query term.
+    pub fn get(&self, vector: &[f32], limit: usize) -> Query<Queryable<'_>> {
+        let mut ids: Vec<ID> = self
+            .search(vector, limit)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        Query::new(Item::Single(Queryable::IDsOwned(ids)), false)
+    }
+
+    /// Replaces any vector id already has.
+    /// Clusters aren't recomputed, reload the index after large changes.
+    pub fn insert(&mut self, id: ID, mut vector: Vec<f32>) {
+        if vector.len() != self.dims {
+            return;
+        }
+        self.remove(id);
+        normalize(&mut vector);
+        let list = nearest(&self.centroids, &vector);
+        self.lists[list].push(id);
+        self.vectors.insert(id, (list, vector));
+    }
+
+    pub fn remove(&mut self, id: ID) {
+        if let Some((list, _)) = self.vectors.remove(&id) {
+            self.lists[list].retain(|&i| i != id);
+        }
+    }
+}
@@ -1,12 +1,37 @@
+mod db;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod history;
+pub mod import;
 pub mod index;
+pub mod matcher;
+pub mod packed;
+pub mod persist;
+#[cfg(feature = "postgres")]
+pub mod pg;
+#[cfg(feature = "proto")]
+pub mod proto;
 pub mod query;
-
+#[cfg(feature = "server")]
+pub mod server;
+pub mod store;
+pub mod tenant;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use db::{Db, DbLoader, IndexContext, WriteContext};
+pub use history::History;
 pub use index::{RangeQuery, TextQuery};
+pub use matcher::Matcher;
+pub use packed::{Packed, PACKED_SIZE};
 pub use query::{MultiQueryResult, Query, QueryResult, Queryable, QueryableOwned};
+pub use store::PostStore;
+pub use tenant::Tenants;
+
+use std::borrow::Cow;
 
 pub type ID = u32;
-pub type Packed = u64;
-pub const PACKED_SIZE: u32 = 64;
 
 #[macro_export]
 macro_rules! type_map {
@@ -95,242 +120,209 @@ impl<const N: usize> Identifier for [&str; N] {
     }
 }
 
-#[macro_export]
-macro_rules! db {
-    ($post_type:ty) => {
-        ::booru_db::type_map!(LoaderMap, ::booru_db::index::IndexLoader<$post_type>);
-        ::booru_db::type_map!(IndexMap, ::booru_db::index::Index<$post_type>);
+/// How identifiers and the identifiers of terms are compared, see
+/// DbLoader::with_identifier_options. Both are off by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IdentifierOptions {
+    /// `Score:>10` is routed like `score:>10`.
+    pub ignore_case: bool,
+    /// Whitespace around an identifier is ignored.
+    pub trim: bool,
+}
 
-        pub struct DbLoader {
-            identifiers: ::std::collections::HashMap<
-                ::std::option::Option<::std::string::String>,
-                ::std::any::TypeId,
-            >,
-            loaders: LoaderMap,
+impl IdentifierOptions {
+    /// The form identifiers are registered and looked up in.
+    pub fn normalize<'a>(&self, identifier: &'a str) -> Cow<'a, str> {
+        let identifier = if self.trim {
+            identifier.trim()
+        } else {
+            identifier
+        };
+        if self.ignore_case && identifier.chars().any(char::is_uppercase) {
+            Cow::Owned(identifier.to_lowercase())
+        } else {
+            Cow::Borrowed(identifier)
         }
+    }
+}
 
-        impl DbLoader {
-            pub fn new() -> Self {
-                Self {
-                    identifiers: ::std::collections::HashMap::new(),
-                    loaders: LoaderMap::new(),
-                }
-            }
+/// Write access to a Db.
+pub trait DbSink<P> {
+    fn next_id(&self) -> ID;
 
-            pub fn load(self, posts: impl ::std::iter::IntoIterator<Item = $post_type>) -> Db {
-                Db::new(self.identifiers, self.loaders, posts)
-            }
+    fn insert(&mut self, id: ID, post: &P);
 
-            pub fn with_default<L: ::booru_db::index::IndexLoader<$post_type>>(
-                mut self,
-                loader: L,
-            ) -> Self {
-                let identifier = None;
-                self.insert_loader(identifier, loader);
-                self
-            }
+    fn update(&mut self, id: ID, old: &P, new: &P);
 
-            pub fn with_loader<
-                I: ::booru_db::Identifier,
-                L: ::booru_db::index::IndexLoader<$post_type>,
-            >(
-                mut self,
-                identifier: I,
-                loader: L,
-            ) -> Self {
-                self.insert_loader(Some(identifier.to_idents()), loader);
-                self
-            }
+    fn remove(&mut self, id: ID, post: &P);
+}
 
-            fn insert_loader<L: ::booru_db::index::IndexLoader<$post_type>>(
-                &mut self,
-                identifiers: ::std::option::Option<::std::vec::Vec<::std::string::String>>,
-                loader: L,
-            ) {
-                let type_id = ::std::any::TypeId::of::<L>();
-                let identifiers = identifiers
-                    .map(|i| {
-                        i.into_iter()
-                            .map(|s| ::std::option::Option::Some(s))
-                            .collect()
-                    })
-                    .unwrap_or(::std::vec::Vec::from([::std::option::Option::None]));
-                for identifier in identifiers {
-                    if self.identifiers.contains_key(&identifier) {
-                        panic!("Duplicate Identifier!");
-                    }
-                    self.identifiers.insert(identifier, type_id);
-                }
-                self.loaders.insert(loader);
-            }
+/// Aliases Db and DbLoader for the post type, kept for crates written before
+/// Db<P> and DbLoader<P> could be named directly.
+#[macro_export]
+macro_rules! db {
+    ($post_type:ty) => {
+        pub type DbLoader = ::booru_db::DbLoader<$post_type>;
+        pub type Db = ::booru_db::Db<$post_type>;
+    };
+}
+
+/// A term Db::validate found a problem with.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ValidationError {
+    /// The identifier isn't known and there is no default index.
+    NoIndex(String),
+    /// The index the term is routed to rejected it.
+    Invalid(String),
+    /// `search:name` where name isn't a saved query.
+    UnknownSavedQuery(String),
+    /// Saved queries nested deeper than SavedQueries::MAX_DEPTH, usually a cycle.
+    TooDeep(String),
+}
+
+impl ValidationError {
+    pub fn term(&self) -> &str {
+        match self {
+            Self::NoIndex(term)
+            | Self::Invalid(term)
+            | Self::UnknownSavedQuery(term)
+            | Self::TooDeep(term) => term,
         }
+    }
+}
 
-        pub struct Db {
-            identifiers: ::std::collections::HashMap<
-                ::std::option::Option<::std::string::String>,
-                ::std::any::TypeId,
-            >,
-            indexes: IndexMap,
-            base_checks: ::booru_db::query::QueryResult,
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoIndex(term) => write!(f, "no index for {term}"),
+            Self::Invalid(term) => write!(f, "invalid term {term}"),
+            Self::UnknownSavedQuery(term) => write!(f, "unknown saved query {term}"),
+            Self::TooDeep(term) => write!(f, "saved queries nested too deep at {term}"),
         }
+    }
+}
 
-        impl Db {
-            fn new(
-                identifiers: ::std::collections::HashMap<
-                    ::std::option::Option<::std::string::String>,
-                    ::std::any::TypeId,
-                >,
-                mut loaders: LoaderMap,
-                posts: impl ::std::iter::IntoIterator<Item = $post_type>,
-            ) -> Self {
-                let mut last_id = ::std::option::Option::None;
-                for (id, post) in posts.into_iter().enumerate() {
-                    last_id = ::std::option::Option::Some(id);
-                    for loader in loaders.values_mut() {
-                        loader.add(id as u32, &post);
-                    }
-                }
+impl std::error::Error for ValidationError {}
+
+/// An identifier registered for a second loader, see DbLoader::add_loader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateIdentifier {
+    /// None for the default index.
+    pub identifier: Option<String>,
+    /// Type name of the loader registered with it first.
+    pub existing: &'static str,
+    /// Type name of the loader being registered.
+    pub loader: &'static str,
+}
 
-                let base_checks = if let ::std::option::Option::Some(last_id) = last_id {
-                    let mut checks = vec![
-                        ::booru_db::Packed::MAX;
-                        (last_id / ::booru_db::PACKED_SIZE as usize) + 1
-                    ];
-                    if let ::std::option::Option::Some(check) = checks.last_mut() {
-                        *check = 0;
-                        let end = (last_id % ::booru_db::PACKED_SIZE as usize) + 1;
-                        for i in 0..end {
-                            *check |= 1 << i;
-                        }
-                    }
-                    ::booru_db::QueryResult::new(checks)
-                } else {
-                    ::booru_db::QueryResult::new(::std::vec::Vec::new())
-                };
-
-                let mut index_identifiers = ::std::collections::HashMap::new();
-                let mut indexes = IndexMap::new();
-                for (identifier, type_id) in identifiers {
-                    let loader = loaders.map.remove(&type_id).unwrap();
-                    let index = loader.load();
-                    index_identifiers.insert(identifier, index.as_any().type_id());
-                    indexes.insert_boxed(index);
-                }
+impl std::fmt::Display for DuplicateIdentifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.identifier {
+            Some(identifier) => write!(f, "identifier {identifier}")?,
+            None => write!(f, "default index")?,
+        }
+        write!(
+            f,
+            " of {} is already registered by {}",
+            self.loader, self.existing
+        )
+    }
+}
 
-                Self {
-                    identifiers: index_identifiers,
-                    indexes,
-                    base_checks,
-                }
-            }
+impl std::error::Error for DuplicateIdentifier {}
 
-            pub fn checks(&self) -> &[::booru_db::Packed] {
-                self.base_checks.checks()
-            }
+/// Index dependencies a Db can't write in order, see Index::dependencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DependencyError {
+    /// An index depends on an index type that isn't loaded.
+    Missing,
+    /// Indexes depend on each other.
+    Cyclic,
+}
 
-            pub fn index<T: 'static + ::booru_db::index::Index<$post_type>>(
-                &self,
-            ) -> ::std::option::Option<&T> {
-                self.indexes.get()
-            }
+impl std::fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing index dependency"),
+            Self::Cyclic => write!(f, "cyclic index dependencies"),
+        }
+    }
+}
 
-            pub fn index_mut<T: 'static + ::booru_db::index::Index<$post_type>>(
-                &mut self,
-            ) -> ::std::option::Option<&mut T> {
-                self.indexes.get_mut()
-            }
+impl std::error::Error for DependencyError {}
 
-            pub fn next_id(&self) -> ::booru_db::ID {
-                let checks = self.checks();
-                let mut id = checks.len() as u32 * ::booru_db::PACKED_SIZE;
-                'outer: for (index, &c) in checks.iter().enumerate() {
-                    if c != ::booru_db::Packed::MAX {
-                        for i in 0..::booru_db::PACKED_SIZE {
-                            if (c & (1 << i)) == 0 {
-                                id = (index as u32 * ::booru_db::PACKED_SIZE) + i;
-                                break 'outer;
-                            }
-                        }
-                    }
-                }
-                id
-            }
+impl From<DependencyError> for std::io::Error {
+    fn from(e: DependencyError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    }
+}
 
-            fn insert_index<I: ::booru_db::Identifier, T: ::booru_db::index::Index<$post_type>>(
-                &mut self,
-                identifier: I,
-                index: T,
-            ) {
-                let type_id = ::std::any::TypeId::of::<T>();
-                for ident in identifier.to_idents() {
-                    let key = ::std::option::Option::Some(ident);
-                    if self.identifiers.contains_key(&key) {
-                        panic!("Duplicate Identifier!");
-                    }
-                    self.identifiers.insert(key, type_id);
-                }
-                self.indexes.insert(index);
-            }
+#[derive(Clone, Debug)]
+pub enum QueryError {
+    InvalidSource,
+    MissingTags(Vec<String>),
+    /// Rejected by the db's NegativePolicy.
+    NegativeOnly,
+}
 
-            pub fn query(
-                &self,
-                query: &::booru_db::Query<String>,
-            ) -> ::std::result::Result<
-                ::booru_db::QueryResult,
-                ::std::vec::Vec<::std::string::String>,
-            > {
-                let query = query
-                    .try_map(|text, inverse| {
-                        let (ident, value) = text
-                            .split_once(':')
-                            .map(|(ident, value)| {
-                                let ident = ::std::option::Option::Some(ident.to_string());
-                                if self.identifiers.contains_key(&ident) {
-                                    (ident, value)
-                                } else {
-                                    (::std::option::Option::None, text.as_str())
-                                }
-                            })
-                            .unwrap_or((::std::option::Option::None, text));
-                        let type_id = self.identifiers.get(&ident);
-                        let index = self.indexes.map.get(type_id?).unwrap();
-                        index.query(ident.as_deref(), value, inverse)
-                    })
-                    .map_err(|e| {
-                        e.into_iter()
-                            .map(|s| s.to_string())
-                            .collect::<::std::vec::Vec<_>>()
-                    })?;
-                let checks = query.run(self.base_checks.checks());
-                ::std::result::Result::Ok(::booru_db::QueryResult::new(checks))
-            }
+/// A match count estimated by Db::approx_count.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ApproxCount {
+    pub estimate: usize,
+    /// The count is within estimate ± margin about 95% of the time, 0 if exact.
+    pub margin: usize,
+    pub exact: bool,
+}
 
-            pub fn insert(&mut self, id: ::booru_db::ID, post: &$post_type) {
-                self.base_checks.insert(id);
-                for index in self.indexes.values_mut() {
-                    index.insert(id, post)
-                }
-            }
+/// The standalone match count of a term, see Db::query_with_counts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TermCount {
+    pub term: String,
+    /// Whether the term was negated in the query, matched still counts the term itself.
+    pub inverse: bool,
+    pub matched: usize,
+}
 
-            pub fn remove(&mut self, id: ::booru_db::ID, post: &$post_type) {
-                self.base_checks.remove(id);
-                for index in self.indexes.values_mut() {
-                    index.remove(id, post);
-                }
-            }
+/// A problem found by Db::verify.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifyIssue {
+    /// The identifier of the index at fault, `base`, `hidden`, `store` or `tenants`
+    /// for the Db's own data and `default` for the default index.
+    pub source: String,
+    /// The post at fault, None for problems with the data as a whole.
+    pub id: Option<ID>,
+    pub message: String,
+}
 
-            pub fn update(&mut self, id: ::booru_db::ID, old: &$post_type, new: &$post_type) {
-                self.base_checks.insert(id);
-                for index in self.indexes.values_mut() {
-                    index.update(id, old, new);
-                }
-            }
-        }
-    };
+/// What Db::verify found, no issues if the Db is consistent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    pub issues: Vec<VerifyIssue>,
+    /// Posts checked against every index, 0 without a store.
+    pub posts_checked: usize,
 }
 
-#[derive(Clone, Debug)]
-pub enum QueryError {
-    InvalidSource,
-    MissingTags(Vec<String>),
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, source: &str, id: Option<ID>, message: impl Into<String>) {
+        self.issues.push(VerifyIssue {
+            source: source.to_string(),
+            id,
+            message: message.into(),
+        });
+    }
+}
+
+/// How Db::query_checked handles queries without a positive term like `-tag`,
+/// which have to visit every post.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NegativePolicy {
+    #[default]
+    Allow,
+    Reject,
+    /// Allowed with at most this many terms, each term is a pass over every post.
+    MaxTerms(usize),
 }
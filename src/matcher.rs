@@ -0,0 +1,68 @@
+use crate::{Db, Query, ID};
+
+/// Stored queries checked against one post at a time,
+/// such as subscriptions notified when a matching post is uploaded.
+pub struct Matcher<K> {
+    queries: Vec<(K, Query<String>)>,
+}
+
+impl<K> Default for Matcher<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Matcher<K> {
+    pub fn new() -> Self {
+        Self {
+            queries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Query<String>)> {
+        self.queries.iter().map(|(key, query)| (key, query))
+    }
+
+    /// Keys of the stored queries id matches, in the order they were inserted.
+    /// Queries with terms db can't look up don't match.
+    pub fn matches<'k, P: 'static>(&'k self, db: &Db<P>, id: ID) -> Vec<&'k K> {
+        self.queries
+            .iter()
+            .filter(|(_, query)| db.matches(query, id).unwrap_or(false))
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+impl<K: Eq> Matcher<K> {
+    /// Returns the query previously stored for key.
+    pub fn insert(&mut self, key: K, query: Query<String>) -> Option<Query<String>> {
+        match self.queries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => Some(std::mem::replace(old, query)),
+            None => {
+                self.queries.push((key, query));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<Query<String>> {
+        let index = self.queries.iter().position(|(k, _)| k == key)?;
+        Some(self.queries.remove(index).1)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Query<String>> {
+        self.queries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, query)| query)
+    }
+}
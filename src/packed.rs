@@ -0,0 +1,76 @@
+//! The word checks are made of and the bit math on it. Ids are bits, id n is bit
+//! `n % PACKED_SIZE` of word `n / PACKED_SIZE`. Code outside this module goes through
+//! these helpers so the word type can be changed here alone. Any unsigned integer
+//! other than ID's works, snapshots borrow checks from 8 byte aligned memory so
+//! saving them also needs a word of at most 8 bytes, and can only be opened with
+//! the same Packed.
+
+use crate::ID;
+
+pub type Packed = u64;
+pub const PACKED_SIZE: u32 = Packed::BITS;
+
+/// The index of the word holding id.
+#[inline(always)]
+pub fn word(id: ID) -> usize {
+    (id / PACKED_SIZE) as usize
+}
+
+/// The bit of id in its word.
+#[inline(always)]
+pub fn bit(id: ID) -> Packed {
+    1 << (id % PACKED_SIZE)
+}
+
+/// The id of the lowest bit of the word at index.
+#[inline(always)]
+pub fn first_id(index: usize) -> ID {
+    index as ID * PACKED_SIZE
+}
+
+/// Number of words needed to hold ids up to and including id.
+#[inline(always)]
+pub fn words_for(id: ID) -> usize {
+    word(id) + 1
+}
+
+#[inline(always)]
+pub fn contains(checks: &[Packed], id: ID) -> bool {
+    checks
+        .get(word(id))
+        .is_some_and(|check| check & bit(id) != 0)
+}
+
+/// Number of ids set.
+#[inline(always)]
+pub fn count(checks: &[Packed]) -> usize {
+    checks.iter().map(|check| check.count_ones() as usize).sum()
+}
+
+/// The ids set in check, the word at index, highest first.
+#[inline(always)]
+pub fn ids_rev(index: usize, mut check: Packed) -> impl Iterator<Item = ID> {
+    let first = first_id(index);
+    std::iter::from_fn(move || {
+        if check == 0 {
+            return None;
+        }
+        let offset = PACKED_SIZE - 1 - check.leading_zeros();
+        check &= !(1 << offset);
+        Some(first + offset)
+    })
+}
+
+/// The ids set in check, the word at index, lowest first.
+#[inline(always)]
+pub fn ids(index: usize, mut check: Packed) -> impl Iterator<Item = ID> {
+    let first = first_id(index);
+    std::iter::from_fn(move || {
+        if check == 0 {
+            return None;
+        }
+        let offset = check.trailing_zeros();
+        check &= check - 1;
+        Some(first + offset)
+    })
+}
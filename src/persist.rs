@@ -0,0 +1,788 @@
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    mem::{align_of, size_of},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    query::{Queryable, QueryableOwned},
+    Packed, ID,
+};
+
+const MAGIC: &[u8; 8] = b"BOORUDB\0";
+/// The first released version is 2, version 1 snapshots without checksums aren't read.
+pub const VERSION: u32 = 2;
+// Older snapshots can't be read to migrate them.
+const MIN_VERSION: u32 = 2;
+
+// magic, version, padding
+const HEADER_SIZE: usize = 16;
+// table offset, magic
+const FOOTER_SIZE: usize = 16;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// FNV-1a, enough to catch partial writes and flipped bytes.
+fn checksum(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Section names used by Db for each index type from its identifiers.
+/// Named after the identifiers so they stay the same between builds.
+pub fn index_section_names(
+    identifiers: &HashMap<Option<String>, TypeId>,
+) -> HashMap<TypeId, String> {
+    let mut grouped: HashMap<TypeId, Vec<&str>> = HashMap::new();
+    for (identifier, type_id) in identifiers {
+        let identifier = identifier.as_deref().unwrap_or("");
+        grouped.entry(*type_id).or_default().push(identifier);
+    }
+    grouped
+        .into_iter()
+        .map(|(type_id, mut identifiers)| {
+            identifiers.sort_unstable();
+            (type_id, format!("index:{}", identifiers.join(",")))
+        })
+        .collect()
+}
+
+/// Read only view of a file. Memory mapped with the mmap feature on unix,
+/// otherwise the file is read into an aligned buffer.
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+    mapped: bool,
+    // keeps the fallback buffer alive, u64 so ptr is aligned for Packed.
+    _buf: Vec<u64>,
+}
+
+// The mapping is read only.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the file at path, or reads it without the mmap feature.
+    ///
+    /// # Safety
+    /// The file must not be written to or truncated while the Mmap or anything borrowed
+    /// from it is alive, the bytes could change under slices that were validated.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Reads the file at path into memory, safe for files that may be written to.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_file(File::open(path)?)
+    }
+
+    #[cfg(all(unix, feature = "mmap"))]
+    fn from_file(file: File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Self::from_vec(Vec::new(), 0));
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+            mapped: true,
+            _buf: Vec::new(),
+        })
+    }
+
+    #[cfg(not(all(unix, feature = "mmap")))]
+    fn from_file(file: File) -> io::Result<Self> {
+        Self::read_file(file)
+    }
+
+    fn read_file(mut file: File) -> io::Result<Self> {
+        use std::io::Read;
+
+        let len = file.metadata()?.len() as usize;
+        let mut buf = vec![0u64; len.div_ceil(8)];
+        let bytes = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, len) };
+        file.read_exact(bytes)?;
+        Ok(Self::from_vec(buf, len))
+    }
+
+    fn from_vec(buf: Vec<u64>, len: usize) -> Self {
+        Self {
+            ptr: buf.as_ptr() as *const u8,
+            len,
+            mapped: false,
+            _buf: buf,
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(unix, feature = "mmap"))]
+        if self.mapped {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+fn cast_slice<T>(bytes: &[u8]) -> &[T] {
+    assert_eq!(bytes.as_ptr() as usize % align_of::<T>(), 0);
+    assert_eq!(bytes.len() % size_of::<T>(), 0);
+    // Only used for ID and Packed which are valid for any bit pattern.
+    unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size_of::<T>()) }
+}
+
+/// Writes a snapshot to a temporary file next to path and renames it over path once
+/// written and synced, so a crash never leaves a partly written snapshot at path.
+pub fn write_atomic(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut SnapshotWriter) -> io::Result<()>,
+) -> io::Result<()> {
+    write_atomic_version(path.as_ref(), VERSION, write)
+}
+
+// Like write_atomic for migrations writing an older version than VERSION.
+fn write_atomic_version(
+    path: &Path,
+    version: u32,
+    write: impl FnOnce(&mut SnapshotWriter) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = (|| {
+        let file = File::create(&temp)?;
+        let mut writer = SnapshotWriter::with_version(BufWriter::new(file.try_clone()?), version)?;
+        write(&mut writer)?;
+        writer.finish()?;
+        file.sync_all()
+    })();
+    match result {
+        Ok(()) => fs::rename(&temp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Writes a snapshot made of named sections.
+/// Sections start 8 byte aligned so ids and checks can be borrowed straight from a Mmap.
+pub struct SnapshotWriter {
+    w: Box<dyn Write>,
+    pos: u64,
+    sections: Vec<(String, u64, u64, u64)>,
+    // name, start and checksum so far of the section being written.
+    current: Option<(String, u64, u64)>,
+}
+
+impl SnapshotWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn new(w: impl Write + 'static) -> io::Result<Self> {
+        Self::with_version(w, VERSION)
+    }
+
+    // Migrations write each version in turn up to VERSION.
+    fn with_version(w: impl Write + 'static, version: u32) -> io::Result<Self> {
+        let mut writer = Self {
+            w: Box::new(w),
+            pos: 0,
+            sections: Vec::new(),
+            current: None,
+        };
+        writer.write_bytes(MAGIC)?;
+        writer.write_u32(version)?;
+        writer.align(8)?;
+        Ok(writer)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn begin_section(&mut self, name: &str) -> io::Result<()> {
+        assert!(
+            self.current.is_none(),
+            "section {name} started in a section"
+        );
+        self.align(8)?;
+        self.current = Some((name.to_string(), self.pos, FNV_OFFSET));
+        Ok(())
+    }
+
+    pub fn end_section(&mut self) -> io::Result<()> {
+        let (name, start, hash) = self.current.take().expect("no section started");
+        self.sections.push((name, start, self.pos - start, hash));
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.w.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        if let Some((_, _, hash)) = &mut self.current {
+            *hash = checksum(*hash, bytes);
+        }
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> io::Result<()> {
+        self.write_bytes(&[value])
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn align(&mut self, to: u64) -> io::Result<()> {
+        while !self.pos.is_multiple_of(to) {
+            self.write_u8(0)?;
+        }
+        Ok(())
+    }
+
+    /// Aligned so SectionReader::read_ids can borrow them.
+    pub fn write_ids(&mut self, ids: &[ID]) -> io::Result<()> {
+        self.align(8)?;
+        for id in ids {
+            self.write_u32(*id)?;
+        }
+        self.align(8)
+    }
+
+    /// Aligned so SectionReader::read_checks can borrow them.
+    pub fn write_checks(&mut self, checks: &[Packed]) -> io::Result<()> {
+        self.align(8)?;
+        for check in checks {
+            self.write_bytes(&check.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_queryable(&mut self, queryable: &QueryableOwned) -> io::Result<()> {
+        match queryable {
+            QueryableOwned::Checks { checks, matched } => {
+                self.write_u8(1)?;
+                self.align(8)?;
+                self.write_u64(checks.len() as u64)?;
+                self.write_u64(*matched as u64)?;
+                self.write_checks(checks)
+            }
+            QueryableOwned::IDs { ids } => {
+                self.write_u8(0)?;
+                self.align(8)?;
+                self.write_u64(ids.len() as u64)?;
+                self.write_u64(ids.len() as u64)?;
+                self.write_ids(ids)
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        assert!(self.current.is_none(), "section not ended");
+        self.align(8)?;
+        let table_offset = self.pos;
+        let sections = std::mem::take(&mut self.sections);
+        self.write_u64(sections.len() as u64)?;
+        for (name, offset, len, hash) in &sections {
+            self.write_u32(name.len() as u32)?;
+            self.write_bytes(name.as_bytes())?;
+            self.align(8)?;
+            self.write_u64(*offset)?;
+            self.write_u64(*len)?;
+            self.write_u64(*hash)?;
+        }
+        self.write_u64(table_offset)?;
+        self.write_bytes(MAGIC)?;
+        self.w.flush()
+    }
+}
+
+pub struct Snapshot {
+    mmap: Arc<Mmap>,
+    version: u32,
+    sections: HashMap<String, Range<usize>>,
+    checksums: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Maps the snapshot at path, see Mmap::open. Checksums aren't checked as that
+    /// would read every page of the file, see verify.
+    ///
+    /// # Safety
+    /// The file must not be written to or truncated while the Snapshot or any Section
+    /// or index opened from it is alive.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_mmap(Mmap::open(path)?)
+    }
+
+    /// Reads the snapshot at path into memory and verifies it, see Mmap::read.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let snapshot = Self::from_mmap(Mmap::read(path)?)?;
+        snapshot.verify()?;
+        Ok(snapshot)
+    }
+
+    pub fn from_mmap(mmap: Mmap) -> io::Result<Self> {
+        if cfg!(target_endian = "big") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "snapshots are little endian",
+            ));
+        }
+        let mmap = Arc::new(mmap);
+        let bytes = mmap.as_bytes();
+        if bytes.len() < HEADER_SIZE + FOOTER_SIZE || &bytes[..8] != MAGIC {
+            return Err(invalid_data("not a snapshot"));
+        }
+        if &bytes[bytes.len() - 8..] != MAGIC {
+            return Err(invalid_data("snapshot is incomplete"));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version > VERSION {
+            return Err(invalid_data("snapshot version is newer than supported"));
+        }
+        if version < MIN_VERSION {
+            return Err(invalid_data("snapshot version is older than supported"));
+        }
+
+        let footer = bytes.len() - FOOTER_SIZE;
+        let table_offset = u64::from_le_bytes(bytes[footer..footer + 8].try_into().unwrap());
+        let mut reader = SectionReader {
+            bytes,
+            pos: table_offset as usize,
+            end: footer,
+        };
+        // name length, offset, len and checksum.
+        let count = reader.read_len(28)?;
+        let mut sections = HashMap::with_capacity(count);
+        let mut checksums = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let name_len = reader.read_u32()? as usize;
+            let name = std::str::from_utf8(reader.read_bytes(name_len)?)
+                .map_err(|_| invalid_data("section name isn't utf8"))?
+                .to_string();
+            reader.align(8)?;
+            let offset = reader.read_u64()? as usize;
+            let len = reader.read_u64()? as usize;
+            if offset.checked_add(len).map(|end| end > footer) != Some(false) {
+                return Err(invalid_data("section out of bounds"));
+            }
+            checksums.insert(name.clone(), reader.read_u64()?);
+            sections.insert(name, offset..offset + len);
+        }
+        Ok(Self {
+            mmap,
+            version,
+            sections,
+            checksums,
+        })
+    }
+
+    /// Checks every section against its checksum, catching partial writes and
+    /// flipped bytes.
+    pub fn verify(&self) -> io::Result<()> {
+        let bytes = self.mmap.as_bytes();
+        for (name, &hash) in &self.checksums {
+            if checksum(FNV_OFFSET, &bytes[self.sections[name].clone()]) != hash {
+                return Err(invalid_data(&format!("section {name} is corrupt")));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.sections.keys().map(|name| name.as_str())
+    }
+
+    pub fn section(&self, name: &str) -> Option<Section> {
+        let range = self.sections.get(name)?.clone();
+        Some(Section {
+            mmap: self.mmap.clone(),
+            range,
+        })
+    }
+}
+
+/// Rewrites a snapshot of an older version in the format of the next version,
+/// see upgrade.
+pub trait Migration {
+    /// The version of the snapshots this migration reads, it writes version + 1.
+    fn version(&self) -> u32;
+
+    fn migrate(&self, snapshot: &Snapshot, w: &mut SnapshotWriter) -> io::Result<()>;
+}
+
+/// Copies every section unchanged, for migrations that only change the file layout.
+/// Sections stay 8 byte aligned so offsets within them keep their alignment.
+pub fn copy_sections(snapshot: &Snapshot, w: &mut SnapshotWriter) -> io::Result<()> {
+    let mut names: Vec<&str> = snapshot.section_names().collect();
+    names.sort_unstable();
+    for name in names {
+        let section = snapshot.section(name).unwrap();
+        w.begin_section(name)?;
+        w.write_bytes(section.bytes())?;
+        w.end_section()?;
+    }
+    Ok(())
+}
+
+/// Writes the snapshot at from to the current version at to, running the migration
+/// of each version in turn, such as 2 to 3 then 3 to 4. Snapshots already at the
+/// current version are copied. Each version is written with write_atomic.
+pub fn upgrade(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    migrations: &[&dyn Migration],
+) -> io::Result<()> {
+    let to = to.as_ref();
+    let mut snapshot = Snapshot::read(from)?;
+    if snapshot.version() == VERSION {
+        return write_atomic(to, |writer| copy_sections(&snapshot, writer));
+    }
+    while snapshot.version() < VERSION {
+        let version = snapshot.version();
+        let Some(migration) = migrations.iter().find(|m| m.version() == version) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no migration from snapshot version {version}"),
+            ));
+        };
+        write_atomic_version(to, version + 1, |writer| {
+            migration.migrate(&snapshot, writer)
+        })?;
+        // Read into memory, so the next version can overwrite to.
+        snapshot = Snapshot::read(to)?;
+    }
+    Ok(())
+}
+
+/// Position of a QueryableOwned written with SnapshotWriter::write_queryable.
+#[derive(Clone, Copy, Debug)]
+pub struct StoredQueryable {
+    checks: bool,
+    offset: usize,
+    len: usize,
+    matched: usize,
+}
+
+impl StoredQueryable {
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+}
+
+/// A section of a Snapshot, keeps the file mapped while alive.
+#[derive(Clone)]
+pub struct Section {
+    mmap: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl Section {
+    pub fn bytes(&self) -> &[u8] {
+        &self.mmap.as_bytes()[self.range.clone()]
+    }
+
+    pub fn reader(&self) -> SectionReader<'_> {
+        SectionReader {
+            bytes: self.mmap.as_bytes(),
+            pos: self.range.start,
+            end: self.range.end,
+        }
+    }
+
+    /// offset is relative to the start of the file like SectionReader::position.
+    pub fn bytes_at(&self, offset: usize, len: usize) -> &[u8] {
+        assert!(offset >= self.range.start && len <= self.range.end.saturating_sub(offset));
+        &self.mmap.as_bytes()[offset..offset + len]
+    }
+
+    pub fn ids(&self, offset: usize, len: usize) -> &[ID] {
+        let size = len.checked_mul(size_of::<ID>()).expect("ids out of bounds");
+        cast_slice(self.bytes_at(offset, size))
+    }
+
+    pub fn checks(&self, offset: usize, len: usize) -> &[Packed] {
+        let size = len
+            .checked_mul(size_of::<Packed>())
+            .expect("checks out of bounds");
+        cast_slice(self.bytes_at(offset, size))
+    }
+
+    pub fn queryable(&self, stored: &StoredQueryable) -> Queryable<'_> {
+        if stored.checks {
+            Queryable::Checks(self.checks(stored.offset, stored.len))
+        } else {
+            Queryable::IDs(self.ids(stored.offset, stored.len))
+        }
+    }
+}
+
+/// Reads a section, positions are relative to the start of the file.
+pub struct SectionReader<'s> {
+    bytes: &'s [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'s> SectionReader<'s> {
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.end.saturating_sub(self.pos)
+    }
+
+    /// Reads a count of items taking at least min_size bytes each, failing if they
+    /// can't fit in the rest of the section so it is safe to allocate for.
+    pub fn read_len(&mut self, min_size: usize) -> io::Result<usize> {
+        let len =
+            usize::try_from(self.read_u64()?).map_err(|_| invalid_data("length out of bounds"))?;
+        match len.checked_mul(min_size.max(1)) {
+            Some(size) if size <= self.remaining() => Ok(len),
+            _ => Err(invalid_data("length out of bounds")),
+        }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> io::Result<&'s [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.end)
+            .ok_or_else(|| invalid_data("unexpected end of section"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn align(&mut self, to: usize) -> io::Result<()> {
+        let padding = (to - self.pos % to) % to;
+        self.skip(padding)
+    }
+
+    pub fn read_ids(&mut self, len: usize) -> io::Result<&'s [ID]> {
+        self.align(8)?;
+        let size = len
+            .checked_mul(size_of::<ID>())
+            .ok_or_else(|| invalid_data("unexpected end of section"))?;
+        let ids = cast_slice(self.read_bytes(size)?);
+        self.align(8)?;
+        Ok(ids)
+    }
+
+    pub fn read_checks(&mut self, len: usize) -> io::Result<&'s [Packed]> {
+        self.align(8)?;
+        let size = len
+            .checked_mul(size_of::<Packed>())
+            .ok_or_else(|| invalid_data("unexpected end of section"))?;
+        Ok(cast_slice(self.read_bytes(size)?))
+    }
+
+    /// Skips over the queryable's data returning where it is.
+    pub fn read_queryable(&mut self) -> io::Result<StoredQueryable> {
+        let checks = match self.read_u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_data("invalid queryable kind")),
+        };
+        self.align(8)?;
+        let len = self.read_u64()? as usize;
+        let matched = self.read_u64()? as usize;
+        let offset = self.pos;
+        if checks {
+            self.read_checks(len)?;
+        } else {
+            self.read_ids(len)?;
+        }
+        Ok(StoredQueryable {
+            checks,
+            offset,
+            len,
+            matched,
+        })
+    }
+}
+
+/// Writes keys with the ids among sorted ids each contains, for the save_delta of
+/// key indexes. Keys with few ids are walked instead of ids, see QueryableOwned::filter_ids.
+pub(crate) fn write_key_delta<'k, K: PersistKey + 'k>(
+    w: &mut SnapshotWriter,
+    entries: impl Iterator<Item = (&'k K, &'k QueryableOwned)>,
+    ids: &[ID],
+) -> io::Result<()> {
+    let entries: Vec<(&K, Vec<ID>)> = entries
+        .map(|(key, queryable)| (key, queryable.filter_ids(ids)))
+        .filter(|(_, contained)| !contained.is_empty())
+        .collect();
+    w.write_u64(entries.len() as u64)?;
+    for (key, contained) in entries {
+        key.write_key(w)?;
+        w.write_u64(contained.len() as u64)?;
+        w.write_ids(&contained)?;
+    }
+    Ok(())
+}
+
+/// Reads what write_key_delta wrote.
+pub(crate) fn read_key_delta<K: PersistKey>(
+    r: &mut SectionReader,
+) -> io::Result<Vec<(K, Vec<ID>)>> {
+    // a key of at least a byte and its id count.
+    let len = r.read_len(9)?;
+    let mut entries = Vec::with_capacity(len);
+    for _ in 0..len {
+        let key = K::read_key(r)?;
+        let count = r.read_u64()? as usize;
+        entries.push((key, r.read_ids(count)?.to_vec()));
+    }
+    Ok(entries)
+}
+
+/// Keys with a variable length encoding.
+pub trait PersistKey: Sized {
+    fn write_key(&self, w: &mut SnapshotWriter) -> io::Result<()>;
+
+    fn read_key(r: &mut SectionReader) -> io::Result<Self>;
+}
+
+/// Values with a fixed size encoding, allowing them to be binary searched in place.
+pub trait PersistValue: Copy + Ord {
+    const SIZE: usize;
+
+    fn encode(&self, out: &mut [u8]);
+
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+macro_rules! persist_int {
+    ($($t:ty),*) => {
+        $(
+            impl PersistValue for $t {
+                const SIZE: usize = size_of::<$t>();
+
+                fn encode(&self, out: &mut [u8]) {
+                    out.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().unwrap())
+                }
+            }
+
+            impl PersistKey for $t {
+                fn write_key(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+                    w.write_bytes(&self.to_le_bytes())
+                }
+
+                fn read_key(r: &mut SectionReader) -> io::Result<Self> {
+                    Ok(<$t>::from_le_bytes(r.read_bytes(size_of::<$t>())?.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+persist_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl PersistValue for bool {
+    const SIZE: usize = 1;
+
+    fn encode(&self, out: &mut [u8]) {
+        out[0] = *self as u8;
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+}
+
+impl PersistKey for bool {
+    fn write_key(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u8(*self as u8)
+    }
+
+    fn read_key(r: &mut SectionReader) -> io::Result<Self> {
+        Ok(r.read_u8()? != 0)
+    }
+}
+
+impl PersistKey for String {
+    fn write_key(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u32(self.len() as u32)?;
+        w.write_bytes(self.as_bytes())
+    }
+
+    fn read_key(r: &mut SectionReader) -> io::Result<Self> {
+        let len = r.read_u32()? as usize;
+        let bytes = r.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("key isn't utf8"))
+    }
+}
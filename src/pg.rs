@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use futures_util::TryStreamExt;
+use sqlx::{
+    postgres::{PgListener, PgPool, PgRow},
+    Row,
+};
+
+use crate::{DbSink, ID};
+
+/// A row mapped to its primary key and post.
+/// A None post marks the row as deleted, e.g. from a soft delete column.
+pub type PgRecord<P> = (i64, Option<P>);
+
+type MapRow<P> = Box<dyn Fn(&PgRow) -> Result<PgRecord<P>, sqlx::Error> + Send + Sync>;
+
+/// Keeps a Db in sync with a Postgres table.
+/// The last version of every post is kept so updates and removes can be
+/// applied to indexes that need the old post.
+pub struct PgSource<P> {
+    pool: PgPool,
+    load_query: String,
+    row_query: String,
+    changed_query: Option<(String, String)>,
+    map: MapRow<P>,
+    posts: HashMap<i64, (ID, P)>,
+    cursor: Option<i64>,
+}
+
+impl<P> PgSource<P> {
+    /// load_query selects every post, row_query selects a single post by key as $1.
+    pub fn new(
+        pool: PgPool,
+        load_query: impl Into<String>,
+        row_query: impl Into<String>,
+        map: impl Fn(&PgRow) -> Result<PgRecord<P>, sqlx::Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            pool,
+            load_query: load_query.into(),
+            row_query: row_query.into(),
+            changed_query: None,
+            map: Box::new(map),
+            posts: HashMap::new(),
+            cursor: None,
+        }
+    }
+
+    /// Enables poll. query selects posts changed after $1,
+    /// cursor_column is an i64 such as a version or epoch milliseconds of updated_at.
+    pub fn with_changes(
+        mut self,
+        query: impl Into<String>,
+        cursor_column: impl Into<String>,
+    ) -> Self {
+        self.changed_query = Some((query.into(), cursor_column.into()));
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn id(&self, key: i64) -> Option<ID> {
+        self.posts.get(&key).map(|(id, _)| *id)
+    }
+
+    pub fn post(&self, key: i64) -> Option<&P> {
+        self.posts.get(&key).map(|(_, post)| post)
+    }
+
+    pub fn len(&self) -> usize {
+        self.posts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.posts.is_empty()
+    }
+
+    fn read_cursor(&mut self, row: &PgRow) -> Result<(), sqlx::Error> {
+        if let Some((_, column)) = &self.changed_query {
+            let cursor: i64 = row.try_get(column.as_str())?;
+            self.cursor = Some(self.cursor.map_or(cursor, |c| c.max(cursor)));
+        }
+        Ok(())
+    }
+
+    /// Streams every post from load_query into add, giving them ids in order.
+    /// Use with DbLoader::add.
+    pub async fn load(&mut self, mut add: impl FnMut(&P)) -> Result<(), sqlx::Error> {
+        let pool = self.pool.clone();
+        let query = self.load_query.clone();
+        let mut rows = sqlx::query(&query).fetch(&pool);
+        let mut next_id: ID = 0;
+        while let Some(row) = rows.try_next().await? {
+            self.read_cursor(&row)?;
+            let (key, post) = (self.map)(&row)?;
+            let Some(post) = post else {
+                continue;
+            };
+            add(&post);
+            self.posts.insert(key, (next_id, post));
+            next_id += 1;
+        }
+        Ok(())
+    }
+
+    /// Current version of a single post, None if it no longer exists.
+    pub async fn fetch(&self, key: i64) -> Result<Option<P>, sqlx::Error> {
+        let row = sqlx::query(&self.row_query)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok((self.map)(&row)?.1),
+            None => Ok(None),
+        }
+    }
+
+    /// Posts changed since the last load or poll.
+    /// Pass the records to apply, no lock on the Db is needed while fetching.
+    pub async fn poll(&mut self) -> Result<Vec<PgRecord<P>>, sqlx::Error> {
+        let Some((query, _)) = self.changed_query.clone() else {
+            return Ok(Vec::new());
+        };
+        let pool = self.pool.clone();
+        let mut rows = sqlx::query(&query)
+            .bind(self.cursor.unwrap_or(i64::MIN))
+            .fetch(&pool);
+        let mut records = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            self.read_cursor(&row)?;
+            records.push((self.map)(&row)?);
+        }
+        Ok(records)
+    }
+
+    /// Listens on a NOTIFY channel whose payloads are post keys.
+    /// Use fetch on each key and then apply the result.
+    pub async fn listen(&self, channel: &str) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(channel).await?;
+        Ok(listener)
+    }
+
+    /// Waits for the next notification on listener and fetches the post it names.
+    pub async fn recv(&self, listener: &mut PgListener) -> Result<PgRecord<P>, sqlx::Error> {
+        let notification = listener.recv().await?;
+        let key = notification
+            .payload()
+            .trim()
+            .parse()
+            .map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        Ok((key, self.fetch(key).await?))
+    }
+
+    /// Inserts, updates or removes the post in db.
+    pub fn apply(&mut self, db: &mut impl DbSink<P>, (key, post): PgRecord<P>) {
+        match (self.posts.get_mut(&key), post) {
+            (Some((id, old)), Some(new)) => {
+                db.update(*id, old, &new);
+                *old = new;
+            }
+            (Some(_), None) => {
+                let (id, old) = self.posts.remove(&key).unwrap();
+                db.remove(id, &old);
+            }
+            (None, Some(new)) => {
+                let id = db.next_id();
+                db.insert(id, &new);
+                self.posts.insert(key, (id, new));
+            }
+            (None, None) => {}
+        }
+    }
+
+    pub fn apply_all(
+        &mut self,
+        db: &mut impl DbSink<P>,
+        records: impl IntoIterator<Item = PgRecord<P>>,
+    ) {
+        for record in records {
+            self.apply(db, record);
+        }
+    }
+}
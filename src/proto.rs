@@ -0,0 +1,435 @@
+//! Protobuf encoding of the messages in proto/booru_db.proto.
+
+use std::fmt;
+
+use crate::{query::Item, Query, QueryResult, ID};
+
+/// Nested queries deeper than this are rejected when decoding.
+pub const MAX_DEPTH: usize = 64;
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LEN: u8 = 2;
+const FIXED32: u8 = 5;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    InvalidVarint,
+    InvalidWireType(u8),
+    InvalidUtf8,
+    MissingItem,
+    TooDeep,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of message"),
+            Self::InvalidVarint => write!(f, "invalid varint"),
+            Self::InvalidWireType(wire_type) => write!(f, "invalid wire type {wire_type}"),
+            Self::InvalidUtf8 => write!(f, "invalid utf-8 in string field"),
+            Self::MissingItem => write!(f, "query has no item"),
+            Self::TooDeep => write!(f, "query nested deeper than {MAX_DEPTH}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait ProtoMessage: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    fn decode(buf: &[u8]) -> Result<Self, DecodeError>;
+
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    write_varint(buf, ((field as u64) << 3) | wire_type as u64);
+}
+
+fn write_uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    if value != 0 {
+        write_key(buf, field, VARINT);
+        write_varint(buf, value);
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buf, field, LEN);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.buf.get(self.pos).ok_or(DecodeError::UnexpectedEof)?;
+            self.pos += 1;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidVarint)
+    }
+
+    fn read_key(&mut self) -> Result<(u32, u8), DecodeError> {
+        let key = self.read_varint()?;
+        Ok(((key >> 3) as u32, (key & 7) as u8))
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or(DecodeError::UnexpectedEof)?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.read_varint()? as usize;
+        self.read_slice(len)
+    }
+
+    fn read_string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn skip(&mut self, wire_type: u8) -> Result<(), DecodeError> {
+        match wire_type {
+            VARINT => self.read_varint().map(|_| ()),
+            FIXED64 => self.read_slice(8).map(|_| ()),
+            LEN => self.read_bytes().map(|_| ()),
+            FIXED32 => self.read_slice(4).map(|_| ()),
+            _ => Err(DecodeError::InvalidWireType(wire_type)),
+        }
+    }
+}
+
+fn encode_query(query: &Query<String>, buf: &mut Vec<u8>) {
+    write_uint(buf, 1, query.inverse as u64);
+    match &query.item {
+        Item::AndChain(queries) | Item::OrChain(queries) => {
+            let field = if matches!(query.item, Item::AndChain(_)) {
+                2
+            } else {
+                3
+            };
+            let mut list = Vec::new();
+            for query in queries {
+                let mut nested = Vec::new();
+                encode_query(query, &mut nested);
+                write_bytes(&mut list, 1, &nested);
+            }
+            write_bytes(buf, field, &list);
+        }
+        Item::Single(term) => write_bytes(buf, 4, term.as_bytes()),
+        Item::All => write_uint(buf, 5, 1),
+        Item::Empty => write_uint(buf, 6, 1),
+    }
+}
+
+fn decode_query(buf: &[u8], depth: usize) -> Result<Query<String>, DecodeError> {
+    if depth > MAX_DEPTH {
+        return Err(DecodeError::TooDeep);
+    }
+    let mut reader = Reader::new(buf);
+    let mut inverse = false;
+    let mut item = None;
+    while !reader.is_empty() {
+        match reader.read_key()? {
+            (1, VARINT) => inverse = reader.read_varint()? != 0,
+            (field @ (2 | 3), LEN) => {
+                let mut list = Reader::new(reader.read_bytes()?);
+                let mut queries = Vec::new();
+                while !list.is_empty() {
+                    match list.read_key()? {
+                        (1, LEN) => queries.push(decode_query(list.read_bytes()?, depth + 1)?),
+                        (_, wire_type) => list.skip(wire_type)?,
+                    }
+                }
+                item = Some(if field == 2 {
+                    Item::AndChain(queries)
+                } else {
+                    Item::OrChain(queries)
+                });
+            }
+            (4, LEN) => item = Some(Item::Single(reader.read_string()?)),
+            (5, VARINT) => {
+                reader.read_varint()?;
+                item = Some(Item::All);
+            }
+            (6, VARINT) => {
+                reader.read_varint()?;
+                item = Some(Item::Empty);
+            }
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    let item = item.ok_or(DecodeError::MissingItem)?;
+    Ok(Query::new(item, inverse))
+}
+
+impl ProtoMessage for Query<String> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_query(self, buf);
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        decode_query(buf, 0)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchRequest {
+    pub query: Option<Query<String>>,
+    pub text: String,
+    pub offset: u64,
+    pub limit: u32,
+    pub reverse: bool,
+}
+
+impl SearchRequest {
+    /// The query tree if set, otherwise text parsed with Query::parse.
+    pub fn to_query(&self) -> Option<Query<String>> {
+        match &self.query {
+            Some(query) => Some(query.clone()),
+            None => Query::parse(&self.text).ok(),
+        }
+    }
+}
+
+impl ProtoMessage for SearchRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if let Some(query) = &self.query {
+            write_bytes(buf, 1, &query.encode_to_vec());
+        }
+        if !self.text.is_empty() {
+            write_bytes(buf, 2, self.text.as_bytes());
+        }
+        write_uint(buf, 3, self.offset);
+        write_uint(buf, 4, self.limit as u64);
+        write_uint(buf, 5, self.reverse as u64);
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(buf);
+        let mut request = Self::default();
+        while !reader.is_empty() {
+            match reader.read_key()? {
+                (1, LEN) => request.query = Some(Query::decode(reader.read_bytes()?)?),
+                (2, LEN) => request.text = reader.read_string()?,
+                (3, VARINT) => request.offset = reader.read_varint()?,
+                (4, VARINT) => request.limit = reader.read_varint()? as u32,
+                (5, VARINT) => request.reverse = reader.read_varint()? != 0,
+                (_, wire_type) => reader.skip(wire_type)?,
+            }
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchResponse {
+    pub matched: u64,
+    pub ids: Vec<ID>,
+    pub invalid_terms: Vec<String>,
+}
+
+impl SearchResponse {
+    /// Page of result as requested.
+    pub fn from_result(result: &QueryResult, request: &SearchRequest) -> Self {
+        Self {
+            matched: result.matched() as u64,
+            ids: result.get(
+                request.offset as usize,
+                request.limit as usize,
+                request.reverse,
+            ),
+            invalid_terms: Vec::new(),
+        }
+    }
+
+    pub fn invalid(invalid_terms: Vec<String>) -> Self {
+        Self {
+            invalid_terms,
+            ..Default::default()
+        }
+    }
+}
+
+impl ProtoMessage for SearchResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_uint(buf, 1, self.matched);
+        if !self.ids.is_empty() {
+            let mut packed = Vec::with_capacity(self.ids.len() * 4);
+            for &id in &self.ids {
+                write_varint(&mut packed, id as u64);
+            }
+            write_bytes(buf, 2, &packed);
+        }
+        for term in &self.invalid_terms {
+            write_bytes(buf, 3, term.as_bytes());
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(buf);
+        let mut response = Self::default();
+        while !reader.is_empty() {
+            match reader.read_key()? {
+                (1, VARINT) => response.matched = reader.read_varint()?,
+                // Repeated scalars may be packed or not.
+                (2, LEN) => {
+                    let mut packed = Reader::new(reader.read_bytes()?);
+                    while !packed.is_empty() {
+                        response.ids.push(packed.read_varint()? as ID);
+                    }
+                }
+                (2, VARINT) => response.ids.push(reader.read_varint()? as ID),
+                (3, LEN) => response.invalid_terms.push(reader.read_string()?),
+                (_, wire_type) => reader.skip(wire_type)?,
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(tag: &str, inverse: bool) -> Query<String> {
+        Query::new(Item::Single(tag.to_string()), inverse)
+    }
+
+    fn nested(depth: usize) -> Query<String> {
+        let mut query = single("tag", false);
+        for _ in 0..depth {
+            query = Query::new(Item::AndChain(vec![query]), false);
+        }
+        query
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let query = Query::new(
+            Item::AndChain(vec![
+                single("1girl", false),
+                single("solo", true),
+                Query::new(
+                    Item::OrChain(vec![single("smile", false), Query::new(Item::All, true)]),
+                    true,
+                ),
+                Query::new(Item::Empty, false),
+            ]),
+            false,
+        );
+        assert_eq!(Query::decode(&query.encode_to_vec()), Ok(query));
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let request = SearchRequest {
+            query: Some(single("solo", true)),
+            text: "1girl -solo".to_string(),
+            offset: 300,
+            limit: 50,
+            reverse: true,
+        };
+        assert_eq!(SearchRequest::decode(&request.encode_to_vec()), Ok(request));
+        let request = SearchRequest::default();
+        assert!(request.encode_to_vec().is_empty());
+        assert_eq!(SearchRequest::decode(&[]), Ok(request));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let response = SearchResponse {
+            matched: 1 << 40,
+            ids: vec![0, 1, 127, 128, ID::MAX],
+            invalid_terms: vec!["missing".to_string(), String::new()],
+        };
+        assert_eq!(
+            SearchResponse::decode(&response.encode_to_vec()),
+            Ok(response)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        // A single field so every shorter prefix ends inside it.
+        let request = SearchRequest {
+            query: Some(Query::new(
+                Item::OrChain(vec![single("1girl", false), single("solo", true)]),
+                false,
+            )),
+            ..Default::default()
+        };
+        let buf = request.encode_to_vec();
+        for len in 1..buf.len() {
+            assert_eq!(
+                SearchRequest::decode(&buf[..len]),
+                Err(DecodeError::UnexpectedEof),
+                "truncated to {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn over_long_varint() {
+        let mut buf = vec![3 << 3 | VARINT];
+        buf.extend([0xFF; 10]);
+        buf.push(0x01);
+        assert_eq!(SearchRequest::decode(&buf), Err(DecodeError::InvalidVarint));
+    }
+
+    #[test]
+    fn max_depth() {
+        let query = nested(MAX_DEPTH);
+        assert_eq!(Query::decode(&query.encode_to_vec()), Ok(query));
+        let query = nested(MAX_DEPTH + 1);
+        assert_eq!(
+            Query::decode(&query.encode_to_vec()),
+            Err(DecodeError::TooDeep)
+        );
+    }
+
+    #[test]
+    fn missing_item() {
+        let buf = [1 << 3 | VARINT, 1];
+        assert_eq!(Query::decode(&buf), Err(DecodeError::MissingItem));
+    }
+}
@@ -0,0 +1,411 @@
+use std::{iter::Peekable, ops::Range};
+
+use crate::{Packed, ID};
+
+use super::{
+    queryable::{Queryable, QueryableOwned},
+    run::{self, Kind, RunNode},
+    Item, Query,
+};
+
+/// Position of a node in a QueryArena.
+pub type NodeId = u32;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ArenaItem<T> {
+    /// Children are QueryArena::children of the node.
+    AndChain(Range<u32>),
+    OrChain(Range<u32>),
+    Single(T),
+    /// Matches every id.
+    All,
+    /// Matches no id.
+    Empty,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ArenaNode<T> {
+    pub item: ArenaItem<T>,
+    pub inverse: bool,
+}
+
+/// Queries kept as flat nodes with the children of every chain in one list, instead
+/// of a Vec per chain like Query. clear keeps the allocations, so a server parsing
+/// many queries per second can reuse one arena without allocating per query.
+#[derive(Clone, Debug)]
+pub struct QueryArena<T> {
+    nodes: Vec<ArenaNode<T>>,
+    children: Vec<NodeId>,
+    // Children of the chains still being built.
+    pending: Vec<NodeId>,
+}
+
+impl<T> Default for QueryArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> QueryArena<T> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            children: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Removes every node and keeps the allocations.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.children.clear();
+        self.pending.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: NodeId) -> &ArenaNode<T> {
+        &self.nodes[id as usize]
+    }
+
+    /// Empty unless the node is a chain.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        match &self.node(id).item {
+            ArenaItem::AndChain(range) | ArenaItem::OrChain(range) => {
+                &self.children[range.start as usize..range.end as usize]
+            }
+            _ => &[],
+        }
+    }
+
+    pub fn push(&mut self, item: ArenaItem<T>, inverse: bool) -> NodeId {
+        self.nodes.push(ArenaNode { item, inverse });
+        (self.nodes.len() - 1) as NodeId
+    }
+
+    /// Adds query and returns its root.
+    pub fn push_query(&mut self, query: Query<T>) -> NodeId {
+        match query.item {
+            Item::AndChain(items) => self.push_chain(items, false, query.inverse),
+            Item::OrChain(items) => self.push_chain(items, true, query.inverse),
+            Item::Single(tag) => self.push(ArenaItem::Single(tag), query.inverse),
+            Item::All => self.push(ArenaItem::All, query.inverse),
+            Item::Empty => self.push(ArenaItem::Empty, query.inverse),
+        }
+    }
+
+    fn push_chain(&mut self, items: Vec<Query<T>>, or: bool, inverse: bool) -> NodeId {
+        let start = self.pending.len();
+        for item in items {
+            let id = self.push_query(item);
+            self.pending.push(id);
+        }
+        self.close_chain(start, or, inverse)
+    }
+
+    // Moves pending[start..] to children as the chain's items.
+    fn close_chain(&mut self, start: usize, or: bool, inverse: bool) -> NodeId {
+        let from = self.children.len() as u32;
+        self.children.extend(self.pending.drain(start..));
+        let range = from..self.children.len() as u32;
+        let item = if or {
+            ArenaItem::OrChain(range)
+        } else {
+            ArenaItem::AndChain(range)
+        };
+        self.push(item, inverse)
+    }
+
+    pub fn to_query(&self, id: NodeId) -> Query<T>
+    where
+        T: Clone,
+    {
+        let node = self.node(id);
+        let items = || {
+            self.children(id)
+                .iter()
+                .map(|&id| self.to_query(id))
+                .collect()
+        };
+        let item = match &node.item {
+            ArenaItem::AndChain(_) => Item::AndChain(items()),
+            ArenaItem::OrChain(_) => Item::OrChain(items()),
+            ArenaItem::Single(tag) => Item::Single(tag.clone()),
+            ArenaItem::All => Item::All,
+            ArenaItem::Empty => Item::Empty,
+        };
+        Query::new(item, node.inverse)
+    }
+
+    /// Query::parse into the arena, returns the root.
+    #[allow(clippy::result_unit_err)]
+    pub fn parse<'s>(&mut self, query: &'s str) -> Result<NodeId, ()>
+    where
+        T: From<&'s str>,
+    {
+        let mut tokens = query.split_whitespace().peekable();
+        let root = self.parse_chain(&mut tokens, false);
+        if tokens.next().is_some() {
+            return Err(());
+        }
+        Ok(root)
+    }
+
+    // Same rules as parse_item in parse.rs, with pending[or_start..] as the OrChain
+    // and pending[and_start..or_start] as the AndChain.
+    fn parse_chain<'s>(
+        &mut self,
+        input: &mut Peekable<impl Iterator<Item = &'s str>>,
+        inverse: bool,
+    ) -> NodeId
+    where
+        T: From<&'s str>,
+    {
+        let and_start = self.pending.len();
+        let mut or_start = and_start;
+        let mut was_or = false;
+
+        while let Some(token) = input.next() {
+            let mut is_or = false;
+            let item = match token {
+                "-" => None,
+                "()" => None,
+                "or" => {
+                    is_or = true;
+                    None
+                }
+                "-(" => Some(self.parse_chain(input, true)),
+                "(" => Some(self.parse_chain(input, false)),
+                ")" => break,
+                mut tag => {
+                    let inverse = tag.starts_with('-');
+                    if inverse {
+                        tag = &tag[1..];
+                    }
+                    Some(self.push(ArenaItem::Single(T::from(tag)), inverse))
+                }
+            };
+            let or_empty = or_start == self.pending.len();
+            if let Some(item) = item {
+                if !was_or && !or_empty {
+                    or_start = self.close_or(or_start);
+                }
+                if was_or || (or_start == self.pending.len() && input.peek() == Some(&"or")) {
+                    self.pending.push(item);
+                } else {
+                    self.pending.push(item);
+                    or_start = self.pending.len();
+                }
+            } else if !is_or && !or_empty {
+                or_start = self.close_or(or_start);
+            }
+            was_or = is_or;
+        }
+        if or_start != self.pending.len() {
+            self.close_or(or_start);
+        }
+        self.close_chain(and_start, false, inverse)
+    }
+
+    // Closes the OrChain at pending[start..] as an item of the AndChain, returns
+    // where the next OrChain starts.
+    fn close_or(&mut self, start: usize) -> usize {
+        let id = self.close_chain(start, true, false);
+        self.pending.push(id);
+        self.pending.len()
+    }
+
+    /// Query::try_map from root into out, returns the root in out.
+    pub fn try_map_into<F: Fn(&T, bool) -> Option<Query<R>>, R>(
+        &self,
+        root: NodeId,
+        out: &mut QueryArena<R>,
+        f: F,
+    ) -> Result<NodeId, Vec<T>>
+    where
+        T: Clone,
+    {
+        let start = out.pending.len();
+        let result = self.inner_try_map(root, out, &f, self.node(root).inverse);
+        out.pending.truncate(start);
+        result
+    }
+
+    fn inner_try_map<F: Fn(&T, bool) -> Option<Query<R>>, R>(
+        &self,
+        id: NodeId,
+        out: &mut QueryArena<R>,
+        f: &F,
+        inverse: bool,
+    ) -> Result<NodeId, Vec<T>>
+    where
+        T: Clone,
+    {
+        let node = self.node(id);
+        match &node.item {
+            ArenaItem::AndChain(_) | ArenaItem::OrChain(_) => {
+                let or = matches!(node.item, ArenaItem::OrChain(_));
+                let start = out.pending.len();
+                let mut missing = Vec::new();
+                for &child in self.children(id) {
+                    let child_inverse = inverse ^ self.node(child).inverse;
+                    match self.inner_try_map(child, out, f, child_inverse) {
+                        Ok(item) => out.pending.push(item),
+                        Err(m) => missing.extend(m),
+                    }
+                }
+                let failed = if or {
+                    out.pending.len() == start && !self.children(id).is_empty()
+                } else {
+                    !missing.is_empty()
+                };
+                if failed {
+                    out.pending.truncate(start);
+                    Err(missing)
+                } else {
+                    Ok(out.close_chain(start, or, node.inverse))
+                }
+            }
+            ArenaItem::Single(tag) => {
+                if let Some(item) = f(tag, node.inverse) {
+                    Ok(out.push_query(item))
+                } else if inverse {
+                    Err(Vec::new())
+                } else {
+                    Err(vec![tag.clone()])
+                }
+            }
+            ArenaItem::All => Ok(out.push(ArenaItem::All, node.inverse)),
+            ArenaItem::Empty => Ok(out.push(ArenaItem::Empty, node.inverse)),
+        }
+    }
+}
+
+impl<'i> QueryArena<Queryable<'i>> {
+    /// Query::run for the query at root.
+    pub fn run(&self, root: NodeId, base_checks: &[Packed]) -> Vec<Packed> {
+        run::run(self.run_node(root), base_checks)
+    }
+
+    /// Query::run_base for the query at root.
+    pub fn run_base(&self, root: NodeId, base: &QueryableOwned) -> Vec<Packed> {
+        run::run_base(self.run_node(root), base)
+    }
+
+    /// Query::matches for the query at root.
+    pub fn matches(&self, root: NodeId, id: ID) -> bool {
+        run::matches(self.run_node(root), id)
+    }
+
+    fn run_node(&self, id: NodeId) -> RunRef<'_, 'i> {
+        RunRef { arena: self, id }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RunRef<'n, 'i> {
+    arena: &'n QueryArena<Queryable<'i>>,
+    id: NodeId,
+}
+
+impl<'n, 'i: 'n> RunNode<'n, 'i> for RunRef<'n, 'i> {
+    type Children = RunChildren<'n, 'i>;
+
+    fn inverse(self) -> bool {
+        self.arena.node(self.id).inverse
+    }
+
+    fn kind(self) -> Kind<'n, 'i> {
+        match &self.arena.node(self.id).item {
+            ArenaItem::AndChain(_) => Kind::And,
+            ArenaItem::OrChain(_) => Kind::Or,
+            ArenaItem::Single(tag) => Kind::Single(tag),
+            ArenaItem::All => Kind::All,
+            ArenaItem::Empty => Kind::Empty,
+        }
+    }
+
+    fn children(self) -> Self::Children {
+        RunChildren {
+            arena: self.arena,
+            ids: self.arena.children(self.id).iter(),
+        }
+    }
+}
+
+struct RunChildren<'n, 'i> {
+    arena: &'n QueryArena<Queryable<'i>>,
+    ids: std::slice::Iter<'n, NodeId>,
+}
+
+impl<'n, 'i> Iterator for RunChildren<'n, 'i> {
+    type Item = RunRef<'n, 'i>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = *self.ids.next()?;
+        Some(RunRef {
+            arena: self.arena,
+            id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERIES: &[&str] = &[
+        "",
+        "a -b",
+        "a or b c",
+        "a or -( b c or d ) -e",
+        "( a or b ) or c -",
+        "-( a or b )",
+        "a ) b",
+    ];
+
+    #[test]
+    fn parse_matches_query_parse() {
+        let mut arena = QueryArena::new();
+        for text in QUERIES {
+            arena.clear();
+            let parsed = arena.parse(text).map(|root| arena.to_query(root));
+            assert_eq!(parsed, Query::<String>::parse(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn run_matches_query_run() {
+        let ids: [&[ID]; 3] = [&[1, 2, 3, 70], &[2, 3, 64], &[3, 100]];
+        let map = |tag: &String, inverse| {
+            let index = (tag.as_bytes()[0] - b'a') as usize;
+            let ids = ids.get(index)?;
+            Some(Query::new(Item::Single(Queryable::IDs(ids)), inverse))
+        };
+        let base = [Packed::MAX; 2];
+        let mut arena = QueryArena::new();
+        let mut mapped = QueryArena::new();
+        for text in QUERIES {
+            let Ok(query) = Query::<String>::parse(text) else {
+                continue;
+            };
+            arena.clear();
+            mapped.clear();
+            let root = arena.parse(text).unwrap();
+            let mapped_root = arena.try_map_into(root, &mut mapped, map);
+            match query.try_map(map) {
+                Ok(query) => {
+                    let root = mapped_root.unwrap();
+                    assert_eq!(mapped.run(root, &base), query.run(&base), "{text}");
+                }
+                Err(missing) => assert_eq!(mapped_root, Err(missing), "{text}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::ops::Not;
+
+use super::{Item, Query};
+
+/// Builds a Query without formatting and parsing a string,
+/// `!QueryBuilder::all_of(["a", "b"]).or(QueryBuilder::term("c"))`.
+#[derive(Clone, Debug)]
+pub struct QueryBuilder<T> {
+    query: Query<T>,
+}
+
+impl<T> QueryBuilder<T> {
+    pub fn term(term: T) -> Self {
+        Self::from(Query::new(Item::Single(term), false))
+    }
+
+    /// Matches every id.
+    pub fn all() -> Self {
+        Self::from(Query::all())
+    }
+
+    /// Matches no id.
+    pub fn empty() -> Self {
+        Self::from(Query::empty())
+    }
+
+    /// Every item must match, items can be terms, builders or queries.
+    pub fn all_of<I: Into<QueryBuilder<T>>>(items: impl IntoIterator<Item = I>) -> Self {
+        let items = items.into_iter().map(|item| item.into().query).collect();
+        Self::from(Query::new(Item::AndChain(items), false))
+    }
+
+    /// Any item must match.
+    pub fn any_of<I: Into<QueryBuilder<T>>>(items: impl IntoIterator<Item = I>) -> Self {
+        let items = items.into_iter().map(|item| item.into().query).collect();
+        Self::from(Query::new(Item::OrChain(items), false))
+    }
+
+    /// No item may match.
+    pub fn none_of<I: Into<QueryBuilder<T>>>(items: impl IntoIterator<Item = I>) -> Self {
+        Self::any_of(items).not()
+    }
+
+    pub fn and(self, other: impl Into<QueryBuilder<T>>) -> Self {
+        Self::from(self.query.and(other.into().query))
+    }
+
+    pub fn or(self, other: impl Into<QueryBuilder<T>>) -> Self {
+        let other = other.into().query;
+        let item = match self.query.item {
+            Item::OrChain(mut items) if !self.query.inverse => {
+                items.push(other);
+                Item::OrChain(items)
+            }
+            item => Item::OrChain(vec![Query::new(item, self.query.inverse), other]),
+        };
+        Self::from(Query::new(item, false))
+    }
+
+    pub fn build(self) -> Query<T> {
+        self.query
+    }
+}
+
+impl<T> Not for QueryBuilder<T> {
+    type Output = Self;
+
+    /// Matches exactly the ids self doesn't.
+    fn not(self) -> Self {
+        Self::from(self.query.negated())
+    }
+}
+
+impl<T> From<Query<T>> for QueryBuilder<T> {
+    fn from(query: Query<T>) -> Self {
+        Self { query }
+    }
+}
+
+impl<T> From<QueryBuilder<T>> for Query<T> {
+    fn from(builder: QueryBuilder<T>) -> Self {
+        builder.query
+    }
+}
+
+impl From<&str> for QueryBuilder<String> {
+    fn from(term: &str) -> Self {
+        Self::term(term.to_string())
+    }
+}
+
+impl From<String> for QueryBuilder<String> {
+    fn from(term: String) -> Self {
+        Self::term(term)
+    }
+}
+
+impl<'s> From<&'s str> for QueryBuilder<&'s str> {
+    fn from(term: &'s str) -> Self {
+        Self::term(term)
+    }
+}
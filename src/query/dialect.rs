@@ -0,0 +1,213 @@
+use super::{Item, Query, QueryBuilder};
+
+/// Search syntax of another booru, parsed into the same Query tree as Query::parse
+/// so saved searches keep working after migrating.
+pub trait Dialect {
+    #[allow(clippy::result_unit_err)]
+    fn parse(&self, query: &str) -> Result<Query<String>, ()>;
+}
+
+/// The syntax Query::parse reads.
+pub struct Native;
+
+impl Dialect for Native {
+    fn parse(&self, query: &str) -> Result<Query<String>, ()> {
+        Query::parse(query)
+    }
+}
+
+/// `a ~b ~c -d`, `~` tags are ORed together, `( )` groups,
+/// `or` binds looser than the implicit and, `score:10..` and `score:..10`.
+pub struct Danbooru;
+
+impl Dialect for Danbooru {
+    fn parse(&self, query: &str) -> Result<Query<String>, ()> {
+        let tokens = split_parens(query);
+        let mut tokens = tokens.iter().map(String::as_str);
+        parse_or(&mut tokens, false)
+    }
+}
+
+/// Same as Danbooru, `~` for or, `-` for not and `( )` groups.
+pub struct E621;
+
+impl Dialect for E621 {
+    fn parse(&self, query: &str) -> Result<Query<String>, ()> {
+        Danbooru.parse(query)
+    }
+}
+
+/// `{a ~ b ~ c}` or `( a ~ b )` for or, `-` for not, `-{a ~ b}` matches neither.
+pub struct Gelbooru;
+
+impl Dialect for Gelbooru {
+    fn parse(&self, query: &str) -> Result<Query<String>, ()> {
+        let tokens = split_braces(query);
+        let mut tokens = tokens.iter().map(String::as_str);
+        let mut items = Vec::new();
+        let mut negate = false;
+        while let Some(token) = tokens.next() {
+            match token {
+                "{" | "(" | "-(" => {
+                    let group = parse_gelbooru_group(&mut tokens)?;
+                    // `-{a ~ b}` is split into `-` and `{`.
+                    if negate || token == "-(" {
+                        items.push(group.negated());
+                    } else {
+                        items.push(group);
+                    }
+                }
+                "}" | ")" | "~" => return Err(()),
+                token => items.extend(term(token)),
+            }
+            negate = token == "-";
+        }
+        Ok(Query::new(Item::AndChain(items), false))
+    }
+}
+
+// Alternatives separated by `~` up to the closing brace.
+fn parse_gelbooru_group<'t>(
+    tokens: &mut impl Iterator<Item = &'t str>,
+) -> Result<Query<String>, ()> {
+    let mut alternatives = Vec::new();
+    let mut items = Vec::new();
+    loop {
+        match tokens.next().ok_or(())? {
+            "}" | ")" => break,
+            "~" => alternatives.push(and_chain(std::mem::take(&mut items))),
+            "{" | "(" | "-(" => return Err(()),
+            token => items.extend(term(token)),
+        }
+    }
+    alternatives.push(and_chain(items));
+    Ok(Query::new(Item::OrChain(alternatives), false))
+}
+
+// Alternatives separated by `or`, each an and_chain of terms, up to `)` when nested.
+fn parse_or<'t>(
+    tokens: &mut impl Iterator<Item = &'t str>,
+    nested: bool,
+) -> Result<Query<String>, ()> {
+    let mut alternatives = Vec::new();
+    let mut items = Vec::new();
+    let mut any_of = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            ")" if nested => break,
+            ")" => return Err(()),
+            "or" => {
+                alternatives.push(finish_and(&mut items, &mut any_of));
+            }
+            "and" => {}
+            "(" => items.push(parse_or(tokens, true)?),
+            "-(" => {
+                let group = parse_or(tokens, true)?;
+                items.push((!QueryBuilder::from(group)).build());
+            }
+            token => match token.strip_prefix('~') {
+                Some(token) => any_of.extend(term(token)),
+                None => items.extend(term(token)),
+            },
+        }
+    }
+    alternatives.push(finish_and(&mut items, &mut any_of));
+    if alternatives.len() == 1 {
+        return Ok(alternatives.pop().unwrap());
+    }
+    Ok(Query::new(Item::OrChain(alternatives), false))
+}
+
+// `~` tags become one OrChain alongside the other items.
+fn finish_and(items: &mut Vec<Query<String>>, any_of: &mut Vec<Query<String>>) -> Query<String> {
+    let mut items = std::mem::take(items);
+    if !any_of.is_empty() {
+        items.push(Query::new(Item::OrChain(std::mem::take(any_of)), false));
+    }
+    Query::new(Item::AndChain(items), false)
+}
+
+fn and_chain(mut items: Vec<Query<String>>) -> Query<String> {
+    if items.len() == 1 {
+        return items.pop().unwrap();
+    }
+    Query::new(Item::AndChain(items), false)
+}
+
+// A tag or metatag, None for a lone `-`.
+fn term(token: &str) -> Option<Query<String>> {
+    let (token, inverse) = match token.strip_prefix('-') {
+        Some(token) => (token, true),
+        None => (token, false),
+    };
+    if token.is_empty() {
+        return None;
+    }
+    Some(Query::new(Item::Single(normalize_range(token)), inverse))
+}
+
+// Open ended ranges `score:10..` and `score:..10` become `score:>=10` and `score:<=10`.
+fn normalize_range(token: &str) -> String {
+    if let Some((ident, value)) = token.split_once(':') {
+        if value.matches("..").count() == 1 {
+            if let Some(min) = value.strip_suffix("..").filter(|v| !v.is_empty()) {
+                return format!("{ident}:>={min}");
+            }
+            if let Some(max) = value.strip_prefix("..").filter(|v| !v.is_empty()) {
+                return format!("{ident}:<={max}");
+            }
+        }
+    }
+    token.to_string()
+}
+
+// Splits parentheses off tokens unless they're part of the tag, `(a` but not `tag_(artist)`.
+fn split_parens(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for mut token in query.split_whitespace() {
+        let mut close = 0;
+        loop {
+            let opens = token.matches('(').count();
+            let closes = token.matches(')').count();
+            if let Some(rest) = token.strip_prefix("-(").filter(|_| opens > closes) {
+                tokens.push("-(".to_string());
+                token = rest;
+            } else if let Some(rest) = token.strip_prefix('(').filter(|_| opens > closes) {
+                tokens.push("(".to_string());
+                token = rest;
+            } else if let Some(rest) = token.strip_suffix(')').filter(|_| closes > opens) {
+                close += 1;
+                token = rest;
+            } else {
+                break;
+            }
+        }
+        if !token.is_empty() {
+            tokens.push(token.to_string());
+        }
+        tokens.extend((0..close).map(|_| ")".to_string()));
+    }
+    tokens
+}
+
+// Splits braces and `~` off tokens, `{a` becomes `{` and `a`.
+fn split_braces(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for token in query.split_whitespace() {
+        let mut current = String::new();
+        for c in token.chars() {
+            if matches!(c, '{' | '}') || (c == '~' && current.is_empty()) {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                tokens.push(c.to_string());
+            } else {
+                current.push(c);
+            }
+        }
+        if !current.is_empty() {
+            tokens.push(current);
+        }
+    }
+    tokens
+}
@@ -0,0 +1,127 @@
+use std::fmt::{self, Display, Formatter};
+
+use super::{Item, Query};
+
+/// Renders the query in the syntax Query::parse reads, with parentheses only where needed.
+impl<T: Display> Display for Query<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        write_and(self, f, &mut first)
+    }
+}
+
+impl<T: Clone + Display + Ord> Query<T> {
+    /// The simplified query as a string, equivalent queries written differently
+    /// such as `b a` and `a b a` render the same.
+    pub fn to_canonical(&self) -> String {
+        let mut query = self.clone();
+        query.simplify();
+        query.to_string()
+    }
+}
+
+fn separate(f: &mut Formatter<'_>, first: &mut bool) -> fmt::Result {
+    if !*first {
+        f.write_str(" ")?;
+    }
+    *first = false;
+    Ok(())
+}
+
+// Writes query as space separated items of an AndChain.
+fn write_and<T: Display>(query: &Query<T>, f: &mut Formatter<'_>, first: &mut bool) -> fmt::Result {
+    match (&query.item, query.inverse) {
+        (Item::AndChain(items), false) if !items.is_empty() => {
+            for item in items {
+                write_and(item, f, first)?;
+            }
+            Ok(())
+        }
+        (Item::OrChain(items), false) if items.len() == 1 && !is_and_chain(&items[0]) => {
+            write_and(&items[0], f, first)
+        }
+        (Item::OrChain(items), false) if !items.is_empty() => {
+            separate(f, first)?;
+            write_or_items(items, f)
+        }
+        (_, _) => {
+            separate(f, first)?;
+            write_or(query, f)
+        }
+    }
+}
+
+fn write_or_items<T: Display>(items: &[Query<T>], f: &mut Formatter<'_>) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            f.write_str(" or ")?;
+        }
+        write_or(item, f)?;
+    }
+    // A lone group would be read as part of the enclosing AndChain,
+    // which differs when that chain is inverted.
+    if let [item] = items {
+        if is_and_chain(item) {
+            f.write_str(" or -( )")?;
+        }
+    }
+    Ok(())
+}
+
+fn is_and_chain<T>(query: &Query<T>) -> bool {
+    matches!(query.item, Item::AndChain(_))
+}
+
+// Writes query as one item of an OrChain.
+fn write_or<T: Display>(query: &Query<T>, f: &mut Formatter<'_>) -> fmt::Result {
+    match (&query.item, query.inverse) {
+        (Item::Single(tag), inverse) => {
+            if inverse {
+                f.write_str("-")?;
+            }
+            write!(f, "{tag}")
+        }
+        // An empty group matches everything, an inverted empty group nothing.
+        (Item::All | Item::Empty, inverse) => {
+            if matches!(query.item, Item::All) != inverse {
+                f.write_str("( )")
+            } else {
+                f.write_str("-( )")
+            }
+        }
+        (Item::OrChain(items), false) if !items.is_empty() => write_or_items(items, f),
+        // An empty OrChain matches nothing.
+        (Item::OrChain(items), inverse) if items.is_empty() => {
+            f.write_str(if inverse { "( )" } else { "-( )" })
+        }
+        (Item::AndChain(items), false) if items.len() == 1 => write_or(&items[0], f),
+        (Item::AndChain(items), false) => {
+            f.write_str("(")?;
+            for item in items {
+                f.write_str(" ")?;
+                write_or_group(item, f)?;
+            }
+            f.write_str(" )")
+        }
+        // Inverting an AndChain inverts each item, see Query::negated.
+        (Item::AndChain(items), true) => {
+            f.write_str("-(")?;
+            for item in items {
+                f.write_str(" ")?;
+                write_or_group(item, f)?;
+            }
+            f.write_str(" )")
+        }
+        (Item::OrChain(items), _) => {
+            f.write_str(if query.inverse { "-( " } else { "( " })?;
+            write_or_items(items, f)?;
+            f.write_str(" )")
+        }
+    }
+}
+
+// Writes an item inside parentheses, where it is part of an AndChain again.
+fn write_or_group<T: Display>(query: &Query<T>, f: &mut Formatter<'_>) -> fmt::Result {
+    let mut first = true;
+    write_and(query, f, &mut first)
+}
@@ -0,0 +1,91 @@
+use super::{Item, Query};
+
+/// Planner hints written after a query, `tag1 tag2 /*+ order(tag2,tag1) nocache */`,
+/// so a pathological query can be planned by hand, see Db::query_hinted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryHints {
+    /// Top level terms to run first in this order, `-tag` for negated terms.
+    /// Every term then runs in the order written instead of by estimate.
+    pub order: Option<Vec<String>>,
+    /// Looks every term up in its index, skipping warmed terms and cached pairs.
+    pub nocache: bool,
+}
+
+impl QueryHints {
+    pub const START: &'static str = "/*+";
+    pub const END: &'static str = "*/";
+
+    /// Splits the hints off the end of text, text is returned as is without any.
+    /// Err if the hints aren't closed, anything follows them or a hint is unknown.
+    #[allow(clippy::result_unit_err)]
+    pub fn split(text: &str) -> Result<(&str, QueryHints), ()> {
+        let Some(start) = text.find(Self::START) else {
+            return Ok((text, QueryHints::default()));
+        };
+        let rest = &text[start + Self::START.len()..];
+        let end = rest.find(Self::END).ok_or(())?;
+        if !rest[end + Self::END.len()..].trim().is_empty() {
+            return Err(());
+        }
+        Ok((&text[..start], Self::parse(&rest[..end])?))
+    }
+
+    /// Parses the hints between `/*+` and `*/`.
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<QueryHints, ()> {
+        let mut hints = QueryHints::default();
+        let mut rest = text.trim();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("nocache") {
+                hints.nocache = true;
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("order(") {
+                let end = after.find(')').ok_or(())?;
+                let terms = after[..end]
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|term| !term.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                hints.order = Some(terms);
+                rest = &after[end + 1..];
+            } else {
+                return Err(());
+            }
+            // Hints are separated by whitespace or commas.
+            let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+            if trimmed.len() == rest.len() && !rest.is_empty() {
+                return Err(());
+            }
+            rest = trimmed;
+        }
+        Ok(hints)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_none() && !self.nocache
+    }
+
+    /// Moves the top level terms named by order to the front of query in that order.
+    /// Other items keep their place after them, names not in query are ignored.
+    pub fn apply_order(&self, query: &mut Query<String>) {
+        let Some(order) = &self.order else {
+            return;
+        };
+        if query.inverse {
+            return;
+        }
+        let Item::AndChain(items) = &mut query.item else {
+            return;
+        };
+        let position = |item: &Query<String>| match &item.item {
+            Item::Single(text) => order.iter().position(|name| {
+                name.strip_prefix('-').unwrap_or(name) == text
+                    && name.starts_with('-') == item.inverse
+            }),
+            _ => None,
+        };
+        // Stable, so unnamed items stay in the order written.
+        items.sort_by_key(|item| position(item).unwrap_or(order.len()));
+    }
+}
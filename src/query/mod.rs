@@ -1,20 +1,49 @@
+pub mod arena;
+pub mod builder;
+pub mod dialect;
+pub mod display;
+pub mod hints;
 pub mod multi_result;
+pub mod pairs;
 pub mod parse;
 pub mod queryable;
 pub mod result;
+pub mod rewrite;
 pub mod run;
+pub mod saved;
+pub mod score;
+pub mod scratch;
 pub mod simplify;
+pub mod stats;
 pub mod util;
+pub mod visit;
+pub mod warm;
 
-pub use multi_result::MultiQueryResult;
-pub use queryable::{Queryable, QueryableOwned};
+pub use arena::{ArenaItem, ArenaNode, NodeId, QueryArena};
+pub use builder::QueryBuilder;
+pub use dialect::{Danbooru, Dialect, Gelbooru, Native, E621};
+pub use hints::QueryHints;
+pub use multi_result::{Interleave, MultiQueryResult, SortSource, SourceSummary};
+pub use pairs::PairCache;
+pub use queryable::{LazyFn, LazyQueryable, Queryable, QueryableOwned};
 pub use result::QueryResult;
+pub use rewrite::{Rewrite, Rewrites};
+pub use saved::SavedQueries;
+pub use score::{ScoreExpr, ScoreSource};
+pub use scratch::Scratch;
+pub use stats::EvalStats;
+pub use visit::Visitor;
+pub use warm::WarmTerms;
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum Item<T> {
     AndChain(Vec<Query<T>>),
     OrChain(Vec<Query<T>>),
     Single(T),
+    /// Matches every id.
+    All,
+    /// Matches no id.
+    Empty,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
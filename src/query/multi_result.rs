@@ -1,6 +1,59 @@
+#[cfg(feature = "rand")]
 use rand::{thread_rng, Rng};
 
-use crate::{index::ChunkedVec, QueryResult, ID};
+use crate::{
+    index::{ChunkedVec, RangeIndex},
+    QueryResult, ID,
+};
+
+/// A source's ids in sort order with a key comparable across sources,
+/// see MultiQueryResult::get_sorted_by.
+pub trait SortSource<K> {
+    /// Highest key first if reverse.
+    fn sorted(&self, reverse: bool) -> Box<dyn Iterator<Item = (K, ID)> + '_>;
+}
+
+impl<K, V: Clone + Into<K>> SortSource<K> for ChunkedVec<(V, ID)> {
+    fn sorted(&self, reverse: bool) -> Box<dyn Iterator<Item = (K, ID)> + '_> {
+        let key = |(value, id): &(V, ID)| (value.clone().into(), *id);
+        if reverse {
+            Box::new(self.iter().rev().map(key))
+        } else {
+            Box::new(self.iter().map(key))
+        }
+    }
+}
+
+impl<K, V: Clone + Eq + Ord + Into<K>> SortSource<K> for RangeIndex<V> {
+    fn sorted(&self, reverse: bool) -> Box<dyn Iterator<Item = (K, ID)> + '_> {
+        let key = |(value, id): (&V, &ID)| (value.clone().into(), *id);
+        let (values, ids) = (self.values().iter(), self.ids().iter());
+        if reverse {
+            Box::new(values.rev().zip(ids.rev()).map(key))
+        } else {
+            Box::new(values.zip(ids).map(key))
+        }
+    }
+}
+
+/// How MultiQueryResult::get_interleaved takes turns between sources.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Interleave {
+    /// One match from each source in turn, sources that run out are skipped.
+    #[default]
+    RoundRobin,
+    /// Each source gets a share of every page proportional to its matches.
+    Proportional,
+}
+
+/// Matches of one source, see MultiQueryResult::summary.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceSummary<'r> {
+    pub source: &'r str,
+    pub matched: usize,
+    /// Matches that can be shown under the per source limit.
+    pub shown: usize,
+}
 
 pub struct MultiQueryResult {
     pub sources: Vec<String>,
@@ -98,7 +151,159 @@ impl MultiQueryResult {
         self.results.get_mut(index)
     }
 
+    /// Matches of each source for showing next to the results, shown is capped at
+    /// per_source_limit as in get_sorted_with_limits.
+    pub fn summary(&self, per_source_limit: usize) -> Vec<SourceSummary<'_>> {
+        self.sources
+            .iter()
+            .zip(&self.results)
+            .map(|(source, result)| SourceSummary {
+                source,
+                matched: result.matched(),
+                shown: result.matched().min(per_source_limit),
+            })
+            .collect()
+    }
+
+    /// Like get_sorted_by but each source only has its first per_source_limit matches
+    /// in sort order, so one large source can't crowd out the others.
+    /// Pages past the sum of SourceSummary::shown are empty.
+    pub fn get_sorted_with_limits<K: Ord>(
+        &self,
+        sorted: &[&dyn SortSource<K>],
+        index: usize,
+        limit: usize,
+        reverse: bool,
+        per_source_limit: usize,
+    ) -> Vec<(usize, ID)> {
+        let sorted = sorted.iter().map(|sort| sort.sorted(reverse)).collect();
+        self.merge_sorted(sorted, index, limit, reverse, per_source_limit)
+    }
+
+    /// Like get_sorted for sources sorted by different types, such as a date in one
+    /// Db and an id in another, compared by the common key K they convert to.
+    pub fn get_sorted_by<K: Ord>(
+        &self,
+        sorted: &[&dyn SortSource<K>],
+        index: usize,
+        limit: usize,
+        reverse: bool,
+    ) -> Vec<(usize, ID)> {
+        let sorted = sorted.iter().map(|sort| sort.sorted(reverse)).collect();
+        self.merge_sorted(sorted, index, limit, reverse, usize::MAX)
+    }
+
+    // Merges sources already in page order, reverse picks the highest key next.
+    fn merge_sorted<'s, K: Ord>(
+        &self,
+        sorted: Vec<Box<dyn Iterator<Item = (K, ID)> + 's>>,
+        index: usize,
+        limit: usize,
+        reverse: bool,
+        per_source_limit: usize,
+    ) -> Vec<(usize, ID)> {
+        assert_eq!(self.results.len(), sorted.len());
+        let mut sorted: Vec<_> = sorted.into_iter().map(Iterator::peekable).collect();
+        let mut taken = vec![0; sorted.len()];
+        let mut ids = Vec::with_capacity(limit.min(self.remaining));
+        let mut ids_found = 0;
+        while ids.len() < limit {
+            let mut next: Option<(&K, ID, usize)> = None;
+            for (result_index, sort) in sorted.iter_mut().enumerate() {
+                if taken[result_index] >= per_source_limit {
+                    continue;
+                }
+                let result = &self.results[result_index];
+                while sort.next_if(|(_, id)| !result.contains(*id)).is_some() {}
+                let Some((key, id)) = sort.peek() else {
+                    continue;
+                };
+                let value = (key, *id, result_index);
+                let better =
+                    next.is_none_or(|next| if reverse { value > next } else { value < next });
+                if better {
+                    next = Some(value);
+                }
+            }
+            let Some((_, id, result_index)) = next else {
+                break;
+            };
+            sorted[result_index].next();
+            taken[result_index] += 1;
+            ids_found += 1;
+            if ids_found > index {
+                ids.push((result_index, id));
+            }
+        }
+        ids
+    }
+
+    /// Like get_sorted but mixes sources by taking turns instead of by value,
+    /// such as one feed of several boorus. Each source is in id order,
+    /// descending if reverse.
+    pub fn get_interleaved(
+        &self,
+        index: usize,
+        limit: usize,
+        reverse: bool,
+        mode: Interleave,
+    ) -> Vec<(usize, ID)> {
+        let counts: Vec<usize> = self.results.iter().map(|r| r.matched()).collect();
+        let total: usize = counts.iter().sum();
+        if limit == 0 || index >= total {
+            return Vec::new();
+        }
+        let end = index.saturating_add(limit).min(total);
+        let mut taken = vec![0; counts.len()];
+        // Where the page starts in each source and which source each position takes from.
+        let mut starts = vec![0; counts.len()];
+        let mut turns = Vec::with_capacity(end - index);
+        let mut next = 0;
+        for position in 0..end {
+            let source = match mode {
+                Interleave::RoundRobin => {
+                    while taken[next] >= counts[next] {
+                        next = (next + 1) % counts.len();
+                    }
+                    let source = next;
+                    next = (next + 1) % counts.len();
+                    source
+                }
+                // The source furthest behind its share, lowest index first on ties.
+                Interleave::Proportional => (0..counts.len())
+                    .filter(|&i| taken[i] < counts[i])
+                    .min_by(|&a, &b| {
+                        let share = |i: usize| (taken[i] as f64 + 0.5) / counts[i] as f64;
+                        share(a).total_cmp(&share(b))
+                    })
+                    .unwrap(),
+            };
+            if position == index {
+                starts.clone_from(&taken);
+            }
+            if position >= index {
+                turns.push(source);
+            }
+            taken[source] += 1;
+        }
+        let mut pages: Vec<std::vec::IntoIter<ID>> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| {
+                result
+                    .get(starts[i], taken[i] - starts[i], reverse)
+                    .into_iter()
+            })
+            .collect();
+        turns
+            .into_iter()
+            .map(|source| (source, pages[source].next().unwrap()))
+            .collect()
+    }
+
     /// removes matches from results to prevent returning duplicates
+    #[cfg(feature = "rand")]
     pub fn get_random(&mut self, mut limit: usize) -> Vec<(usize, ID)> {
         limit = limit.min(self.remaining);
         let mut ids = Vec::with_capacity(limit);
@@ -120,9 +325,9 @@ impl MultiQueryResult {
         ids
     }
 
-    pub fn get_sorted<V: Eq + Ord>(
+    pub fn get_sorted<V: Clone + Eq + Ord>(
         &self,
-        sorted: &Vec<&ChunkedVec<(V, ID)>>,
+        sorted: &[&RangeIndex<V>],
         mut index: usize,
         mut limit: usize,
         mut reverse: bool,
@@ -137,7 +342,7 @@ impl MultiQueryResult {
         limit = limit.min(self.remaining);
         if self.results.len() == 1 {
             return self.results[0]
-                .get_sorted(sorted[0].iter().map(|(_, id)| *id), index, limit, reverse)
+                .get_sorted(sorted[0].ids().iter().copied(), index, limit, reverse)
                 .into_iter()
                 .map(|id| (0, id))
                 .collect();
@@ -154,18 +359,21 @@ impl MultiQueryResult {
         if reverse {
             let mut sorted: Vec<_> = sorted
                 .iter()
-                .map(|sort| sort.iter().rev().peekable())
+                .map(|sort| {
+                    let (values, ids) = (sort.values().iter(), sort.ids().iter());
+                    values.rev().zip(ids.rev()).peekable()
+                })
                 .collect();
             loop {
                 let mut highest_value: Option<(&V, ID, usize)> = None;
                 for (result_index, sort) in sorted.iter_mut().enumerate() {
                     let result = &self.results[result_index];
                     while let Some((value, id)) = sort.peek() {
-                        if !result.contains(*id) {
+                        if !result.contains(**id) {
                             sort.next();
                             continue;
                         }
-                        let value = (value, *id, result_index);
+                        let value = (*value, **id, result_index);
                         if let Some(highest) = &highest_value {
                             if value > *highest {
                                 highest_value = Some(value);
@@ -193,17 +401,20 @@ impl MultiQueryResult {
                 }
             }
         } else {
-            let mut sorted: Vec<_> = sorted.iter().map(|sort| sort.iter().peekable()).collect();
+            let mut sorted: Vec<_> = sorted
+                .iter()
+                .map(|sort| sort.values().iter().zip(sort.ids().iter()).peekable())
+                .collect();
             loop {
                 let mut lowest_value = None;
                 for (result_index, sort) in sorted.iter_mut().enumerate() {
                     let result = &self.results[result_index];
                     while let Some((value, id)) = sort.peek() {
-                        if !result.contains(*id) {
+                        if !result.contains(**id) {
                             sort.next();
                             continue;
                         }
-                        let value = (value, *id, result_index);
+                        let value = (*value, **id, result_index);
                         if let Some(lowest) = &lowest_value {
                             if value < *lowest {
                                 lowest_value = Some(value);
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{Query, ID};
+
+use super::{Item, Queryable, QueryableOwned};
+
+// Joins a cached pair into the one term the Db looks it up by.
+const SEPARATOR: char = '\u{1f}';
+
+/// Intersections of tag pairs that are often queried together such as `1girl solo`,
+/// looked up as a single term instead of ANDing both. See Db::adapt_pairs.
+#[derive(Debug, Default)]
+pub struct PairCache {
+    capacity: usize,
+    pairs: HashMap<String, QueryableOwned>,
+    // times each pair was queried since the last adapt.
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl Clone for PairCache {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            pairs: self.pairs.clone(),
+            counts: Mutex::new(self.counts.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl PairCache {
+    /// Caches up to capacity pairs, 0 disables counting.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Self::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// The term a pair is cached under, the same for either order.
+    pub fn key(a: &str, b: &str) -> String {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        format!("{a}{SEPARATOR}{b}")
+    }
+
+    /// The two terms of a key.
+    pub fn split(key: &str) -> Option<(&str, &str)> {
+        key.split_once(SEPARATOR)
+    }
+
+    pub fn insert(&mut self, key: String, intersection: QueryableOwned) {
+        self.pairs.insert(key, intersection);
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.pairs.remove(key).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.pairs.clear();
+        self.counts.get_mut().unwrap().clear();
+    }
+
+    pub fn get(&self, key: &str) -> Option<Queryable<'_>> {
+        self.pairs.get(key).map(Queryable::from)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.contains_key(key)
+    }
+
+    /// Records whether id is in the intersection, after a post was inserted, removed or updated.
+    pub fn set(&mut self, key: &str, id: ID, matches: bool) {
+        if let Some(intersection) = self.pairs.get_mut(key) {
+            if matches {
+                intersection.insert(id);
+            } else {
+                intersection.remove(id);
+            }
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.pairs.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Counts every pair of tags query requires together.
+    pub fn record(&self, query: &Query<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut keys = Vec::new();
+        required_pairs(query, query.inverse, &mut keys);
+        if keys.is_empty() {
+            return;
+        }
+        let mut counts = self.counts.lock().unwrap();
+        for key in keys {
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+
+    /// The pairs queried most since the last call with their counts, most first,
+    /// resetting the counts. Used by Db::adapt_pairs.
+    pub fn take_counts(&mut self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self.counts.get_mut().unwrap().drain().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// Replaces pairs of required tags that are cached with their key.
+    pub fn combine(&self, query: &Query<String>) -> Query<String> {
+        let mut query = query.clone();
+        if !self.pairs.is_empty() {
+            let inverse = query.inverse;
+            self.combine_inner(&mut query, inverse);
+        }
+        query
+    }
+
+    // Follows the runner, an AndChain passes its inverse to its items, an OrChain doesn't.
+    fn combine_inner(&self, query: &mut Query<String>, inverse: bool) {
+        match &mut query.item {
+            Item::AndChain(items) => {
+                let mut required: Vec<usize> = Vec::new();
+                for (index, item) in items.iter_mut().enumerate() {
+                    match &item.item {
+                        Item::Single(_) if item.inverse == inverse => required.push(index),
+                        Item::Single(_) => {}
+                        _ => self.combine_inner(item, item.inverse ^ inverse),
+                    }
+                }
+                let mut combined = Vec::new();
+                let mut used = vec![false; items.len()];
+                for (i, &a) in required.iter().enumerate() {
+                    for &b in &required[i + 1..] {
+                        if used[a] || used[b] {
+                            continue;
+                        }
+                        let key = Self::key(tag(&items[a]), tag(&items[b]));
+                        if self.pairs.contains_key(&key) {
+                            used[a] = true;
+                            used[b] = true;
+                            combined.push(Query::new(Item::Single(key), inverse));
+                        }
+                    }
+                }
+                if !combined.is_empty() {
+                    let mut index = 0;
+                    items.retain(|_| {
+                        index += 1;
+                        !used[index - 1]
+                    });
+                    items.extend(combined);
+                }
+            }
+            Item::OrChain(items) => {
+                for item in items {
+                    let inverse = item.inverse;
+                    self.combine_inner(item, inverse);
+                }
+            }
+            Item::Single(_) | Item::All | Item::Empty => {}
+        }
+    }
+}
+
+fn tag(query: &Query<String>) -> &str {
+    match &query.item {
+        Item::Single(tag) => tag,
+        _ => unreachable!(),
+    }
+}
+
+fn required_pairs(query: &Query<String>, inverse: bool, keys: &mut Vec<String>) {
+    match &query.item {
+        Item::AndChain(items) => {
+            let mut required = Vec::new();
+            for item in items {
+                match &item.item {
+                    Item::Single(tag) if item.inverse == inverse => required.push(tag.as_str()),
+                    Item::Single(_) => {}
+                    _ => required_pairs(item, item.inverse ^ inverse, keys),
+                }
+            }
+            for (i, a) in required.iter().enumerate() {
+                for b in &required[i + 1..] {
+                    if a != b {
+                        keys.push(PairCache::key(a, b));
+                    }
+                }
+            }
+        }
+        Item::OrChain(items) => {
+            for item in items {
+                required_pairs(item, item.inverse, keys);
+            }
+        }
+        Item::Single(_) | Item::All | Item::Empty => {}
+    }
+}
@@ -1,47 +1,40 @@
+use std::iter::Peekable;
+
 use super::{Item, Query};
 
 impl<'s, S: From<&'s str>> Query<S> {
     // TODO: actual parser
+    #[allow(clippy::result_unit_err)]
     pub fn parse(query: &'s str) -> Result<Query<S>, ()> {
-        let split: Vec<&str> = query.split_whitespace().collect();
-        let (index, item) = parse_item(&split);
-        if index != split.len() {
+        // Tokens are read straight from the iterator, only chains allocate.
+        let mut tokens = query.split_whitespace().peekable();
+        let item = parse_item(&mut tokens);
+        if tokens.next().is_some() {
             return Err(());
         }
         Ok(Query::new(item, false))
     }
 }
 
-fn parse_item<'s, S: From<&'s str>>(input: &[&'s str]) -> (usize, Item<S>) {
-    let mut index = 0;
+fn parse_item<'s, S: From<&'s str>>(
+    input: &mut Peekable<impl Iterator<Item = &'s str>>,
+) -> Item<S> {
     let mut and_chain = Vec::new();
     let mut or_chain = Vec::new();
     let mut was_or = false;
 
-    while index < input.len() {
+    while let Some(token) = input.next() {
         let mut is_or = false;
-        let item = &input[index];
-        let item = match *item {
+        let item = match token {
             "-" => None,
             "()" => None,
             "or" => {
                 is_or = true;
                 None
             }
-            "-(" => {
-                let (i, item) = parse_item(&input[index + 1..]);
-                index += i;
-                Some(Query::new(item, true))
-            }
-            "(" => {
-                let (i, item) = parse_item(&input[index + 1..]);
-                index += i;
-                Some(Query::new(item, false))
-            }
-            ")" => {
-                index += 1;
-                break;
-            }
+            "-(" => Some(Query::new(parse_item(input), true)),
+            "(" => Some(Query::new(parse_item(input), false)),
+            ")" => break,
             mut tag => {
                 let inverse = tag.starts_with('-');
                 if inverse {
@@ -55,9 +48,7 @@ fn parse_item<'s, S: From<&'s str>>(input: &[&'s str]) -> (usize, Item<S>) {
                 and_chain.push(Query::new(Item::OrChain(or_chain), false));
                 or_chain = Vec::new();
             }
-            if was_or
-                || (or_chain.is_empty() && index + 1 < input.len() && input[index + 1] == "or")
-            {
+            if was_or || (or_chain.is_empty() && input.peek() == Some(&"or")) {
                 or_chain.push(item);
             } else {
                 if !or_chain.is_empty() {
@@ -71,10 +62,9 @@ fn parse_item<'s, S: From<&'s str>>(input: &[&'s str]) -> (usize, Item<S>) {
             or_chain = Vec::new();
         }
         was_or = is_or;
-        index += 1;
     }
     if !or_chain.is_empty() {
         and_chain.push(Query::new(Item::OrChain(or_chain), false));
     }
-    (index, Item::AndChain(and_chain))
+    Item::AndChain(and_chain)
 }
@@ -1,6 +1,9 @@
-use crate::{Packed, ID, PACKED_SIZE};
+use crate::{packed, Packed, ID};
 
-use super::util::{size_of_checks, size_of_ids, to_checks, to_ids};
+use super::{
+    scratch::Scratch,
+    util::{size_of_checks, size_of_ids, to_checks, to_ids},
+};
 
 pub fn apply_checks(from: &[Packed], checks: &mut [Packed], inverse: bool) {
     let iter = checks.iter_mut().zip(from.iter());
@@ -20,37 +23,104 @@ pub fn apply_checks(from: &[Packed], checks: &mut [Packed], inverse: bool) {
     }
 }
 
+/// Sets checks to the ids in from, or to every other id if inverse.
+/// Ids past the end of checks are skipped. Bits are only ever set or cleared so
+/// duplicates in from are harmless, debug builds still panic on them as every
+/// Queryable source is expected to hold each id once.
 pub fn apply_ids(from: &[ID], checks: &mut [Packed], inverse: bool) {
     checks.fill(if inverse { Packed::MAX } else { 0 });
-    assert_eq!(PACKED_SIZE % 8, 0);
-    let ptr = checks.as_mut_ptr() as *mut u8;
-    let len = checks.len() * PACKED_SIZE as usize / 8;
-    let checks = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
-    if inverse {
-        for id in from {
-            let index = (id / 8) as usize;
-            let offset = id % 8;
-            if index < checks.len() {
-                checks[index] ^= 1 << offset;
+    for id in from {
+        let (index, bit) = (packed::word(*id), packed::bit(*id));
+        if index < checks.len() {
+            debug_assert_eq!(
+                checks[index] & bit == 0,
+                !inverse,
+                "duplicate id {id} in Queryable ids"
+            );
+            if inverse {
+                checks[index] &= !bit;
+            } else {
+                checks[index] |= bit;
             }
         }
-    } else {
-        for id in from {
-            let index = (id / 8) as usize;
-            let offset = id % 8;
-            if index < checks.len() {
-                checks[index] |= 1 << offset;
+    }
+}
+
+/// Sets or clears the bit of each id, ids past the end of checks are skipped.
+pub fn set_ids(ids: &[ID], checks: &mut [Packed], set: bool) {
+    for id in ids {
+        let (index, bit) = (packed::word(*id), packed::bit(*id));
+        if index < checks.len() {
+            if set {
+                checks[index] |= bit;
+            } else {
+                checks[index] &= !bit;
             }
         }
     }
 }
 
-#[derive(Debug)]
+/// A term that is only built if the runner needs the whole set, see Queryable::Lazy.
+pub trait LazyQueryable<'i> {
+    /// Estimated number of ids matched.
+    fn estimate(&self) -> usize;
+
+    /// Used instead of build when few candidate ids are left.
+    fn contains(&self, id: ID) -> bool;
+
+    fn build(&self) -> Queryable<'i>;
+}
+
+/// LazyQueryable from closures, see Queryable::lazy_fn.
+pub struct LazyFn<C, B> {
+    estimate: usize,
+    contains: C,
+    build: B,
+}
+
+impl<'i, C: Fn(ID) -> bool, B: Fn() -> Queryable<'i>> LazyQueryable<'i> for LazyFn<C, B> {
+    fn estimate(&self) -> usize {
+        self.estimate
+    }
+
+    fn contains(&self, id: ID) -> bool {
+        (self.contains)(id)
+    }
+
+    fn build(&self) -> Queryable<'i> {
+        (self.build)()
+    }
+}
+
 pub enum Queryable<'i> {
     Checks(&'i [Packed]),
     ChecksOwned(Vec<Packed>),
     IDs(&'i [ID]),
     IDsOwned(Vec<ID>),
+    /// The union of several unsorted id slices such as a RangeIndex range,
+    /// ORed into a mask in one pass instead of one term per slice.
+    IDSlices(Vec<&'i [ID]>),
+    /// Built only when ANDed with more candidates than it is estimated to match,
+    /// otherwise each candidate is checked with LazyQueryable::contains.
+    Lazy(Box<dyn LazyQueryable<'i> + 'i>),
+    /// Every id but those of the inner term, such as `-1girl` without building the
+    /// half of the db it matches, as key and keys field indexes look up `-key`.
+    /// Applied by the runner as the inner term inverted.
+    Not(Box<Queryable<'i>>),
+}
+
+impl std::fmt::Debug for Queryable<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Checks(checks) => f.debug_tuple("Checks").field(checks).finish(),
+            Self::ChecksOwned(checks) => f.debug_tuple("ChecksOwned").field(checks).finish(),
+            Self::IDs(ids) => f.debug_tuple("IDs").field(ids).finish(),
+            Self::IDsOwned(ids) => f.debug_tuple("IDsOwned").field(ids).finish(),
+            Self::IDSlices(slices) => f.debug_tuple("IDSlices").field(slices).finish(),
+            Self::Lazy(lazy) => f.debug_tuple("Lazy").field(&lazy.estimate()).finish(),
+            Self::Not(inner) => f.debug_tuple("Not").field(inner).finish(),
+        }
+    }
 }
 
 impl<'i> From<&'i QueryableOwned> for Queryable<'i> {
@@ -62,13 +132,107 @@ impl<'i> From<&'i QueryableOwned> for Queryable<'i> {
     }
 }
 
+impl Queryable<'static> {
+    /// Matches no id, see Query::empty to skip the term entirely.
+    pub const EMPTY: Self = Queryable::IDs(&[]);
+}
+
 impl<'i> Queryable<'i> {
-    fn borrowed(&'i self) -> Queryable<'i> {
+    pub fn lazy(lazy: impl LazyQueryable<'i> + 'i) -> Self {
+        Self::Lazy(Box::new(lazy))
+    }
+
+    /// estimate is the expected number of ids matched.
+    pub fn lazy_fn(
+        estimate: usize,
+        contains: impl Fn(ID) -> bool + 'i,
+        build: impl Fn() -> Queryable<'i> + 'i,
+    ) -> Self {
+        Self::lazy(LazyFn {
+            estimate,
+            contains,
+            build,
+        })
+    }
+
+    /// Every id but self's, Not of a Not is its inner term.
+    pub fn complement(self) -> Self {
+        match self {
+            Queryable::Not(inner) => *inner,
+            queryable => Queryable::Not(Box::new(queryable)),
+        }
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        matches!(self, Queryable::Lazy(_))
+    }
+
+    /// True if id is matched, without building Lazy or applying the term to checks.
+    /// IDSlices are unsorted and scanned in full, the runner applies them to checks
+    /// once instead of calling this for each id.
+    pub fn contains(&self, id: ID) -> bool {
+        match self {
+            Queryable::Checks(checks) => packed::contains(checks, id),
+            Queryable::ChecksOwned(checks) => packed::contains(checks, id),
+            Queryable::IDs(ids) => ids.binary_search(&id).is_ok(),
+            Queryable::IDsOwned(ids) => ids.binary_search(&id).is_ok(),
+            Queryable::IDSlices(slices) => slices.iter().any(|slice| slice.contains(&id)),
+            Queryable::Lazy(lazy) => lazy.contains(id),
+            Queryable::Not(inner) => !inner.contains(id),
+        }
+    }
+
+    /// Estimated number of ids matched, exact except for Lazy.
+    /// len is the number of ids Not takes the complement of, such as Db::len.
+    pub fn estimate(&self, len: usize) -> usize {
+        match self {
+            Queryable::Checks(checks) => packed::count(checks),
+            Queryable::ChecksOwned(checks) => packed::count(checks),
+            Queryable::IDs(ids) => ids.len(),
+            Queryable::IDsOwned(ids) => ids.len(),
+            Queryable::IDSlices(slices) => slices.iter().map(|slice| slice.len()).sum(),
+            Queryable::Lazy(lazy) => lazy.estimate(),
+            Queryable::Not(inner) => len.saturating_sub(inner.estimate(len)),
+        }
+    }
+
+    fn borrowed(&self) -> Queryable<'_> {
         match self {
             Queryable::Checks(checks) => Queryable::Checks(checks),
             Queryable::ChecksOwned(checks) => Queryable::Checks(checks.as_slice()),
             Queryable::IDs(ids) => Queryable::IDs(ids),
             Queryable::IDsOwned(ids) => Queryable::IDs(ids.as_slice()),
+            Queryable::IDSlices(slices) => Queryable::IDSlices(slices.clone()),
+            Queryable::Lazy(_) | Queryable::Not(_) => unreachable!(),
+        }
+    }
+
+    /// Number of ids matched by both self and checks.
+    pub fn matched_in(&self, checks: &[Packed]) -> usize {
+        match self {
+            Queryable::Lazy(lazy) => return lazy.build().matched_in(checks),
+            Queryable::Not(inner) => return packed::count(checks) - inner.matched_in(checks),
+            _ => {}
+        }
+        match self.borrowed() {
+            Queryable::Checks(mask) => checks
+                .iter()
+                .zip(mask.iter())
+                .map(|(c, m)| (c & m).count_ones() as usize)
+                .sum(),
+            Queryable::IDs(ids) => ids
+                .iter()
+                .filter(|&&id| packed::contains(checks, id))
+                .count(),
+            Queryable::IDSlices(slices) => slices
+                .iter()
+                .flat_map(|slice| slice.iter())
+                .filter(|&&id| packed::contains(checks, id))
+                .count(),
+            Queryable::ChecksOwned(_)
+            | Queryable::IDsOwned(_)
+            | Queryable::Lazy(_)
+            | Queryable::Not(_) => unreachable!(),
         }
     }
 
@@ -78,10 +242,36 @@ impl<'i> Queryable<'i> {
             Queryable::ChecksOwned(from) => apply_checks(from, checks, inverse),
             Queryable::IDs(from) => apply_ids(from, checks, inverse),
             Queryable::IDsOwned(from) => apply_ids(from, checks, inverse),
+            Queryable::IDSlices(slices) => {
+                checks.fill(if inverse { Packed::MAX } else { 0 });
+                for slice in slices {
+                    set_ids(slice, checks, !inverse);
+                }
+            }
+            Queryable::Lazy(lazy) => lazy.build().apply(checks, inverse),
+            Queryable::Not(inner) => inner.apply(checks, !inverse),
         };
     }
 
     pub fn and(&self, checks: &mut [Packed], inverse: bool) {
+        if let Queryable::Not(inner) = self {
+            return inner.and(checks, !inverse);
+        }
+        if let Queryable::Lazy(lazy) = self {
+            // Checking a candidate costs more than adding an id to a set.
+            let candidates = packed::count(checks);
+            if candidates.saturating_mul(4) >= lazy.estimate() {
+                return lazy.build().and(checks, inverse);
+            }
+            for (index, check) in checks.iter_mut().enumerate() {
+                for id in packed::ids(index, *check) {
+                    if lazy.contains(id) == inverse {
+                        *check &= !packed::bit(id);
+                    }
+                }
+            }
+            return;
+        }
         match self.borrowed() {
             Queryable::Checks(mask) => {
                 let iter = checks.iter_mut().zip(mask.iter());
@@ -93,23 +283,52 @@ impl<'i> Queryable<'i> {
                     for (c, m) in iter {
                         *c &= m;
                     }
+                    for c in checks.iter_mut().skip(mask.len()) {
+                        *c = 0;
+                    }
                 }
             }
             Queryable::IDs(ids) => {
-                let mut mask = Vec::from_iter(checks.iter().copied());
-                apply_ids(ids, &mut mask, inverse);
-                let iter = checks.iter_mut().zip(mask.iter());
-                for (c, m) in iter {
-                    *c &= m;
+                if inverse {
+                    set_ids(ids, checks, false);
+                } else {
+                    let mut mask = Scratch::filled(checks.len(), 0);
+                    apply_ids(ids, &mut mask, false);
+                    let iter = checks.iter_mut().zip(mask.iter());
+                    for (c, m) in iter {
+                        *c &= m;
+                    }
                 }
             }
-            Queryable::ChecksOwned(_) | Queryable::IDsOwned(_) => {
-                unreachable!()
+            Queryable::IDSlices(slices) => {
+                if inverse {
+                    for slice in slices {
+                        set_ids(slice, checks, false);
+                    }
+                } else {
+                    let mut mask = Scratch::filled(checks.len(), 0);
+                    for slice in slices {
+                        set_ids(slice, &mut mask, true);
+                    }
+                    let iter = checks.iter_mut().zip(mask.iter());
+                    for (c, m) in iter {
+                        *c &= m;
+                    }
+                }
             }
+            Queryable::ChecksOwned(_)
+            | Queryable::IDsOwned(_)
+            | Queryable::Lazy(_)
+            | Queryable::Not(_) => unreachable!(),
         }
     }
 
     pub fn or(&self, checks: &mut [Packed], inverse: bool) {
+        match self {
+            Queryable::Lazy(lazy) => return lazy.build().or(checks, inverse),
+            Queryable::Not(inner) => return inner.or(checks, !inverse),
+            _ => {}
+        }
         match self.borrowed() {
             Queryable::Checks(mask) => {
                 let iter = checks.iter_mut().zip(mask.iter());
@@ -128,25 +347,36 @@ impl<'i> Queryable<'i> {
             }
             Queryable::IDs(ids) => {
                 if inverse {
-                    let mut mask = checks.to_vec();
+                    let mut mask = Scratch::filled(checks.len(), Packed::MAX);
                     apply_ids(ids, &mut mask, inverse);
                     let iter = checks.iter_mut().zip(mask.iter());
                     for (c, m) in iter {
                         *c |= m;
                     }
                 } else {
-                    for id in ids.iter() {
-                        let index = (id / PACKED_SIZE) as usize;
-                        let offset = id % PACKED_SIZE;
-                        if index < checks.len() {
-                            checks[index] |= 1 << offset;
-                        }
-                    }
+                    set_ids(ids, checks, true);
                 }
             }
-            Queryable::ChecksOwned(_) | Queryable::IDsOwned(_) => {
-                unreachable!()
+            Queryable::IDSlices(slices) => {
+                if inverse {
+                    let mut mask = Scratch::filled(checks.len(), Packed::MAX);
+                    for slice in &slices {
+                        set_ids(slice, &mut mask, false);
+                    }
+                    let iter = checks.iter_mut().zip(mask.iter());
+                    for (c, m) in iter {
+                        *c |= m;
+                    }
+                } else {
+                    for slice in slices {
+                        set_ids(slice, checks, true);
+                    }
+                }
             }
+            Queryable::ChecksOwned(_)
+            | Queryable::IDsOwned(_)
+            | Queryable::Lazy(_)
+            | Queryable::Not(_) => unreachable!(),
         }
     }
 }
@@ -165,13 +395,18 @@ impl Default for QueryableOwned {
 
 impl From<Vec<Packed>> for QueryableOwned {
     fn from(checks: Vec<Packed>) -> Self {
-        let matched = checks.iter().map(|c| c.count_ones()).sum::<u32>() as usize;
+        let matched = packed::count(&checks);
         Self::Checks { checks, matched }
     }
 }
 
+/// ids must be sorted without duplicates.
 impl From<Vec<ID>> for QueryableOwned {
     fn from(ids: Vec<ID>) -> Self {
+        debug_assert!(
+            ids.windows(2).all(|w| w[0] < w[1]),
+            "ids must be sorted without duplicates"
+        );
         Self::IDs { ids }
     }
 }
@@ -181,21 +416,25 @@ impl QueryableOwned {
         Queryable::from(self).apply(checks, inverse);
     }
 
+    pub fn matched_in(&self, checks: &[Packed]) -> usize {
+        Queryable::from(self).matched_in(checks)
+    }
+
     pub fn contains(&self, id: ID) -> bool {
         match self {
-            QueryableOwned::Checks { checks, .. } => {
-                let index = (id / PACKED_SIZE) as usize;
-                let offset = id % PACKED_SIZE;
-                if index >= checks.len() {
-                    false
-                } else {
-                    checks[index] & (1 << offset) != 0
-                }
-            }
+            QueryableOwned::Checks { checks, .. } => packed::contains(checks, id),
             QueryableOwned::IDs { ids } => ids.binary_search(&id).is_ok(),
         }
     }
 
+    /// Every id, lowest first.
+    pub fn to_ids(&self) -> Vec<ID> {
+        match self {
+            QueryableOwned::Checks { checks, .. } => to_ids(checks),
+            QueryableOwned::IDs { ids } => ids.clone(),
+        }
+    }
+
     pub fn matched(&self) -> usize {
         match self {
             QueryableOwned::Checks { matched, .. } => *matched,
@@ -203,35 +442,82 @@ impl QueryableOwned {
         }
     }
 
+    /// The ids among sorted ids self contains, walking whichever of the two is shorter.
+    pub fn filter_ids(&self, ids: &[ID]) -> Vec<ID> {
+        match self {
+            QueryableOwned::IDs { ids: own } if own.len() < ids.len() => own
+                .iter()
+                .copied()
+                .filter(|id| ids.binary_search(id).is_ok())
+                .collect(),
+            _ => ids
+                .iter()
+                .copied()
+                .filter(|&id| self.contains(id))
+                .collect(),
+        }
+    }
+
+    /// The length of checks holding every id, without building them.
+    pub fn checks_len(&self) -> usize {
+        match self {
+            QueryableOwned::Checks { checks, .. } => checks.len(),
+            QueryableOwned::IDs { ids } => ids.last().map_or(0, |&id| packed::words_for(id)),
+        }
+    }
+
     /// Safe if id is higher than any id self has.
     pub fn insert_unchecked(&mut self, id: ID) {
         match self {
             QueryableOwned::Checks { checks, matched } => {
-                let index = (id / PACKED_SIZE) as usize;
-                let offset = id % PACKED_SIZE;
+                let (index, bit) = (packed::word(id), packed::bit(id));
                 while index >= checks.len() {
                     checks.push(0);
                 }
+                debug_assert_eq!(checks[index] & bit, 0, "duplicate id {id}");
                 *matched += 1;
-                checks[index] |= 1 << offset;
+                checks[index] |= bit;
             }
             QueryableOwned::IDs { ids } => {
+                debug_assert!(
+                    ids.last().is_none_or(|&last| last < id),
+                    "id {id} out of order"
+                );
                 ids.push(id);
             }
         }
     }
 
+    /// Safe if other's ids are higher than any id self has.
+    pub fn extend_unchecked(&mut self, other: QueryableOwned) {
+        match other {
+            QueryableOwned::Checks { checks, .. } => {
+                for id in to_ids(&checks) {
+                    self.insert_unchecked(id);
+                }
+            }
+            QueryableOwned::IDs { ids } => {
+                if let QueryableOwned::IDs { ids: self_ids } = self {
+                    self_ids.extend(ids);
+                } else {
+                    for id in ids {
+                        self.insert_unchecked(id);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn insert(&mut self, id: ID) {
         match self {
             QueryableOwned::Checks { checks, matched } => {
-                let index = (id / PACKED_SIZE) as usize;
-                let offset = id % PACKED_SIZE;
+                let (index, bit) = (packed::word(id), packed::bit(id));
                 while index >= checks.len() {
                     checks.push(0);
                 }
-                if (checks[index] & (1 << offset)) == 0 {
+                if (checks[index] & bit) == 0 {
                     *matched += 1;
-                    checks[index] |= 1 << offset;
+                    checks[index] |= bit;
                 }
             }
             QueryableOwned::IDs { ids } => {
@@ -246,11 +532,9 @@ impl QueryableOwned {
     pub fn remove(&mut self, id: ID) {
         match self {
             QueryableOwned::Checks { checks, matched } => {
-                let index = (id / PACKED_SIZE) as usize;
-                let offset = id % PACKED_SIZE;
-                if index < checks.len() && (checks[index] & (1 << offset)) != 0 {
+                if packed::contains(checks, id) {
                     *matched -= 1;
-                    checks[index] &= !(1 << offset);
+                    checks[packed::word(id)] &= !packed::bit(id);
                 }
             }
             QueryableOwned::IDs { ids } => {
@@ -262,6 +546,54 @@ impl QueryableOwned {
         self.check_and_convert();
     }
 
+    /// What's wrong with self, such as a matched count that doesn't agree with its checks.
+    pub fn verify(&self) -> Option<String> {
+        match self {
+            QueryableOwned::Checks { checks, matched } => {
+                let count = packed::count(checks);
+                (count != *matched).then(|| format!("matched is {matched} but {count} ids are set"))
+            }
+            QueryableOwned::IDs { ids } => ids
+                .windows(2)
+                .find(|pair| pair[0] >= pair[1])
+                .map(|pair| format!("ids {} and {} out of order", pair[0], pair[1])),
+        }
+    }
+
+    /// Adds every id set in checks in one pass, for bulk updates.
+    pub fn union(&mut self, checks: &[Packed]) {
+        let mut own = self.take_checks();
+        if own.len() < checks.len() {
+            own.resize(checks.len(), 0);
+        }
+        for (c, m) in own.iter_mut().zip(checks) {
+            *c |= m;
+        }
+        *self = Self::from(own);
+        self.check_and_convert();
+    }
+
+    /// Removes every id set in checks in one pass, for bulk updates.
+    pub fn difference(&mut self, checks: &[Packed]) {
+        let mut own = self.take_checks();
+        for (c, m) in own.iter_mut().zip(checks) {
+            *c &= !m;
+        }
+        while own.last() == Some(&0) {
+            own.pop();
+        }
+        *self = Self::from(own);
+        self.check_and_convert();
+    }
+
+    fn take_checks(&mut self) -> Vec<Packed> {
+        match std::mem::take(self) {
+            QueryableOwned::Checks { checks, .. } => checks,
+            QueryableOwned::IDs { ids } if ids.is_empty() => Vec::new(),
+            QueryableOwned::IDs { ids } => to_checks(&ids),
+        }
+    }
+
     pub fn check_and_convert(&mut self) {
         let matched = self.matched();
         let max_id = match self {
@@ -269,7 +601,7 @@ impl QueryableOwned {
                 if checks.is_empty() {
                     return;
                 }
-                checks.len() as u32 * PACKED_SIZE
+                packed::first_id(checks.len())
             }
             QueryableOwned::IDs { ids } => {
                 if ids.is_empty() {
@@ -1,18 +1,28 @@
+use std::sync::Arc;
+
+#[cfg(feature = "rand")]
 use rand::{thread_rng, Rng};
 
-use crate::{Packed, ID, PACKED_SIZE};
+use crate::{packed, Packed, QueryableOwned, ID, PACKED_SIZE};
+
+#[cfg(feature = "rand")]
+use super::score::ScoreSource;
+use super::util::to_checks;
 
 const CHECKS_PER_CHUNK: u32 = 10;
 const CHECKS_CHUNK_SIZE: u32 = CHECKS_PER_CHUNK * PACKED_SIZE;
 
+// checks and counts are shared by clones until one of them is written to.
 #[derive(Clone, Debug)]
 pub struct QueryResult {
-    checks: Vec<Packed>,
+    checks: Arc<Vec<Packed>>,
     matched: usize,
 
     // num of matches for every CHECKS_CHUNK_SIZE IDs
     // [0..640, 640..1280]
-    match_counts: Vec<u32>,
+    match_counts: Arc<Vec<u32>>,
+    // Db::seq when the query ran.
+    seq: Option<u64>,
 }
 
 impl QueryResult {
@@ -32,20 +42,75 @@ impl QueryResult {
         }
 
         Self {
-            checks,
+            checks: Arc::new(checks),
             matched: matched as usize,
-            match_counts,
+            match_counts: Arc::new(match_counts),
+            seq: None,
         }
     }
 
+    pub(crate) fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    /// Db::seq when the query ran, pass it to Db::query_pinned for the next page.
+    /// None for results not returned by a Db query.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// A copy sharing checks with self, for caching a result and handing it to
+    /// several request handlers. Same as clone, writes to either copy clone the checks.
+    pub fn clone_shared(&self) -> Self {
+        self.clone()
+    }
+
+    /// Whether other shares its checks with self, see clone_shared.
+    pub fn is_shared_with(&self, other: &QueryResult) -> bool {
+        Arc::ptr_eq(&self.checks, &other.checks)
+    }
+
+    /// Drops checks after the last match, results of sparse queries on a large db
+    /// are as long as the db.
+    pub fn trim_trailing_zero_words(&mut self) {
+        let len = self
+            .checks
+            .iter()
+            .rposition(|&check| check != 0)
+            .map_or(0, |i| i + 1);
+        if len == self.checks.len() {
+            return;
+        }
+        Arc::make_mut(&mut self.checks).truncate(len);
+        let counts_len = len.div_ceil(CHECKS_PER_CHUNK as usize);
+        Arc::make_mut(&mut self.match_counts).truncate(counts_len);
+    }
+
+    /// Frees unused capacity, such as before caching the result.
+    pub fn shrink_to_fit(&mut self) {
+        if self.checks.capacity() > self.checks.len() {
+            Arc::make_mut(&mut self.checks).shrink_to_fit();
+        }
+        if self.match_counts.capacity() > self.match_counts.len() {
+            Arc::make_mut(&mut self.match_counts).shrink_to_fit();
+        }
+    }
+
+    /// The smallest representation of the matches, ids when they're sparse.
+    /// For keeping results around such as cached pages or scopes.
+    pub fn compact(&self) -> QueryableOwned {
+        let mut owned = QueryableOwned::Checks {
+            checks: self.checks.to_vec(),
+            matched: self.matched,
+        };
+        owned.check_and_convert();
+        owned
+    }
+
     #[inline(always)]
     pub fn contains(&self, id: ID) -> bool {
-        let index = (id / PACKED_SIZE) as usize;
-        let offset = id % PACKED_SIZE;
-        if index >= self.checks.len() {
-            return false;
-        }
-        self.checks[index] & (1 << offset) != 0
+        packed::contains(&self.checks, id)
     }
 
     #[inline(always)]
@@ -59,35 +124,60 @@ impl QueryResult {
     }
 
     pub fn insert(&mut self, id: ID) {
-        let index = (id / PACKED_SIZE) as usize;
-        let offset = id % PACKED_SIZE;
-        while self.checks.len() <= index {
-            self.checks.push(0);
-        }
-        if self.checks[index] & (1 << offset) == 0 {
-            self.checks[index] |= 1 << offset;
-            let counts_index = index / CHECKS_PER_CHUNK as usize;
-            while self.match_counts.len() <= counts_index {
-                self.match_counts.push(0);
-            }
-            self.match_counts[counts_index] += 1;
-            self.matched += 1;
+        let index = packed::word(id);
+        if self.contains(id) {
+            return;
+        }
+        let checks = Arc::make_mut(&mut self.checks);
+        while checks.len() <= index {
+            checks.push(0);
+        }
+        checks[index] |= packed::bit(id);
+        let match_counts = Arc::make_mut(&mut self.match_counts);
+        let counts_index = index / CHECKS_PER_CHUNK as usize;
+        while match_counts.len() <= counts_index {
+            match_counts.push(0);
         }
+        match_counts[counts_index] += 1;
+        self.matched += 1;
     }
 
     pub fn remove(&mut self, id: ID) {
-        let index = (id / PACKED_SIZE) as usize;
-        let offset = id % PACKED_SIZE;
-        if index >= self.checks.len() {
-            return;
-        }
-        if self.checks[index] & (1 << offset) != 0 {
-            self.checks[index] ^= 1 << offset;
-            self.match_counts[index / CHECKS_PER_CHUNK as usize] -= 1;
+        if self.contains(id) {
+            let index = packed::word(id);
+            Arc::make_mut(&mut self.checks)[index] ^= packed::bit(id);
+            Arc::make_mut(&mut self.match_counts)[index / CHECKS_PER_CHUNK as usize] -= 1;
             self.matched -= 1;
         }
     }
 
+    /// Matches per bucket_size ids, [0..bucket_size, bucket_size..2 * bucket_size, ..]
+    /// up to the last check. For sparklines of matches over time when ids follow upload order.
+    /// Multiples of 640 reuse the counts kept for paging.
+    pub fn density_profile(&self, bucket_size: u32) -> Vec<u32> {
+        let bucket_size = bucket_size.max(1);
+        let len = (self.checks.len() as u64 * PACKED_SIZE as u64).div_ceil(bucket_size as u64);
+        let mut profile = vec![0; len as usize];
+        if bucket_size.is_multiple_of(CHECKS_CHUNK_SIZE) {
+            let per_bucket = (bucket_size / CHECKS_CHUNK_SIZE) as usize;
+            for (index, count) in self.match_counts.iter().enumerate() {
+                profile[index / per_bucket] += count;
+            }
+        } else if bucket_size.is_multiple_of(PACKED_SIZE) {
+            let per_bucket = (bucket_size / PACKED_SIZE) as usize;
+            for (index, check) in self.checks.iter().enumerate() {
+                profile[index / per_bucket] += check.count_ones();
+            }
+        } else {
+            for (index, &check) in self.checks.iter().enumerate() {
+                for id in packed::ids(index, check) {
+                    profile[(id / bucket_size) as usize] += 1;
+                }
+            }
+        }
+        profile
+    }
+
     pub fn get_match(&self, index: u32) -> Option<ID> {
         if index >= self.matched as u32 {
             return None;
@@ -102,16 +192,8 @@ impl QueryResult {
                         ids_found += ones;
                         continue;
                     }
-                    for offset in 0..PACKED_SIZE {
-                        if check & (1 << offset) != 0 {
-                            ids_found += 1;
-                            if ids_found > index {
-                                let id =
-                                    (check_index + checks_offset) as u32 * PACKED_SIZE + offset;
-                                return Some(id);
-                            }
-                        }
-                    }
+                    return packed::ids(check_index + checks_offset, *check)
+                        .nth((index - ids_found) as usize);
                 }
             }
             ids_found += count;
@@ -120,55 +202,45 @@ impl QueryResult {
     }
 
     // TODO use self.get_match then continue from its index
+    /// Up to limit matches starting at the index-th match, by id or by id descending if reverse.
+    /// Empty if index is past the last match, fewer than limit if fewer are left.
     pub fn get(&self, index: usize, mut limit: usize, reverse: bool) -> Vec<ID> {
-        if limit == 0 {
-            return Vec::new();
-        }
-        if index >= self.matched {
+        if limit == 0 || index >= self.matched {
             return Vec::new();
         }
         limit = limit.min(self.matched - index);
         let mut ids = Vec::with_capacity(limit);
         let mut ids_found = 0;
         if reverse {
-            let max_id = self.checks.len() as u32 * PACKED_SIZE - 1;
-            for (id, check) in self.checks.iter().rev().enumerate() {
+            for (check_index, check) in self.checks.iter().enumerate().rev() {
                 let ones = check.count_ones() as usize;
                 if ids_found + ones <= index + ids.len() {
                     ids_found += ones;
                     continue;
                 }
-                let id = max_id - id as u32 * PACKED_SIZE;
-                for (offset_index, offset) in (0..PACKED_SIZE).rev().enumerate() {
-                    if check & (1 << offset) != 0 {
-                        ids_found += 1;
-                        if ids_found > index {
-                            let id = id - offset_index as u32;
-                            ids.push(id);
-                            if ids.len() >= limit {
-                                return ids;
-                            }
+                for id in packed::ids_rev(check_index, *check) {
+                    ids_found += 1;
+                    if ids_found > index {
+                        ids.push(id);
+                        if ids.len() >= limit {
+                            return ids;
                         }
                     }
                 }
             }
         } else {
-            for (id, check) in self.checks.iter().enumerate() {
+            for (check_index, check) in self.checks.iter().enumerate() {
                 let ones = check.count_ones() as usize;
                 if ids_found + ones <= index + ids.len() {
                     ids_found += ones;
                     continue;
                 }
-                let id = id as u32 * PACKED_SIZE;
-                for offset in 0..PACKED_SIZE {
-                    if check & (1 << offset) != 0 {
-                        ids_found += 1;
-                        if ids_found > index {
-                            let id = id + offset;
-                            ids.push(id);
-                            if ids.len() >= limit {
-                                return ids;
-                            }
+                for id in packed::ids(check_index, *check) {
+                    ids_found += 1;
+                    if ids_found > index {
+                        ids.push(id);
+                        if ids.len() >= limit {
+                            return ids;
                         }
                     }
                 }
@@ -177,7 +249,32 @@ impl QueryResult {
         ids
     }
 
+    /// Ids matched by self but not other and ids matched by other but not self,
+    /// `new.diff(&old)` gives the matches added and removed since old was run.
+    pub fn diff(&self, other: &QueryResult) -> (Vec<ID>, Vec<ID>) {
+        let len = self.checks.len().max(other.checks.len());
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for index in 0..len {
+            let a = self.checks.get(index).copied().unwrap_or(0);
+            let b = other.checks.get(index).copied().unwrap_or(0);
+            let changed = a ^ b;
+            if changed == 0 {
+                continue;
+            }
+            for id in packed::ids(index, changed) {
+                if a & packed::bit(id) != 0 {
+                    added.push(id);
+                } else {
+                    removed.push(id);
+                }
+            }
+        }
+        (added, removed)
+    }
+
     /// removes matches from results to prevent returning duplicates
+    #[cfg(feature = "rand")]
     pub fn get_random(&mut self, mut limit: usize) -> Vec<ID> {
         if limit == 0 {
             return Vec::new();
@@ -194,6 +291,45 @@ impl QueryResult {
         ids
     }
 
+    /// Like get_random but ids are picked with probability proportional to their weight,
+    /// such as a RangeIndex of scores for random but good posts. Ids with no weight or
+    /// a weight of 0 or less are only picked once every id with a weight has been.
+    /// Visits every match.
+    #[cfg(feature = "rand")]
+    pub fn get_random_weighted(&mut self, limit: usize, weights: &dyn ScoreSource) -> Vec<ID> {
+        let limit = limit.min(self.matched);
+        if limit == 0 {
+            return Vec::new();
+        }
+        let mut rng = thread_rng();
+        // Weighted sampling without replacement, the ids with the highest
+        // ln(u) / weight for u uniform in (0, 1] are a weighted sample.
+        let mut keyed: Vec<(bool, f64, ID)> = Vec::with_capacity(self.matched);
+        for (index, &check) in self.checks.iter().enumerate() {
+            for id in packed::ids(index, check) {
+                let u: f64 = 1.0 - rng.gen::<f64>();
+                match weights.score(id).filter(|&weight| weight > 0.0) {
+                    Some(weight) => keyed.push((true, u.ln() / weight, id)),
+                    None => keyed.push((false, u, id)),
+                }
+            }
+        }
+        let by_key =
+            |a: &(bool, f64, ID), b: &(bool, f64, ID)| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1));
+        if limit < keyed.len() {
+            keyed.select_nth_unstable_by(limit - 1, by_key);
+            keyed.truncate(limit);
+        }
+        keyed.sort_unstable_by(by_key);
+        let ids: Vec<ID> = keyed.into_iter().map(|(_, _, id)| id).collect();
+        for &id in &ids {
+            self.remove(id);
+        }
+        ids
+    }
+
+    /// Like get but ordered by sort, ids in sort that didn't match are skipped.
+    /// Starts from the end of sort when index is in the back half of the matches.
     pub fn get_sorted(
         &self,
         sort: impl DoubleEndedIterator<Item = ID>,
@@ -201,10 +337,7 @@ impl QueryResult {
         mut limit: usize,
         mut reverse: bool,
     ) -> Vec<ID> {
-        if limit == 0 {
-            return Vec::new();
-        }
-        if index >= self.matched {
+        if limit == 0 || index >= self.matched {
             return Vec::new();
         }
         limit = limit.min(self.matched - index);
@@ -213,7 +346,8 @@ impl QueryResult {
         let backwards = index >= self.matched / 2;
         if backwards {
             reverse = !reverse;
-            index = self.matched - index - limit;
+            // index + limit <= matched after clamping limit.
+            index = self.matched - (index + limit);
         }
         if reverse {
             for id in sort.rev() {
@@ -246,3 +380,56 @@ impl QueryResult {
         ids
     }
 }
+
+impl From<QueryableOwned> for QueryResult {
+    fn from(owned: QueryableOwned) -> Self {
+        match owned {
+            QueryableOwned::Checks { checks, .. } => Self::new(checks),
+            QueryableOwned::IDs { ids } => Self::new(to_checks(&ids)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(ids: &[ID]) -> QueryResult {
+        QueryResult::new(to_checks(ids))
+    }
+
+    #[test]
+    fn get_empty_result() {
+        let empty = result(&[]);
+        assert!(empty.get(0, 10, false).is_empty());
+        assert!(empty.get(0, 10, true).is_empty());
+        assert!(empty.get_sorted(0..10, 0, 10, false).is_empty());
+        assert!(empty.get_sorted(0..10, 0, 10, true).is_empty());
+    }
+
+    #[test]
+    fn get_index_past_the_end() {
+        let result = result(&[1, 64, 200]);
+        for reverse in [false, true] {
+            assert!(result.get(3, 10, reverse).is_empty());
+            assert!(result.get(usize::MAX, usize::MAX, reverse).is_empty());
+            assert!(result.get_sorted(0..256, 3, 10, reverse).is_empty());
+            assert!(result
+                .get_sorted(0..256, usize::MAX, usize::MAX, reverse)
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn get_limit_past_matched() {
+        let result = result(&[1, 64, 200]);
+        assert_eq!(result.get(0, 10, false), [1, 64, 200]);
+        assert_eq!(result.get(0, 10, true), [200, 64, 1]);
+        assert_eq!(result.get(1, usize::MAX, false), [64, 200]);
+        assert_eq!(result.get(1, usize::MAX, true), [64, 1]);
+        assert_eq!(result.get_sorted(0..256, 0, 10, false), [1, 64, 200]);
+        assert_eq!(result.get_sorted(0..256, 0, 10, true), [200, 64, 1]);
+        assert_eq!(result.get_sorted(0..256, 2, usize::MAX, false), [200]);
+        assert_eq!(result.get_sorted(0..256, 2, usize::MAX, true), [1]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use super::{Item, Query};
+
+/// A pass over a parsed query before its terms are looked up in indexes,
+/// such as renaming legacy metatags or adding `-rating:e` for anonymous users.
+pub trait Rewrite: Send + Sync {
+    fn rewrite(&self, query: Query<String>) -> Query<String>;
+}
+
+impl<F: Fn(Query<String>) -> Query<String> + Send + Sync> Rewrite for F {
+    fn rewrite(&self, query: Query<String>) -> Query<String> {
+        self(query)
+    }
+}
+
+/// Rewrite passes run in the order they were added, followed by Query::simplify.
+/// Clones share their passes.
+#[derive(Clone)]
+pub struct Rewrites {
+    passes: Vec<Arc<dyn Rewrite>>,
+    simplify: bool,
+}
+
+impl Default for Rewrites {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rewrites {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            simplify: true,
+        }
+    }
+
+    pub fn with(mut self, rewrite: impl Rewrite + 'static) -> Self {
+        self.push(rewrite);
+        self
+    }
+
+    /// Whether the rewritten query is simplified, defaults to true.
+    pub fn with_simplify(mut self, simplify: bool) -> Self {
+        self.simplify = simplify;
+        self
+    }
+
+    pub fn push(&mut self, rewrite: impl Rewrite + 'static) {
+        self.passes.push(Arc::new(rewrite));
+    }
+
+    pub fn clear(&mut self) {
+        self.passes.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn apply(&self, mut query: Query<String>) -> Query<String> {
+        for pass in &self.passes {
+            query = pass.rewrite(query);
+        }
+        if self.simplify && !self.passes.is_empty() {
+            query.simplify();
+        }
+        query
+    }
+}
+
+impl<T> Query<T> {
+    /// Replaces every term with the query f returns for it, `-old` becomes `-(new)`.
+    pub fn replace_terms(self, f: &mut impl FnMut(T) -> Query<T>) -> Query<T> {
+        match self.item {
+            Item::AndChain(items) => Query::new(
+                Item::AndChain(items.into_iter().map(|q| q.replace_terms(f)).collect()),
+                self.inverse,
+            ),
+            Item::OrChain(items) => Query::new(
+                Item::OrChain(items.into_iter().map(|q| q.replace_terms(f)).collect()),
+                self.inverse,
+            ),
+            Item::Single(term) => {
+                let query = f(term);
+                if self.inverse {
+                    query.negated()
+                } else {
+                    query
+                }
+            }
+            item @ (Item::All | Item::Empty) => Query::new(item, self.inverse),
+        }
+    }
+
+    /// Both self and other must match, for adding mandatory filters.
+    pub fn and(self, other: Query<T>) -> Query<T> {
+        match self.item {
+            Item::AndChain(mut items) if !self.inverse => {
+                items.push(other);
+                Query::new(Item::AndChain(items), false)
+            }
+            item => Query::new(
+                Item::AndChain(vec![Query::new(item, self.inverse), other]),
+                false,
+            ),
+        }
+    }
+}
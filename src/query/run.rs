@@ -1,6 +1,15 @@
-use crate::Packed;
+use crate::{
+    packed::{self, PACKED_SIZE},
+    Packed, ID,
+};
 
-use super::{queryable::Queryable, Item, Query};
+use super::{
+    queryable::{Queryable, QueryableOwned},
+    scratch::Scratch,
+    stats,
+    util::to_checks,
+    Item, Query,
+};
 
 fn bit_checks<F: FnMut((&mut Packed, &Packed))>(a: &mut [Packed], b: &[Packed], f: F) {
     a.iter_mut().zip(b.iter()).for_each(f);
@@ -22,53 +31,319 @@ fn or_checks(a: &mut [Packed], b: &[Packed]) {
 //     bit_checks(a, b, |(a, b)| *a |= !b);
 // }
 
+// Stops at the first set word, so dense checks cost next to nothing.
+fn none_set(checks: &[Packed]) -> bool {
+    checks.iter().all(|&check| check == 0)
+}
+
+// Base checks with at most one id per this many words are run an id at a time.
+const ID_DOMAIN_WORDS: usize = 8;
+
+// Counts ids in checks until there are too many to run an id at a time.
+fn few_ids(checks: &[Packed]) -> bool {
+    let limit = checks.len() / ID_DOMAIN_WORDS;
+    let mut count = 0;
+    for check in checks {
+        count += check.count_ones() as usize;
+        if count > limit {
+            return false;
+        }
+    }
+    true
+}
+
+// What the runner needs of a mapped query node, so Query and QueryArena share it.
+pub(crate) trait RunNode<'n, 'i: 'n>: Copy {
+    type Children: Iterator<Item = Self>;
+
+    fn inverse(self) -> bool;
+    fn kind(self) -> Kind<'n, 'i>;
+    /// Empty unless the node is a chain.
+    fn children(self) -> Self::Children;
+}
+
+pub(crate) enum Kind<'n, 'i> {
+    And,
+    Or,
+    Single(&'n Queryable<'i>),
+    All,
+    Empty,
+}
+
+impl<'n, 'i: 'n> RunNode<'n, 'i> for &'n Query<Queryable<'i>> {
+    type Children = std::slice::Iter<'n, Query<Queryable<'i>>>;
+
+    fn inverse(self) -> bool {
+        self.inverse
+    }
+
+    fn kind(self) -> Kind<'n, 'i> {
+        match &self.item {
+            Item::AndChain(_) => Kind::And,
+            Item::OrChain(_) => Kind::Or,
+            Item::Single(tag) => Kind::Single(tag),
+            Item::All => Kind::All,
+            Item::Empty => Kind::Empty,
+        }
+    }
+
+    fn children(self) -> Self::Children {
+        match &self.item {
+            Item::AndChain(items) | Item::OrChain(items) => items.iter(),
+            _ => [].iter(),
+        }
+    }
+}
+
 impl<'i> Query<Queryable<'i>> {
     pub fn run(&self, base_checks: &[Packed]) -> Vec<Packed> {
-        let mut checks = base_checks.to_vec();
-        if let Item::Single(tag) = &self.item {
-            tag.and(&mut checks, self.inverse);
-        } else {
-            self.inner_run(&mut checks, self.inverse);
-            and_checks(&mut checks, base_checks);
+        run(self, base_checks)
+    }
+
+    /// Like run over the ids of base, sparse ids are matched one at a time instead
+    /// of building checks of every id first.
+    pub fn run_base(&self, base: &QueryableOwned) -> Vec<Packed> {
+        run_base(self, base)
+    }
+
+    /// Like run but terms run in the order written, lazy terms aren't moved last.
+    pub fn run_in_order(&self, base_checks: &[Packed]) -> Vec<Packed> {
+        run_in_order(self, base_checks)
+    }
+
+    /// Like run with only id in base_checks, true if id is in the result.
+    pub fn matches(&self, id: ID) -> bool {
+        inner_matches(self, id, self.inverse)
+    }
+}
+
+// Some(true) if node matches every id and Some(false) if none.
+fn constant<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N, inverse: bool) -> Option<bool> {
+    match node.kind() {
+        Kind::All => Some(!inverse),
+        Kind::Empty => Some(inverse),
+        _ => None,
+    }
+}
+
+pub(crate) fn run<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N, base_checks: &[Packed]) -> Vec<Packed> {
+    match constant(node, node.inverse()) {
+        Some(true) => return base_checks.to_vec(),
+        Some(false) => return vec![0; base_checks.len()],
+        None => {}
+    }
+    if few_ids(base_checks) && cheap_matches(node) {
+        return run_ids(node, base_checks);
+    }
+    stats::touch(base_checks.len());
+    let mut checks = base_checks.to_vec();
+    if let Kind::Single(tag) = node.kind() {
+        stats::touch(checks.len());
+        tag.and(&mut checks, node.inverse());
+    } else {
+        inner_run(node, &mut checks, node.inverse(), false);
+        stats::touch(checks.len() * 2);
+        and_checks(&mut checks, base_checks);
+    }
+    checks
+}
+
+pub(crate) fn run_base<'n, 'i: 'n, N: RunNode<'n, 'i>>(
+    node: N,
+    base: &QueryableOwned,
+) -> Vec<Packed> {
+    match base {
+        QueryableOwned::Checks { checks, .. } => run(node, checks),
+        QueryableOwned::IDs { ids }
+            if ids.len() <= base.checks_len() / ID_DOMAIN_WORDS && cheap_matches(node) =>
+        {
+            let mut checks = vec![0; base.checks_len()];
+            stats::touch(checks.len());
+            for &id in ids {
+                if matches(node, id) {
+                    checks[packed::word(id)] |= packed::bit(id);
+                }
+            }
+            checks
         }
-        checks
+        QueryableOwned::IDs { ids } => run(node, &to_checks(ids)),
     }
+}
 
-    fn inner_run(&self, checks: &mut [Packed], inverse: bool) {
-        match &self.item {
-            Item::AndChain(query_items) => {
-                for query_item in query_items {
-                    query_item.inner_run(checks, query_item.inverse ^ inverse);
+pub(crate) fn run_in_order<'n, 'i: 'n, N: RunNode<'n, 'i>>(
+    node: N,
+    base_checks: &[Packed],
+) -> Vec<Packed> {
+    match constant(node, node.inverse()) {
+        Some(true) => return base_checks.to_vec(),
+        Some(false) => return vec![0; base_checks.len()],
+        None => {}
+    }
+    stats::touch(base_checks.len());
+    let mut checks = base_checks.to_vec();
+    inner_run(node, &mut checks, node.inverse(), true);
+    stats::touch(checks.len() * 2);
+    and_checks(&mut checks, base_checks);
+    checks
+}
+
+pub(crate) fn matches<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N, id: ID) -> bool {
+    inner_matches(node, id, node.inverse())
+}
+
+// Tests each id of base_checks with matches instead of running every term over
+// all of the checks, for when there are only a handful of candidates.
+fn run_ids<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N, base_checks: &[Packed]) -> Vec<Packed> {
+    stats::touch(base_checks.len() * 2);
+    let mut checks = vec![0; base_checks.len()];
+    for (index, &check) in base_checks.iter().enumerate() {
+        for id in packed::ids(index, check) {
+            if matches(node, id) {
+                checks[index] |= packed::bit(id);
+            }
+        }
+    }
+    checks
+}
+
+// IDSlices are scanned in full by contains, slower than applying them once.
+fn cheap_matches<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N) -> bool {
+    fn cheap(tag: &Queryable) -> bool {
+        match tag {
+            Queryable::IDSlices(_) => false,
+            Queryable::Not(inner) => cheap(inner),
+            _ => true,
+        }
+    }
+    match node.kind() {
+        Kind::And | Kind::Or => node.children().all(cheap_matches),
+        Kind::Single(tag) => cheap(tag),
+        Kind::All | Kind::Empty => true,
+    }
+}
+
+// Follows the same rules as inner_run for a single id.
+fn inner_matches<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: N, id: ID, inverse: bool) -> bool {
+    match node.kind() {
+        Kind::And => {
+            let mut items = node.children().peekable();
+            if items.peek().is_none() && inverse {
+                return false;
+            }
+            items.all(|item| inner_matches(item, id, item.inverse() ^ inverse))
+        }
+        Kind::Or => {
+            let any = node
+                .children()
+                .any(|item| inner_matches(item, id, item.inverse()));
+            any != inverse
+        }
+        Kind::Single(tag) => tag.contains(id) != inverse,
+        Kind::All => !inverse,
+        Kind::Empty => inverse,
+    }
+}
+
+fn is_lazy<'n, 'i: 'n, N: RunNode<'n, 'i>>(node: &N) -> bool {
+    match node.kind() {
+        Kind::Single(tag) => tag.is_lazy(),
+        _ => false,
+    }
+}
+
+fn inner_run<'n, 'i: 'n, N: RunNode<'n, 'i>>(
+    node: N,
+    checks: &mut [Packed],
+    inverse: bool,
+    in_order: bool,
+) {
+    match node.kind() {
+        Kind::And => {
+            // Nothing to invert, -( ) matches nothing.
+            let empty = node.children().next().is_none() && inverse;
+            if empty
+                || node
+                    .children()
+                    .any(|item| constant(item, item.inverse() ^ inverse) == Some(false))
+            {
+                checks.fill(0);
+                return;
+            }
+            // Items only clear bits, once none are left the rest can't match.
+            if in_order {
+                for item in node.children() {
+                    inner_run(item, checks, item.inverse() ^ inverse, true);
+                    if none_set(checks) {
+                        return;
+                    }
+                }
+                return;
+            }
+            // Lazy terms run last when there are the fewest candidates left to check.
+            for item in node.children().filter(|item| !is_lazy(item)) {
+                inner_run(item, checks, item.inverse() ^ inverse, false);
+                if none_set(checks) {
+                    return;
                 }
             }
-            Item::OrChain(query_items) => {
-                let mut checks_2 = checks.to_vec();
-                checks_2.fill(0);
-                let mut checks_3 = None;
-                for query_item in query_items {
-                    if let Item::Single(tag) = &query_item.item {
-                        tag.or(&mut checks_2, query_item.inverse);
-                    } else {
-                        let checks_3 = if let Some(c) = &mut checks_3 {
-                            c
-                        } else {
-                            checks_3 = Some(checks.to_vec());
-                            checks_3.as_mut().unwrap()
-                        };
+            let mut lazy: Vec<N> = node.children().filter(is_lazy).collect();
+            lazy.sort_by_key(|item| match item.kind() {
+                Kind::Single(tag) => tag.estimate(checks.len() * PACKED_SIZE as usize),
+                _ => 0,
+            });
+            for item in lazy {
+                inner_run(item, checks, item.inverse() ^ inverse, false);
+                if none_set(checks) {
+                    return;
+                }
+            }
+        }
+        Kind::Or => {
+            if node
+                .children()
+                .any(|item| constant(item, item.inverse()) == Some(true))
+            {
+                if inverse {
+                    checks.fill(0);
+                }
+                return;
+            }
+            stats::record(|stats| stats.or_chain_materializations += 1);
+            let mut checks_2 = Scratch::filled(checks.len(), 0);
+            let mut checks_3: Option<Scratch> = None;
+            for item in node.children() {
+                match item.kind() {
+                    Kind::Single(tag) => {
+                        stats::touch(checks.len());
+                        tag.or(&mut checks_2, item.inverse());
+                    }
+                    // Only Empty is left after the check above.
+                    Kind::All | Kind::Empty => {}
+                    Kind::And | Kind::Or => {
+                        let checks_3 = checks_3
+                            .get_or_insert_with(|| Scratch::filled(checks.len(), Packed::MAX));
+                        stats::touch(checks.len() * 2);
                         checks_3.fill(Packed::MAX);
-                        query_item.inner_run(checks_3, query_item.inverse);
+                        inner_run(item, checks_3, item.inverse(), in_order);
                         or_checks(&mut checks_2, checks_3);
                     }
                 }
+            }
 
-                if self.inverse {
-                    and_not_checks(checks, &checks_2);
-                } else {
-                    and_checks(checks, &checks_2);
-                }
+            stats::touch(checks.len() * 2);
+            if inverse {
+                and_not_checks(checks, &checks_2);
+            } else {
+                and_checks(checks, &checks_2);
             }
-            Item::Single(tag) => {
-                tag.and(checks, inverse);
+        }
+        Kind::Single(tag) => {
+            stats::touch(checks.len());
+            tag.and(checks, inverse);
+        }
+        kind @ (Kind::All | Kind::Empty) => {
+            if matches!(kind, Kind::All) == inverse {
+                checks.fill(0);
             }
         }
     }
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use super::Query;
+
+/// Named queries usable inside other queries as `search:name`.
+#[derive(Clone, Debug, Default)]
+pub struct SavedQueries {
+    queries: HashMap<String, Query<String>>,
+}
+
+impl SavedQueries {
+    pub const IDENT: &'static str = "search";
+    /// Saved queries nested deeper than this fail to expand, which also stops cycles.
+    pub const MAX_DEPTH: usize = 16;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        query: Query<String>,
+    ) -> Option<Query<String>> {
+        self.queries.insert(name.into(), query)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn insert_parsed(&mut self, name: impl Into<String>, query: &str) -> Result<(), ()> {
+        let query = Query::parse(query)?;
+        self.insert(name, query);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Query<String>> {
+        self.queries.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Query<String>> {
+        self.queries.get(name)
+    }
+
+    /// The saved query name if text is `search:name`.
+    pub fn name<'t>(&self, text: &'t str) -> Option<&'t str> {
+        text.strip_prefix(Self::IDENT)?.strip_prefix(':')
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queries.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+}
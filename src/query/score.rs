@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::{index::RangeIndex, QueryResult, ID};
+
+/// Something a ScoreExpr variable reads, ids without a value count as 0.
+pub trait ScoreSource {
+    fn score(&self, id: ID) -> Option<f64>;
+}
+
+impl<V: Clone + Eq + Ord + Into<f64>> ScoreSource for RangeIndex<V> {
+    fn score(&self, id: ID) -> Option<f64> {
+        self.id_values().get(&id).map(|value| value.clone().into())
+    }
+}
+
+impl<F: Fn(ID) -> Option<f64>> ScoreSource for F {
+    fn score(&self, id: ID) -> Option<f64> {
+        self(id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+/// Arithmetic over per post values such as `score*0.7 + favs*0.3 - age_days*0.1`,
+/// used to rank a QueryResult. Supports `+ - * /`, parentheses and numbers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreExpr {
+    expr: Expr,
+}
+
+impl ScoreExpr {
+    /// Expressions nesting operators or parentheses deeper than this fail to parse,
+    /// which also bounds how deep eval recurses.
+    pub const MAX_DEPTH: usize = 64;
+
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(text: &str) -> Result<Self, ()> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            nesting: 0,
+        };
+        let (expr, _) = parser.expr()?;
+        if parser.index != parser.tokens.len() {
+            return Err(());
+        }
+        Ok(Self { expr })
+    }
+
+    /// Every variable in the expression, sorted.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        push_variables(&self.expr, &mut variables);
+        variables.sort_unstable();
+        variables.dedup();
+        variables
+    }
+
+    /// Score of id, None if a variable has no source.
+    pub fn eval(&self, sources: &HashMap<&str, &dyn ScoreSource>, id: ID) -> Option<f64> {
+        eval(&self.expr, sources, id)
+    }
+
+    /// Up to limit matches of result with their scores, highest first and by id for ties.
+    /// None if a variable has no source.
+    pub fn rank(
+        &self,
+        sources: &HashMap<&str, &dyn ScoreSource>,
+        result: &QueryResult,
+        limit: usize,
+    ) -> Option<Vec<(ID, f64)>> {
+        if self.variables().iter().any(|v| !sources.contains_key(v)) {
+            return None;
+        }
+        let mut ranked: Vec<(ID, f64)> = result
+            .get(0, result.matched(), false)
+            .into_iter()
+            .map(|id| (id, eval(&self.expr, sources, id).unwrap()))
+            .collect();
+        ranked.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(limit);
+        Some(ranked)
+    }
+}
+
+fn push_variables<'e>(expr: &'e Expr, variables: &mut Vec<&'e str>) {
+    match expr {
+        Expr::Number(_) => {}
+        Expr::Var(name) => variables.push(name),
+        Expr::Neg(e) => push_variables(e, variables),
+        Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) | Expr::Div(a, b) => {
+            push_variables(a, variables);
+            push_variables(b, variables);
+        }
+    }
+}
+
+fn eval(expr: &Expr, sources: &HashMap<&str, &dyn ScoreSource>, id: ID) -> Option<f64> {
+    Some(match expr {
+        Expr::Number(n) => *n,
+        Expr::Var(name) => sources.get(name.as_str())?.score(id).unwrap_or(0.0),
+        Expr::Neg(e) => -eval(e, sources, id)?,
+        Expr::Add(a, b) => eval(a, sources, id)? + eval(b, sources, id)?,
+        Expr::Sub(a, b) => eval(a, sources, id)? - eval(b, sources, id)?,
+        Expr::Mul(a, b) => eval(a, sources, id)? * eval(b, sources, id)?,
+        Expr::Div(a, b) => eval(a, sources, id)? / eval(b, sources, id)?,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Var(String),
+    Op(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ()> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if matches!(c, '+' | '-' | '*' | '/' | '(' | ')') {
+            tokens.push(Token::Op(c));
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Number(text[start..end].parse().map_err(|_| ())?));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Var(text[start..end].to_string()));
+        } else {
+            return Err(());
+        }
+    }
+    Ok(tokens)
+}
+
+// Each rule returns its Expr with the Expr's depth.
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    // Open parentheses and negations, which recurse without deepening the Expr.
+    nesting: usize,
+}
+
+// The depth of a node over children of depth a and b.
+fn deeper(a: usize, b: usize) -> Result<usize, ()> {
+    let depth = a.max(b) + 1;
+    if depth > ScoreExpr::MAX_DEPTH {
+        return Err(());
+    }
+    Ok(depth)
+}
+
+impl Parser {
+    fn next_op(&mut self, ops: &[char]) -> Option<char> {
+        match self.tokens.get(self.index) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.index += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<(Expr, usize), ()> {
+        let (mut expr, mut depth) = self.term()?;
+        while let Some(op) = self.next_op(&['+', '-']) {
+            let (rhs, rhs_depth) = self.term()?;
+            depth = deeper(depth, rhs_depth)?;
+            let rhs = Box::new(rhs);
+            expr = match op {
+                '+' => Expr::Add(Box::new(expr), rhs),
+                _ => Expr::Sub(Box::new(expr), rhs),
+            };
+        }
+        Ok((expr, depth))
+    }
+
+    fn term(&mut self) -> Result<(Expr, usize), ()> {
+        let (mut expr, mut depth) = self.factor()?;
+        while let Some(op) = self.next_op(&['*', '/']) {
+            let (rhs, rhs_depth) = self.factor()?;
+            depth = deeper(depth, rhs_depth)?;
+            let rhs = Box::new(rhs);
+            expr = match op {
+                '*' => Expr::Mul(Box::new(expr), rhs),
+                _ => Expr::Div(Box::new(expr), rhs),
+            };
+        }
+        Ok((expr, depth))
+    }
+
+    fn factor(&mut self) -> Result<(Expr, usize), ()> {
+        if let Some(op) = self.next_op(&['-', '(']) {
+            self.nesting += 1;
+            if self.nesting > ScoreExpr::MAX_DEPTH {
+                return Err(());
+            }
+            let result = if op == '-' {
+                let (expr, depth) = self.factor()?;
+                (Expr::Neg(Box::new(expr)), deeper(depth, 0)?)
+            } else {
+                let result = self.expr()?;
+                self.next_op(&[')']).ok_or(())?;
+                result
+            };
+            self.nesting -= 1;
+            return Ok(result);
+        }
+        let token = self.tokens.get(self.index).ok_or(())?;
+        self.index += 1;
+        match token {
+            Token::Number(n) => Ok((Expr::Number(*n), 0)),
+            Token::Var(name) => Ok((Expr::Var(name.clone()), 0)),
+            Token::Op(_) => Err(()),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    cell::RefCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::Packed;
+
+// Buffers kept per thread, enough for a few levels of nested OrChains.
+const MAX_POOLED: usize = 8;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<Packed>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Temporary checks taken from a per thread pool and put back when dropped, so the
+/// runner's masks and OrChain buffers don't allocate a db sized Vec per term.
+pub struct Scratch {
+    checks: Vec<Packed>,
+}
+
+impl Scratch {
+    pub fn filled(len: usize, value: Packed) -> Self {
+        let mut checks = POOL
+            .with(|pool| pool.borrow_mut().pop())
+            .unwrap_or_default();
+        checks.clear();
+        checks.resize(len, value);
+        Self { checks }
+    }
+
+    /// Frees the buffers pooled by the current thread, such as after a burst of queries.
+    pub fn release() {
+        POOL.with(|pool| pool.borrow_mut().clear());
+    }
+}
+
+impl Deref for Scratch {
+    type Target = [Packed];
+
+    fn deref(&self) -> &Self::Target {
+        &self.checks
+    }
+}
+
+impl DerefMut for Scratch {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.checks
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let checks = std::mem::take(&mut self.checks);
+        // try_with as the pool may already be gone while the thread exits.
+        let _ = POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED {
+                pool.push(checks);
+            }
+        });
+    }
+}
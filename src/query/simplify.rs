@@ -2,6 +2,7 @@ use super::{Item, Query};
 
 impl<T: Eq + Ord> Query<T> {
     pub fn simplify(&mut self) {
+        self.fold_constants();
         self.remove_single_chains();
         self.remove_redundant_chains();
         self.remove_empty();
@@ -9,6 +10,117 @@ impl<T: Eq + Ord> Query<T> {
         self.dedup();
     }
 
+    /// Moves inverses off chains onto the terms below them, so only terms are inverted
+    /// afterwards. An inverted AndChain inverts each of its items. An inverted OrChain
+    /// matches what none of its items do, which De Morgan's laws turn into an AndChain
+    /// of the inverted items, so `-( a or b )`, an AndChain inverting an OrChain, is `-a -b`.
+    pub fn push_inverse(&mut self) {
+        self.push_inverse_inner(self.inverse);
+    }
+
+    fn push_inverse_inner(&mut self, inverse: bool) {
+        self.inverse = false;
+        match &mut self.item {
+            Item::Single(_) => self.inverse = inverse,
+            // -( ) matches nothing.
+            Item::AndChain(items) if items.is_empty() && inverse => self.item = Item::Empty,
+            Item::AndChain(items) => {
+                for item in items {
+                    item.push_inverse_inner(item.inverse ^ inverse);
+                }
+            }
+            Item::OrChain(items) => {
+                for item in items.iter_mut() {
+                    item.push_inverse_inner(item.inverse);
+                }
+                if inverse {
+                    self.negate();
+                }
+            }
+            Item::All if inverse => self.item = Item::Empty,
+            Item::Empty if inverse => self.item = Item::All,
+            Item::All | Item::Empty => {}
+        }
+    }
+
+    // Expects push_inverse to have run.
+    fn negate(&mut self) {
+        match &mut self.item {
+            Item::Single(_) => self.inverse ^= true,
+            Item::AndChain(items) | Item::OrChain(items) => {
+                let mut items = std::mem::take(items);
+                for item in items.iter_mut() {
+                    item.negate();
+                }
+                self.item = match self.item {
+                    Item::AndChain(_) => Item::OrChain(items),
+                    _ => Item::AndChain(items),
+                };
+            }
+            Item::All => self.item = Item::Empty,
+            Item::Empty => self.item = Item::All,
+        }
+    }
+
+    /// Replaces chains that always or never match with Item::All or Item::Empty,
+    /// such as `tag -tag`, `tag or -tag` and chains containing a constant.
+    pub fn fold_constants(&mut self) {
+        self.push_inverse();
+        self.fold();
+    }
+
+    // Expects push_inverse to have run.
+    fn fold(&mut self) {
+        let is_and = match &self.item {
+            Item::AndChain(_) => true,
+            Item::OrChain(_) => false,
+            _ => return,
+        };
+        let (Item::AndChain(items) | Item::OrChain(items)) = &mut self.item else {
+            return;
+        };
+        let mut flat = Vec::with_capacity(items.len());
+        for mut item in std::mem::take(items) {
+            item.fold();
+            match item.item {
+                // All is the identity of an AndChain and absorbs an OrChain, Empty the reverse.
+                Item::All | Item::Empty => {
+                    if matches!(item.item, Item::All) != is_and {
+                        self.item = item.item;
+                        return;
+                    }
+                }
+                Item::AndChain(inner) if is_and => flat.extend(inner),
+                Item::OrChain(inner) if !is_and => flat.extend(inner),
+                _ => flat.push(item),
+            }
+        }
+        flat.sort();
+        flat.dedup();
+        let contradiction = flat
+            .windows(2)
+            .any(|pair| match (&pair[0].item, &pair[1].item) {
+                (Item::Single(a), Item::Single(b)) => a == b && pair[0].inverse != pair[1].inverse,
+                _ => false,
+            });
+        self.item = if contradiction || flat.is_empty() {
+            // `tag -tag` never matches, `tag or -tag` always does.
+            if is_and == contradiction {
+                Item::Empty
+            } else {
+                Item::All
+            }
+        } else if flat.len() == 1 {
+            let item = flat.pop().unwrap();
+            self.inverse = item.inverse;
+            item.item
+        } else if is_and {
+            Item::AndChain(flat)
+        } else {
+            Item::OrChain(flat)
+        };
+    }
+
     pub fn sort(&mut self) {
         match &mut self.item {
             Item::AndChain(items) | Item::OrChain(items) => {
@@ -118,3 +230,78 @@ impl<T: Eq + Ord> Query<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplified(text: &str) -> Query<String> {
+        let mut query = Query::parse(text).unwrap();
+        query.simplify();
+        query
+    }
+
+    fn term(tag: &str, inverse: bool) -> Query<String> {
+        Query::new(Item::Single(tag.to_string()), inverse)
+    }
+
+    #[test]
+    fn contradictions_fold_to_constants() {
+        assert_eq!(simplified("tag -tag").item, Item::Empty);
+        assert_eq!(simplified("tag or -tag").item, Item::All);
+        assert_eq!(simplified("-( )").item, Item::Empty);
+        assert_eq!(simplified("a -( b or -b )").item, Item::Empty);
+        assert_eq!(simplified("a ( b or -b )"), term("a", false));
+    }
+
+    #[test]
+    fn inverted_or_chain_becomes_inverted_terms() {
+        let query = simplified("-( a or b )");
+        let expected = Query::new(
+            Item::AndChain(vec![term("a", true), term("b", true)]),
+            false,
+        );
+        assert_eq!(query, expected);
+        assert_eq!(query.to_string(), "-a -b");
+    }
+
+    #[test]
+    fn constants_in_chains() {
+        let mut and = Query::new(Item::AndChain(vec![term("a", false), Query::all()]), false);
+        and.simplify();
+        assert_eq!(and, term("a", false));
+
+        let mut and = Query::new(
+            Item::AndChain(vec![term("a", false), Query::empty()]),
+            false,
+        );
+        and.simplify();
+        assert_eq!(and.item, Item::Empty);
+
+        let mut or = Query::new(Item::OrChain(vec![term("a", false), Query::empty()]), false);
+        or.simplify();
+        assert_eq!(or, term("a", false));
+
+        let mut or = Query::new(Item::OrChain(vec![term("a", false), Query::all()]), false);
+        or.simplify();
+        assert_eq!(or.item, Item::All);
+    }
+
+    #[test]
+    fn inverted_constants_swap() {
+        let mut all = Query::<String>::new(Item::All, true);
+        all.simplify();
+        assert_eq!(all, Query::empty());
+
+        let mut empty = Query::<String>::new(Item::Empty, true);
+        empty.simplify();
+        assert_eq!(empty, Query::all());
+
+        let mut or = Query::new(
+            Item::OrChain(vec![term("a", false), Query::new(Item::Empty, true)]),
+            false,
+        );
+        or.simplify();
+        assert_eq!(or, Query::all());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    time::Duration,
+};
+
+use crate::Packed;
+
+/// What running a query cost, for logging slow queries, see Db::query_with_stats.
+/// Bytes are counted from the checks each step of the runner reads or writes,
+/// terms applied as ids touch less than counted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvalStats {
+    /// Terms in the query after saved queries and rewrites.
+    pub terms: usize,
+    pub bitset_bytes_touched: usize,
+    /// OrChains built into a temporary bitset before being applied.
+    pub or_chain_materializations: usize,
+    /// From looking the terms up to having the result.
+    pub duration: Duration,
+}
+
+thread_local! {
+    // Some while collect is running on this thread.
+    static COLLECTING: RefCell<Option<EvalStats>> = const { RefCell::new(None) };
+    // Whether COLLECTING is Some, so runs without stats skip the borrow.
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+// Adds the cost of a step to the stats being collected, if any.
+pub(crate) fn record(f: impl FnOnce(&mut EvalStats)) {
+    if !ACTIVE.with(Cell::get) {
+        return;
+    }
+    COLLECTING.with(|collecting| {
+        if let Some(stats) = collecting.borrow_mut().as_mut() {
+            f(stats);
+        }
+    });
+}
+
+// A pass over this many words of checks.
+pub(crate) fn touch(words: usize) {
+    record(|stats| stats.bitset_bytes_touched += words * mem::size_of::<Packed>());
+}
+
+// Collects the stats of the steps run calls record for.
+pub(crate) fn collect<T>(run: impl FnOnce() -> T) -> (T, EvalStats) {
+    let outer = COLLECTING.with(|collecting| collecting.replace(Some(EvalStats::default())));
+    let active = ACTIVE.with(|flag| flag.replace(true));
+    let value = run();
+    ACTIVE.with(|flag| flag.set(active));
+    let stats = COLLECTING.with(|collecting| collecting.replace(outer));
+    (value, stats.unwrap_or_default())
+}
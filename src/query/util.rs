@@ -1,5 +1,6 @@
-use crate::{Packed, Queryable, ID, PACKED_SIZE};
+use crate::{packed, Packed, Queryable, ID, PACKED_SIZE};
 
+pub use super::queryable::{apply_checks, apply_ids, set_ids};
 use super::{Item, Query};
 
 pub fn size_of_checks(max_id: ID) -> usize {
@@ -17,7 +18,7 @@ pub fn to_checks(ids: &[ID]) -> Vec<Packed> {
     if ids.is_empty() {
         return Vec::new();
     }
-    let capacity = ((*ids.last().unwrap_or(&0) + 1) as f32 / PACKED_SIZE as f32).ceil() as usize;
+    let capacity = packed::words_for(*ids.last().unwrap());
     let mut checks = Vec::with_capacity(capacity);
     checks.extend((0..capacity).map(|_| 0));
     Queryable::IDs(ids).apply(&mut checks, false);
@@ -25,19 +26,9 @@ pub fn to_checks(ids: &[ID]) -> Vec<Packed> {
 }
 
 pub fn to_ids(checks: &[Packed]) -> Vec<ID> {
-    let capacity = checks.iter().map(|c| c.count_ones()).sum::<u32>() as usize;
-    let mut ids = Vec::with_capacity(capacity);
-    for (index, check) in checks.iter().enumerate() {
-        if *check == 0 {
-            continue;
-        }
-        let index = index as u32 * PACKED_SIZE;
-        for offset in 0..PACKED_SIZE {
-            if check & (1 << offset) != 0 {
-                let id = index + offset;
-                ids.push(id);
-            }
-        }
+    let mut ids = Vec::with_capacity(packed::count(checks));
+    for (index, &check) in checks.iter().enumerate() {
+        ids.extend(packed::ids(index, check));
     }
     ids
 }
@@ -47,10 +38,33 @@ impl<T> Query<T> {
         Self { item, inverse }
     }
 
+    /// Matches every id, for indexes to return without building a full set.
+    pub fn all() -> Self {
+        Self::new(Item::All, false)
+    }
+
+    /// Matches no id.
+    pub fn empty() -> Self {
+        Self::new(Item::Empty, false)
+    }
+
+    /// Matches exactly the ids self doesn't.
+    /// Inverting an AndChain inverts each item rather than the whole chain, so chains
+    /// other than a plain OrChain are wrapped in an inverted OrChain.
+    pub fn negated(self) -> Self {
+        match self.item {
+            Item::Single(term) => Self::new(Item::Single(term), !self.inverse),
+            Item::All if !self.inverse => Self::empty(),
+            Item::Empty if !self.inverse => Self::all(),
+            Item::OrChain(items) if !self.inverse => Self::new(Item::OrChain(items), true),
+            item => Self::new(Item::OrChain(vec![Self::new(item, self.inverse)]), true),
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         match &self.item {
             Item::AndChain(items) | Item::OrChain(items) => items.is_empty(),
-            Item::Single(_) => false,
+            Item::Single(_) | Item::All | Item::Empty => false,
         }
     }
 
@@ -59,25 +73,39 @@ impl<T> Query<T> {
             Item::AndChain(items) | Item::OrChain(items) => {
                 1 + items.iter().map(|item| item.item_count()).sum::<usize>()
             }
-            Item::Single(_) => 1,
+            Item::Single(_) | Item::All | Item::Empty => 1,
         }
     }
 
     pub fn tags(&self) -> Vec<(&T, bool)> {
         let mut tags = Vec::new();
+        self.push_tags(&mut tags);
+        tags
+    }
+
+    fn push_tags<'a>(&'a self, tags: &mut Vec<(&'a T, bool)>) {
         match &self.item {
             Item::AndChain(items) | Item::OrChain(items) => {
                 for item in items {
-                    tags.extend(item.tags());
+                    item.push_tags(tags);
                 }
             }
             Item::Single(tag) => tags.push((tag, self.inverse)),
+            Item::All | Item::Empty => {}
         }
-        tags
     }
 }
 
 impl<T: Clone> Query<T> {
+    /// Whether the query matches posts that have none of its terms, like `-tag`,
+    /// such queries match nearly every post.
+    pub fn is_negative_only(&self) -> bool {
+        let query = self
+            .try_map(|_, inverse| Some(Query::new(Item::Single(Queryable::EMPTY), inverse)))
+            .unwrap_or_else(|_| unreachable!());
+        query.matches(0)
+    }
+
     pub fn try_map<F: Clone + Fn(&T, bool) -> Option<Query<R>>, R>(
         &self,
         f: F,
@@ -94,19 +122,18 @@ impl<T: Clone> Query<T> {
         match &self.item {
             Item::AndChain(query_items) => {
                 let mut missing = Vec::new();
-                let items: Vec<Query<R>> = query_items
-                    .iter()
-                    .filter_map(|item| {
-                        let item = item.inner_try_map(f.clone(), inverse);
-                        match item {
-                            Ok(item) => Some(item),
-                            Err(m) => {
-                                missing.extend(m);
-                                None
-                            }
+                // filter_map can't size the Vec up front, so reserve for every item.
+                let mut items: Vec<Query<R>> = Vec::with_capacity(query_items.len());
+                items.extend(query_items.iter().filter_map(|item| {
+                    let item = item.inner_try_map(f.clone(), inverse);
+                    match item {
+                        Ok(item) => Some(item),
+                        Err(m) => {
+                            missing.extend(m);
+                            None
                         }
-                    })
-                    .collect();
+                    }
+                }));
                 if !missing.is_empty() {
                     Err(missing)
                 } else {
@@ -119,19 +146,17 @@ impl<T: Clone> Query<T> {
             Item::OrChain(query_items) => {
                 let len = query_items.len();
                 let mut missing = Vec::new();
-                let items: Vec<Query<R>> = query_items
-                    .iter()
-                    .filter_map(|item| {
-                        let item = item.inner_try_map(f.clone(), inverse);
-                        match item {
-                            Ok(item) => Some(item),
-                            Err(m) => {
-                                missing.extend(m);
-                                None
-                            }
+                let mut items: Vec<Query<R>> = Vec::with_capacity(query_items.len());
+                items.extend(query_items.iter().filter_map(|item| {
+                    let item = item.inner_try_map(f.clone(), inverse);
+                    match item {
+                        Ok(item) => Some(item),
+                        Err(m) => {
+                            missing.extend(m);
+                            None
                         }
-                    })
-                    .collect();
+                    }
+                }));
                 if items.is_empty() && len != 0 {
                     Err(missing)
                 } else {
@@ -150,6 +175,8 @@ impl<T: Clone> Query<T> {
                     Err(vec![tag.clone()])
                 }
             }
+            Item::All => Ok(Query::new(Item::All, self.inverse)),
+            Item::Empty => Ok(Query::new(Item::Empty, self.inverse)),
         }
     }
 }
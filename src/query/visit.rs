@@ -0,0 +1,46 @@
+use super::{Item, Query};
+
+/// Walks a Query depth first, see Query::walk.
+pub trait Visitor<T> {
+    /// Called for every query before its items, returning false skips them.
+    fn enter(&mut self, _query: &Query<T>) -> bool {
+        true
+    }
+
+    fn term(&mut self, _term: &T, _inverse: bool) {}
+
+    /// Called for every query after its items.
+    fn leave(&mut self, _query: &Query<T>) {}
+}
+
+impl<T> Query<T> {
+    pub fn walk(&self, visitor: &mut impl Visitor<T>) {
+        if !visitor.enter(self) {
+            return;
+        }
+        match &self.item {
+            Item::AndChain(items) | Item::OrChain(items) => {
+                for item in items {
+                    item.walk(visitor);
+                }
+            }
+            Item::Single(term) => visitor.term(term, self.inverse),
+            Item::All | Item::Empty => {}
+        }
+        visitor.leave(self);
+    }
+
+    /// Rebuilds the query bottom up, f gets every query after its items were transformed.
+    pub fn transform(self, f: &mut impl FnMut(Query<T>) -> Query<T>) -> Query<T> {
+        let item = match self.item {
+            Item::AndChain(items) => {
+                Item::AndChain(items.into_iter().map(|item| item.transform(f)).collect())
+            }
+            Item::OrChain(items) => {
+                Item::OrChain(items.into_iter().map(|item| item.transform(f)).collect())
+            }
+            item => item,
+        };
+        f(Query::new(item, self.inverse))
+    }
+}
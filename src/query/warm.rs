@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use crate::{packed, Packed, ID};
+
+use super::Queryable;
+
+/// Terms with their ids and the complement precomputed as checks, see Db::warm.
+/// `-huge_tag` and terms built per query such as ranges become a copy of a mask.
+#[derive(Clone, Debug, Default)]
+pub struct WarmTerms {
+    terms: HashMap<String, WarmTerm>,
+}
+
+#[derive(Clone, Debug)]
+struct WarmTerm {
+    checks: Vec<Packed>,
+    // !checks, ids past the end of both are in the complement.
+    complement: Vec<Packed>,
+}
+
+impl WarmTerms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// checks are the ids matched by term.
+    pub fn insert(&mut self, term: impl Into<String>, checks: Vec<Packed>) {
+        let complement = checks.iter().map(|c| !c).collect();
+        self.terms
+            .insert(term.into(), WarmTerm { checks, complement });
+    }
+
+    pub fn remove(&mut self, term: &str) -> bool {
+        self.terms.remove(term).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.terms.clear();
+    }
+
+    /// The ids matched by term, or the ids not matched by it if inverse.
+    pub fn get(&self, term: &str, inverse: bool) -> Option<Queryable<'_>> {
+        let warm = self.terms.get(term)?;
+        let checks = if inverse {
+            &warm.complement
+        } else {
+            &warm.checks
+        };
+        Some(Queryable::Checks(checks))
+    }
+
+    /// Records whether id matches term, after a post was inserted, removed or updated.
+    pub fn set(&mut self, term: &str, id: ID, matches: bool) {
+        let Some(warm) = self.terms.get_mut(term) else {
+            return;
+        };
+        let (index, bit) = (packed::word(id), packed::bit(id));
+        if index >= warm.checks.len() {
+            warm.checks.resize(index + 1, 0);
+            warm.complement.resize(index + 1, Packed::MAX);
+        }
+        if matches {
+            warm.checks[index] |= bit;
+            warm.complement[index] &= !bit;
+        } else {
+            warm.checks[index] &= !bit;
+            warm.complement[index] |= bit;
+        }
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.terms.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::{Query, QueryResult, ID};
+
+/// What the server needs from a Db.
+/// Usually implemented for a wrapper holding the Db behind a RwLock.
+pub trait Searcher: Send + Sync + 'static {
+    /// Errors are the terms no index could parse, as returned by Db::query.
+    fn query(&self, query: &Query<String>) -> Result<QueryResult, Vec<String>>;
+
+    /// Ids for a page of results, sorted by id descending by default.
+    fn page(&self, result: &QueryResult, offset: usize, limit: usize) -> Vec<ID> {
+        result.get(offset, limit, true)
+    }
+
+    /// Completions for prefix with their counts.
+    fn autocomplete(&self, _prefix: &str, _limit: usize) -> Vec<(String, usize)> {
+        Vec::new()
+    }
+
+    fn stats(&self) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message }).to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            414 => "URI Too Long",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+// Only ASCII hex digits, so `%+F` isn't read as a signed number.
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Reads a line of at most limit bytes, None if it is longer.
+fn read_line(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    limit: usize,
+) -> io::Result<Option<usize>> {
+    let read = reader.take(limit as u64 + 1).read_line(line)?;
+    if read > limit {
+        return Ok(None);
+    }
+    Ok(Some(read))
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Serves /search?q=..&page=..&limit=.., /autocomplete?q=..&limit=.. and /stats as JSON.
+pub struct Server<S> {
+    searcher: Arc<S>,
+    page_size: usize,
+    max_page_size: usize,
+    threads: usize,
+    read_timeout: Duration,
+    max_line: usize,
+    max_headers: usize,
+    started: Instant,
+    requests: AtomicU64,
+    error_log: Option<ErrorLog>,
+}
+
+type ErrorLog = Arc<dyn Fn(&io::Error) + Send + Sync>;
+
+impl<S: Searcher> Server<S> {
+    pub fn new(searcher: Arc<S>) -> Self {
+        Self {
+            searcher,
+            page_size: 20,
+            max_page_size: 100,
+            threads: 8,
+            read_timeout: Duration::from_secs(10),
+            max_line: 8 * 1024,
+            max_headers: 64 * 1024,
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            error_log: None,
+        }
+    }
+
+    /// Default and maximum value of the limit parameter.
+    pub fn with_page_size(mut self, page_size: usize, max_page_size: usize) -> Self {
+        self.max_page_size = max_page_size.max(1);
+        self.page_size = page_size.clamp(1, self.max_page_size);
+        self
+    }
+
+    /// Number of connections handled at once, more wait to be accepted.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Connections sending nothing for this long are closed.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Longest request line and total size of the request line and headers in bytes.
+    pub fn with_request_limits(mut self, max_line: usize, max_headers: usize) -> Self {
+        self.max_line = max_line.max(1);
+        self.max_headers = max_headers.max(self.max_line);
+        self
+    }
+
+    /// Called with errors accepting or handling a connection, which are otherwise
+    /// ignored. The server keeps running either way.
+    pub fn with_error_log(mut self, f: impl Fn(&io::Error) + Send + Sync + 'static) -> Self {
+        self.error_log = Some(Arc::new(f));
+        self
+    }
+
+    fn log_error(&self, e: &io::Error) {
+        if let Some(log) = &self.error_log {
+            log(e);
+        }
+    }
+
+    pub fn searcher(&self) -> &Arc<S> {
+        &self.searcher
+    }
+
+    pub fn serve(self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr)?)
+    }
+
+    /// Handles connections on a pool of with_threads threads.
+    /// Errors accepting a connection are passed to with_error_log and the server keeps running.
+    pub fn serve_listener(self, listener: TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        // Accepting waits while every thread is busy and the queue is full.
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(server.threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..server.threads {
+            let server = server.clone();
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let stream = match receiver.lock().unwrap().recv() {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                if let Err(e) = server.handle_connection(stream) {
+                    server.log_error(&e);
+                }
+            });
+        }
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    server.log_error(&e);
+                    // Such as running out of file descriptors, give connections time to close.
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let response = match self.read_request(&mut reader)? {
+            Ok(request_line) => {
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default();
+                let target = parts.next().unwrap_or_default();
+                self.handle(method, target)
+            }
+            Err(response) => response,
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            response.reason(),
+            response.body.len(),
+            response.body
+        )?;
+        stream.flush()
+    }
+
+    // The request line, after reading the headers up to the limits.
+    fn read_request(&self, reader: &mut impl BufRead) -> io::Result<Result<String, Response>> {
+        let mut request_line = String::new();
+        if read_line(reader, &mut request_line, self.max_line)?.is_none() {
+            return Ok(Err(Response::error(414, "request line too long")));
+        }
+        let mut total = request_line.len();
+        let mut header = String::new();
+        loop {
+            header.clear();
+            let limit = self.max_line.min(self.max_headers.saturating_sub(total));
+            match read_line(reader, &mut header, limit)? {
+                None => return Ok(Err(Response::error(431, "headers too large"))),
+                Some(0) => break,
+                Some(read) => total += read,
+            }
+            if header.trim_end().is_empty() {
+                break;
+            }
+        }
+        Ok(Ok(request_line))
+    }
+
+    /// Routes a request without any networking.
+    pub fn handle(&self, method: &str, target: &str) -> Response {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if method != "GET" {
+            return Response::error(405, "only GET is supported");
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let params: Vec<(String, String)> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+        match path {
+            "/search" => self.search(&params),
+            "/autocomplete" => self.autocomplete(&params),
+            "/stats" => self.stats(),
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    fn limit(&self, params: &[(String, String)]) -> Result<usize, Response> {
+        match param(params, "limit") {
+            None => Ok(self.page_size),
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) => Ok(limit.clamp(1, self.max_page_size)),
+                Err(_) => Err(Response::error(400, "invalid limit")),
+            },
+        }
+    }
+
+    fn search(&self, params: &[(String, String)]) -> Response {
+        let limit = match self.limit(params) {
+            Ok(limit) => limit,
+            Err(response) => return response,
+        };
+        // Pages start at 1.
+        let page = match param(params, "page").map(str::parse::<usize>) {
+            None => 1,
+            Some(Ok(page)) if page > 0 => page,
+            Some(_) => return Response::error(400, "invalid page"),
+        };
+        let text = param(params, "q").unwrap_or_default();
+        let Ok(query) = Query::<String>::parse(text) else {
+            return Response::error(400, "invalid query");
+        };
+        let result = match self.searcher.query(&query) {
+            Ok(result) => result,
+            Err(invalid) => {
+                return Response {
+                    status: 400,
+                    body: json!({ "error": "invalid terms", "terms": invalid }).to_string(),
+                };
+            }
+        };
+        let offset = (page - 1).saturating_mul(limit);
+        let ids = self.searcher.page(&result, offset, limit);
+        let body = json!({
+            "matched": result.matched(),
+            "page": page,
+            "limit": limit,
+            "ids": ids,
+        });
+        Response::json(body.to_string())
+    }
+
+    fn autocomplete(&self, params: &[(String, String)]) -> Response {
+        let limit = match self.limit(params) {
+            Ok(limit) => limit,
+            Err(response) => return response,
+        };
+        let prefix = param(params, "q").unwrap_or_default();
+        let items: Vec<_> = self
+            .searcher
+            .autocomplete(prefix, limit)
+            .into_iter()
+            .take(limit)
+            .map(|(text, count)| json!({ "text": text, "count": count }))
+            .collect();
+        Response::json(json!(items).to_string())
+    }
+
+    fn stats(&self) -> Response {
+        let mut stats = self.searcher.stats();
+        stats.push(("uptime_secs".to_string(), self.started.elapsed().as_secs()));
+        stats.push((
+            "requests".to_string(),
+            self.requests.load(Ordering::Relaxed),
+        ));
+        let fields: serde_json::Map<_, _> = stats
+            .into_iter()
+            .map(|(name, value)| (name, value.into()))
+            .collect();
+        Response::json(serde_json::Value::Object(fields).to_string())
+    }
+}
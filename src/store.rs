@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use crate::ID;
+
+/// Posts kept by the Db under their id, see DbLoader::with_store.
+/// Lets Db::remove_id and Db::update_id look up the old post themselves and
+/// Db::hydrate turn a page of ids into posts.
+/// Posts are shared with snapshots, writes only clone the list of pointers.
+pub struct PostStore<P> {
+    posts: Vec<Option<Arc<P>>>,
+    len: usize,
+    // Posts are given to the Db by reference.
+    clone: fn(&P) -> P,
+}
+
+impl<P> Clone for PostStore<P> {
+    fn clone(&self) -> Self {
+        Self {
+            posts: self.posts.clone(),
+            len: self.len,
+            clone: self.clone,
+        }
+    }
+}
+
+impl<P: Clone> Default for PostStore<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Clone> PostStore<P> {
+    pub fn new() -> Self {
+        Self {
+            posts: Vec::new(),
+            len: 0,
+            clone: P::clone,
+        }
+    }
+}
+
+impl<P> PostStore<P> {
+    pub fn get(&self, id: ID) -> Option<&P> {
+        self.posts.get(id as usize)?.as_deref()
+    }
+
+    /// Like get but the post can outlive a write to the store.
+    pub fn get_shared(&self, id: ID) -> Option<Arc<P>> {
+        self.posts.get(id as usize)?.clone()
+    }
+
+    pub fn contains(&self, id: ID) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Returns the post id had.
+    pub fn insert(&mut self, id: ID, post: P) -> Option<Arc<P>> {
+        let index = id as usize;
+        if index >= self.posts.len() {
+            self.posts.resize(index + 1, None);
+        }
+        let old = self.posts[index].replace(Arc::new(post));
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Clones post into the store.
+    pub fn insert_ref(&mut self, id: ID, post: &P) -> Option<Arc<P>> {
+        self.insert(id, (self.clone)(post))
+    }
+
+    /// Changes the post of id in place, cloning it first if a snapshot shares it.
+    /// Returns false if id has no post.
+    pub fn edit(&mut self, id: ID, f: impl FnOnce(&mut P)) -> bool
+    where
+        P: Clone,
+    {
+        match self.posts.get_mut(id as usize) {
+            Some(Some(post)) => {
+                f(Arc::make_mut(post));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: ID) -> Option<Arc<P>> {
+        let old = self.posts.get_mut(id as usize)?.take();
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Every post with its id, lowest id first.
+    pub fn iter(&self) -> impl Iterator<Item = (ID, &P)> {
+        self.posts
+            .iter()
+            .enumerate()
+            .filter_map(|(id, post)| Some((id as ID, post.as_deref()?)))
+    }
+}
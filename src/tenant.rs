@@ -0,0 +1,160 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use crate::{
+    persist::{
+        read_key_delta, write_key_delta, PersistKey, Section, SectionReader, SnapshotWriter,
+    },
+    Packed, Queryable, QueryableOwned, ID,
+};
+
+type TenantFn<P> = dyn Fn(&P) -> Option<String> + Send + Sync;
+
+/// Ids of each tenant, such as the site a post belongs to when one process serves
+/// several boorus. Indexes are shared, Db::query_tenant only narrows the base ids.
+pub struct Tenants<P> {
+    tenant: Arc<TenantFn<P>>,
+    ids: HashMap<String, QueryableOwned>,
+}
+
+impl<P> Clone for Tenants<P> {
+    fn clone(&self) -> Self {
+        Self {
+            tenant: self.tenant.clone(),
+            ids: self.ids.clone(),
+        }
+    }
+}
+
+impl<P> Tenants<P> {
+    /// tenant returns the tenant of a post, posts without one are only in unscoped queries.
+    pub fn new(tenant: impl Fn(&P) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            tenant: Arc::new(tenant),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn tenant_of(&self, post: &P) -> Option<String> {
+        (self.tenant)(post)
+    }
+
+    pub fn get(&self, tenant: &str) -> Option<Queryable<'_>> {
+        self.ids.get(tenant).map(Queryable::from)
+    }
+
+    /// Every tenant with its number of ids, hidden ids included.
+    pub fn counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.ids
+            .iter()
+            .map(|(tenant, ids)| (tenant.as_str(), ids.matched()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// base narrowed to the ids of tenant, empty if tenant has none.
+    pub fn scope(&self, tenant: &str, base: &[Packed]) -> Vec<Packed> {
+        let mut scoped = base.to_vec();
+        match self.get(tenant) {
+            Some(ids) => ids.and(&mut scoped, false),
+            None => scoped.fill(0),
+        }
+        scoped
+    }
+
+    /// Safe if id is higher than any id added before, used while loading.
+    pub(crate) fn add(&mut self, id: ID, post: &P) {
+        if let Some(tenant) = self.tenant_of(post) {
+            self.ids.entry(tenant).or_default().insert_unchecked(id);
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        for ids in self.ids.values_mut() {
+            ids.check_and_convert();
+        }
+    }
+
+    pub fn insert(&mut self, id: ID, post: &P) {
+        if let Some(tenant) = self.tenant_of(post) {
+            self.ids.entry(tenant).or_default().insert(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: ID, post: &P) {
+        let Some(tenant) = self.tenant_of(post) else {
+            return;
+        };
+        if let Some(ids) = self.ids.get_mut(&tenant) {
+            ids.remove(id);
+            if ids.matched() == 0 {
+                self.ids.remove(&tenant);
+            }
+        }
+    }
+
+    /// Like Db::update also adds id if it's missing from the tenant of new.
+    pub fn update(&mut self, id: ID, old: &P, new: &P) {
+        if self.tenant_of(old) != self.tenant_of(new) {
+            self.remove(id, old);
+        }
+        self.insert(id, new);
+    }
+
+    /// Writes every tenant's ids, read back by open.
+    pub(crate) fn save(&self, w: &mut SnapshotWriter) -> io::Result<()> {
+        w.write_u64(self.ids.len() as u64)?;
+        for (tenant, ids) in &self.ids {
+            tenant.write_key(w)?;
+            w.write_queryable(ids)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the ids of every tenant with those written by save.
+    pub(crate) fn open(&mut self, section: &Section) -> io::Result<()> {
+        let mut reader = section.reader();
+        // a tenant's length, the queryable kind and its len and matched.
+        let count = reader.read_len(21)?;
+        let mut tenants = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let tenant = String::read_key(&mut reader)?;
+            let stored = reader.read_queryable()?;
+            let ids = match section.queryable(&stored) {
+                Queryable::Checks(checks) => QueryableOwned::from(checks.to_vec()),
+                Queryable::IDs(ids) => QueryableOwned::from(ids.to_vec()),
+                _ => unreachable!(),
+            };
+            tenants.insert(tenant, ids);
+        }
+        self.ids = tenants;
+        Ok(())
+    }
+
+    /// Writes the tenants of ids, read back by apply_delta.
+    pub(crate) fn save_delta(&self, ids: &[ID], w: &mut SnapshotWriter) -> io::Result<()> {
+        write_key_delta(w, self.ids.iter(), ids)
+    }
+
+    /// Replaces the tenants of ids with those written by save_delta.
+    pub(crate) fn apply_delta(&mut self, ids: &[ID], r: &mut SectionReader) -> io::Result<()> {
+        for queryable in self.ids.values_mut() {
+            for id in queryable.filter_ids(ids) {
+                queryable.remove(id);
+            }
+        }
+        self.ids.retain(|_, queryable| queryable.matched() > 0);
+        for (tenant, ids) in read_key_delta::<String>(r)? {
+            let queryable = self.ids.entry(tenant).or_default();
+            for id in ids {
+                queryable.insert(id);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::{
+    query::{util::to_ids, Item},
+    Db, Query, Queryable, ID,
+};
+
+/// Posts kept in a Vec, every term is checked against every post by a closure.
+/// Slow but simple enough to trust, for checking an Index gives the same results.
+/// Terms are combined with the same rules Db::query uses.
+pub struct ReferenceDb<P> {
+    posts: Vec<Option<P>>,
+    #[allow(clippy::type_complexity)]
+    matches: Box<dyn Fn(&P, &str) -> bool>,
+}
+
+impl<P> ReferenceDb<P> {
+    /// matches is given a post and a term such as `tag` or `score:>10`.
+    pub fn new(matches: impl Fn(&P, &str) -> bool + 'static) -> Self {
+        Self {
+            posts: Vec::new(),
+            matches: Box::new(matches),
+        }
+    }
+
+    /// Posts are given ids in order like DbLoader::load.
+    pub fn with_posts(mut self, posts: impl IntoIterator<Item = P>) -> Self {
+        self.posts.extend(posts.into_iter().map(Some));
+        self
+    }
+
+    pub fn get(&self, id: ID) -> Option<&P> {
+        self.posts.get(id as usize)?.as_ref()
+    }
+
+    pub fn insert(&mut self, id: ID, post: P) {
+        let index = id as usize;
+        if index >= self.posts.len() {
+            self.posts.resize_with(index + 1, || None);
+        }
+        self.posts[index] = Some(post);
+    }
+
+    pub fn remove(&mut self, id: ID) -> Option<P> {
+        self.posts.get_mut(id as usize)?.take()
+    }
+
+    /// Ids of every post matching query in ascending order.
+    pub fn query(&self, query: &Query<String>) -> Vec<ID> {
+        let query = query
+            .try_map(|term, inverse| {
+                let ids = self.ids_with(term);
+                Some(Query::new(Item::Single(Queryable::IDsOwned(ids)), inverse))
+            })
+            .unwrap_or_else(|_| unreachable!());
+        self.ids_with_post()
+            .filter(|&id| query.matches(id))
+            .collect()
+    }
+
+    fn ids_with(&self, term: &str) -> Vec<ID> {
+        self.ids_with_post()
+            .filter(|&id| (self.matches)(self.get(id).unwrap(), term))
+            .collect()
+    }
+
+    fn ids_with_post(&self) -> impl Iterator<Item = ID> + '_ {
+        self.posts
+            .iter()
+            .enumerate()
+            .filter(|(_, post)| post.is_some())
+            .map(|(id, _)| id as ID)
+    }
+}
+
+/// A query whose results differ between a Db and a ReferenceDb.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub query: String,
+    /// Matched by the reference but not the db.
+    pub missing: Vec<ID>,
+    /// Matched by the db but not the reference.
+    pub extra: Vec<ID>,
+    /// Terms the db couldn't look up.
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: missing {:?}, extra {:?}",
+            self.query, self.missing, self.extra
+        )?;
+        if !self.errors.is_empty() {
+            write!(f, ", unknown terms {:?}", self.errors)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs query on both and returns how the db's results differ.
+/// Hidden posts should be removed from the reference first.
+pub fn compare<P: 'static>(
+    db: &Db<P>,
+    reference: &ReferenceDb<P>,
+    query: &str,
+) -> Result<(), Mismatch> {
+    let parsed = Query::parse(query).unwrap_or_else(|_| panic!("invalid query {query:?}"));
+    let expected = reference.query(&parsed);
+    let (actual, errors) = match db.query(&parsed) {
+        Ok(result) => (to_ids(result.checks()), Vec::new()),
+        Err(errors) => (Vec::new(), errors),
+    };
+    if actual == expected && errors.is_empty() {
+        return Ok(());
+    }
+    let missing = expected
+        .iter()
+        .filter(|id| actual.binary_search(id).is_err())
+        .copied()
+        .collect();
+    let extra = actual
+        .iter()
+        .filter(|id| expected.binary_search(id).is_err())
+        .copied()
+        .collect();
+    Err(Mismatch {
+        query: query.to_string(),
+        missing,
+        extra,
+        errors,
+    })
+}
+
+/// Panics listing every query whose results differ, see compare.
+pub fn assert_agrees<P: 'static>(db: &Db<P>, reference: &ReferenceDb<P>, queries: &[&str]) {
+    let mismatches: Vec<String> = queries
+        .iter()
+        .filter_map(|query| compare(db, reference, query).err())
+        .map(|mismatch| mismatch.to_string())
+        .collect();
+    if !mismatches.is_empty() {
+        panic!(
+            "{} of {} queries differ from the reference:\n{}",
+            mismatches.len(),
+            queries.len(),
+            mismatches.join("\n")
+        );
+    }
+}
+
+/// Every combination of terms up to depth deep, for feeding assert_agrees.
+/// Includes negations, `a b`, `a or b`, `-a b` and `( a or b ) c` shapes.
+pub fn combinations(terms: &[&str], depth: usize) -> Vec<String> {
+    let mut queries: Vec<String> = terms
+        .iter()
+        .flat_map(|term| [term.to_string(), format!("-{term}")])
+        .collect();
+    let singles = queries.clone();
+    let mut layer = singles.clone();
+    for _ in 1..depth {
+        let mut next = Vec::new();
+        for query in &layer {
+            for single in &singles {
+                next.push(format!("{query} {single}"));
+                next.push(format!("{query} or {single}"));
+                next.push(format!("( {query} ) {single}"));
+                next.push(format!("-( {query} ) {single}"));
+            }
+        }
+        queries.extend(next.iter().cloned());
+        layer = next;
+    }
+    queries
+}